@@ -1,4 +1,4 @@
-use otel_multivariate_time_series::event::{EventCollector, BatchPolicy, EventBatchHandler};
+use otel_multivariate_time_series::event::{EventCollector, BatchPolicy, EventBatchHandler, ArrowEventBatchHandler};
 use otel_multivariate_time_series::error::Error;
 use std::path::PathBuf;
 use std::io::BufReader;
@@ -14,6 +14,7 @@ use crate::json_trace::JsonTrace;
 fn main() -> Result<(), Error> {
     let event_collector = EventCollector::new(BatchPolicy::new(100, chrono::Duration::seconds(10)));
     let mut trace_handler: EventBatchHandler<JsonTrace> = event_collector.event_handler();
+    let mut arrow_trace_handler: ArrowEventBatchHandler<JsonTrace> = event_collector.arrow_event_handler().expect("unsupported arrow schema");
 
     let file = File::open(data_file("trace_samples.json")).expect("trace_samples.json not found");
     let reader = BufReader::new(file);
@@ -25,13 +26,19 @@ fn main() -> Result<(), Error> {
         if count == 10 {
             break;
         }
-        trace_handler.record(json_trace);
+        trace_handler.record(json_trace.clone());
+        arrow_trace_handler.record(json_trace).expect("arrow record error");
         count+=1;
     }
+    arrow_trace_handler.flush().expect("arrow flush error");
 
-    let json_value = trace_handler.to_json_value();
+    let json_value = trace_handler.to_json_value().expect("json rendering error");
     println!("{}", serde_json::to_string_pretty(&json_value).expect("invalid json serialization"));
 
+    for record_batch in arrow_trace_handler.record_batches().expect("arrow decoding error") {
+        println!("arrow record batch: {} rows x {} columns", record_batch.num_rows(), record_batch.num_columns());
+    }
+
     Ok(())
 }
 
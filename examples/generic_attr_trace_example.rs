@@ -71,7 +71,7 @@ impl ProfilableProtocol for Test {
     }
 
     fn deserialize(&mut self, buffer: Vec<u8>) {
-        self.trace_handler.deserialize(buffer);
+        self.trace_handler.deserialize(buffer).expect("valid resource events");
     }
 
     fn clear(&mut self) {
@@ -4,6 +4,8 @@ use serde::Deserialize;
 use otel_multivariate_time_series::event::{BatchPolicy, ArrowEventBatchHandler, reset_validity_bitmap, set_nth_bit, OpenTelemetryArrowEvent, OpenTelemetryEvent, EventBatchHandler};
 use prost::Message;
 use arrow::datatypes::{Schema, Field, DataType};
+use arrow::array::{Int64Builder, StringBuilder, StructBuilder, ListBuilder};
+use arrow::error::ArrowError;
 use otel_multivariate_time_series::opentelemetry::proto::events::v1::{Int64Column, StringColumn, AuxiliaryEntity};
 use otel_multivariate_time_series::opentelemetry::proto::events::v1::auxiliary_entity::LogicalType;
 
@@ -100,6 +102,8 @@ impl OpenTelemetryEvent for JsonTrace {
                 bytes_values: Vec::with_capacity(0),
                 i64_summary_values: Vec::with_capacity(0),
                 f64_summary_values: Vec::with_capacity(0),
+                histogram_values: Vec::with_capacity(0),
+                exponential_histogram_values: Vec::with_capacity(0),
             },
         ]
     }
@@ -188,7 +192,37 @@ impl OpenTelemetryArrowEvent for JsonTrace {
         "urn:project_a:trace:service".into()
     }
 
-    fn record_into(self, handler: &mut ArrowEventBatchHandler<Self>) where Self: Sized {
+    fn record_into(self, handler: &mut ArrowEventBatchHandler<Self>) -> Result<(), ArrowError> where Self: Sized {
+        handler.field_builder::<Int64Builder>(0).expect("kind builder not found").append_option(self.evt.kind)?;
+
+        let status = handler.field_builder::<StructBuilder>(1).expect("status builder not found");
+        status.field_builder::<StringBuilder>(0).expect("status.message builder not found").append_option(self.evt.status.message)?;
+        status.field_builder::<Int64Builder>(1).expect("status.code builder not found").append_option(self.evt.status.code)?;
+        status.append(true)?;
+
+        handler.field_builder::<StringBuilder>(2).expect("trace_id builder not found").append_value(self.evt.trace_id)?;
+        handler.field_builder::<StringBuilder>(3).expect("span_id builder not found").append_value(self.evt.span_id)?;
+        handler.field_builder::<StringBuilder>(4).expect("trace_state builder not found").append_option(self.evt.trace_state)?;
+        handler.field_builder::<StringBuilder>(5).expect("parent_span_id builder not found").append_option(self.evt.parent_span_id)?;
+        handler.field_builder::<StringBuilder>(6).expect("name builder not found").append_value(self.evt.name)?;
+
+        let attributes = handler.field_builder::<ListBuilder<StructBuilder>>(7).expect("attributes builder not found");
+        match self.evt.attributes {
+            None => attributes.append(false)?,
+            Some(attribute_map) => {
+                for (name, value) in attribute_map {
+                    if let Some(value) = value {
+                        let attribute = attributes.values();
+                        attribute.field_builder::<StringBuilder>(0).expect("attr.name builder not found").append_value(name)?;
+                        attribute.field_builder::<StringBuilder>(1).expect("attr.value builder not found").append_value(value)?;
+                        attribute.append(true)?;
+                    }
+                }
+                attributes.append(true)?;
+            }
+        }
+
+        Ok(())
     }
 
     fn arrow_schema(_batch_policy: &BatchPolicy) -> Schema {
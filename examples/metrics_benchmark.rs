@@ -19,6 +19,8 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     otel_v1::metrics::profile(&mut profiler, &dataset,max_iter)?;
     otel_columnar::metrics::profile(&mut profiler, &dataset, max_iter)?;
+    otel_columnar::metrics::profile_dictionary_encoding(&mut profiler, &dataset, max_iter)?;
+    otel_columnar::metrics::profile_delta_encoding(&mut profiler, &dataset, max_iter)?;
     otel_arrow::metrics::profile(&mut profiler, &dataset, max_iter)?;
 
     profiler.check_processing_results();
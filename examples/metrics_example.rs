@@ -1,8 +1,5 @@
-use chrono::Utc;
-
 use http_transaction::HttpTransaction;
-use otel_multivariate_time_series::event::{BatchPolicy, Error, EventCollector, EventBatchHandler, OpenTelemetryEvent};
-use otel_multivariate_time_series::opentelemetry::proto::events::v1::{Int64Column, StringColumn};
+use otel_multivariate_time_series::event::{BatchPolicy, Error, EventCollector, EventBatchHandler};
 
 mod http_transaction;
 
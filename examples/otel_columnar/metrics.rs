@@ -1,4 +1,5 @@
 use otel_multivariate_time_series::multivariate_ts_gen::MultivariateDataPoint;
+use otel_multivariate_time_series::delta_encoding::Encoding;
 
 use crate::dataset::Dataset;
 use crate::profiler::{Profiler, ProfilableProtocol};
@@ -14,19 +15,49 @@ use std::error::Error;
 struct Test {
     dataset: Dataset<MultivariateDataPoint>,
     resource_events: Option<ResourceEvents>,
+    dictionary_encoding: bool,
+    time_encoding: Encoding,
 }
 
 pub fn profile(profiler: &mut Profiler, dataset: &Dataset<MultivariateDataPoint>, max_iter: usize) -> Result<(), Box<dyn Error>> {
     let mut test = Test {
         dataset: dataset.clone(),
         resource_events: None,
+        dictionary_encoding: false,
+        time_encoding: Encoding::None,
+    };
+    profiler.profile(&mut test, max_iter)
+}
+
+/// Same as `profile` with the low cardinality string columns dictionary encoded.
+pub fn profile_dictionary_encoding(profiler: &mut Profiler, dataset: &Dataset<MultivariateDataPoint>, max_iter: usize) -> Result<(), Box<dyn Error>> {
+    let mut test = Test {
+        dataset: dataset.clone(),
+        resource_events: None,
+        dictionary_encoding: true,
+        time_encoding: Encoding::None,
+    };
+    profiler.profile(&mut test, max_iter)
+}
+
+/// Same as `profile` with the timestamp columns double-delta encoded.
+pub fn profile_delta_encoding(profiler: &mut Profiler, dataset: &Dataset<MultivariateDataPoint>, max_iter: usize) -> Result<(), Box<dyn Error>> {
+    let mut test = Test {
+        dataset: dataset.clone(),
+        resource_events: None,
+        dictionary_encoding: false,
+        time_encoding: Encoding::DoubleDelta,
     };
     profiler.profile(&mut test, max_iter)
 }
 
 impl ProfilableProtocol for Test {
     fn name(&self) -> String {
-        "columnar".into()
+        match (self.dictionary_encoding, self.time_encoding) {
+            (true, _) => "columnar_dict".into(),
+            (false, Encoding::None) => "columnar".into(),
+            (false, _) => "columnar_delta".into(),
+        }
     }
 
     fn init_batch_size(&mut self, _batch_size: usize) {}
@@ -36,7 +67,16 @@ impl ProfilableProtocol for Test {
     }
 
     fn create_batch(&mut self, start_at: usize, size: usize) {
-        self.resource_events = Some(gen_columnar_metrics(&self.dataset.values[start_at..start_at + size]));
+        let mut resource_events = gen_columnar_metrics(&self.dataset.values[start_at..start_at + size]);
+        if self.dictionary_encoding {
+            resource_events.encode_dictionaries();
+        }
+        for instrumentation_library_events in &mut resource_events.instrumentation_library_events {
+            for batch in &mut instrumentation_library_events.batches {
+                batch.encode_timestamps(self.time_encoding);
+            }
+        }
+        self.resource_events = Some(resource_events);
     }
 
     fn process(&self) -> String {
@@ -95,6 +135,9 @@ pub fn gen_columnar_metrics(time_series: &[MultivariateDataPoint]) -> ResourceEv
                         size: time_series.len() as u32,
                         start_time_unix_nano_column: time_series.iter().map(|p| p.ts.timestamp_nanos() as u64).collect(),
                         end_time_unix_nano_column: time_series.iter().map(|p| p.ts.timestamp_nanos() as u64).collect(),
+                        time_encoding: 0,
+                        encoded_start_time_unix_nano_column: vec![],
+                        encoded_end_time_unix_nano_column: vec![],
                         i64_values: vec![
                             Int64Column {
                                 name: "tls_handshake_ms".into(),
@@ -105,6 +148,8 @@ pub fn gen_columnar_metrics(time_series: &[MultivariateDataPoint]) -> ResourceEv
                                 is_monotonic: false,
                                 values: time_series.iter().map(|p| p.evt.fields.tls_handshake_ms).collect(),
                                 validity_bitmap: vec![],
+                                encoding: 0,
+                                encoded_values: vec![],
                             },
                             Int64Column {
                                 name: "dns_lookup_ms".into(),
@@ -115,6 +160,8 @@ pub fn gen_columnar_metrics(time_series: &[MultivariateDataPoint]) -> ResourceEv
                                 is_monotonic: false,
                                 values: time_series.iter().map(|p| p.evt.fields.dns_lookup_ms).collect(),
                                 validity_bitmap: vec![],
+                                encoding: 0,
+                                encoded_values: vec![],
                             },
                             Int64Column {
                                 name: "server_processing_ms".into(),
@@ -125,6 +172,8 @@ pub fn gen_columnar_metrics(time_series: &[MultivariateDataPoint]) -> ResourceEv
                                 is_monotonic: false,
                                 values: time_series.iter().map(|p| p.evt.fields.server_processing_ms).collect(),
                                 validity_bitmap: vec![],
+                                encoding: 0,
+                                encoded_values: vec![],
                             },
                             Int64Column {
                                 name: "tcp_connection_ms".into(),
@@ -135,6 +184,8 @@ pub fn gen_columnar_metrics(time_series: &[MultivariateDataPoint]) -> ResourceEv
                                 is_monotonic: false,
                                 values: time_series.iter().map(|p| p.evt.fields.tcp_connection_ms).collect(),
                                 validity_bitmap: vec![],
+                                encoding: 0,
+                                encoded_values: vec![],
                             },
                             Int64Column {
                                 name: "content_transfer_ms".into(),
@@ -145,6 +196,8 @@ pub fn gen_columnar_metrics(time_series: &[MultivariateDataPoint]) -> ResourceEv
                                 is_monotonic: false,
                                 values: time_series.iter().map(|p| p.evt.fields.content_transfer_ms).collect(),
                                 validity_bitmap: vec![],
+                                encoding: 0,
+                                encoded_values: vec![],
                             },
                             Int64Column {
                                 name: "health_status".into(),
@@ -155,6 +208,8 @@ pub fn gen_columnar_metrics(time_series: &[MultivariateDataPoint]) -> ResourceEv
                                 is_monotonic: false,
                                 values: time_series.iter().map(|p| p.evt.fields.health_status).collect(),
                                 validity_bitmap: vec![],
+                                encoding: 0,
+                                encoded_values: vec![],
                             },
                             Int64Column {
                                 name: "failure_count".into(),
@@ -165,6 +220,8 @@ pub fn gen_columnar_metrics(time_series: &[MultivariateDataPoint]) -> ResourceEv
                                 is_monotonic: false,
                                 values: time_series.iter().map(|p| p.evt.fields.failure_count).collect(),
                                 validity_bitmap: vec![],
+                                encoding: 0,
+                                encoded_values: vec![],
                             },
                             Int64Column {
                                 name: "size".into(),
@@ -175,6 +232,8 @@ pub fn gen_columnar_metrics(time_series: &[MultivariateDataPoint]) -> ResourceEv
                                 is_monotonic: false,
                                 values: time_series.iter().map(|p| p.evt.fields.size).collect(),
                                 validity_bitmap: vec![],
+                                encoding: 0,
+                                encoded_values: vec![],
                             },
                         ],
                         f64_values: vec![],
@@ -185,6 +244,8 @@ pub fn gen_columnar_metrics(time_series: &[MultivariateDataPoint]) -> ResourceEv
                                 description: "".into(),
                                 values: time_series.iter().map(|p| p.evt.tags.method.clone()).collect(),
                                 validity_bitmap: vec![],
+                                dictionary: vec![],
+                                dictionary_indices: vec![],
                             },
                             StringColumn {
                                 name: "dns_lookup_ms_label_class".into(),
@@ -192,6 +253,8 @@ pub fn gen_columnar_metrics(time_series: &[MultivariateDataPoint]) -> ResourceEv
                                 description: "".into(),
                                 values: time_series.iter().map(|p| p.evt.tags.dns_lookup_ms_label_class.clone()).collect(),
                                 validity_bitmap: vec![],
+                                dictionary: vec![],
+                                dictionary_indices: vec![],
                             },
                             StringColumn {
                                 name: "source".into(),
@@ -199,6 +262,8 @@ pub fn gen_columnar_metrics(time_series: &[MultivariateDataPoint]) -> ResourceEv
                                 description: "".into(),
                                 values: time_series.iter().map(|p| p.evt.tags.source.clone()).collect(),
                                 validity_bitmap: vec![],
+                                dictionary: vec![],
+                                dictionary_indices: vec![],
                             },
                             StringColumn {
                                 name: "url".into(),
@@ -206,6 +271,8 @@ pub fn gen_columnar_metrics(time_series: &[MultivariateDataPoint]) -> ResourceEv
                                 description: "".into(),
                                 values: time_series.iter().map(|p| p.evt.tags.url.clone()).collect(),
                                 validity_bitmap: vec![],
+                                dictionary: vec![],
+                                dictionary_indices: vec![],
                             },
                             StringColumn {
                                 name: "tls_handshake_ms_label_class".into(),
@@ -213,6 +280,8 @@ pub fn gen_columnar_metrics(time_series: &[MultivariateDataPoint]) -> ResourceEv
                                 description: "".into(),
                                 values: time_series.iter().map(|p| p.evt.tags.tls_handshake_ms_label_class.clone()).collect(),
                                 validity_bitmap: vec![],
+                                dictionary: vec![],
+                                dictionary_indices: vec![],
                             },
                             StringColumn {
                                 name: "remote_address".into(),
@@ -220,6 +289,8 @@ pub fn gen_columnar_metrics(time_series: &[MultivariateDataPoint]) -> ResourceEv
                                 description: "".into(),
                                 values: time_series.iter().map(|p| p.evt.tags.remote_address.clone()).collect(),
                                 validity_bitmap: vec![],
+                                dictionary: vec![],
+                                dictionary_indices: vec![],
                             },
                             StringColumn {
                                 name: "content_transfer_ms_label_class".into(),
@@ -227,6 +298,8 @@ pub fn gen_columnar_metrics(time_series: &[MultivariateDataPoint]) -> ResourceEv
                                 description: "".into(),
                                 values: time_series.iter().map(|p| p.evt.tags.content_transfer_ms_label_class.clone()).collect(),
                                 validity_bitmap: vec![],
+                                dictionary: vec![],
+                                dictionary_indices: vec![],
                             },
                             StringColumn {
                                 name: "server_processing_ms_label_class".into(),
@@ -234,6 +307,8 @@ pub fn gen_columnar_metrics(time_series: &[MultivariateDataPoint]) -> ResourceEv
                                 description: "".into(),
                                 values: time_series.iter().map(|p| p.evt.tags.server_processing_ms_label_class.clone()).collect(),
                                 validity_bitmap: vec![],
+                                dictionary: vec![],
+                                dictionary_indices: vec![],
                             },
                             StringColumn {
                                 name: "tcp_connection_ms_label_class".into(),
@@ -241,6 +316,8 @@ pub fn gen_columnar_metrics(time_series: &[MultivariateDataPoint]) -> ResourceEv
                                 description: "".into(),
                                 values: time_series.iter().map(|p| p.evt.tags.tcp_connection_ms_label_class.clone()).collect(),
                                 validity_bitmap: vec![],
+                                dictionary: vec![],
+                                dictionary_indices: vec![],
                             },
                         ],
                         bool_values: vec![],
@@ -248,9 +325,12 @@ pub fn gen_columnar_metrics(time_series: &[MultivariateDataPoint]) -> ResourceEv
                         i64_summary_values: vec![],
                         f64_summary_values: vec![],
                         auxiliary_entities: vec![],
+                        histogram_values: vec![],
+                        exponential_histogram_values: vec![],
                     }
                 ],
                 dropped_events_count: 0,
+                schema_url: "".into(),
             }
         ],
         schema_url: "tbd".into(),
@@ -8,6 +8,9 @@ use flate2::Compression;
 use std::io::Write;
 use std::time::Instant;
 use otel_multivariate_time_series::opentelemetry::proto::metrics::v1::ResourceMetrics;
+use otel_multivariate_time_series::opentelemetry::proto::resource::v1::Resource;
+use otel_multivariate_time_series::opentelemetry::proto::common::v1::{KeyValue, AnyValue, InstrumentationLibrary};
+use otel_multivariate_time_series::opentelemetry::proto::common::v1::any_value::Value;
 use bytes::Bytes;
 use plotters::prelude::*;
 
@@ -60,7 +63,7 @@ fn main() -> Result<(), Box<dyn Error>> {
         println!();
 
         let before_gen_time = Instant::now();
-        let resource_metrics = gen_columnar_metrics(&time_series);
+        let resource_metrics = gen_columnar_metrics(&time_series, resource(), InstrumentationLibrary { name: "rust-std".into(), version: "1.0".into() });
         let gen_time = Instant::now();
         let mut buf: Vec<u8> = Vec::new();
         let before_ser_time = Instant::now();
@@ -312,5 +315,13 @@ pub fn build_charts(std_uncompressed_size_vec: &[(i64, i64)],
         .unwrap();
 }
 
-
-
+fn resource() -> Resource {
+    Resource {
+        attributes: vec![
+            KeyValue { key: "key_1".into(), value: Some(AnyValue { value: Some(Value::StringValue("val1".into())) }) },
+            KeyValue { key: "key_2".into(), value: Some(AnyValue { value: Some(Value::StringValue("val2".into())) }) },
+            KeyValue { key: "key_3".into(), value: Some(AnyValue { value: Some(Value::StringValue("val3".into())) }) },
+        ],
+        dropped_attributes_count: 0,
+    }
+}
@@ -143,6 +143,8 @@ impl OpenTelemetryEvent for Trace {
                 bytes_values: Vec::with_capacity(0),
                 i64_summary_values: Vec::with_capacity(0),
                 f64_summary_values: Vec::with_capacity(0),
+                histogram_values: Vec::with_capacity(0),
+                exponential_histogram_values: Vec::with_capacity(0),
             },
             // Links
             AuxiliaryEntity {
@@ -165,6 +167,8 @@ impl OpenTelemetryEvent for Trace {
                 bytes_values: Vec::with_capacity(0),
                 i64_summary_values: Vec::with_capacity(0),
                 f64_summary_values: Vec::with_capacity(0),
+                histogram_values: Vec::with_capacity(0),
+                exponential_histogram_values: Vec::with_capacity(0),
             },
         ]
     }
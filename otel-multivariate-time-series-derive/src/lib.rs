@@ -0,0 +1,874 @@
+//! Derive macros generating the column plumbing of `otel_multivariate_time_series::event::OpenTelemetryEvent`
+//! and `otel_multivariate_time_series::event::OpenTelemetryAuxiliaryEntity`, and of their decoding counterparts
+//! `otel_multivariate_time_series::event_decoder::FromBatchEvent` and
+//! `otel_multivariate_time_series::event_decoder::FromAuxiliaryEntity`.
+//!
+//! Column ranks are derived from the declaration order of the fields, so the generated `*_columns()`
+//! constructors and the generated `record_into` can't get out of sync.
+//!
+//! Supported field types:
+//! * i8, i16, i32, i64, isize, u8, u16, u32, u64, usize -> `Int64Column`
+//! * f32, f64 -> `DoubleColumn`
+//! * String -> `StringColumn`
+//! * bool -> `BoolColumn`
+//! * Vec<u8> -> `BytesColumn`
+//! * Histogram, ExponentialHistogram (see `otel_multivariate_time_series::histogram`) -> `HistogramColumn`,
+//!   `ExponentialHistogramColumn`
+//! * Summary<i64>, Summary<f64> (see `otel_multivariate_time_series::summary`) -> `Int64SummaryColumn`,
+//!   `DoubleSummaryColumn`
+//! * Option<T> -> optional column (i.e. with a validity bitmap) for any of the previous types
+//! * Vec<T> -> auxiliary entity, T must implement `OpenTelemetryAuxiliaryEntity` (events only)
+//!
+//! Container attributes:
+//! * `#[otel(urn = "...")]` - schema url of the event (required for events).
+//! * `#[otel(schema_url = "...")]` - schema url of an auxiliary entity (optional).
+//!
+//! Field attributes:
+//! * `name = "..."` - column name (default: field name).
+//! * `description = "..."`, `unit = "..."` - column metadata.
+//! * `logical_type = "..."` - attribute, gauge, sum, uuid, span_id, trace_id for columns and
+//!   attribute, trace_event, trace_link, other for auxiliary entities.
+//! * `aggregation_temporality = "delta" | "cumulative"`, `monotonic` - sum columns metadata.
+//! * `optional` - declares a validity bitmap for a non `Option` field.
+//! * `start_time`, `end_time` - field used as start/end time of the event (any type implementing `UnixNano`).
+//! * `skip` - field ignored by the generated code (initialized with `Default::default()` when decoded).
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, GenericArgument, Ident, Lit, Meta, NestedMeta, PathArguments, Result, Type};
+
+#[proc_macro_derive(OpenTelemetryEvent, attributes(otel))]
+pub fn derive_open_telemetry_event(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_event(&input).unwrap_or_else(|err| err.to_compile_error()).into()
+}
+
+#[proc_macro_derive(OpenTelemetryAuxiliaryEntity, attributes(otel))]
+pub fn derive_open_telemetry_auxiliary_entity(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_auxiliary_entity(&input).unwrap_or_else(|err| err.to_compile_error()).into()
+}
+
+#[proc_macro_derive(FromBatchEvent, attributes(otel))]
+pub fn derive_from_batch_event(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_from_batch_event(&input).unwrap_or_else(|err| err.to_compile_error()).into()
+}
+
+#[proc_macro_derive(FromAuxiliaryEntity, attributes(otel))]
+pub fn derive_from_auxiliary_entity(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_from_auxiliary_entity(&input).unwrap_or_else(|err| err.to_compile_error()).into()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ColumnType {
+    Int64,
+    Double,
+    String,
+    Bool,
+    Bytes,
+    Histogram,
+    ExponentialHistogram,
+    Int64Summary,
+    DoubleSummary,
+}
+
+enum FieldKind {
+    Column(ColumnType),
+    AuxiliaryEntity(Box<Type>),
+    StartTime,
+    EndTime,
+    Skip,
+}
+
+struct FieldSpec {
+    ident: Ident,
+    ty: Type,
+    name: String,
+    description: String,
+    unit: String,
+    logical_type: Option<(String, Span)>,
+    aggregation_temporality: Option<(String, Span)>,
+    is_monotonic: bool,
+    optional: bool,
+    is_option: bool,
+    kind: FieldKind,
+}
+
+#[derive(Default)]
+struct ContainerSpec {
+    urn: Option<String>,
+    schema_url: Option<String>,
+}
+
+/// Column constructors and record statements generated for one column family.
+#[derive(Default)]
+struct ColumnFamily {
+    constructors: Vec<TokenStream2>,
+    records: Vec<TokenStream2>,
+}
+
+#[derive(Default)]
+struct Columns {
+    int64: ColumnFamily,
+    double: ColumnFamily,
+    string: ColumnFamily,
+    bool: ColumnFamily,
+    bytes: ColumnFamily,
+    histogram: ColumnFamily,
+    exponential_histogram: ColumnFamily,
+    int64_summary: ColumnFamily,
+    double_summary: ColumnFamily,
+}
+
+fn root() -> TokenStream2 {
+    quote!(::otel_multivariate_time_series)
+}
+
+fn expand_event(input: &DeriveInput) -> Result<TokenStream2> {
+    let root = root();
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let container = parse_container_attributes(&input.attrs)?;
+    let urn = container.urn.ok_or_else(|| Error::new(input.span(), "missing #[otel(urn = \"...\")] attribute"))?;
+    let fields = parse_fields(input)?;
+
+    let columns = gen_columns(&fields, &quote!(batch), false)?;
+
+    let mut start_time = quote!(let start_time_unix_nano = #root::event::unix_nano_now(););
+    let mut end_time = quote!(let end_time_unix_nano = start_time_unix_nano;);
+    let mut auxiliary_constructors = vec![];
+    let mut auxiliary_records = vec![];
+
+    for field in &fields {
+        let field_ident = &field.ident;
+        match &field.kind {
+            FieldKind::StartTime => {
+                start_time = quote!(let start_time_unix_nano = #root::event::UnixNano::unix_nano(&self.#field_ident););
+            }
+            FieldKind::EndTime => {
+                end_time = quote!(let end_time_unix_nano = #root::event::UnixNano::unix_nano(&self.#field_ident););
+            }
+            FieldKind::AuxiliaryEntity(ty) => {
+                let rank = auxiliary_constructors.len();
+                let parent_column = &field.name;
+                let logical_type = auxiliary_logical_type(field)?;
+                auxiliary_constructors.push(quote! {
+                    <#ty as #root::event::OpenTelemetryAuxiliaryEntity>::auxiliary_entity(#parent_column, #logical_type, batch_policy)
+                });
+                auxiliary_records.push(quote! {
+                    for value in self.#field_ident {
+                        <#ty as #root::event::OpenTelemetryAuxiliaryEntity>::record_into_auxiliary_entity(value, &mut batch.auxiliary_entities[#rank], parent_rank);
+                    }
+                });
+            }
+            _ => {}
+        }
+    }
+
+    let events_v1 = quote!(#root::opentelemetry::proto::events::v1);
+    let column_fns = gen_column_fns(&columns);
+    let records = columns.records();
+
+    Ok(quote! {
+        impl #impl_generics #root::event::OpenTelemetryEvent for #ident #ty_generics #where_clause {
+            fn urn() -> String {
+                #urn.into()
+            }
+
+            #column_fns
+
+            fn auxiliary_entities(batch_policy: &#root::event::BatchPolicy) -> Vec<#events_v1::AuxiliaryEntity> where Self: Sized {
+                vec![#(#auxiliary_constructors),*]
+            }
+
+            #[allow(clippy::unnecessary_cast)]
+            fn record_into(self, handler: &mut #root::event::EventBatchHandler<Self>) where Self: Sized {
+                #start_time
+                #end_time
+                let batch = &mut handler.resource_events.instrumentation_library_events[0].batches[0];
+
+                batch.start_time_unix_nano_column.push(start_time_unix_nano);
+                batch.end_time_unix_nano_column.push(end_time_unix_nano);
+
+                #(#records)*
+
+                batch.size += 1;
+
+                #[allow(unused_variables)]
+                let parent_rank = batch.size - 1;
+                #(#auxiliary_records)*
+            }
+        }
+    })
+}
+
+fn expand_auxiliary_entity(input: &DeriveInput) -> Result<TokenStream2> {
+    let root = root();
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let container = parse_container_attributes(&input.attrs)?;
+    let schema_url = container.schema_url.unwrap_or_default();
+    let fields = parse_fields(input)?;
+    check_auxiliary_entity_fields(&fields)?;
+
+    let columns = gen_columns(&fields, &quote!(auxiliary_entity), true)?;
+    let events_v1 = quote!(#root::opentelemetry::proto::events::v1);
+    let int64_columns = &columns.int64.constructors;
+    let double_columns = &columns.double.constructors;
+    let string_columns = &columns.string.constructors;
+    let bool_columns = &columns.bool.constructors;
+    let bytes_columns = &columns.bytes.constructors;
+    let histogram_columns = &columns.histogram.constructors;
+    let exponential_histogram_columns = &columns.exponential_histogram.constructors;
+    let int64_summary_columns = &columns.int64_summary.constructors;
+    let double_summary_columns = &columns.double_summary.constructors;
+    let records = columns.records();
+
+    Ok(quote! {
+        impl #impl_generics #root::event::OpenTelemetryAuxiliaryEntity for #ident #ty_generics #where_clause {
+            fn auxiliary_entity(parent_column: &str, logical_type: #events_v1::auxiliary_entity::LogicalType, batch_policy: &#root::event::BatchPolicy) -> #events_v1::AuxiliaryEntity {
+                #events_v1::AuxiliaryEntity {
+                    schema_url: #schema_url.into(),
+                    logical_type: logical_type as i32,
+                    size: 0,
+                    parent_column: parent_column.into(),
+                    parent_ranks: Vec::with_capacity(batch_policy.max_size as usize),
+                    i64_values: vec![#(#int64_columns),*],
+                    f64_values: vec![#(#double_columns),*],
+                    string_values: vec![#(#string_columns),*],
+                    bool_values: vec![#(#bool_columns),*],
+                    bytes_values: vec![#(#bytes_columns),*],
+                    i64_summary_values: vec![#(#int64_summary_columns),*],
+                    f64_summary_values: vec![#(#double_summary_columns),*],
+                    histogram_values: vec![#(#histogram_columns),*],
+                    exponential_histogram_values: vec![#(#exponential_histogram_columns),*],
+                }
+            }
+
+            #[allow(clippy::unnecessary_cast)]
+            fn record_into_auxiliary_entity(self, auxiliary_entity: &mut #events_v1::AuxiliaryEntity, parent_rank: u32) {
+                auxiliary_entity.parent_ranks.push(parent_rank);
+
+                #(#records)*
+
+                auxiliary_entity.size += 1;
+            }
+        }
+    })
+}
+
+fn expand_from_batch_event(input: &DeriveInput) -> Result<TokenStream2> {
+    let root = root();
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    parse_container_attributes(&input.attrs)?;
+    let fields = parse_fields(input)?;
+    let initializers = fields.iter().map(gen_field_decoder).collect::<Vec<_>>();
+
+    Ok(quote! {
+        impl #impl_generics #root::event_decoder::FromBatchEvent for #ident #ty_generics #where_clause {
+            #[allow(clippy::unnecessary_cast)]
+            fn from_event_row(row: &#root::event_decoder::EventRow) -> Result<Self, #root::event::Error> {
+                Ok(#ident {
+                    #(#initializers),*
+                })
+            }
+        }
+    })
+}
+
+fn expand_from_auxiliary_entity(input: &DeriveInput) -> Result<TokenStream2> {
+    let root = root();
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    parse_container_attributes(&input.attrs)?;
+    let fields = parse_fields(input)?;
+    check_auxiliary_entity_fields(&fields)?;
+    let initializers = fields.iter().map(gen_field_decoder).collect::<Vec<_>>();
+
+    Ok(quote! {
+        impl #impl_generics #root::event_decoder::FromAuxiliaryEntity for #ident #ty_generics #where_clause {
+            #[allow(clippy::unnecessary_cast)]
+            fn from_row(row: &#root::event_decoder::Row) -> Result<Self, #root::event::Error> {
+                Ok(#ident {
+                    #(#initializers),*
+                })
+            }
+        }
+    })
+}
+
+/// Generates the initializer of a field from a row (see `gen_column_record` for the reverse operation).
+fn gen_field_decoder(field: &FieldSpec) -> TokenStream2 {
+    let root = root();
+    let ident = &field.ident;
+    let name = &field.name;
+
+    let value = match &field.kind {
+        FieldKind::Skip => quote!(Default::default()),
+        FieldKind::StartTime => quote!(#root::event_decoder::FromUnixNano::from_unix_nano(row.start_time_unix_nano()?)),
+        FieldKind::EndTime => quote!(#root::event_decoder::FromUnixNano::from_unix_nano(row.end_time_unix_nano()?)),
+        FieldKind::AuxiliaryEntity(ty) => quote! {
+            row.auxiliary_rows(#name)?
+                .map(|row| <#ty as #root::event_decoder::FromAuxiliaryEntity>::from_row(&row))
+                .collect::<Result<Vec<_>, _>>()?
+        },
+        FieldKind::Column(column_type) => {
+            let ty = if field.is_option {
+                generic_argument(&field.ty, "Option").expect("option type")
+            } else {
+                &field.ty
+            };
+            let (getter, converted) = match column_type {
+                ColumnType::Int64 => (quote!(i64_value), quote!(value as #ty)),
+                ColumnType::Double => (quote!(f64_value), quote!(value as #ty)),
+                ColumnType::String => (quote!(string_value), quote!(value.to_string())),
+                ColumnType::Bool => (quote!(bool_value), quote!(value)),
+                ColumnType::Bytes => (quote!(bytes_value), quote!(value.to_vec())),
+                ColumnType::Histogram => (quote!(histogram_value), quote!(value)),
+                ColumnType::ExponentialHistogram => (quote!(exponential_histogram_value), quote!(value)),
+                ColumnType::Int64Summary => (quote!(i64_summary_value), quote!(value)),
+                ColumnType::DoubleSummary => (quote!(f64_summary_value), quote!(value)),
+            };
+            let value = quote!(row.#getter(#name)?.map(|value| #converted));
+
+            if field.is_option {
+                value
+            } else if field.optional {
+                quote!(#value.unwrap_or_default())
+            } else {
+                quote!(#root::event_decoder::required(#value, #name, row.row())?)
+            }
+        }
+    };
+
+    quote!(#ident: #value)
+}
+
+fn check_auxiliary_entity_fields(fields: &[FieldSpec]) -> Result<()> {
+    for field in fields {
+        match field.kind {
+            FieldKind::AuxiliaryEntity(_) => return Err(Error::new(field.ident.span(), "nested auxiliary entities are not supported")),
+            FieldKind::StartTime | FieldKind::EndTime => return Err(Error::new(field.ident.span(), "auxiliary entities don't have start/end time columns")),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+impl Columns {
+    fn family_mut(&mut self, column_type: ColumnType) -> &mut ColumnFamily {
+        match column_type {
+            ColumnType::Int64 => &mut self.int64,
+            ColumnType::Double => &mut self.double,
+            ColumnType::String => &mut self.string,
+            ColumnType::Bool => &mut self.bool,
+            ColumnType::Bytes => &mut self.bytes,
+            ColumnType::Histogram => &mut self.histogram,
+            ColumnType::ExponentialHistogram => &mut self.exponential_histogram,
+            ColumnType::Int64Summary => &mut self.int64_summary,
+            ColumnType::DoubleSummary => &mut self.double_summary,
+        }
+    }
+
+    fn records(&self) -> Vec<&TokenStream2> {
+        self.int64.records.iter()
+            .chain(self.double.records.iter())
+            .chain(self.string.records.iter())
+            .chain(self.bool.records.iter())
+            .chain(self.bytes.records.iter())
+            .chain(self.histogram.records.iter())
+            .chain(self.exponential_histogram.records.iter())
+            .chain(self.int64_summary.records.iter())
+            .chain(self.double_summary.records.iter())
+            .collect()
+    }
+}
+
+fn gen_column_fns(columns: &Columns) -> TokenStream2 {
+    let root = root();
+    let events_v1 = quote!(#root::opentelemetry::proto::events::v1);
+    let mut fns = TokenStream2::new();
+
+    let families = [
+        (&columns.int64, quote!(int64_columns), quote!(Int64Column)),
+        (&columns.double, quote!(double_columns), quote!(DoubleColumn)),
+        (&columns.string, quote!(string_columns), quote!(StringColumn)),
+        (&columns.bool, quote!(bool_columns), quote!(BoolColumn)),
+        (&columns.bytes, quote!(bytes_columns), quote!(BytesColumn)),
+        (&columns.histogram, quote!(histogram_columns), quote!(HistogramColumn)),
+        (&columns.exponential_histogram, quote!(exponential_histogram_columns), quote!(ExponentialHistogramColumn)),
+        (&columns.int64_summary, quote!(int64_summary_columns), quote!(Int64SummaryColumn)),
+        (&columns.double_summary, quote!(double_summary_columns), quote!(DoubleSummaryColumn)),
+    ];
+
+    for (family, fn_name, column_type) in families.iter() {
+        if family.constructors.is_empty() {
+            continue;
+        }
+        let constructors = &family.constructors;
+        fns.extend(quote! {
+            fn #fn_name(batch_policy: &#root::event::BatchPolicy) -> Vec<#events_v1::#column_type> {
+                vec![#(#constructors),*]
+            }
+        });
+    }
+
+    fns
+}
+
+fn gen_columns(fields: &[FieldSpec], container: &TokenStream2, unbounded: bool) -> Result<Columns> {
+    let mut columns = Columns::default();
+
+    for field in fields {
+        let column_type = match field.kind {
+            FieldKind::Column(column_type) => column_type,
+            _ => continue,
+        };
+        let family = columns.family_mut(column_type);
+        let rank = family.constructors.len();
+        family.constructors.push(gen_column_constructor(field, column_type)?);
+        family.records.push(gen_column_record(field, column_type, container, rank, unbounded));
+    }
+
+    Ok(columns)
+}
+
+fn gen_column_constructor(field: &FieldSpec, column_type: ColumnType) -> Result<TokenStream2> {
+    let root = root();
+    let events_v1 = quote!(#root::opentelemetry::proto::events::v1);
+    let name = &field.name;
+    let description = &field.description;
+    let unit = &field.unit;
+    let is_monotonic = field.is_monotonic;
+    let validity_bitmap = if field.optional {
+        quote!(#root::event::validity_bitmap(batch_policy.max_size as usize))
+    } else {
+        quote!(vec![])
+    };
+    let values = quote!(Vec::with_capacity(batch_policy.max_size as usize));
+    let aggregation_temporality = aggregation_temporality(field)?;
+
+    if !unit.is_empty() && !matches!(column_type, ColumnType::Int64 | ColumnType::Double | ColumnType::Histogram | ColumnType::ExponentialHistogram | ColumnType::Int64Summary | ColumnType::DoubleSummary) {
+        return Err(Error::new(field.ident.span(), "unit is only supported on numerical columns"));
+    }
+
+    Ok(match column_type {
+        ColumnType::Int64 => {
+            let logical_type = column_logical_type(field, quote!(#events_v1::int64_column::LogicalType), &["attribute", "gauge", "sum"])?;
+            quote! {
+                #events_v1::Int64Column {
+                    name: #name.into(),
+                    logical_type: #logical_type as i32,
+                    description: #description.into(),
+                    unit: #unit.into(),
+                    aggregation_temporality: #aggregation_temporality as i32,
+                    is_monotonic: #is_monotonic,
+                    values: #values,
+                    validity_bitmap: #validity_bitmap,
+                    encoding: 0,
+                    encoded_values: vec![],
+                }
+            }
+        }
+        ColumnType::Double => {
+            let logical_type = column_logical_type(field, quote!(#events_v1::double_column::LogicalType), &["attribute", "gauge", "sum"])?;
+            quote! {
+                #events_v1::DoubleColumn {
+                    name: #name.into(),
+                    logical_type: #logical_type as i32,
+                    description: #description.into(),
+                    unit: #unit.into(),
+                    aggregation_temporality: #aggregation_temporality as i32,
+                    is_monotonic: #is_monotonic,
+                    values: #values,
+                    validity_bitmap: #validity_bitmap,
+                }
+            }
+        }
+        ColumnType::String => {
+            let logical_type = column_logical_type(field, quote!(#events_v1::string_column::LogicalType), &["attribute", "uuid"])?;
+            quote! {
+                #events_v1::StringColumn {
+                    name: #name.into(),
+                    logical_type: #logical_type as i32,
+                    description: #description.into(),
+                    values: #values,
+                    validity_bitmap: #validity_bitmap,
+                    dictionary: vec![],
+                    dictionary_indices: vec![],
+                }
+            }
+        }
+        ColumnType::Bool => {
+            let logical_type = column_logical_type(field, quote!(#events_v1::bool_column::LogicalType), &["attribute"])?;
+            quote! {
+                #events_v1::BoolColumn {
+                    name: #name.into(),
+                    logical_type: #logical_type as i32,
+                    description: #description.into(),
+                    values: #values,
+                    validity_bitmap: #validity_bitmap,
+                }
+            }
+        }
+        ColumnType::Bytes => {
+            let logical_type = column_logical_type(field, quote!(#events_v1::bytes_column::LogicalType), &["attribute", "gauge", "sum", "uuid", "span_id", "trace_id"])?;
+            quote! {
+                #events_v1::BytesColumn {
+                    name: #name.into(),
+                    logical_type: #logical_type as i32,
+                    description: #description.into(),
+                    encoding: #events_v1::bytes_column::Encoding::None as i32,
+                    values: #values,
+                    validity_bitmap: #validity_bitmap,
+                }
+            }
+        }
+        ColumnType::Histogram | ColumnType::ExponentialHistogram => {
+            if let Some((_, span)) = &field.logical_type {
+                return Err(Error::new(*span, "histogram columns don't have a logical type"));
+            }
+            let column_type = if column_type == ColumnType::Histogram {
+                quote!(HistogramColumn)
+            } else {
+                quote!(ExponentialHistogramColumn)
+            };
+            quote! {
+                #events_v1::#column_type {
+                    name: #name.into(),
+                    description: #description.into(),
+                    unit: #unit.into(),
+                    aggregation_temporality: #aggregation_temporality as i32,
+                    values: Some(Default::default()),
+                    validity_bitmap: #validity_bitmap,
+                }
+            }
+        }
+        ColumnType::Int64Summary | ColumnType::DoubleSummary => {
+            if let Some((_, span)) = &field.logical_type {
+                return Err(Error::new(*span, "summary columns don't have a logical type"));
+            }
+            let column_type = if column_type == ColumnType::Int64Summary {
+                quote!(Int64SummaryColumn)
+            } else {
+                quote!(DoubleSummaryColumn)
+            };
+            quote! {
+                #events_v1::#column_type {
+                    name: #name.into(),
+                    description: #description.into(),
+                    unit: #unit.into(),
+                    aggregation_temporality: #aggregation_temporality as i32,
+                    min_values: #values,
+                    max_values: #values,
+                    count_values: #values,
+                    sum_values: #values,
+                    validity_bitmap: #validity_bitmap,
+                    quantile_offsets: vec![],
+                    quantiles: vec![],
+                    quantile_values: vec![],
+                }
+            }
+        }
+    })
+}
+
+fn gen_column_record(field: &FieldSpec, column_type: ColumnType, container: &TokenStream2, rank: usize, unbounded: bool) -> TokenStream2 {
+    let root = root();
+    let ident = &field.ident;
+    let column = match column_type {
+        ColumnType::Int64 => quote!(#container.i64_values[#rank]),
+        ColumnType::Double => quote!(#container.f64_values[#rank]),
+        ColumnType::String => quote!(#container.string_values[#rank]),
+        ColumnType::Bool => quote!(#container.bool_values[#rank]),
+        ColumnType::Bytes => quote!(#container.bytes_values[#rank]),
+        ColumnType::Histogram => quote!(#container.histogram_values[#rank]),
+        ColumnType::ExponentialHistogram => quote!(#container.exponential_histogram_values[#rank]),
+        ColumnType::Int64Summary => quote!(#container.i64_summary_values[#rank]),
+        ColumnType::DoubleSummary => quote!(#container.f64_summary_values[#rank]),
+    };
+
+    // Histogram and summary columns maintain their validity bitmap (see `HistogramColumn::push`).
+    if matches!(column_type, ColumnType::Histogram | ColumnType::ExponentialHistogram | ColumnType::Int64Summary | ColumnType::DoubleSummary) {
+        return if field.is_option {
+            quote!(#column.push(self.#ident.as_ref());)
+        } else {
+            quote!(#column.push(Some(&self.#ident));)
+        };
+    }
+
+    let value = quote!(value);
+    let converted = match column_type {
+        ColumnType::Int64 => quote!(#value as i64),
+        ColumnType::Double => quote!(#value as f64),
+        _ => value.clone(),
+    };
+
+    if !field.optional {
+        return quote! {
+            let value = self.#ident;
+            #column.values.push(#converted);
+        };
+    }
+
+    // Auxiliary entities are not bounded by the batch policy, their validity bitmaps must grow with the values.
+    let set_bit = if unbounded {
+        format_ident!("grow_and_set_nth_bit")
+    } else {
+        format_ident!("set_nth_bit")
+    };
+
+    let optional_value = if field.is_option {
+        quote!(self.#ident)
+    } else {
+        quote!(Some(self.#ident))
+    };
+
+    quote! {
+        match #optional_value {
+            None => #column.values.push(Default::default()),
+            Some(value) => {
+                let column = &mut #column;
+                column.values.push(#converted);
+                let nth_bit = column.values.len() - 1;
+                #root::event::#set_bit(&mut column.validity_bitmap, nth_bit);
+            }
+        }
+    }
+}
+
+fn column_logical_type(field: &FieldSpec, enum_path: TokenStream2, allowed: &[&str]) -> Result<TokenStream2> {
+    let (logical_type, span) = match &field.logical_type {
+        Some((logical_type, span)) => (logical_type.as_str(), *span),
+        None => ("attribute", field.ident.span()),
+    };
+    if !allowed.contains(&logical_type) {
+        return Err(Error::new(span, format!("unsupported logical type '{}' for this column (expected one of: {})", logical_type, allowed.join(", "))));
+    }
+    let variant = variant_ident(logical_type);
+    Ok(quote!(#enum_path::#variant))
+}
+
+fn auxiliary_logical_type(field: &FieldSpec) -> Result<TokenStream2> {
+    let root = root();
+    let (logical_type, span) = match &field.logical_type {
+        Some((logical_type, span)) => (logical_type.as_str(), *span),
+        None => ("other", field.ident.span()),
+    };
+    let allowed = ["attribute", "trace_event", "trace_link", "other"];
+    if !allowed.contains(&logical_type) {
+        return Err(Error::new(span, format!("unsupported logical type '{}' for an auxiliary entity (expected one of: {})", logical_type, allowed.join(", "))));
+    }
+    let variant = variant_ident(logical_type);
+    Ok(quote!(#root::opentelemetry::proto::events::v1::auxiliary_entity::LogicalType::#variant))
+}
+
+fn aggregation_temporality(field: &FieldSpec) -> Result<TokenStream2> {
+    let root = root();
+    let variant = match &field.aggregation_temporality {
+        None => "unspecified",
+        Some((temporality, span)) => match temporality.as_str() {
+            "delta" | "cumulative" => temporality.as_str(),
+            _ => return Err(Error::new(*span, "unsupported aggregation temporality (expected delta or cumulative)")),
+        },
+    };
+    let variant = variant_ident(variant);
+    Ok(quote!(#root::opentelemetry::proto::events::v1::AggregationTemporality::#variant))
+}
+
+/// Converts a snake case logical type into the corresponding prost enum variant (e.g. trace_event -> TraceEvent).
+fn variant_ident(logical_type: &str) -> Ident {
+    format_ident!("{}", logical_type.split('_').map(capitalize).collect::<String>())
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn parse_container_attributes(attrs: &[syn::Attribute]) -> Result<ContainerSpec> {
+    let mut container = ContainerSpec::default();
+
+    for meta in otel_metas(attrs)? {
+        match &meta {
+            Meta::NameValue(name_value) if name_value.path.is_ident("urn") => container.urn = Some(lit_str(&name_value.lit)?),
+            Meta::NameValue(name_value) if name_value.path.is_ident("schema_url") => container.schema_url = Some(lit_str(&name_value.lit)?),
+            _ => return Err(Error::new(meta.span(), "unsupported otel container attribute")),
+        }
+    }
+
+    Ok(container)
+}
+
+fn parse_fields(input: &DeriveInput) -> Result<Vec<FieldSpec>> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(Error::new(input.span(), "only structs with named fields are supported")),
+        },
+        _ => return Err(Error::new(input.span(), "only structs with named fields are supported")),
+    };
+
+    fields.iter().map(parse_field).collect()
+}
+
+fn parse_field(field: &syn::Field) -> Result<FieldSpec> {
+    let ident = field.ident.clone().expect("named field");
+    let mut spec = FieldSpec {
+        name: ident.to_string(),
+        ident,
+        ty: field.ty.clone(),
+        description: String::new(),
+        unit: String::new(),
+        logical_type: None,
+        aggregation_temporality: None,
+        is_monotonic: false,
+        optional: false,
+        is_option: false,
+        kind: FieldKind::Skip,
+    };
+    let mut skip = false;
+    let mut start_time = false;
+    let mut end_time = false;
+
+    for meta in otel_metas(&field.attrs)? {
+        match &meta {
+            Meta::NameValue(name_value) => {
+                let value = lit_str(&name_value.lit)?;
+                let path = &name_value.path;
+                if path.is_ident("name") {
+                    spec.name = value;
+                } else if path.is_ident("description") {
+                    spec.description = value;
+                } else if path.is_ident("unit") {
+                    spec.unit = value;
+                } else if path.is_ident("logical_type") {
+                    spec.logical_type = Some((value, name_value.lit.span()));
+                } else if path.is_ident("aggregation_temporality") {
+                    spec.aggregation_temporality = Some((value, name_value.lit.span()));
+                } else {
+                    return Err(Error::new(meta.span(), "unsupported otel field attribute"));
+                }
+            }
+            Meta::Path(path) if path.is_ident("monotonic") => spec.is_monotonic = true,
+            Meta::Path(path) if path.is_ident("optional") => spec.optional = true,
+            Meta::Path(path) if path.is_ident("skip") => skip = true,
+            Meta::Path(path) if path.is_ident("start_time") => start_time = true,
+            Meta::Path(path) if path.is_ident("end_time") => end_time = true,
+            _ => return Err(Error::new(meta.span(), "unsupported otel field attribute")),
+        }
+    }
+
+    spec.kind = if skip {
+        FieldKind::Skip
+    } else if start_time {
+        FieldKind::StartTime
+    } else if end_time {
+        FieldKind::EndTime
+    } else {
+        let (ty, is_option) = match generic_argument(&field.ty, "Option") {
+            Some(inner) => (inner, true),
+            None => (&field.ty, false),
+        };
+        spec.optional |= is_option;
+        spec.is_option = is_option;
+
+        if let Some(column_type) = column_type(ty) {
+            FieldKind::Column(column_type)
+        } else if let Some(inner) = generic_argument(ty, "Vec").filter(|_| !is_option) {
+            FieldKind::AuxiliaryEntity(Box::new(inner.clone()))
+        } else {
+            return Err(Error::new(field.ty.span(), "unsupported field type (use #[otel(skip)] to ignore this field)"));
+        }
+    };
+
+    Ok(spec)
+}
+
+fn column_type(ty: &Type) -> Option<ColumnType> {
+    if let Some(inner) = generic_argument(ty, "Vec") {
+        return match last_segment(inner) {
+            Some(ident) if ident == "u8" => Some(ColumnType::Bytes),
+            _ => None,
+        };
+    }
+
+    if let Some(inner) = generic_argument(ty, "Summary") {
+        return match last_segment(inner) {
+            Some(ident) if ident == "i64" => Some(ColumnType::Int64Summary),
+            Some(ident) if ident == "f64" => Some(ColumnType::DoubleSummary),
+            _ => None,
+        };
+    }
+
+    let ident = last_segment(ty)?;
+    match ident.to_string().as_str() {
+        "i8" | "i16" | "i32" | "i64" | "isize" | "u8" | "u16" | "u32" | "u64" | "usize" => Some(ColumnType::Int64),
+        "f32" | "f64" => Some(ColumnType::Double),
+        "String" => Some(ColumnType::String),
+        "bool" => Some(ColumnType::Bool),
+        "Histogram" => Some(ColumnType::Histogram),
+        "ExponentialHistogram" => Some(ColumnType::ExponentialHistogram),
+        _ => None,
+    }
+}
+
+fn last_segment(ty: &Type) -> Option<&Ident> {
+    match ty {
+        Type::Path(type_path) if type_path.qself.is_none() => type_path.path.segments.last().map(|segment| &segment.ident),
+        _ => None,
+    }
+}
+
+/// Returns the generic argument of `ty` if `ty` is `wrapper<T>` (e.g. Option<T>, Vec<T>).
+fn generic_argument<'a>(ty: &'a Type, wrapper: &str) -> Option<&'a Type> {
+    let type_path = match ty {
+        Type::Path(type_path) if type_path.qself.is_none() => type_path,
+        _ => return None,
+    };
+    let segment = type_path.path.segments.last()?;
+    if segment.ident != wrapper {
+        return None;
+    }
+    match &segment.arguments {
+        PathArguments::AngleBracketed(arguments) if arguments.args.len() == 1 => match arguments.args.first() {
+            Some(GenericArgument::Type(inner)) => Some(inner),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn otel_metas(attrs: &[syn::Attribute]) -> Result<Vec<Meta>> {
+    let mut metas = vec![];
+
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("otel")) {
+        match attr.parse_meta()? {
+            Meta::List(list) => {
+                for nested in list.nested {
+                    match nested {
+                        NestedMeta::Meta(meta) => metas.push(meta),
+                        NestedMeta::Lit(lit) => return Err(Error::new(lit.span(), "unexpected literal in otel attribute")),
+                    }
+                }
+            }
+            meta => return Err(Error::new(meta.span(), "expected #[otel(...)]")),
+        }
+    }
+
+    Ok(metas)
+}
+
+fn lit_str(lit: &Lit) -> Result<String> {
+    match lit {
+        Lit::Str(lit_str) => Ok(lit_str.value()),
+        _ => Err(Error::new(lit.span(), "expected a string literal")),
+    }
+}
@@ -0,0 +1,201 @@
+//! Columnar representation of the OTLP attributes (`KeyValue`) and values (`AnyValue`).
+//!
+//! A value is split into one optional column per primitive type (string, int, double, bool, bytes), only one of them
+//! being defined for a given row. Arrays and key/value lists are stored as JSON in an additional string column.
+
+use crate::event::{Error, OpenTelemetryAuxiliaryEntity};
+use crate::event_decoder::FromAuxiliaryEntity;
+use crate::opentelemetry::proto::common::v1::{AnyValue, KeyValue};
+use crate::opentelemetry::proto::common::v1::any_value::Value;
+
+/// An attribute stored in an auxiliary entity (see `AuxiliaryEntity.LogicalType.ATTRIBUTE`).
+#[derive(Debug, Clone, Default, PartialEq, OpenTelemetryAuxiliaryEntity, FromAuxiliaryEntity)]
+pub struct Attribute {
+    pub key: String,
+    #[otel(name = "value")]
+    pub string_value: Option<String>,
+    #[otel(name = "value")]
+    pub int_value: Option<i64>,
+    #[otel(name = "value")]
+    pub double_value: Option<f64>,
+    #[otel(name = "value")]
+    pub bool_value: Option<bool>,
+    #[otel(name = "value")]
+    pub bytes_value: Option<Vec<u8>>,
+    #[otel(name = "value_json")]
+    pub json_value: Option<String>,
+}
+
+/// An attribute of a nested entity, e.g. of a span event. The auxiliary entities can't be nested, so these attributes
+/// are attached to the top-level event and `parent_index` is the rank of the nested entity within this event.
+#[derive(Debug, Clone, Default, PartialEq, OpenTelemetryAuxiliaryEntity, FromAuxiliaryEntity)]
+pub struct IndexedAttribute {
+    pub parent_index: u32,
+    pub key: String,
+    #[otel(name = "value")]
+    pub string_value: Option<String>,
+    #[otel(name = "value")]
+    pub int_value: Option<i64>,
+    #[otel(name = "value")]
+    pub double_value: Option<f64>,
+    #[otel(name = "value")]
+    pub bool_value: Option<bool>,
+    #[otel(name = "value")]
+    pub bytes_value: Option<Vec<u8>>,
+    #[otel(name = "value_json")]
+    pub json_value: Option<String>,
+}
+
+/// An `AnyValue` split into typed values, at most one of them is defined.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TypedValue {
+    pub string_value: Option<String>,
+    pub int_value: Option<i64>,
+    pub double_value: Option<f64>,
+    pub bool_value: Option<bool>,
+    pub bytes_value: Option<Vec<u8>>,
+    pub json_value: Option<String>,
+}
+
+impl TypedValue {
+    /// Splits a value, arrays and key/value lists are serialized in JSON.
+    pub fn new(value: Option<AnyValue>) -> Result<Self, Error> {
+        let mut typed_value = TypedValue::default();
+        match value.as_ref().and_then(|value| value.value.as_ref()) {
+            None => {}
+            Some(Value::StringValue(value)) => typed_value.string_value = Some(value.clone()),
+            Some(Value::IntValue(value)) => typed_value.int_value = Some(*value),
+            Some(Value::DoubleValue(value)) => typed_value.double_value = Some(*value),
+            Some(Value::BoolValue(value)) => typed_value.bool_value = Some(*value),
+            Some(Value::BytesValue(value)) => typed_value.bytes_value = Some(value.clone()),
+            Some(Value::ArrayValue(_)) | Some(Value::KvlistValue(_)) => typed_value.json_value = Some(serde_json::to_string(&value)?),
+        }
+        Ok(typed_value)
+    }
+
+    pub fn into_any_value(self) -> Result<Option<AnyValue>, Error> {
+        let value = if let Some(value) = self.string_value {
+            Value::StringValue(value)
+        } else if let Some(value) = self.int_value {
+            Value::IntValue(value)
+        } else if let Some(value) = self.double_value {
+            Value::DoubleValue(value)
+        } else if let Some(value) = self.bool_value {
+            Value::BoolValue(value)
+        } else if let Some(value) = self.bytes_value {
+            Value::BytesValue(value)
+        } else if let Some(value) = self.json_value {
+            return Ok(Some(serde_json::from_str(&value)?));
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(AnyValue { value: Some(value) }))
+    }
+}
+
+impl Attribute {
+    pub fn new(key_value: KeyValue) -> Result<Self, Error> {
+        let typed_value = TypedValue::new(key_value.value)?;
+        Ok(Attribute {
+            key: key_value.key,
+            string_value: typed_value.string_value,
+            int_value: typed_value.int_value,
+            double_value: typed_value.double_value,
+            bool_value: typed_value.bool_value,
+            bytes_value: typed_value.bytes_value,
+            json_value: typed_value.json_value,
+        })
+    }
+
+    pub fn into_key_value(self) -> Result<KeyValue, Error> {
+        let typed_value = TypedValue {
+            string_value: self.string_value,
+            int_value: self.int_value,
+            double_value: self.double_value,
+            bool_value: self.bool_value,
+            bytes_value: self.bytes_value,
+            json_value: self.json_value,
+        };
+        Ok(KeyValue { key: self.key, value: typed_value.into_any_value()? })
+    }
+}
+
+impl IndexedAttribute {
+    pub fn new(parent_index: u32, key_value: KeyValue) -> Result<Self, Error> {
+        let attribute = Attribute::new(key_value)?;
+        Ok(IndexedAttribute {
+            parent_index,
+            key: attribute.key,
+            string_value: attribute.string_value,
+            int_value: attribute.int_value,
+            double_value: attribute.double_value,
+            bool_value: attribute.bool_value,
+            bytes_value: attribute.bytes_value,
+            json_value: attribute.json_value,
+        })
+    }
+
+    pub fn into_key_value(self) -> Result<KeyValue, Error> {
+        Attribute {
+            key: self.key,
+            string_value: self.string_value,
+            int_value: self.int_value,
+            double_value: self.double_value,
+            bool_value: self.bool_value,
+            bytes_value: self.bytes_value,
+            json_value: self.json_value,
+        }.into_key_value()
+    }
+}
+
+/// Converts a list of attributes, stops at the first value that can't be serialized.
+pub fn to_attributes(key_values: Vec<KeyValue>) -> Result<Vec<Attribute>, Error> {
+    key_values.into_iter().map(Attribute::new).collect()
+}
+
+pub fn to_key_values(attributes: Vec<Attribute>) -> Result<Vec<KeyValue>, Error> {
+    attributes.into_iter().map(Attribute::into_key_value).collect()
+}
+
+/// Maps an empty OTLP string onto a null.
+pub(crate) fn non_empty(value: String) -> Option<String> {
+    Some(value).filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::attribute::{Attribute, TypedValue};
+    use crate::opentelemetry::proto::common::v1::{AnyValue, ArrayValue, KeyValue};
+    use crate::opentelemetry::proto::common::v1::any_value::Value;
+
+    fn any_value(value: Value) -> Option<AnyValue> {
+        Some(AnyValue { value: Some(value) })
+    }
+
+    #[test]
+    fn test_typed_value() {
+        let values = vec![
+            None,
+            any_value(Value::StringValue("value".into())),
+            any_value(Value::IntValue(-1)),
+            any_value(Value::DoubleValue(1.5)),
+            any_value(Value::BoolValue(true)),
+            any_value(Value::BytesValue(vec![1, 2])),
+            any_value(Value::ArrayValue(ArrayValue { values: vec![AnyValue { value: Some(Value::IntValue(1)) }] })),
+        ];
+        for value in values {
+            assert_eq!(TypedValue::new(value.clone()).unwrap().into_any_value().unwrap(), value);
+        }
+
+        let typed_value = TypedValue::new(any_value(Value::IntValue(42))).unwrap();
+        assert_eq!(typed_value, TypedValue { int_value: Some(42), ..Default::default() });
+    }
+
+    #[test]
+    fn test_attribute() {
+        let key_value = KeyValue { key: "http.method".into(), value: any_value(Value::StringValue("GET".into())) };
+        let attribute = Attribute::new(key_value.clone()).unwrap();
+        assert_eq!(attribute.string_value.as_deref(), Some("GET"));
+        assert_eq!(attribute.into_key_value().unwrap(), key_value);
+    }
+}
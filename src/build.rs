@@ -1,11 +1,20 @@
 use std::error::Error;
 
 fn main() -> Result<(), Box<dyn Error>> {
-    prost_build::compile_protos(&[
-        "proto/opentelemetry/proto/metrics/v1/metrics.proto",
-        "proto/opentelemetry/proto/trace/v1/trace.proto",
-        "proto/opentelemetry/proto/events/v1/events.proto",
-        "proto/opentelemetry/proto/arrow_events/v1/events.proto"
-    ], &["proto/"])?;
+    let mut config = prost_build::Config::new();
+    config.type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]");
+    tonic_build::configure()
+        .compile_with_config(config, &[
+            "proto/opentelemetry/proto/metrics/v1/metrics.proto",
+            "proto/opentelemetry/proto/trace/v1/trace.proto",
+            "proto/opentelemetry/proto/logs/v1/logs.proto",
+            "proto/opentelemetry/proto/events/v1/events.proto",
+            "proto/opentelemetry/proto/arrow_events/v1/events.proto",
+            "proto/opentelemetry/proto/collector/metrics/v1/metrics_service.proto",
+            "proto/opentelemetry/proto/collector/trace/v1/trace_service.proto",
+            "proto/opentelemetry/proto/collector/logs/v1/logs_service.proto",
+            "proto/opentelemetry/proto/collector/events/v1/events_service.proto",
+            "proto/opentelemetry/proto/collector/arrow_events/v1/arrow_events_service.proto"
+        ], &["proto/"])?;
     Ok(())
-}
\ No newline at end of file
+}
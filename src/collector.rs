@@ -0,0 +1,358 @@
+//! Thread-safe recording of events into an `EventCollector`.
+//!
+//! An `EventHandle<T>` is a cheap cloneable handle shared between threads. The events are recorded into shards of
+//! `EventBatchHandler<T>` (a thread always records into the same shard). A full batch is moved into the events of the
+//! collector and the partial batches are merged when the collected events are taken (see `EventCollector::take`).
+//! Once the last clone of a handle is dropped, its buffered events are moved into the events of the collector and its
+//! shards are deregistered.
+//!
+//! The number of pending events (recorded or added, but not yet taken) can be bounded with
+//! `EventCollector::with_max_pending_events`. Beyond this bound, `EventHandle::record` fails with
+//! `CollectorError::Full` and the event is reported in the `dropped_events_count` of its instrumentation library.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+
+use crate::event::{Error, EventBatchHandler, EventCollector, OpenTelemetryEvent};
+use crate::opentelemetry::proto::common::v1::InstrumentationLibrary;
+use crate::opentelemetry::proto::events::v1::{InstrumentationLibraryEvents, ResourceEvents};
+use crate::opentelemetry::proto::resource::v1::Resource;
+use crate::sink::EventSink;
+
+#[derive(thiserror::Error, Debug)]
+pub enum CollectorError {
+    #[error("Collector Full (max pending events: {0})")]
+    Full(usize),
+}
+
+/// State shared by the clones of a collector and by its handles.
+#[derive(Debug, Default)]
+pub(crate) struct CollectorState {
+    pending_events: AtomicUsize,
+    /// Collected events, one entry per resource.
+    resource_events: Mutex<Vec<ResourceEvents>>,
+    /// Shards of the live handles.
+    shards: Mutex<Vec<Weak<dyn Shards>>>,
+}
+
+/// Records events of type `T` from any thread, see the module documentation.
+#[derive(Debug)]
+pub struct EventHandle<T: OpenTelemetryEvent + Send> {
+    shards: Arc<HandlerShards<T>>,
+    max_pending_events: usize,
+}
+
+struct HandlerShards<T: OpenTelemetryEvent + Send> {
+    state: Arc<CollectorState>,
+    handlers: Vec<Mutex<EventBatchHandler<T>>>,
+    resource: Resource,
+    instrumentation_library: InstrumentationLibrary,
+    dropped_events_count: AtomicU32,
+}
+
+/// The shards of a handle, independently of the type of its events.
+trait Shards: Debug + Send + Sync {
+    /// Takes the buffered events of every shard and the events dropped since the last call.
+    fn take(&self) -> Vec<ResourceEvents>;
+}
+
+impl EventCollector {
+    /// Sets the number of shards of the handles created by this collector (number of CPUs by default).
+    pub fn with_shards(mut self, shard_count: usize) -> Self {
+        self.shard_count = shard_count.max(1);
+        self
+    }
+
+    /// Bounds the number of events recorded via the handles of this collector and not yet taken (unbounded by
+    /// default).
+    pub fn with_max_pending_events(mut self, max_pending_events: usize) -> Self {
+        self.max_pending_events = max_pending_events;
+        self
+    }
+
+    /// Creates a handle recording events into this collector, with the resource and the instrumentation library of
+    /// the collector.
+    pub fn handle<T: OpenTelemetryEvent + Send + 'static>(&self) -> EventHandle<T> {
+        let shards = Arc::new(HandlerShards {
+            state: self.state.clone(),
+            handlers: (0..self.shard_count).map(|_| Mutex::new(self.event_handler())).collect(),
+            resource: self.resource.clone(),
+            instrumentation_library: self.instrumentation_library.clone(),
+            dropped_events_count: AtomicU32::new(0),
+        });
+        let weak_shards: Weak<dyn Shards> = Arc::downgrade(&shards) as Weak<HandlerShards<T>>;
+        let mut registered_shards = lock(&self.state.shards);
+        registered_shards.retain(|shards| shards.strong_count() > 0);
+        registered_shards.push(weak_shards);
+        drop(registered_shards);
+
+        EventHandle { shards, max_pending_events: self.max_pending_events }
+    }
+
+    /// Adds the buffered events of a handler to the collected events (see `add`), the handler starts a new batch.
+    pub fn collect<T: OpenTelemetryEvent>(&self, handler: &mut EventBatchHandler<T>) {
+        if let Some(resource_events) = handler.take_resource_events() {
+            self.add(resource_events);
+        }
+    }
+
+    /// Adds resource events to the collected events. The events are grouped by resource, then by instrumentation
+    /// library and schema url. Each batch is kept as is, i.e. a batch only contains the events of a single schema url.
+    pub fn add(&self, resource_events: ResourceEvents) {
+        self.state.pending_events.fetch_add(event_count(&resource_events), Ordering::SeqCst);
+        merge(&mut lock(&self.state.resource_events), resource_events);
+    }
+
+    /// Returns the number of events recorded or added, and not yet taken.
+    pub fn pending_events(&self) -> usize {
+        self.state.pending_events.load(Ordering::SeqCst)
+    }
+
+    /// Merges the events buffered by the handles into the collected events, then removes and returns the collected
+    /// events, one `ResourceEvents` per resource.
+    pub fn take(&self) -> Vec<ResourceEvents> {
+        let shards: Vec<_> = lock(&self.state.shards).iter().filter_map(Weak::upgrade).collect();
+        for resource_events in shards.iter().flat_map(|shards| shards.take()) {
+            merge(&mut lock(&self.state.resource_events), resource_events);
+        }
+        // The last handle of a shard may have been dropped meanwhile, its events are moved by this drop.
+        drop(shards);
+
+        let resource_events = std::mem::take(&mut *lock(&self.state.resource_events));
+        let taken_events = resource_events.iter().map(event_count).sum();
+        self.state.pending_events.fetch_sub(taken_events, Ordering::SeqCst);
+        resource_events
+    }
+
+    /// Exports the collected events to the sink, one export per resource.
+    pub fn export(&self, sink: &mut dyn EventSink) -> Result<(), Error> {
+        self.take().into_iter().try_for_each(|resource_events| sink.export_events(resource_events))
+    }
+}
+
+impl<T: OpenTelemetryEvent + Send> EventHandle<T> {
+    /// Records an event into the shard of the current thread. Fails if the collector already holds the max number
+    /// of pending events, the event is then dropped. This is the only failure, the shards have no sink to flush to.
+    pub fn record(&self, event: T) -> Result<(), CollectorError> {
+        let state = &self.shards.state;
+        if state.pending_events.fetch_add(1, Ordering::SeqCst) >= self.max_pending_events {
+            state.pending_events.fetch_sub(1, Ordering::SeqCst);
+            self.shards.dropped_events_count.fetch_add(1, Ordering::SeqCst);
+            return Err(CollectorError::Full(self.max_pending_events));
+        }
+
+        let full_batch = {
+            let mut handler = lock(self.shard());
+            let full_batch = if handler.pending_events() >= handler.batch_policy.max_size as usize {
+                handler.take_resource_events()
+            } else {
+                None
+            };
+            handler.append_event(event, vec![]);
+            full_batch
+        };
+        if let Some(resource_events) = full_batch {
+            merge(&mut lock(&state.resource_events), resource_events);
+        }
+        Ok(())
+    }
+
+    /// Returns the number of events dropped since the collected events have been taken.
+    pub fn dropped_events_count(&self) -> u32 {
+        self.shards.dropped_events_count.load(Ordering::SeqCst)
+    }
+
+    fn shard(&self) -> &Mutex<EventBatchHandler<T>> {
+        let mut hasher = DefaultHasher::new();
+        std::thread::current().id().hash(&mut hasher);
+        &self.shards.handlers[hasher.finish() as usize % self.shards.handlers.len()]
+    }
+}
+
+impl<T: OpenTelemetryEvent + Send> Clone for EventHandle<T> {
+    fn clone(&self) -> Self {
+        EventHandle { shards: self.shards.clone(), max_pending_events: self.max_pending_events }
+    }
+}
+
+impl<T: OpenTelemetryEvent + Send> Drop for HandlerShards<T> {
+    fn drop(&mut self) {
+        for resource_events in self.take() {
+            merge(&mut lock(&self.state.resource_events), resource_events);
+        }
+    }
+}
+
+impl<T: OpenTelemetryEvent + Send> Debug for HandlerShards<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HandlerShards")
+            .field("shard_count", &self.handlers.len())
+            .field("resource", &self.resource)
+            .field("instrumentation_library", &self.instrumentation_library)
+            .field("dropped_events_count", &self.dropped_events_count)
+            .finish()
+    }
+}
+
+impl<T: OpenTelemetryEvent + Send> Shards for HandlerShards<T> {
+    fn take(&self) -> Vec<ResourceEvents> {
+        let mut resource_events: Vec<_> = self.handlers.iter()
+            .filter_map(|handler| lock(handler).take_resource_events())
+            .collect();
+
+        let dropped_events_count = self.dropped_events_count.swap(0, Ordering::SeqCst);
+        if dropped_events_count > 0 {
+            resource_events.push(ResourceEvents {
+                resource: Some(self.resource.clone()),
+                instrumentation_library_events: vec![InstrumentationLibraryEvents {
+                    instrumentation_library: Some(self.instrumentation_library.clone()),
+                    batches: vec![],
+                    dropped_events_count,
+                    schema_url: "".into(),
+                }],
+                schema_url: "".into(),
+            });
+        }
+        resource_events
+    }
+}
+
+/// Adds resource events to a list of resource events grouped by resource, then by instrumentation library and schema url.
+fn merge(collected_events: &mut Vec<ResourceEvents>, resource_events: ResourceEvents) {
+    let position = collected_events.iter()
+        .position(|other| other.resource == resource_events.resource && other.schema_url == resource_events.schema_url);
+    let collected_resource_events = match position {
+        Some(position) => &mut collected_events[position],
+        None => {
+            collected_events.push(ResourceEvents {
+                resource: resource_events.resource.clone(),
+                instrumentation_library_events: vec![],
+                schema_url: resource_events.schema_url.clone(),
+            });
+            collected_events.last_mut().expect("resource events")
+        }
+    };
+
+    for instrumentation_library_events in resource_events.instrumentation_library_events {
+        let collected_library_events = &mut collected_resource_events.instrumentation_library_events;
+        let same_library = |other: &InstrumentationLibraryEvents| other.instrumentation_library == instrumentation_library_events.instrumentation_library
+            && other.schema_url == instrumentation_library_events.schema_url;
+        match collected_library_events.iter_mut().find(|other| same_library(other)) {
+            Some(other) => {
+                other.batches.extend(instrumentation_library_events.batches);
+                other.dropped_events_count += instrumentation_library_events.dropped_events_count;
+            }
+            None => collected_library_events.push(instrumentation_library_events),
+        }
+    }
+}
+
+fn event_count(resource_events: &ResourceEvents) -> usize {
+    resource_events.instrumentation_library_events.iter()
+        .flat_map(|instrumentation_library_events| &instrumentation_library_events.batches)
+        .map(|batch| batch.size as usize)
+        .sum()
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().expect("event collector lock poisoned")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::collector::{CollectorError, EventHandle};
+    use crate::event::{BatchPolicy, EventCollector, OpenTelemetryEvent};
+
+    #[derive(Debug, OpenTelemetryEvent)]
+    #[otel(urn = "urn:test:request")]
+    struct Request {
+        #[otel(start_time)]
+        time: u64,
+        thread: i64,
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_concurrent_record() {
+        assert_send_sync::<EventCollector>();
+        assert_send_sync::<EventHandle<Request>>();
+
+        let event_collector = EventCollector::new(BatchPolicy::new(10, chrono::Duration::seconds(10))).with_shards(2);
+        let handle = event_collector.handle::<Request>();
+        let threads: Vec<_> = (0..4).map(|thread| {
+            let handle = handle.clone();
+            std::thread::spawn(move || {
+                for time in 0..25 {
+                    handle.record(Request { time, thread }).unwrap();
+                }
+            })
+        }).collect();
+        threads.into_iter().for_each(|thread| thread.join().unwrap());
+        assert_eq!(event_collector.pending_events(), 100);
+
+        let resource_events = event_collector.take();
+        assert_eq!(resource_events.len(), 1);
+        let batches = &resource_events[0].instrumentation_library_events[0].batches;
+        assert!(batches.iter().all(|batch| batch.size <= 10 && batch.schema_url == "urn:test:request"));
+        assert_eq!(batches.iter().map(|batch| batch.size).sum::<u32>(), 100);
+        resource_events[0].validate().unwrap();
+        assert_eq!(event_collector.pending_events(), 0);
+        assert!(event_collector.take().is_empty());
+    }
+
+    #[test]
+    fn test_backpressure() {
+        let event_collector = EventCollector::new(BatchPolicy::new(2, chrono::Duration::seconds(10)))
+            .with_shards(1)
+            .with_max_pending_events(3);
+        let handle = event_collector.handle::<Request>();
+        for time in 0..5 {
+            let result = handle.record(Request { time, thread: 0 });
+            assert_eq!(result.is_ok(), time < 3);
+            assert!(matches!(result, Ok(()) | Err(CollectorError::Full(3))));
+        }
+        assert_eq!(handle.dropped_events_count(), 2);
+
+        let resource_events = event_collector.take();
+        let instrumentation_library_events = &resource_events[0].instrumentation_library_events[0];
+        assert_eq!(instrumentation_library_events.batches.iter().map(|batch| batch.size).collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!(instrumentation_library_events.dropped_events_count, 2);
+        assert_eq!(handle.dropped_events_count(), 0);
+
+        handle.record(Request { time: 5, thread: 0 }).unwrap();
+        assert_eq!(event_collector.pending_events(), 1);
+    }
+
+    #[test]
+    fn test_handle_drop() {
+        let event_collector = EventCollector::new(BatchPolicy::new(2, chrono::Duration::seconds(10)))
+            .with_shards(1)
+            .with_max_pending_events(5);
+        for time in 0..2 {
+            let handle = event_collector.handle::<Request>();
+            let clone = handle.clone();
+            handle.record(Request { time, thread: 0 }).unwrap();
+            drop(handle);
+            clone.record(Request { time, thread: 1 }).unwrap();
+            assert_eq!(event_collector.state.shards.lock().unwrap().len(), 1);
+        }
+
+        // The events of the dropped handles are kept, the rejected events are not counted as pending.
+        assert_eq!(event_collector.pending_events(), 4);
+        let handle = event_collector.handle::<Request>();
+        assert_eq!(event_collector.state.shards.lock().unwrap().len(), 1);
+        handle.record(Request { time: 2, thread: 0 }).unwrap();
+        assert!(matches!(handle.record(Request { time: 3, thread: 0 }), Err(CollectorError::Full(5))));
+        assert_eq!(event_collector.pending_events(), 5);
+
+        let resource_events = event_collector.take();
+        let instrumentation_library_events = &resource_events[0].instrumentation_library_events[0];
+        assert_eq!(instrumentation_library_events.batches.iter().map(|batch| batch.size).sum::<u32>(), 5);
+        assert_eq!(instrumentation_library_events.dropped_events_count, 1);
+        assert_eq!(event_collector.pending_events(), 0);
+    }
+}
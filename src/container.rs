@@ -0,0 +1,633 @@
+//! Container file format storing batches for offline analysis.
+//!
+//! A file is made of `header | block* | footer | trailer`:
+//! * header: magic, length (u32 LE) and JSON `ContainerHeader` (format version and registry of the schema URLs).
+//! * block: kind (1 byte), compression (1 byte), length (u32 LE) and payload, either a protobuf batch (`ResourceEvents`,
+//!   Arrow `ResourceEvents` or `ResourceMetrics`) or an Arrow IPC stream, optionally compressed.
+//! * footer: JSON `ContainerFooter`, the index of the blocks with their schema URLs and min/max timestamps.
+//! * trailer: offset (u64 LE) and length (u32 LE) of the footer, followed by the magic.
+//!
+//! `ContainerReader` loads the header and the footer, then seeks to the requested blocks only, e.g. to the blocks
+//! overlapping a time range. The lengths read from the file are checked against its size and the decompressed blocks
+//! are bounded by `with_max_block_size`.
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use arrow::error::ArrowError;
+use arrow::ipc::reader::StreamReader;
+use arrow::ipc::writer::StreamWriter;
+use arrow::record_batch::RecordBatch;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+use crate::delta_encoding::{column_encoding, decode, Encoding};
+use crate::event::Error;
+use crate::http::{Compression, HttpError};
+use crate::opentelemetry::proto::events::v1::ResourceEvents;
+use crate::opentelemetry::proto::metrics::v1::ResourceMetrics;
+use crate::opentelemetry::proto::metrics::v1::metric::Data;
+use crate::sink::{ResourceBatch, ARROW_EVENTS_TAG, DEFAULT_MAX_FRAME_LENGTH, EVENTS_TAG, METRICS_TAG};
+
+const MAGIC: &[u8; 8] = b"OTELCNT1";
+const VERSION: u32 = 1;
+const BLOCK_HEADER_LEN: usize = 6;
+const TRAILER_LEN: usize = 20;
+const ARROW_IPC_TAG: u8 = 4;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContainerHeader {
+    pub version: u32,
+    /// Schema URLs of the batches, referenced by index from the block index.
+    pub schema_urls: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ContainerFooter {
+    pub blocks: Vec<BlockIndex>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockKind {
+    Events,
+    ArrowEvents,
+    Metrics,
+    /// Arrow IPC stream of record batches.
+    ArrowIpc,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockIndex {
+    /// Offset of the block in the file.
+    pub offset: u64,
+    /// Length of the (compressed) payload.
+    pub len: u32,
+    pub kind: BlockKind,
+    pub compression: Compression,
+    /// Indexes of the schema URLs of the block in the header registry.
+    pub schema_ids: Vec<u32>,
+    /// Timestamps range of the block, unknown (None) if the block has no timestamp (e.g. Arrow events).
+    pub min_time_unix_nano: Option<u64>,
+    pub max_time_unix_nano: Option<u64>,
+}
+
+/// Content of a block.
+#[derive(Debug)]
+pub enum Block {
+    Batch(ResourceBatch),
+    RecordBatches { schema_url: String, record_batches: Vec<RecordBatch> },
+}
+
+/// Writes a container file, `finish` must be called to write the block index.
+///
+/// A failed write may leave a partial block in the file, the writer is then poisoned and rejects the next writes.
+#[derive(Debug)]
+pub struct ContainerWriter<W: Write> {
+    writer: W,
+    offset: u64,
+    header: ContainerHeader,
+    compression: Compression,
+    blocks: Vec<BlockIndex>,
+    buffer: Vec<u8>,
+    poisoned: bool,
+}
+
+#[derive(Debug)]
+pub struct ContainerReader<R: Read + Seek> {
+    reader: R,
+    /// Size of the file.
+    len: u64,
+    header: ContainerHeader,
+    footer: ContainerFooter,
+    max_block_size: usize,
+}
+
+impl BlockKind {
+    fn tag(self) -> u8 {
+        match self {
+            BlockKind::Events => EVENTS_TAG,
+            BlockKind::ArrowEvents => ARROW_EVENTS_TAG,
+            BlockKind::Metrics => METRICS_TAG,
+            BlockKind::ArrowIpc => ARROW_IPC_TAG,
+        }
+    }
+}
+
+impl BlockIndex {
+    /// Returns true if the block may contain timestamps in `[start, end]`, a block without known range always matches.
+    pub fn overlaps(&self, start: u64, end: u64) -> bool {
+        match (self.min_time_unix_nano, self.max_time_unix_nano) {
+            (Some(min), Some(max)) => min <= end && max >= start,
+            _ => true,
+        }
+    }
+}
+
+impl ContainerWriter<BufWriter<File>> {
+    /// Creates (or truncates) the file.
+    pub fn create<P: AsRef<Path>>(path: P, schema_urls: impl IntoIterator<Item=impl Into<String>>) -> Result<Self, Error> {
+        Self::new(BufWriter::new(File::create(path)?), schema_urls)
+    }
+}
+
+impl<W: Write> ContainerWriter<W> {
+    /// Writes the header. The batches can only reference the schema URLs of this registry (empty URLs excepted).
+    pub fn new(mut writer: W, schema_urls: impl IntoIterator<Item=impl Into<String>>) -> Result<Self, Error> {
+        let header = ContainerHeader {
+            version: VERSION,
+            schema_urls: schema_urls.into_iter().map(Into::into).collect(),
+        };
+        let json = serde_json::to_vec(&header)?;
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(json.len() as u32).to_le_bytes())?;
+        writer.write_all(&json)?;
+
+        Ok(ContainerWriter {
+            writer,
+            offset: (MAGIC.len() + 4 + json.len()) as u64,
+            header,
+            compression: Compression::None,
+            blocks: vec![],
+            buffer: vec![],
+            poisoned: false,
+        })
+    }
+
+    /// Sets the compression of the blocks (none by default).
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Changes the compression of the next blocks.
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    /// Writes a batch as a protobuf block.
+    pub fn write_batch(&mut self, batch: &ResourceBatch) -> Result<(), Error> {
+        let kind = match batch {
+            ResourceBatch::Events(_) => BlockKind::Events,
+            ResourceBatch::ArrowEvents(_) => BlockKind::ArrowEvents,
+            ResourceBatch::Metrics(_) => BlockKind::Metrics,
+        };
+        let schema_urls: Vec<&str> = match batch {
+            ResourceBatch::Events(resource_events) => resource_events.instrumentation_library_events.iter()
+                .flat_map(|instrumentation_library_events| &instrumentation_library_events.batches)
+                .map(|batch_event| batch_event.schema_url.as_str())
+                .collect(),
+            ResourceBatch::ArrowEvents(resource_events) => resource_events.instrumentation_library_events.iter()
+                .flat_map(|instrumentation_library_events| &instrumentation_library_events.batches)
+                .map(|batch_event| batch_event.schema_url.as_str())
+                .collect(),
+            ResourceBatch::Metrics(resource_metrics) => vec![resource_metrics.schema_url.as_str()],
+        };
+        let schema_ids = self.schema_ids(&schema_urls)?;
+        let time_range = time_range(batch)?;
+
+        let mut buffer = std::mem::take(&mut self.buffer);
+        buffer.clear();
+        batch.encode(&mut buffer)?;
+        self.write_block(kind, buffer, schema_ids, time_range)
+    }
+
+    /// Writes a record batch as an Arrow IPC block, with the time range of its events if known.
+    pub fn write_record_batch(&mut self, schema_url: &str, record_batch: &RecordBatch, time_range: Option<(u64, u64)>) -> Result<(), Error> {
+        let schema_ids = self.schema_ids(&[schema_url])?;
+
+        let mut buffer = std::mem::take(&mut self.buffer);
+        buffer.clear();
+        {
+            let mut writer = StreamWriter::try_new(&mut buffer, &record_batch.schema())?;
+            writer.write(record_batch)?;
+            writer.finish()?;
+        }
+        self.write_block(BlockKind::ArrowIpc, buffer, schema_ids, time_range)
+    }
+
+    /// Writes the footer and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.check_poisoned()?;
+        let json = serde_json::to_vec(&ContainerFooter { blocks: std::mem::take(&mut self.blocks) })?;
+        self.writer.write_all(&json)?;
+        self.writer.write_all(&self.offset.to_le_bytes())?;
+        self.writer.write_all(&(json.len() as u32).to_le_bytes())?;
+        self.writer.write_all(MAGIC)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    pub fn blocks(&self) -> &[BlockIndex] {
+        &self.blocks
+    }
+
+    fn write_block(&mut self, kind: BlockKind, payload: Vec<u8>, schema_ids: Vec<u32>, time_range: Option<(u64, u64)>) -> Result<(), Error> {
+        self.check_poisoned()?;
+        let payload = self.compression.compress(payload)?;
+        let len = u32::try_from(payload.len()).map_err(|_| Error::InvalidFormat(format!("block of {} bytes too large", payload.len())))?;
+
+        let mut block_header = [0u8; BLOCK_HEADER_LEN];
+        block_header[0] = kind.tag();
+        block_header[1] = compression_tag(self.compression);
+        block_header[2..].copy_from_slice(&len.to_le_bytes());
+        if let Err(err) = self.writer.write_all(&block_header).and_then(|_| self.writer.write_all(&payload)) {
+            self.poisoned = true;
+            return Err(err.into());
+        }
+
+        self.blocks.push(BlockIndex {
+            offset: self.offset,
+            len,
+            kind,
+            compression: self.compression,
+            schema_ids,
+            min_time_unix_nano: time_range.map(|(min, _)| min),
+            max_time_unix_nano: time_range.map(|(_, max)| max),
+        });
+        self.offset += (BLOCK_HEADER_LEN + payload.len()) as u64;
+        self.buffer = payload;
+        Ok(())
+    }
+
+    fn check_poisoned(&self) -> Result<(), Error> {
+        if self.poisoned {
+            return Err(std::io::Error::other("container writer poisoned by a failed write").into());
+        }
+        Ok(())
+    }
+
+    /// Returns the sorted and deduplicated indexes of the schema URLs, fails if one of them is not registered.
+    fn schema_ids(&self, schema_urls: &[&str]) -> Result<Vec<u32>, Error> {
+        let mut schema_ids = vec![];
+        for &schema_url in schema_urls.iter().filter(|schema_url| !schema_url.is_empty()) {
+            let schema_id = self.header.schema_urls.iter()
+                .position(|registered_url| registered_url == schema_url)
+                .ok_or_else(|| Error::UnknownSchema(schema_url.to_string()))?;
+            schema_ids.push(schema_id as u32);
+        }
+        schema_ids.sort_unstable();
+        schema_ids.dedup();
+        Ok(schema_ids)
+    }
+}
+
+impl ContainerReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> ContainerReader<R> {
+    /// Reads the header and the footer.
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        check_magic(&magic)?;
+        let header_len = read_u32(&mut reader)?;
+        let header: ContainerHeader = serde_json::from_slice(&read_vec(&mut reader, header_len as usize, len)?)?;
+        if header.version != VERSION {
+            return Err(Error::InvalidFormat(format!("unsupported container version {}", header.version)));
+        }
+
+        reader.seek(SeekFrom::End(-(TRAILER_LEN as i64)))?;
+        let mut trailer = [0u8; TRAILER_LEN];
+        reader.read_exact(&mut trailer)?;
+        check_magic(&trailer[12..])?;
+        let mut footer_offset = [0u8; 8];
+        footer_offset.copy_from_slice(&trailer[..8]);
+        let footer_len = u32::from_le_bytes([trailer[8], trailer[9], trailer[10], trailer[11]]);
+        reader.seek(SeekFrom::Start(u64::from_le_bytes(footer_offset)))?;
+        let footer: ContainerFooter = serde_json::from_slice(&read_vec(&mut reader, footer_len as usize, len)?)?;
+
+        Ok(ContainerReader { reader, len, header, footer, max_block_size: DEFAULT_MAX_FRAME_LENGTH })
+    }
+
+    /// Sets the max size of a decompressed block (`DEFAULT_MAX_FRAME_LENGTH` by default), a larger block is rejected.
+    pub fn with_max_block_size(mut self, max_block_size: usize) -> Self {
+        self.max_block_size = max_block_size;
+        self
+    }
+
+    pub fn header(&self) -> &ContainerHeader {
+        &self.header
+    }
+
+    pub fn schema_urls(&self) -> &[String] {
+        &self.header.schema_urls
+    }
+
+    pub fn blocks(&self) -> &[BlockIndex] {
+        &self.footer.blocks
+    }
+
+    /// Returns the indexes of the blocks overlapping `[start, end]` (see `BlockIndex::overlaps`).
+    pub fn blocks_in_range(&self, start: u64, end: u64) -> Vec<usize> {
+        self.footer.blocks.iter().enumerate()
+            .filter(|(_, block)| block.overlaps(start, end))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Reads, decompresses and decodes the nth block.
+    pub fn read_block(&mut self, i: usize) -> Result<Block, Error> {
+        let index = self.footer.blocks.get(i)
+            .ok_or_else(|| Error::InvalidFormat(format!("unknown block {}", i)))?
+            .clone();
+
+        self.reader.seek(SeekFrom::Start(index.offset))?;
+        let mut block_header = [0u8; BLOCK_HEADER_LEN];
+        self.reader.read_exact(&mut block_header)?;
+        let len = u32::from_le_bytes([block_header[2], block_header[3], block_header[4], block_header[5]]);
+        if block_header[0] != index.kind.tag() || block_header[1] != compression_tag(index.compression) || len != index.len {
+            return Err(Error::InvalidFormat(format!("block {} doesn't match the index", i)));
+        }
+        let payload = read_vec(&mut self.reader, len as usize, self.len)?;
+        let payload = index.compression.decompress(payload, self.max_block_size).map_err(|err| match err {
+            HttpError::PayloadTooLarge(max_size) => Error::InvalidFormat(format!("block {} larger than {} bytes", i, max_size)),
+            err => err.into(),
+        })?;
+
+        match index.kind {
+            BlockKind::ArrowIpc => {
+                let schema_url = index.schema_ids.first()
+                    .and_then(|&schema_id| self.header.schema_urls.get(schema_id as usize))
+                    .cloned()
+                    .unwrap_or_default();
+                let reader = StreamReader::try_new(payload.as_slice())?;
+                let record_batches = reader.collect::<Result<Vec<RecordBatch>, ArrowError>>()?;
+                Ok(Block::RecordBatches { schema_url, record_batches })
+            }
+            kind => Ok(Block::Batch(ResourceBatch::decode(kind.tag(), Bytes::from(payload))?)),
+        }
+    }
+
+    /// Reads the blocks overlapping `[start, end]`, the other blocks are skipped.
+    pub fn read_range(&mut self, start: u64, end: u64) -> Result<Vec<Block>, Error> {
+        self.blocks_in_range(start, end).into_iter()
+            .map(|i| self.read_block(i))
+            .collect()
+    }
+}
+
+/// Returns the min and max timestamps of a batch, None for the Arrow events (opaque payload) or an empty batch.
+fn time_range(batch: &ResourceBatch) -> Result<Option<(u64, u64)>, Error> {
+    let mut range = None;
+    match batch {
+        ResourceBatch::Events(resource_events) => events_time_range(resource_events, &mut range)?,
+        ResourceBatch::ArrowEvents(_) => {}
+        ResourceBatch::Metrics(resource_metrics) => metrics_time_range(resource_metrics, &mut range)?,
+    }
+    Ok(range)
+}
+
+fn events_time_range(resource_events: &ResourceEvents, range: &mut Option<(u64, u64)>) -> Result<(), Error> {
+    let batch_events = resource_events.instrumentation_library_events.iter()
+        .flat_map(|instrumentation_library_events| &instrumentation_library_events.batches);
+    for batch_event in batch_events {
+        if batch_event.time_encoding == Encoding::None as i32 {
+            extend_range(range, batch_event.start_time_unix_nano_column.iter().copied());
+            extend_range(range, batch_event.end_time_unix_nano_column.iter().copied());
+        } else {
+            let encoding = column_encoding(batch_event.time_encoding)?;
+            extend_range(range, decode(encoding, &batch_event.encoded_start_time_unix_nano_column)?.into_iter().map(|time| time as u64));
+            extend_range(range, decode(encoding, &batch_event.encoded_end_time_unix_nano_column)?.into_iter().map(|time| time as u64));
+        }
+    }
+    Ok(())
+}
+
+/// Range of the `time_unix_nano` of the data points.
+fn metrics_time_range(resource_metrics: &ResourceMetrics, range: &mut Option<(u64, u64)>) -> Result<(), Error> {
+    for instrumentation_library_metrics in &resource_metrics.instrumentation_library_metrics {
+        for multivariate_metric in &instrumentation_library_metrics.multivariate_metrics {
+            if multivariate_metric.time_encoding == Encoding::None as i32 {
+                extend_range(range, multivariate_metric.time_unix_nano_column.iter().copied());
+            } else {
+                let encoding = column_encoding(multivariate_metric.time_encoding)?;
+                extend_range(range, decode(encoding, &multivariate_metric.encoded_time_unix_nano_column)?.into_iter().map(|time| time as u64));
+            }
+        }
+
+        for metric in &instrumentation_library_metrics.metrics {
+            match &metric.data {
+                Some(Data::IntGauge(gauge)) => extend_range(range, gauge.data_points.iter().map(|data_point| data_point.time_unix_nano)),
+                Some(Data::Gauge(gauge)) => extend_range(range, gauge.data_points.iter().map(|data_point| data_point.time_unix_nano)),
+                Some(Data::IntSum(sum)) => extend_range(range, sum.data_points.iter().map(|data_point| data_point.time_unix_nano)),
+                Some(Data::Sum(sum)) => extend_range(range, sum.data_points.iter().map(|data_point| data_point.time_unix_nano)),
+                Some(Data::IntHistogram(histogram)) => extend_range(range, histogram.data_points.iter().map(|data_point| data_point.time_unix_nano)),
+                Some(Data::Histogram(histogram)) => extend_range(range, histogram.data_points.iter().map(|data_point| data_point.time_unix_nano)),
+                Some(Data::Summary(summary)) => extend_range(range, summary.data_points.iter().map(|data_point| data_point.time_unix_nano)),
+                None => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+fn extend_range(range: &mut Option<(u64, u64)>, times: impl IntoIterator<Item=u64>) {
+    for time in times {
+        *range = Some(match *range {
+            Some((min, max)) => (min.min(time), max.max(time)),
+            None => (time, time),
+        });
+    }
+}
+
+fn compression_tag(compression: Compression) -> u8 {
+    match compression {
+        Compression::None => 0,
+        Compression::Gzip => 1,
+        Compression::Zstd => 2,
+        Compression::Lz4 => 3,
+    }
+}
+
+fn check_magic(magic: &[u8]) -> Result<(), Error> {
+    if magic != MAGIC {
+        return Err(Error::InvalidFormat("not a container file".into()));
+    }
+    Ok(())
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32, Error> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+/// Reads `len` bytes, fails without allocating them if they go past the end of the file (`file_len`).
+fn read_vec(reader: &mut (impl Read + Seek), len: usize, file_len: u64) -> Result<Vec<u8>, Error> {
+    let offset = reader.stream_position()?;
+    if offset.saturating_add(len as u64) > file_len {
+        return Err(Error::InvalidFormat(format!("truncated container ({} bytes at offset {}, file of {} bytes)", len, offset, file_len)));
+    }
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Write};
+    use std::sync::Arc;
+
+    use arrow::array::Int64Array;
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::record_batch::RecordBatch;
+
+    use crate::container::{Block, BlockKind, ContainerReader, ContainerWriter};
+    use crate::delta_encoding::Encoding;
+    use crate::event::{Error, OpenTelemetryEvent, to_batch_event};
+    use crate::http::Compression;
+    use crate::opentelemetry::proto::events::v1::{InstrumentationLibraryEvents, ResourceEvents};
+    use crate::opentelemetry::proto::metrics::v1::{InstrumentationLibraryMetrics, MultivariateMetric, ResourceMetrics};
+    use crate::sink::ResourceBatch;
+
+    #[derive(Debug, OpenTelemetryEvent)]
+    #[otel(urn = "urn:test:request")]
+    struct Request {
+        #[otel(start_time)]
+        start_time: u64,
+        #[otel(end_time)]
+        end_time: u64,
+    }
+
+    fn requests(times: &[u64]) -> ResourceBatch {
+        let mut batch_event = to_batch_event(times.iter().map(|&time| Request { start_time: time, end_time: time + 5 }).collect()).unwrap();
+        batch_event.encode_timestamps(Encoding::Delta);
+        ResourceBatch::Events(ResourceEvents {
+            instrumentation_library_events: vec![InstrumentationLibraryEvents { batches: vec![batch_event], ..Default::default() }],
+            ..Default::default()
+        })
+    }
+
+    fn metrics(times: Vec<u64>) -> ResourceBatch {
+        ResourceBatch::Metrics(ResourceMetrics {
+            instrumentation_library_metrics: vec![InstrumentationLibraryMetrics {
+                multivariate_metrics: vec![MultivariateMetric { time_unix_nano_column: times, ..Default::default() }],
+                ..Default::default()
+            }],
+            schema_url: "urn:test:metrics".into(),
+            ..Default::default()
+        })
+    }
+
+    fn record_batch() -> RecordBatch {
+        let schema = Schema::new(vec![Field::new("latency", DataType::Int64, false)]);
+        RecordBatch::try_new(Arc::new(schema), vec![Arc::new(Int64Array::from(vec![10, 20]))]).unwrap()
+    }
+
+    fn container() -> Vec<u8> {
+        let mut writer = ContainerWriter::new(vec![], vec!["urn:test:request", "urn:test:metrics"]).unwrap()
+            .with_compression(Compression::Zstd);
+        writer.write_batch(&requests(&[100, 110])).unwrap();
+        writer.set_compression(Compression::Lz4);
+        writer.write_batch(&requests(&[200, 150])).unwrap();
+        writer.set_compression(Compression::None);
+        writer.write_batch(&metrics(vec![300, 310])).unwrap();
+        writer.set_compression(Compression::Gzip);
+        writer.write_record_batch("urn:test:request", &record_batch(), Some((400, 410))).unwrap();
+        writer.write_batch(&ResourceBatch::ArrowEvents(Default::default())).unwrap();
+        assert!(matches!(writer.write_record_batch("urn:test:unknown", &record_batch(), None), Err(Error::UnknownSchema(_))));
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_write_read() {
+        let mut reader = ContainerReader::new(Cursor::new(container())).unwrap();
+        assert_eq!(reader.schema_urls(), ["urn:test:request", "urn:test:metrics"]);
+
+        let blocks = reader.blocks();
+        assert_eq!(blocks.len(), 5);
+        assert_eq!(blocks.iter().map(|block| block.kind).collect::<Vec<_>>(), vec![BlockKind::Events, BlockKind::Events, BlockKind::Metrics, BlockKind::ArrowIpc, BlockKind::ArrowEvents]);
+        assert_eq!((blocks[1].compression, blocks[1].min_time_unix_nano, blocks[1].max_time_unix_nano), (Compression::Lz4, Some(150), Some(205)));
+        assert_eq!((blocks[2].schema_ids.as_slice(), blocks[2].min_time_unix_nano), ([1].as_slice(), Some(300)));
+        assert_eq!(blocks[4].max_time_unix_nano, None);
+
+        for i in 0..blocks.len() {
+            reader.read_block(i).unwrap();
+        }
+        assert!(matches!(reader.read_block(0).unwrap(), Block::Batch(batch) if batch == requests(&[100, 110])));
+        match reader.read_block(3).unwrap() {
+            Block::RecordBatches { schema_url, record_batches } => {
+                assert_eq!(schema_url, "urn:test:request");
+                assert_eq!(record_batches.len(), 1);
+                assert_eq!(record_batches[0].num_rows(), 2);
+            }
+            block => panic!("unexpected block {:?}", block),
+        }
+    }
+
+    #[test]
+    fn test_time_range() {
+        let mut reader = ContainerReader::new(Cursor::new(container())).unwrap();
+        // The blocks without time range are always read.
+        assert_eq!(reader.blocks_in_range(0, 99), vec![4]);
+        assert_eq!(reader.blocks_in_range(116, 149), vec![4]);
+        assert_eq!(reader.blocks_in_range(115, 150), vec![0, 1, 4]);
+        assert_eq!(reader.blocks_in_range(205, 400), vec![1, 2, 3, 4]);
+
+        let blocks = reader.read_range(300, 300).unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert!(matches!(&blocks[0], Block::Batch(batch) if *batch == metrics(vec![300, 310])));
+    }
+
+    #[test]
+    fn test_invalid_container() {
+        let container = container();
+        assert!(ContainerReader::new(Cursor::new(&container[..container.len() - 1])).is_err());
+        assert!(ContainerReader::new(Cursor::new(&container[1..])).is_err());
+
+        // A header length past the end of the file.
+        let mut truncated = container.clone();
+        truncated[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(ContainerReader::new(Cursor::new(truncated)), Err(Error::InvalidFormat(_))));
+
+        // A container without block.
+        let writer = ContainerWriter::new(vec![], Vec::<String>::new()).unwrap();
+        let reader = ContainerReader::new(Cursor::new(writer.finish().unwrap())).unwrap();
+        assert!(reader.blocks().is_empty());
+    }
+
+    #[test]
+    fn test_max_block_size() {
+        let mut reader = ContainerReader::new(Cursor::new(container())).unwrap().with_max_block_size(16);
+        for i in 0..4 {
+            assert!(matches!(reader.read_block(i), Err(Error::InvalidFormat(_))));
+        }
+    }
+
+    /// Accepts `capacity` bytes then fails.
+    struct FailingWriter {
+        capacity: usize,
+    }
+
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.capacity == 0 {
+                return Err(std::io::Error::other("disk full"));
+            }
+            let len = buf.len().min(self.capacity);
+            self.capacity -= len;
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_poisoned_writer() {
+        let mut writer = ContainerWriter::new(FailingWriter { capacity: 100 }, vec!["urn:test:request"]).unwrap();
+        assert!(writer.write_batch(&requests(&[100, 110])).is_err());
+        writer.writer.capacity = usize::MAX;
+        assert!(writer.write_batch(&requests(&[100, 110])).is_err());
+        assert!(writer.finish().is_err());
+    }
+}
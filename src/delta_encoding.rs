@@ -0,0 +1,407 @@
+//! Delta and delta-of-delta encodings of the integer columns (timestamps, `Int64Column`, `IntValues`).
+//!
+//! An encoded column is a sequence of zigzag varints. With the `Delta` encoding each varint is the difference with the
+//! previous value, with the `DoubleDelta` encoding it's the difference between two consecutive differences. Regularly
+//! spaced timestamps and monotonic counters are reduced to a few bytes per value.
+
+use crate::event::Error;
+use crate::opentelemetry::proto::events::v1::{ResourceEvents, BatchEvent, Int64Column};
+use crate::opentelemetry::proto::metrics::v1::{ResourceMetrics, MultivariateMetric, IntValues, ColumnarGauge, ColumnarSum, columnar_number_data_point};
+use crate::opentelemetry::proto::metrics::v1::columnar_metric::Data;
+
+pub use crate::opentelemetry::proto::events::v1::bytes_column::Encoding;
+
+/// Encodes a sequence of integers, returns an empty buffer with `Encoding::None`.
+pub fn encode(encoding: Encoding, values: impl IntoIterator<Item=i64>) -> Vec<u8> {
+    let mut buffer = vec![];
+    let mut previous_value = 0i64;
+    let mut previous_delta = 0i64;
+
+    if encoding == Encoding::None {
+        return buffer;
+    }
+
+    for value in values {
+        let delta = value.wrapping_sub(previous_value);
+        match encoding {
+            Encoding::Delta => write_varint(&mut buffer, zigzag(delta)),
+            Encoding::DoubleDelta => write_varint(&mut buffer, zigzag(delta.wrapping_sub(previous_delta))),
+            Encoding::None => unreachable!(),
+        }
+        previous_value = value;
+        previous_delta = delta;
+    }
+
+    buffer
+}
+
+/// Decodes a sequence of integers encoded with `encode`.
+pub fn decode(encoding: Encoding, mut buffer: &[u8]) -> Result<Vec<i64>, Error> {
+    let mut values = Vec::with_capacity(encoded_len(buffer));
+    let mut previous_value = 0i64;
+    let mut previous_delta = 0i64;
+
+    while !buffer.is_empty() {
+        let encoded_value = unzigzag(read_varint(&mut buffer)?);
+        let delta = match encoding {
+            Encoding::Delta => encoded_value,
+            Encoding::DoubleDelta => previous_delta.wrapping_add(encoded_value),
+            Encoding::None => return Err(Error::InvalidFormat("encoded values without encoding".into())),
+        };
+        previous_value = previous_value.wrapping_add(delta);
+        previous_delta = delta;
+        values.push(previous_value);
+    }
+
+    Ok(values)
+}
+
+/// Number of values of an encoded column.
+pub fn encoded_len(buffer: &[u8]) -> usize {
+    buffer.iter().filter(|&&byte| byte & 0x80 == 0).count()
+}
+
+impl Int64Column {
+    pub fn is_delta_encoded(&self) -> bool {
+        self.encoding != Encoding::None as i32
+    }
+
+    /// Number of values, encoded or not.
+    pub fn len(&self) -> usize {
+        if self.is_delta_encoded() { encoded_len(&self.encoded_values) } else { self.values.len() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Encodes the values of a plain column.
+    pub fn encode_values(&mut self, encoding: Encoding) {
+        if self.is_delta_encoded() || encoding == Encoding::None {
+            return;
+        }
+        self.encoded_values = encode(encoding, self.values.drain(..));
+        self.encoding = encoding as i32;
+    }
+
+    pub fn decode_values(&mut self) -> Result<(), Error> {
+        if !self.is_delta_encoded() {
+            return Ok(());
+        }
+        self.values = decode(column_encoding(self.encoding)?, &self.encoded_values)?;
+        self.encoded_values.clear();
+        self.encoding = Encoding::None as i32;
+        Ok(())
+    }
+}
+
+impl IntValues {
+    pub fn is_delta_encoded(&self) -> bool {
+        self.encoding != Encoding::None as i32
+    }
+
+    /// Number of values, encoded or not.
+    pub fn len(&self) -> usize {
+        if self.is_delta_encoded() { encoded_len(&self.encoded_value) } else { self.value.len() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Encodes the values of a plain column.
+    pub fn encode_values(&mut self, encoding: Encoding) {
+        if self.is_delta_encoded() || encoding == Encoding::None {
+            return;
+        }
+        self.encoded_value = encode(encoding, self.value.drain(..));
+        self.encoding = encoding as i32;
+    }
+
+    pub fn decode_values(&mut self) -> Result<(), Error> {
+        if !self.is_delta_encoded() {
+            return Ok(());
+        }
+        self.value = decode(column_encoding(self.encoding)?, &self.encoded_value)?;
+        self.encoded_value.clear();
+        self.encoding = Encoding::None as i32;
+        Ok(())
+    }
+}
+
+impl ResourceEvents {
+    pub fn decode_delta_columns(&mut self) -> Result<(), Error> {
+        for instrumentation_library_events in &mut self.instrumentation_library_events {
+            for batch in &mut instrumentation_library_events.batches {
+                batch.decode_delta_columns()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl BatchEvent {
+    /// Returns true if the timestamps or an int column (auxiliary entities included) are delta encoded.
+    pub fn is_delta_encoded(&self) -> bool {
+        self.time_encoding != Encoding::None as i32
+            || self.i64_values.iter().any(|column| column.is_delta_encoded())
+            || self.auxiliary_entities.iter().flat_map(|auxiliary_entity| &auxiliary_entity.i64_values).any(|column| column.is_delta_encoded())
+    }
+
+    pub fn encode_timestamps(&mut self, encoding: Encoding) {
+        if self.time_encoding != Encoding::None as i32 || encoding == Encoding::None {
+            return;
+        }
+        self.encoded_start_time_unix_nano_column = encode(encoding, self.start_time_unix_nano_column.drain(..).map(|time| time as i64));
+        self.encoded_end_time_unix_nano_column = encode(encoding, self.end_time_unix_nano_column.drain(..).map(|time| time as i64));
+        self.time_encoding = encoding as i32;
+    }
+
+    /// Decodes the timestamps and the int columns of the batch and of its auxiliary entities.
+    pub fn decode_delta_columns(&mut self) -> Result<(), Error> {
+        if self.time_encoding != Encoding::None as i32 {
+            let encoding = column_encoding(self.time_encoding)?;
+            self.start_time_unix_nano_column = decode(encoding, &self.encoded_start_time_unix_nano_column)?.into_iter().map(|time| time as u64).collect();
+            self.end_time_unix_nano_column = decode(encoding, &self.encoded_end_time_unix_nano_column)?.into_iter().map(|time| time as u64).collect();
+            self.encoded_start_time_unix_nano_column.clear();
+            self.encoded_end_time_unix_nano_column.clear();
+            self.time_encoding = Encoding::None as i32;
+        }
+
+        let auxiliary_columns = self.auxiliary_entities.iter_mut().flat_map(|auxiliary_entity| auxiliary_entity.i64_values.iter_mut());
+        for column in self.i64_values.iter_mut().chain(auxiliary_columns) {
+            column.decode_values()?;
+        }
+        Ok(())
+    }
+}
+
+impl ResourceMetrics {
+    pub fn decode_delta_columns(&mut self) -> Result<(), Error> {
+        for instrumentation_library_metrics in &mut self.instrumentation_library_metrics {
+            for multivariate_metric in &mut instrumentation_library_metrics.multivariate_metrics {
+                multivariate_metric.decode_delta_columns()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl MultivariateMetric {
+    /// Returns true if the timestamps or an int metric are delta encoded.
+    pub fn is_delta_encoded(&self) -> bool {
+        self.time_encoding != Encoding::None as i32
+            || self.metrics.iter().filter_map(|metric| int_values(&metric.data)).any(|values| values.is_delta_encoded())
+    }
+
+    pub fn encode_timestamps(&mut self, encoding: Encoding) {
+        if self.time_encoding != Encoding::None as i32 || encoding == Encoding::None {
+            return;
+        }
+        self.encoded_time_unix_nano_column = encode(encoding, self.time_unix_nano_column.drain(..).map(|time| time as i64));
+        self.encoded_start_time_unix_nano_column = encode(encoding, self.start_time_unix_nano_column.drain(..).map(|time| time as i64));
+        self.time_encoding = encoding as i32;
+    }
+
+    /// Encodes the values of an int metric, does nothing if the metric doesn't exist or is not an int metric.
+    pub fn encode_metric(&mut self, name: &str, encoding: Encoding) {
+        let values = self.metrics.iter_mut()
+            .filter(|metric| metric.name == name)
+            .find_map(|metric| int_values_mut(&mut metric.data));
+        if let Some(values) = values {
+            values.encode_values(encoding);
+        }
+    }
+
+    /// Decodes the timestamps and the int metrics.
+    pub fn decode_delta_columns(&mut self) -> Result<(), Error> {
+        if self.time_encoding != Encoding::None as i32 {
+            let encoding = column_encoding(self.time_encoding)?;
+            self.time_unix_nano_column = decode(encoding, &self.encoded_time_unix_nano_column)?.into_iter().map(|time| time as u64).collect();
+            self.start_time_unix_nano_column = decode(encoding, &self.encoded_start_time_unix_nano_column)?.into_iter().map(|time| time as u64).collect();
+            self.encoded_time_unix_nano_column.clear();
+            self.encoded_start_time_unix_nano_column.clear();
+            self.time_encoding = Encoding::None as i32;
+        }
+
+        for metric in &mut self.metrics {
+            if let Some(values) = int_values_mut(&mut metric.data) {
+                values.decode_values()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn int_values(data: &Option<Data>) -> Option<&IntValues> {
+    let data_points = match data {
+        Some(Data::Gauge(ColumnarGauge { data_points })) => data_points.as_ref(),
+        Some(Data::Sum(ColumnarSum { data_points, .. })) => data_points.as_ref(),
+        Some(Data::Histogram(_)) | Some(Data::ExponentialHistogram(_)) | None => None,
+    };
+    match data_points.and_then(|data_points| data_points.value.as_ref()) {
+        Some(columnar_number_data_point::Value::AsInts(values)) => Some(values),
+        _ => None,
+    }
+}
+
+fn int_values_mut(data: &mut Option<Data>) -> Option<&mut IntValues> {
+    let data_points = match data {
+        Some(Data::Gauge(ColumnarGauge { data_points })) => data_points.as_mut(),
+        Some(Data::Sum(ColumnarSum { data_points, .. })) => data_points.as_mut(),
+        Some(Data::Histogram(_)) | Some(Data::ExponentialHistogram(_)) | None => None,
+    };
+    match data_points.and_then(|data_points| data_points.value.as_mut()) {
+        Some(columnar_number_data_point::Value::AsInts(values)) => Some(values),
+        _ => None,
+    }
+}
+
+pub(crate) fn column_encoding(encoding: i32) -> Result<Encoding, Error> {
+    Encoding::from_i32(encoding).ok_or_else(|| Error::InvalidFormat(format!("unknown column encoding {}", encoding)))
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn read_varint(buffer: &mut &[u8]) -> Result<u64, Error> {
+    let mut value = 0u64;
+
+    for (i, &byte) in buffer.iter().enumerate().take(10) {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            *buffer = &buffer[i + 1..];
+            return Ok(value);
+        }
+    }
+    Err(Error::InvalidFormat("truncated or invalid varint".into()))
+}
+
+#[cfg(test)]
+mod test {
+    use prost::Message;
+
+    use crate::delta_encoding::{encode, decode, encoded_len, Encoding};
+    use crate::event::{BatchPolicy, Error, EventBatchHandler, OpenTelemetryEvent};
+    use crate::metrics_columnar::MultivariateMetricBuilder;
+    use crate::metrics_conversion::to_standard_metrics;
+    use crate::opentelemetry::proto::events::v1::{BatchEvent, Int64Column};
+    use crate::opentelemetry::proto::metrics::v1::AggregationTemporality;
+    use crate::sink::{MemorySink, ResourceBatch};
+
+    #[derive(Debug, OpenTelemetryEvent)]
+    #[otel(urn = "urn:test:request")]
+    struct Request {
+        #[otel(start_time)]
+        time: u64,
+        count: i64,
+    }
+
+    #[test]
+    fn test_encodings() {
+        let timestamps: Vec<i64> = (0..100).map(|i| 1_600_000_000_000_000_000 + i * 1_000_000_000).collect();
+
+        let delta = encode(Encoding::Delta, timestamps.iter().copied());
+        let double_delta = encode(Encoding::DoubleDelta, timestamps.iter().copied());
+        assert!(double_delta.len() < delta.len());
+        assert!(delta.len() < timestamps.len() * 8);
+        assert_eq!(encoded_len(&double_delta), 100);
+        assert_eq!(decode(Encoding::Delta, &delta).unwrap(), timestamps);
+        assert_eq!(decode(Encoding::DoubleDelta, &double_delta).unwrap(), timestamps);
+
+        let values = vec![i64::MIN, -1, 0, 1, i64::MAX, 42, -42];
+        assert_eq!(decode(Encoding::Delta, &encode(Encoding::Delta, values.clone())).unwrap(), values);
+        assert_eq!(decode(Encoding::DoubleDelta, &encode(Encoding::DoubleDelta, values.clone())).unwrap(), values);
+
+        assert!(matches!(decode(Encoding::Delta, &[0x80]), Err(Error::InvalidFormat(_))));
+    }
+
+    #[test]
+    fn test_batch_event() {
+        let batch = BatchEvent {
+            size: 3,
+            start_time_unix_nano_column: vec![10, 20, 30],
+            end_time_unix_nano_column: vec![15, 25, 35],
+            i64_values: vec![Int64Column { name: "count".into(), values: vec![1, 2, 4], ..Default::default() }],
+            ..Default::default()
+        };
+
+        let mut encoded_batch = batch.clone();
+        encoded_batch.encode_timestamps(Encoding::DoubleDelta);
+        encoded_batch.i64_values[0].encode_values(Encoding::Delta);
+        assert!(encoded_batch.is_delta_encoded());
+        assert!(encoded_batch.start_time_unix_nano_column.is_empty());
+        assert_eq!(encoded_batch.i64_values[0].len(), 3);
+        encoded_batch.validate().unwrap();
+
+        encoded_batch.decode_delta_columns().unwrap();
+        assert_eq!(encoded_batch, batch);
+    }
+
+    #[test]
+    fn test_multivariate_metric() {
+        let mut builder = MultivariateMetricBuilder::new()
+            .int_sum("requests", "", "", AggregationTemporality::Cumulative, true)
+            .time_encoding(Encoding::DoubleDelta)
+            .metric_encoding("requests", Encoding::Delta);
+        for i in 0..10u64 {
+            builder.append_with_start_time(0u64, i * 10, &[], &[(i as i64 * 3).into()]).unwrap();
+        }
+        let multivariate_metric = builder.build().unwrap();
+        assert!(multivariate_metric.is_delta_encoded());
+        assert!(multivariate_metric.time_unix_nano_column.is_empty());
+        assert_eq!(to_standard_metrics(&multivariate_metric).unwrap().len(), 1);
+
+        let mut decoded_metric = multivariate_metric;
+        decoded_metric.decode_delta_columns().unwrap();
+        assert!(!decoded_metric.is_delta_encoded());
+        assert_eq!(decoded_metric.time_unix_nano_column, (0..10u64).map(|i| i * 10).collect::<Vec<_>>());
+        assert_eq!(decoded_metric.start_time_unix_nano_column, vec![0; 10]);
+    }
+
+    #[test]
+    fn test_json_value() {
+        let batch_policy = BatchPolicy::new(10, chrono::Duration::seconds(10));
+        let sink = MemorySink::new();
+        let mut handler = EventBatchHandler::<Request>::new(batch_policy.clone())
+            .with_time_encoding(Encoding::DoubleDelta)
+            .with_column_encoding("count", Encoding::Delta)
+            .with_sink(sink.clone());
+        let mut plain_handler = EventBatchHandler::<Request>::new(batch_policy.clone());
+        for i in 0..3 {
+            handler.record(Request { time: 10 + i * 10, count: i as i64 * 5 }).unwrap();
+            plain_handler.record(Request { time: 10 + i * 10, count: i as i64 * 5 }).unwrap();
+        }
+        handler.flush().unwrap();
+        let resource_events = match sink.take().pop() {
+            Some(ResourceBatch::Events(resource_events)) => resource_events,
+            batch => panic!("unexpected batch {:?}", batch),
+        };
+        assert!(resource_events.instrumentation_library_events[0].batches[0].is_delta_encoded());
+        let expected_json = plain_handler.to_json_value().unwrap();
+
+        // Rendered as is.
+        let mut encoded_handler = EventBatchHandler::<Request>::new(batch_policy.clone());
+        encoded_handler.resource_events = resource_events.clone();
+        assert_eq!(encoded_handler.to_json_value().unwrap(), expected_json);
+
+        // Decoded by deserialize.
+        let mut deserialized_handler = EventBatchHandler::<Request>::new(batch_policy);
+        deserialized_handler.deserialize(resource_events.encode_to_vec()).unwrap();
+        assert!(!deserialized_handler.resource_events.instrumentation_library_events[0].batches[0].is_delta_encoded());
+        assert_eq!(deserialized_handler.to_json_value().unwrap(), expected_json);
+    }
+}
@@ -0,0 +1,209 @@
+//! Dictionary encoding of the string columns (`StringColumn`, `ColumnarAttribute`).
+//!
+//! A dictionary encoded column stores each distinct value once in `dictionary` and one index per row in
+//! `dictionary_indices`. The encoding is only applied when the estimated protobuf size is smaller than the plain
+//! encoding, i.e. for low cardinality columns.
+
+use std::collections::HashMap;
+
+use crate::opentelemetry::proto::events::v1::{ResourceEvents, BatchEvent, StringColumn};
+use crate::opentelemetry::proto::metrics::v1::{ResourceMetrics, MultivariateMetric, ColumnarAttribute};
+
+impl StringColumn {
+    /// Number of values, dictionary encoded or not.
+    pub fn len(&self) -> usize {
+        if self.is_dictionary_encoded() { self.dictionary_indices.len() } else { self.values.len() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_dictionary_encoded(&self) -> bool {
+        !self.dictionary.is_empty()
+    }
+
+    /// Returns the value of a row regardless of the encoding (the validity bitmap is not checked).
+    pub fn value(&self, row: usize) -> Option<&str> {
+        value(&self.values, &self.dictionary, &self.dictionary_indices, row)
+    }
+
+    /// Encodes the column with a dictionary if it makes it smaller. Returns true if the column is dictionary encoded.
+    pub fn encode_dictionary(&mut self) -> bool {
+        encode(&mut self.values, &mut self.dictionary, &mut self.dictionary_indices)
+    }
+
+    pub fn decode_dictionary(&mut self) {
+        decode(&mut self.values, &mut self.dictionary, &mut self.dictionary_indices)
+    }
+}
+
+impl ColumnarAttribute {
+    /// Number of values, dictionary encoded or not.
+    pub fn len(&self) -> usize {
+        if self.is_dictionary_encoded() { self.dictionary_indices.len() } else { self.values.len() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_dictionary_encoded(&self) -> bool {
+        !self.dictionary.is_empty()
+    }
+
+    /// Returns the value of a row regardless of the encoding.
+    pub fn value(&self, row: usize) -> Option<&str> {
+        value(&self.values, &self.dictionary, &self.dictionary_indices, row)
+    }
+
+    /// Encodes the attribute with a dictionary if it makes it smaller. Returns true if the attribute is dictionary
+    /// encoded.
+    pub fn encode_dictionary(&mut self) -> bool {
+        encode(&mut self.values, &mut self.dictionary, &mut self.dictionary_indices)
+    }
+
+    pub fn decode_dictionary(&mut self) {
+        decode(&mut self.values, &mut self.dictionary, &mut self.dictionary_indices)
+    }
+}
+
+impl ResourceEvents {
+    pub fn encode_dictionaries(&mut self) {
+        for instrumentation_library_events in &mut self.instrumentation_library_events {
+            for batch in &mut instrumentation_library_events.batches {
+                batch.encode_dictionaries();
+            }
+        }
+    }
+}
+
+impl BatchEvent {
+    /// Dictionary encodes the low cardinality string columns of the batch and of its auxiliary entities.
+    pub fn encode_dictionaries(&mut self) {
+        let auxiliary_columns = self.auxiliary_entities.iter_mut().flat_map(|auxiliary_entity| auxiliary_entity.string_values.iter_mut());
+        for column in self.string_values.iter_mut().chain(auxiliary_columns) {
+            column.encode_dictionary();
+        }
+    }
+}
+
+impl ResourceMetrics {
+    pub fn encode_dictionaries(&mut self) {
+        for instrumentation_library_metrics in &mut self.instrumentation_library_metrics {
+            for multivariate_metric in &mut instrumentation_library_metrics.multivariate_metrics {
+                multivariate_metric.encode_dictionaries();
+            }
+        }
+    }
+}
+
+impl MultivariateMetric {
+    /// Dictionary encodes the low cardinality attributes.
+    pub fn encode_dictionaries(&mut self) {
+        for attribute in &mut self.attributes {
+            attribute.encode_dictionary();
+        }
+    }
+}
+
+fn value<'a>(values: &'a [String], dictionary: &'a [String], dictionary_indices: &[u32], row: usize) -> Option<&'a str> {
+    if dictionary.is_empty() {
+        values.get(row).map(|value| value.as_str())
+    } else {
+        dictionary_indices.get(row)
+            .and_then(|&index| dictionary.get(index as usize))
+            .map(|value| value.as_str())
+    }
+}
+
+fn encode(values: &mut Vec<String>, dictionary: &mut Vec<String>, dictionary_indices: &mut Vec<u32>) -> bool {
+    if !dictionary.is_empty() {
+        return true;
+    }
+
+    let mut ranks: HashMap<&str, u32> = HashMap::new();
+    let mut indices = Vec::with_capacity(values.len());
+    for value in values.iter() {
+        let next_rank = ranks.len() as u32;
+        indices.push(*ranks.entry(value.as_str()).or_insert(next_rank));
+    }
+
+    let plain_size: usize = values.iter().map(|value| string_size(value)).sum();
+    let dictionary_size: usize = ranks.keys().map(|value| string_size(value)).sum::<usize>()
+        + indices.iter().map(|&index| varint_size(index as u64)).sum::<usize>()
+        + 1 + varint_size(indices.len() as u64);
+    if ranks.is_empty() || dictionary_size >= plain_size {
+        return false;
+    }
+
+    let mut new_dictionary = vec![String::new(); ranks.len()];
+    for (value, rank) in ranks {
+        new_dictionary[rank as usize] = value.to_string();
+    }
+    *dictionary = new_dictionary;
+    *dictionary_indices = indices;
+    values.clear();
+    true
+}
+
+fn decode(values: &mut Vec<String>, dictionary: &mut Vec<String>, dictionary_indices: &mut Vec<u32>) {
+    if dictionary.is_empty() {
+        return;
+    }
+
+    *values = dictionary_indices.iter()
+        .map(|&index| dictionary.get(index as usize).cloned().unwrap_or_default())
+        .collect();
+    dictionary.clear();
+    dictionary_indices.clear();
+}
+
+/// Size of a repeated string entry (tag + length + bytes).
+fn string_size(value: &str) -> usize {
+    1 + varint_size(value.len() as u64) + value.len()
+}
+
+fn varint_size(value: u64) -> usize {
+    (64 - (value | 1).leading_zeros() as usize).div_ceil(7)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::opentelemetry::proto::events::v1::StringColumn;
+    use crate::opentelemetry::proto::metrics::v1::ColumnarAttribute;
+    use prost::Message;
+
+    #[test]
+    fn test_string_column() {
+        let values: Vec<String> = (0..100).map(|i| ["GET", "POST", "PUT"][i % 3].to_string()).collect();
+        let mut column = StringColumn { name: "method".into(), values: values.clone(), ..Default::default() };
+        let plain_size = column.encoded_len();
+
+        assert!(column.encode_dictionary());
+        assert!(column.values.is_empty());
+        assert_eq!(column.dictionary, vec!["GET".to_string(), "POST".to_string(), "PUT".to_string()]);
+        assert_eq!(column.len(), 100);
+        assert_eq!(column.value(4), Some("POST"));
+        assert_eq!(column.value(100), None);
+        assert!(column.encoded_len() < plain_size);
+
+        column.decode_dictionary();
+        assert!(!column.is_dictionary_encoded());
+        assert_eq!(column.values, values);
+    }
+
+    #[test]
+    fn test_high_cardinality() {
+        let values: Vec<String> = (0..100).map(|i| format!("user_{}", i)).collect();
+        let mut attribute = ColumnarAttribute { name: "user".into(), values: values.clone(), ..Default::default() };
+
+        assert!(!attribute.encode_dictionary());
+        assert_eq!(attribute.values, values);
+        assert_eq!(attribute.value(42), Some("user_42"));
+
+        let mut empty_attribute = ColumnarAttribute { name: "empty".into(), ..Default::default() };
+        assert!(!empty_attribute.encode_dictionary());
+        assert!(empty_attribute.is_empty());
+    }
+}
@@ -0,0 +1,353 @@
+//! Dynamic schema mode of the `EventBatchHandler` (see `EventBatchHandler::with_dynamic_attributes`).
+//!
+//! The attributes recorded with an event (see `EventBatchHandler::record_with_attributes`) are stored in typed
+//! columns named after their keys. A column is created the first time a key is seen with a given type in a batch,
+//! the previous rows being backfilled with nulls. Once `max_columns` columns have been created, the attributes with
+//! new keys are stored in the overflow entity (an `Attribute` auxiliary entity), as well as the attributes that can't
+//! be stored in a column: null, array and key/value list values, keys conflicting with a static column of the event
+//! and keys repeated within an event.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::attribute::Attribute;
+use crate::event::{BatchPolicy, Error, OpenTelemetryAuxiliaryEntity, OpenTelemetryEvent, grow_and_set_nth_bit, validity_bitmap};
+use crate::event_decoder::{BatchEventReader, Columns, FromAuxiliaryEntity};
+use crate::opentelemetry::proto::common::v1::{AnyValue, KeyValue};
+use crate::opentelemetry::proto::common::v1::any_value::Value;
+use crate::opentelemetry::proto::events::v1::{BatchEvent, BoolColumn, BytesColumn, DoubleColumn, Int64Column, StringColumn, bool_column, bytes_column, double_column, int64_column, string_column};
+use crate::opentelemetry::proto::events::v1::auxiliary_entity::LogicalType as AuxiliaryEntityLogicalType;
+
+/// Parent column of the overflow entity, always the last auxiliary entity of a batch in dynamic schema mode.
+pub const OVERFLOW_ATTRIBUTES: &str = "overflow_attributes";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ColumnType {
+    Int64,
+    Double,
+    String,
+    Bool,
+    Bytes,
+}
+
+/// Dynamic columns of the current batch of a handler.
+#[derive(Debug, Clone)]
+pub struct DynamicAttributes {
+    max_columns: usize,
+    batch_policy: BatchPolicy,
+    static_columns: HashSet<String>,
+    /// Rank of the dynamic columns in their column family.
+    columns: HashMap<(String, ColumnType), usize>,
+}
+
+impl DynamicAttributes {
+    pub fn new<T: OpenTelemetryEvent>(max_columns: usize, batch_policy: BatchPolicy) -> Self {
+        DynamicAttributes {
+            max_columns,
+            static_columns: static_columns::<T>(),
+            batch_policy,
+            columns: HashMap::new(),
+        }
+    }
+
+    /// Removes the dynamic columns of a batch and starts a new overflow entity.
+    pub fn reset(&mut self, batch: &mut BatchEvent) {
+        let static_columns = &self.static_columns;
+        batch.i64_values.retain(|column| static_columns.contains(&column.name));
+        batch.f64_values.retain(|column| static_columns.contains(&column.name));
+        batch.string_values.retain(|column| static_columns.contains(&column.name));
+        batch.bool_values.retain(|column| static_columns.contains(&column.name));
+        batch.bytes_values.retain(|column| static_columns.contains(&column.name));
+        self.columns.clear();
+
+        if batch.auxiliary_entities.last().map(|entity| entity.parent_column == OVERFLOW_ATTRIBUTES).unwrap_or(false) {
+            batch.auxiliary_entities.pop();
+        }
+        batch.auxiliary_entities.push(Attribute::auxiliary_entity(OVERFLOW_ATTRIBUTES, AuxiliaryEntityLogicalType::Attribute, &self.batch_policy));
+    }
+
+    /// Records the attributes of the last event of the batch, the dynamic columns not set by this event get a null.
+    pub fn record(&mut self, batch: &mut BatchEvent, attributes: Vec<Attribute>) {
+        let row = batch.size as usize - 1;
+
+        for attribute in attributes {
+            if !self.record_column_value(batch, &attribute, row) {
+                let overflow_attributes = batch.auxiliary_entities.last_mut().expect("overflow entity");
+                attribute.record_into_auxiliary_entity(overflow_attributes, row as u32);
+            }
+        }
+
+        let size = batch.size as usize;
+        for (&(_, column_type), &rank) in &self.columns {
+            match column_type {
+                ColumnType::Int64 => batch.i64_values[rank].values.resize(size, 0),
+                ColumnType::Double => batch.f64_values[rank].values.resize(size, 0.0),
+                ColumnType::String => batch.string_values[rank].values.resize(size, String::new()),
+                ColumnType::Bool => batch.bool_values[rank].values.resize(size, false),
+                ColumnType::Bytes => batch.bytes_values[rank].values.resize(size, vec![]),
+            }
+        }
+    }
+
+    /// Returns false if the attribute must be stored in the overflow entity.
+    fn record_column_value(&mut self, batch: &mut BatchEvent, attribute: &Attribute, row: usize) -> bool {
+        let column_type = match column_type(attribute) {
+            Some(column_type) if !self.static_columns.contains(&attribute.key) => column_type,
+            _ => return false,
+        };
+        let rank = match self.columns.get(&(attribute.key.clone(), column_type)) {
+            Some(&rank) => rank,
+            None if self.columns.len() < self.max_columns => {
+                let rank = self.new_column(batch, &attribute.key, column_type, row);
+                self.columns.insert((attribute.key.clone(), column_type), rank);
+                rank
+            }
+            None => return false,
+        };
+
+        let (values_len, validity_bitmap) = match column_type {
+            ColumnType::Int64 => {
+                let column = &mut batch.i64_values[rank];
+                if column.values.len() > row { return false; }
+                column.values.push(attribute.int_value.unwrap_or_default());
+                (column.values.len(), &mut column.validity_bitmap)
+            }
+            ColumnType::Double => {
+                let column = &mut batch.f64_values[rank];
+                if column.values.len() > row { return false; }
+                column.values.push(attribute.double_value.unwrap_or_default());
+                (column.values.len(), &mut column.validity_bitmap)
+            }
+            ColumnType::String => {
+                let column = &mut batch.string_values[rank];
+                if column.values.len() > row { return false; }
+                column.values.push(attribute.string_value.clone().unwrap_or_default());
+                (column.values.len(), &mut column.validity_bitmap)
+            }
+            ColumnType::Bool => {
+                let column = &mut batch.bool_values[rank];
+                if column.values.len() > row { return false; }
+                column.values.push(attribute.bool_value.unwrap_or_default());
+                (column.values.len(), &mut column.validity_bitmap)
+            }
+            ColumnType::Bytes => {
+                let column = &mut batch.bytes_values[rank];
+                if column.values.len() > row { return false; }
+                column.values.push(attribute.bytes_value.clone().unwrap_or_default());
+                (column.values.len(), &mut column.validity_bitmap)
+            }
+        };
+        grow_and_set_nth_bit(validity_bitmap, values_len - 1);
+        true
+    }
+
+    /// Creates a column whose `row` first values are nulls.
+    fn new_column(&self, batch: &mut BatchEvent, key: &str, column_type: ColumnType, row: usize) -> usize {
+        let name = key.to_string();
+        let validity_bitmap = validity_bitmap(self.batch_policy.max_size.max(row as u32 + 1) as usize);
+
+        match column_type {
+            ColumnType::Int64 => {
+                batch.i64_values.push(Int64Column {
+                    name,
+                    logical_type: int64_column::LogicalType::Attribute as i32,
+                    values: vec![0; row],
+                    validity_bitmap,
+                    ..Default::default()
+                });
+                batch.i64_values.len() - 1
+            }
+            ColumnType::Double => {
+                batch.f64_values.push(DoubleColumn {
+                    name,
+                    logical_type: double_column::LogicalType::Attribute as i32,
+                    values: vec![0.0; row],
+                    validity_bitmap,
+                    ..Default::default()
+                });
+                batch.f64_values.len() - 1
+            }
+            ColumnType::String => {
+                batch.string_values.push(StringColumn {
+                    name,
+                    logical_type: string_column::LogicalType::Attribute as i32,
+                    values: vec![String::new(); row],
+                    validity_bitmap,
+                    ..Default::default()
+                });
+                batch.string_values.len() - 1
+            }
+            ColumnType::Bool => {
+                batch.bool_values.push(BoolColumn {
+                    name,
+                    logical_type: bool_column::LogicalType::Attribute as i32,
+                    values: vec![false; row],
+                    validity_bitmap,
+                    ..Default::default()
+                });
+                batch.bool_values.len() - 1
+            }
+            ColumnType::Bytes => {
+                batch.bytes_values.push(BytesColumn {
+                    name,
+                    logical_type: bytes_column::LogicalType::Attribute as i32,
+                    values: vec![vec![]; row],
+                    validity_bitmap,
+                    ..Default::default()
+                });
+                batch.bytes_values.len() - 1
+            }
+        }
+    }
+}
+
+/// Decodes, for each event of a batch recorded in dynamic schema mode, the attributes stored in the dynamic columns
+/// (in column order) followed by the attributes of the overflow entity.
+pub fn decode_dynamic_attributes<T: OpenTelemetryEvent>(batch: &BatchEvent) -> Result<Vec<Vec<KeyValue>>, Error> {
+    let static_columns = static_columns::<T>();
+    let reader = BatchEventReader::new(batch)?;
+    let columns = Columns::of_batch(reader.batch());
+    let dynamic = |name: &String| !static_columns.contains(name);
+    let mut attributes = Vec::with_capacity(reader.len());
+
+    for row in reader.rows() {
+        let mut key_values = vec![];
+        let mut push = |key: &str, value: Option<Value>| {
+            if let Some(value) = value {
+                key_values.push(KeyValue { key: key.into(), value: Some(AnyValue { value: Some(value) }) });
+            }
+        };
+
+        for column in columns.i64_values.iter().filter(|column| dynamic(&column.name)) {
+            push(&column.name, row.i64_value(&column.name)?.map(Value::IntValue));
+        }
+        for column in columns.f64_values.iter().filter(|column| dynamic(&column.name)) {
+            push(&column.name, row.f64_value(&column.name)?.map(Value::DoubleValue));
+        }
+        for column in columns.string_values.iter().filter(|column| dynamic(&column.name)) {
+            push(&column.name, row.string_value(&column.name)?.map(|value| Value::StringValue(value.into())));
+        }
+        for column in columns.bool_values.iter().filter(|column| dynamic(&column.name)) {
+            push(&column.name, row.bool_value(&column.name)?.map(Value::BoolValue));
+        }
+        for column in columns.bytes_values.iter().filter(|column| dynamic(&column.name)) {
+            push(&column.name, row.bytes_value(&column.name)?.map(|value| Value::BytesValue(value.to_vec())));
+        }
+
+        for overflow_row in row.auxiliary_rows(OVERFLOW_ATTRIBUTES)? {
+            key_values.push(Attribute::from_row(&overflow_row)?.into_key_value()?);
+        }
+        attributes.push(key_values);
+    }
+
+    Ok(attributes)
+}
+
+fn static_columns<T: OpenTelemetryEvent>() -> HashSet<String> {
+    let batch_policy = BatchPolicy::new(0, chrono::Duration::zero());
+    let mut static_columns = HashSet::new();
+    static_columns.extend(T::int64_columns(&batch_policy).into_iter().map(|column| column.name));
+    static_columns.extend(T::double_columns(&batch_policy).into_iter().map(|column| column.name));
+    static_columns.extend(T::string_columns(&batch_policy).into_iter().map(|column| column.name));
+    static_columns.extend(T::bool_columns(&batch_policy).into_iter().map(|column| column.name));
+    static_columns.extend(T::bytes_columns(&batch_policy).into_iter().map(|column| column.name));
+    static_columns
+}
+
+fn column_type(attribute: &Attribute) -> Option<ColumnType> {
+    if attribute.string_value.is_some() {
+        Some(ColumnType::String)
+    } else if attribute.int_value.is_some() {
+        Some(ColumnType::Int64)
+    } else if attribute.double_value.is_some() {
+        Some(ColumnType::Double)
+    } else if attribute.bool_value.is_some() {
+        Some(ColumnType::Bool)
+    } else if attribute.bytes_value.is_some() {
+        Some(ColumnType::Bytes)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dynamic_attributes::{OVERFLOW_ATTRIBUTES, decode_dynamic_attributes};
+    use crate::event::{BatchPolicy, EventBatchHandler, OpenTelemetryEvent};
+    use crate::event_decoder::{FromBatchEvent, decode_batch_event};
+    use crate::opentelemetry::proto::common::v1::{AnyValue, ArrayValue, KeyValue};
+    use crate::opentelemetry::proto::common::v1::any_value::Value;
+    use crate::sink::{MemorySink, ResourceBatch};
+
+    #[derive(Debug, Clone, PartialEq, OpenTelemetryEvent, FromBatchEvent)]
+    #[otel(urn = "urn:test:request")]
+    struct Request {
+        #[otel(start_time)]
+        start_time: u64,
+        method: String,
+    }
+
+    fn key_value(key: &str, value: Value) -> KeyValue {
+        KeyValue { key: key.into(), value: Some(AnyValue { value: Some(value) }) }
+    }
+
+    #[test]
+    fn test_dynamic_attributes() {
+        let sink = MemorySink::new();
+        let mut handler = EventBatchHandler::<Request>::new(BatchPolicy::new(10, chrono::Duration::seconds(10)))
+            .with_sink(sink.clone())
+            .with_dynamic_attributes(2);
+
+        let request = |start_time| Request { start_time, method: "GET".into() };
+        let attributes = vec![
+            vec![key_value("user", Value::StringValue("user_1".into()))],
+            vec![],
+            vec![
+                key_value("user", Value::StringValue("user_2".into())),
+                key_value("status", Value::IntValue(200)),
+                key_value("user", Value::StringValue("user_3".into())),
+            ],
+            vec![
+                key_value("cached", Value::BoolValue(true)),
+                key_value("method", Value::StringValue("POST".into())),
+                key_value("tags", Value::ArrayValue(ArrayValue { values: vec![] })),
+            ],
+        ];
+        for (start_time, attributes) in attributes.iter().enumerate() {
+            handler.record_with_attributes(request(start_time as u64), attributes.clone()).unwrap();
+        }
+        handler.record(request(4)).unwrap();
+        handler.flush().unwrap();
+
+        handler.record_with_attributes(request(5), vec![key_value("cached", Value::BoolValue(false))]).unwrap();
+        handler.flush().unwrap();
+
+        let batches: Vec<_> = sink.take().into_iter()
+            .map(|batch| match batch {
+                ResourceBatch::Events(mut resource_events) => resource_events.instrumentation_library_events.remove(0).batches.remove(0),
+                batch => panic!("unexpected batch: {:?}", batch),
+            })
+            .collect();
+
+        let batch = &batches[0];
+        batch.validate().unwrap();
+        assert_eq!(batch.string_values.iter().map(|column| column.name.as_str()).collect::<Vec<_>>(), vec!["method", "user"]);
+        assert_eq!(batch.i64_values[0].name, "status");
+        assert_eq!(batch.i64_values[0].values, vec![0, 0, 200, 0, 0]);
+        assert_eq!(batch.i64_values[0].validity_bitmap[0], 0b00100);
+        assert!(batch.bool_values.is_empty());
+        let overflow_attributes = batch.auxiliary_entities.last().unwrap();
+        assert_eq!(overflow_attributes.parent_column, OVERFLOW_ATTRIBUTES);
+        assert_eq!(overflow_attributes.parent_ranks, vec![2, 3, 3, 3]);
+
+        assert_eq!(decode_batch_event::<Request>(batch).unwrap().len(), 5);
+        let mut expected_attributes = attributes;
+        expected_attributes[2].swap(0, 1);
+        expected_attributes.push(vec![]);
+        assert_eq!(decode_dynamic_attributes::<Request>(batch).unwrap(), expected_attributes);
+
+        // The dynamic columns are created per batch.
+        assert_eq!(batches[1].bool_values.len(), 1);
+        assert!(batches[1].i64_values.is_empty());
+        assert_eq!(decode_dynamic_attributes::<Request>(&batches[1]).unwrap(), vec![vec![key_value("cached", Value::BoolValue(false))]]);
+    }
+}
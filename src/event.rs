@@ -1,12 +1,30 @@
+use std::borrow::Cow;
 use std::marker::PhantomData;
-use crate::opentelemetry::proto::events::v1::{StringColumn, Int64Column, DoubleColumn, BytesColumn, Int64SummaryColumn, DoubleSummaryColumn, BoolColumn, ResourceEvents, InstrumentationLibraryEvents, BatchEvent, AuxiliaryEntity};
+use crate::opentelemetry::proto::events::v1::{StringColumn, Int64Column, DoubleColumn, BytesColumn, Int64SummaryColumn, DoubleSummaryColumn, HistogramColumn, ExponentialHistogramColumn, BoolColumn, ResourceEvents, InstrumentationLibraryEvents, BatchEvent, AuxiliaryEntity};
 use crate::opentelemetry::proto::resource::v1::Resource;
 use crate::opentelemetry::proto::common::v1::InstrumentationLibrary;
 use serde_json::{Value, Number, Map};
-use arrow::datatypes::Schema;
+use arrow::datatypes::{Schema, Field, DataType, TimeUnit};
+use arrow::array::{make_array, ArrayBuilder, ArrayRef, MutableArrayData, StructBuilder, ListBuilder, StringBuilder, BinaryBuilder, BooleanBuilder, Int8Builder, Int16Builder, Int32Builder, Int64Builder, UInt8Builder, UInt16Builder, UInt32Builder, UInt64Builder, Float32Builder, Float64Builder, TimestampNanosecondBuilder};
+use arrow::record_batch::{RecordBatch, RecordBatchOptions};
+use arrow::ipc::writer::StreamWriter;
+use arrow::ipc::reader::StreamReader;
+use arrow::error::ArrowError;
+use std::sync::Arc;
 use crate::opentelemetry::proto::arrow_events::v1 as arrow_events;
 use prost::{Message, EncodeError};
 use bytes::Bytes;
+use chrono::{DateTime, TimeZone, Utc};
+use crate::opentelemetry::proto::events::v1::auxiliary_entity::LogicalType as AuxiliaryEntityLogicalType;
+
+use crate::sink::EventSink;
+use crate::delta_encoding::Encoding;
+use crate::dynamic_attributes::DynamicAttributes;
+use crate::collector::CollectorState;
+use crate::attribute::{Attribute, to_attributes};
+use crate::opentelemetry::proto::common::v1::KeyValue;
+
+pub use otel_multivariate_time_series_derive::{OpenTelemetryEvent, OpenTelemetryAuxiliaryEntity};
 
 #[derive(Debug, Clone)]
 pub struct BatchPolicy {
@@ -21,6 +39,13 @@ pub struct EventBatchHandler<T: OpenTelemetryEvent> {
     // ToDo pub(crate) ?
     pub batch_policy: BatchPolicy,
     pub resource_events: ResourceEvents,
+    resource: Resource,
+    instrumentation_library: InstrumentationLibrary,
+    oldest_event_time: Option<DateTime<Utc>>,
+    sink: Option<Box<dyn EventSink + Send>>,
+    time_encoding: Encoding,
+    column_encodings: Vec<(String, Encoding)>,
+    dynamic_attributes: Option<DynamicAttributes>,
     phantom_data: PhantomData<T>,
 }
 
@@ -32,18 +57,62 @@ pub struct ArrowEventBatchHandler<T: OpenTelemetryArrowEvent> {
     pub batch_policy: BatchPolicy,
     pub resource_events: arrow_events::ResourceEvents,
     pub arrow_schema: Schema,
+    /// Row builder, one child builder per top-level field of the arrow schema.
+    pub builder: StructBuilder,
+    /// Rows recorded before an event failing partway through, encoded by the next flush.
+    complete_rows: Vec<RecordBatch>,
+    resource: Resource,
+    instrumentation_library: InstrumentationLibrary,
+    sink: Option<Box<dyn EventSink>>,
     phantom_data: PhantomData<T>,
 }
 
-#[derive(Debug)]
+/// Creates the event handlers of an application and groups the events exported by handlers of different resources
+/// and instrumentation libraries. Clones share the same collected events, the collector can be shared between
+/// threads recording via `EventHandle`s (see `collector`).
+#[derive(Debug, Clone)]
 pub struct EventCollector {
     default_batch_policy: BatchPolicy,
+    pub(crate) resource: Resource,
+    pub(crate) instrumentation_library: InstrumentationLibrary,
+    pub(crate) shard_count: usize,
+    pub(crate) max_pending_events: usize,
+    pub(crate) state: Arc<CollectorState>,
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("IO Error (error: {0})")]
     IoError(#[from] std::io::Error),
+    #[error("Arrow Error (error: {0})")]
+    ArrowError(#[from] ArrowError),
+    #[error("Protobuf Encode Error (error: {0})")]
+    EncodeError(#[from] EncodeError),
+    #[error("Protobuf Decode Error (error: {0})")]
+    DecodeError(#[from] prost::DecodeError),
+    #[error("JSON Error (error: {0})")]
+    JsonError(#[from] serde_json::Error),
+    #[error("Invalid Format (error: {0})")]
+    InvalidFormat(String),
+    #[error("Missing Column (column: {0})")]
+    MissingColumn(String),
+    #[error("Missing Value (column: {column}, row: {row})")]
+    MissingValue { column: String, row: usize },
+    #[error("Invalid Column Length (column: {column}, length: {length}, expected: {expected_length})")]
+    InvalidColumnLength { column: String, length: usize, expected_length: usize },
+    #[error("Invalid Validity Bitmap (column: {column}, length: {length} bytes, size: {size} rows)")]
+    InvalidValidityBitmap { column: String, length: usize, size: usize },
+    #[error("Invalid Parent Rank (auxiliary entity: {parent_column}, row: {row}, parent rank: {parent_rank}, parent size: {parent_size})")]
+    InvalidParentRank { parent_column: String, row: usize, parent_rank: u32, parent_size: usize },
+    #[error("Unknown Schema (schema url: {0})")]
+    UnknownSchema(String),
+    #[error("Schema Mismatch (schema url: {schema_url}, error: {message})")]
+    SchemaMismatch { schema_url: String, message: String },
+    #[error("Invalid Dictionary Index (column: {column}, row: {row}, index: {index}, dictionary size: {dictionary_size})")]
+    InvalidDictionaryIndex { column: String, row: usize, index: u32, dictionary_size: usize },
+    /// Failure of a sink specific to its transport or storage (e.g. `grpc::GrpcError`, `wal::WalError`).
+    #[error("Sink Error (error: {0})")]
+    SinkError(Box<dyn std::error::Error + Send + Sync>),
 }
 
 pub trait OpenTelemetryEvent {
@@ -55,6 +124,8 @@ pub trait OpenTelemetryEvent {
     fn bytes_columns(_batch_policy: &BatchPolicy) -> Vec<BytesColumn> { Vec::with_capacity(0) }
     fn int64_summary_columns(_batch_policy: &BatchPolicy) -> Vec<Int64SummaryColumn> { Vec::with_capacity(0) }
     fn double_summary_columns(_batch_policy: &BatchPolicy) -> Vec<DoubleSummaryColumn> { Vec::with_capacity(0) }
+    fn histogram_columns(_batch_policy: &BatchPolicy) -> Vec<HistogramColumn> { Vec::with_capacity(0) }
+    fn exponential_histogram_columns(_batch_policy: &BatchPolicy) -> Vec<ExponentialHistogramColumn> { Vec::with_capacity(0) }
     fn auxiliary_entities(_batch_policy: &BatchPolicy) -> Vec<AuxiliaryEntity> where Self: Sized { Vec::with_capacity(0) }
     fn record_into(self, handler: &mut EventBatchHandler<Self>) where Self: Sized;
 
@@ -68,6 +139,8 @@ pub trait OpenTelemetryEvent {
             is_monotonic: false,
             values: Vec::with_capacity(batch_policy.max_size as usize),
             validity_bitmap: vec![],
+            encoding: 0,
+            encoded_values: vec![],
         }
     }
 
@@ -81,6 +154,8 @@ pub trait OpenTelemetryEvent {
             is_monotonic: false,
             values: Vec::with_capacity(batch_policy.max_size as usize),
             validity_bitmap: validity_bitmap(batch_policy.max_size as usize),
+            encoding: 0,
+            encoded_values: vec![],
         }
     }
 
@@ -104,6 +179,8 @@ pub trait OpenTelemetryEvent {
             description: "".to_string(),
             values: Vec::with_capacity(batch_policy.max_size as usize),
             validity_bitmap: vec![],
+            dictionary: vec![],
+            dictionary_indices: vec![],
         }
     }
 
@@ -114,14 +191,47 @@ pub trait OpenTelemetryEvent {
             description: "".to_string(),
             values: Vec::with_capacity(batch_policy.max_size as usize),
             validity_bitmap: validity_bitmap(batch_policy.max_size as usize),
+            dictionary: vec![],
+            dictionary_indices: vec![],
         }
     }
 }
 
+/// An entity attached to an event with a one to many relationship (e.g. the events or the links of a span).
+/// Usually implemented via `#[derive(OpenTelemetryAuxiliaryEntity)]` and referenced by a `Vec<T>` field of an event.
+pub trait OpenTelemetryAuxiliaryEntity {
+    fn auxiliary_entity(parent_column: &str, logical_type: AuxiliaryEntityLogicalType, batch_policy: &BatchPolicy) -> AuxiliaryEntity;
+    fn record_into_auxiliary_entity(self, auxiliary_entity: &mut AuxiliaryEntity, parent_rank: u32);
+}
+
+/// A timestamp convertible into a number of nanoseconds since the unix epoch.
+pub trait UnixNano {
+    fn unix_nano(&self) -> u64;
+}
+
+impl UnixNano for u64 {
+    fn unix_nano(&self) -> u64 {
+        *self
+    }
+}
+
+impl UnixNano for i64 {
+    fn unix_nano(&self) -> u64 {
+        *self as u64
+    }
+}
+
+impl<Tz: TimeZone> UnixNano for DateTime<Tz> {
+    fn unix_nano(&self) -> u64 {
+        self.timestamp_nanos_opt().unwrap_or_default() as u64
+    }
+}
+
 pub trait OpenTelemetryArrowEvent {
     fn urn() -> String;
     fn arrow_schema(_batch_policy: &BatchPolicy) -> Schema;
-    fn record_into(self, handler: &mut ArrowEventBatchHandler<Self>) where Self: Sized;
+    /// Appends the fields of the event to the field builders of the handler (one value per top-level field).
+    fn record_into(self, handler: &mut ArrowEventBatchHandler<Self>) -> Result<(), ArrowError> where Self: Sized;
 }
 
 impl BatchPolicy {
@@ -132,47 +242,112 @@ impl BatchPolicy {
 
 impl<T> EventBatchHandler<T> where T: OpenTelemetryEvent {
     pub fn new(batch_policy: BatchPolicy) -> Self {
+        let resource = Resource::default();
+        let instrumentation_library = default_instrumentation_library();
         EventBatchHandler {
             schema_url: T::urn(),
-            batch_policy: batch_policy.clone(),
+            resource_events: Self::new_resource_events(&resource, &instrumentation_library, &batch_policy),
+            resource,
+            instrumentation_library,
+            batch_policy,
+            oldest_event_time: None,
+            sink: None,
+            time_encoding: Encoding::None,
+            column_encodings: vec![],
+            dynamic_attributes: None,
             phantom_data: PhantomData::default(),
-            resource_events: ResourceEvents {
-                resource: Some(Resource {
-                    attributes: vec![],
-                    dropped_attributes_count: 0,
-                }),
+        }
+    }
+
+    /// Sets the sink receiving the completed batches. Without sink, a completed batch is discarded.
+    pub fn with_sink(mut self, sink: impl EventSink + Send + 'static) -> Self {
+        self.sink = Some(Box::new(sink));
+        self
+    }
+
+    /// Sets the resource of the recorded events (empty by default).
+    pub fn with_resource(mut self, resource: Resource) -> Self {
+        self.resource_events.resource = Some(resource.clone());
+        self.resource = resource;
+        self
+    }
+
+    /// Sets the instrumentation library of the recorded events (`otel-rust` by default).
+    pub fn with_instrumentation_library(mut self, instrumentation_library: InstrumentationLibrary) -> Self {
+        self.resource_events.instrumentation_library_events[0].instrumentation_library = Some(instrumentation_library.clone());
+        self.instrumentation_library = instrumentation_library;
+        self
+    }
+
+    /// Sets the encoding of the start/end time columns of the exported batches.
+    pub fn with_time_encoding(mut self, encoding: Encoding) -> Self {
+        self.time_encoding = encoding;
+        self
+    }
+
+    /// Sets the encoding of an int64 column of the exported batches (e.g. a counter or a sequence number).
+    pub fn with_column_encoding(mut self, column: impl Into<String>, encoding: Encoding) -> Self {
+        self.column_encodings.push((column.into(), encoding));
+        self
+    }
+
+    /// Enables the dynamic schema mode, the attributes recorded with `record_with_attributes` are stored in at most
+    /// `max_columns` typed columns per batch (see `dynamic_attributes`).
+    pub fn with_dynamic_attributes(mut self, max_columns: usize) -> Self {
+        let mut dynamic_attributes = DynamicAttributes::new::<T>(max_columns, self.batch_policy.clone());
+        dynamic_attributes.reset(&mut self.resource_events.instrumentation_library_events[0].batches[0]);
+        self.dynamic_attributes = Some(dynamic_attributes);
+        self
+    }
+
+    fn new_resource_events(resource: &Resource, instrumentation_library: &InstrumentationLibrary, batch_policy: &BatchPolicy) -> ResourceEvents {
+        ResourceEvents {
+                resource: Some(resource.clone()),
                 instrumentation_library_events: vec![
                     InstrumentationLibraryEvents {
-                        instrumentation_library: Some(InstrumentationLibrary { name: "otel-rust".into(), version: "1.0".into() }),
+                        instrumentation_library: Some(instrumentation_library.clone()),
                         batches: vec![
                             BatchEvent {
                                 schema_url: T::urn(),
                                 size: 0,
                                 start_time_unix_nano_column: Vec::with_capacity(batch_policy.max_size as usize),
                                 end_time_unix_nano_column: Vec::with_capacity(batch_policy.max_size as usize),
-                                i64_values: T::int64_columns(&batch_policy),
-                                f64_values: T::double_columns(&batch_policy),
-                                string_values: T::string_columns(&batch_policy),
-                                bool_values: T::bool_columns(&batch_policy),
-                                bytes_values: T::bytes_columns(&batch_policy),
-                                i64_summary_values: T::int64_summary_columns(&batch_policy),
-                                f64_summary_values: T::double_summary_columns(&batch_policy),
-                                auxiliary_entities: T::auxiliary_entities(&batch_policy),
+                                i64_values: T::int64_columns(batch_policy),
+                                f64_values: T::double_columns(batch_policy),
+                                string_values: T::string_columns(batch_policy),
+                                bool_values: T::bool_columns(batch_policy),
+                                bytes_values: T::bytes_columns(batch_policy),
+                                i64_summary_values: T::int64_summary_columns(batch_policy),
+                                f64_summary_values: T::double_summary_columns(batch_policy),
+                                auxiliary_entities: T::auxiliary_entities(batch_policy),
+                                time_encoding: 0,
+                                encoded_start_time_unix_nano_column: vec![],
+                                encoded_end_time_unix_nano_column: vec![],
+                                histogram_values: T::histogram_columns(batch_policy),
+                                exponential_histogram_values: T::exponential_histogram_columns(batch_policy),
                             }
                         ],
                         dropped_events_count: 0,
+                        schema_url: "".into(),
                     }
                 ],
                 schema_url: "".into(),
-            },
-        }
+            }
     }
 
-    pub fn to_json_value(&self) -> Value {
+    /// Renders the events as JSON objects, the delta-encoded columns are decoded first.
+    pub fn to_json_value(&self) -> Result<Value, Error> {
         let mut values = vec![];
 
         for instrumentation_library_event in &self.resource_events.instrumentation_library_events {
             for batch_event in &instrumentation_library_event.batches {
+                let batch_event = if batch_event.is_delta_encoded() {
+                    let mut decoded_batch_event = batch_event.clone();
+                    decoded_batch_event.decode_delta_columns()?;
+                    Cow::Owned(decoded_batch_event)
+                } else {
+                    Cow::Borrowed(batch_event)
+                };
                 let first_event_rank = values.len();
 
                 for i in 0..batch_event.size as usize {
@@ -186,6 +361,8 @@ impl<T> EventBatchHandler<T> where T: OpenTelemetryEvent {
                     Self::insert_f64_values(&mut json_object, &batch_event.f64_values, i);
                     Self::insert_string_values(&mut json_object, &batch_event.string_values, i);
                     Self::insert_bool_values(&mut json_object, &batch_event.bool_values, i);
+                    Self::insert_histogram_values(&mut json_object, &batch_event.histogram_values, &batch_event.exponential_histogram_values, i);
+                    Self::insert_summary_values(&mut json_object, &batch_event.i64_summary_values, &batch_event.f64_summary_values, i);
 
                     values.push(Value::Object(json_object));
                 }
@@ -203,6 +380,8 @@ impl<T> EventBatchHandler<T> where T: OpenTelemetryEvent {
                                 Self::insert_f64_values(&mut json_object, &auxiliary_entity.f64_values, j);
                                 Self::insert_string_values(&mut json_object, &auxiliary_entity.string_values, j);
                                 Self::insert_bool_values(&mut json_object, &auxiliary_entity.bool_values, j);
+                                Self::insert_histogram_values(&mut json_object, &auxiliary_entity.histogram_values, &auxiliary_entity.exponential_histogram_values, j);
+                                Self::insert_summary_values(&mut json_object, &auxiliary_entity.i64_summary_values, &auxiliary_entity.f64_summary_values, j);
 
                                 auxiliary_values.push(Value::Object(json_object));
                             } else {
@@ -217,6 +396,8 @@ impl<T> EventBatchHandler<T> where T: OpenTelemetryEvent {
                                 Self::insert_f64_values(&mut json_object, &auxiliary_entity.f64_values, j);
                                 Self::insert_string_values(&mut json_object, &auxiliary_entity.string_values, j);
                                 Self::insert_bool_values(&mut json_object, &auxiliary_entity.bool_values, j);
+                                Self::insert_histogram_values(&mut json_object, &auxiliary_entity.histogram_values, &auxiliary_entity.exponential_histogram_values, j);
+                                Self::insert_summary_values(&mut json_object, &auxiliary_entity.i64_summary_values, &auxiliary_entity.f64_summary_values, j);
 
                                 auxiliary_values.push(Value::Object(json_object));
                             }
@@ -231,7 +412,7 @@ impl<T> EventBatchHandler<T> where T: OpenTelemetryEvent {
             }
         }
 
-        Value::Array(values)
+        Ok(Value::Array(values))
     }
 
     fn insert_i64_values(json_object: &mut Map<String, Value>, i64_values: &[Int64Column], rank: usize) {
@@ -256,7 +437,9 @@ impl<T> EventBatchHandler<T> where T: OpenTelemetryEvent {
     fn insert_string_values(json_object: &mut Map<String, Value>, string_values: &[StringColumn], rank: usize) {
         for column in string_values {
             if column.validity_bitmap.is_empty() || is_valid_value(&column.validity_bitmap, rank) {
-                json_object.insert(column.name.clone(), Value::String(column.values[rank].clone()));
+                if let Some(value) = column.value(rank) {
+                    json_object.insert(column.name.clone(), Value::String(value.to_string()));
+                }
             }
         }
     }
@@ -268,139 +451,598 @@ impl<T> EventBatchHandler<T> where T: OpenTelemetryEvent {
             }
         }
     }
+
+    fn insert_histogram_values(json_object: &mut Map<String, Value>, histogram_values: &[HistogramColumn], exponential_histogram_values: &[ExponentialHistogramColumn], rank: usize) {
+        for column in histogram_values {
+            if let Some(histogram) = column.value(rank) {
+                json_object.insert(column.name.clone(), histogram.to_json_value());
+            }
+        }
+        for column in exponential_histogram_values {
+            if let Some(histogram) = column.value(rank) {
+                json_object.insert(column.name.clone(), histogram.to_json_value());
+            }
+        }
+    }
+
+    fn insert_summary_values(json_object: &mut Map<String, Value>, i64_summary_values: &[Int64SummaryColumn], f64_summary_values: &[DoubleSummaryColumn], rank: usize) {
+        for column in i64_summary_values {
+            if let Some(summary) = column.value(rank) {
+                json_object.insert(column.name.clone(), summary.to_json_value());
+            }
+        }
+        for column in f64_summary_values {
+            if let Some(summary) = column.value(rank) {
+                json_object.insert(column.name.clone(), summary.to_json_value());
+            }
+        }
+    }
 }
 
 impl EventCollector {
     pub fn new(batch_policy: BatchPolicy) -> Self {
         EventCollector {
             default_batch_policy: batch_policy,
+            resource: Resource::default(),
+            instrumentation_library: default_instrumentation_library(),
+            shard_count: std::thread::available_parallelism().map_or(1, |parallelism| parallelism.get()),
+            max_pending_events: usize::MAX,
+            state: Arc::default(),
         }
     }
 
+    /// Sets the default resource of the handlers created by this collector.
+    pub fn with_resource(mut self, resource: Resource) -> Self {
+        self.resource = resource;
+        self
+    }
+
+    /// Sets the default instrumentation library of the handlers created by this collector.
+    pub fn with_instrumentation_library(mut self, instrumentation_library: InstrumentationLibrary) -> Self {
+        self.instrumentation_library = instrumentation_library;
+        self
+    }
+
     pub fn event_handler<T: OpenTelemetryEvent>(&self) -> EventBatchHandler<T> {
         EventBatchHandler::new(self.default_batch_policy.clone())
+            .with_resource(self.resource.clone())
+            .with_instrumentation_library(self.instrumentation_library.clone())
     }
 
-    pub fn arrow_event_handler<T: OpenTelemetryArrowEvent>(&self) -> ArrowEventBatchHandler<T> {
-        ArrowEventBatchHandler {
-            schema_url: T::urn(),
-            batch_policy: self.default_batch_policy.clone(),
-            phantom_data: PhantomData::default(),
-            resource_events: arrow_events::ResourceEvents {
-                resource: Some(Resource {
-                    attributes: vec![],
-                    dropped_attributes_count: 0,
-                }),
-                instrumentation_library_events: vec![
-                    arrow_events::InstrumentationLibraryEvents {
-                        instrumentation_library: Some(InstrumentationLibrary { name: "otel-rust".into(), version: "1.0".into() }),
-                        batches: vec![
-                            arrow_events::BatchEvent {
-                                schema_url: T::urn(),
-                                size: 0,
-                                arrow_buffer: vec![],
-                            }
-                        ],
-                        dropped_events_count: 0,
-                    }
-                ],
-                schema_url: "".into(),
-            },
-            arrow_schema: T::arrow_schema(&self.default_batch_policy),
-        }
+    /// Fails if the arrow schema of the events contains an unsupported data type.
+    pub fn arrow_event_handler<T: OpenTelemetryArrowEvent>(&self) -> Result<ArrowEventBatchHandler<T>, Error> {
+        Ok(ArrowEventBatchHandler::new(self.default_batch_policy.clone())?
+            .with_resource(self.resource.clone())
+            .with_instrumentation_library(self.instrumentation_library.clone()))
     }
 }
 
+fn default_instrumentation_library() -> InstrumentationLibrary {
+    InstrumentationLibrary { name: "otel-rust".into(), version: "1.0".into() }
+}
+
 impl<T: OpenTelemetryEvent> EventBatchHandler<T> {
+    /// Records an event. With a sink, the batch is flushed as soon as it contains `max_size` events. The event is
+    /// kept if this flush fails, the batch is then flushed again by the next `record` (the new event is not recorded
+    /// if this flush fails too) or `flush`. Without a sink, a full batch is dropped when the next event is recorded.
     pub fn record(&mut self, event: T) -> Result<(), Error> {
-        if self.resource_events.instrumentation_library_events[0].batches[0].size == self.batch_policy.max_size {
-            self.reset_batch_event();
+        self.record_event(event, vec![])
+    }
+
+    /// Records an event and its attributes, fails if the dynamic schema mode is not enabled.
+    pub fn record_with_attributes(&mut self, event: T, attributes: Vec<KeyValue>) -> Result<(), Error> {
+        if self.dynamic_attributes.is_none() {
+            return Err(Error::InvalidFormat("dynamic attributes are not enabled".into()));
+        }
+        self.record_event(event, to_attributes(attributes)?)
+    }
+
+    fn record_event(&mut self, event: T, attributes: Vec<Attribute>) -> Result<(), Error> {
+        if self.pending_events() >= self.batch_policy.max_size as usize {
+            self.flush()?;
         }
 
+        self.append_event(event, attributes);
+
+        if self.sink.is_some() && self.pending_events() >= self.batch_policy.max_size as usize {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Adds an event to the current batch, whatever its size.
+    pub(crate) fn append_event(&mut self, event: T, attributes: Vec<Attribute>) {
+        if self.oldest_event_time.is_none() {
+            self.oldest_event_time = Some(Utc::now());
+        }
         event.record_into(self);
+        if let Some(dynamic_attributes) = self.dynamic_attributes.as_mut() {
+            dynamic_attributes.record(&mut self.resource_events.instrumentation_library_events[0].batches[0], attributes);
+        }
+    }
+
+    /// Flushes the current batch if its oldest event has been buffered for at least `max_delay`. This method is
+    /// expected to be called periodically, `now` is a parameter to make the time-based flushing deterministic.
+    /// Returns true if the batch has been flushed.
+    pub fn tick(&mut self, now: DateTime<Utc>) -> Result<bool, Error> {
+        match self.oldest_event_time {
+            Some(oldest_event_time) if now - oldest_event_time >= self.batch_policy.max_delay => {
+                self.flush()?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Emits the buffered events to the sink (if any) and starts a new batch. Nothing is emitted if the current
+    /// batch is empty. The current batch is kept if the sink fails, so that a later flush can emit it again.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if self.resource_events.instrumentation_library_events[0].batches[0].size == 0 {
+            return Ok(());
+        }
+
+        if self.sink.is_some() {
+            let resource_events = self.encode_resource_events(self.resource_events.clone());
+            if let Some(sink) = self.sink.as_mut() {
+                sink.export_events(resource_events)?;
+            }
+        }
+        self.reset_batch_event();
         Ok(())
     }
 
+    /// Returns the buffered events, encoded as they would be exported, and starts a new batch. Returns `None` if the
+    /// current batch is empty.
+    pub fn take_resource_events(&mut self) -> Option<ResourceEvents> {
+        if self.resource_events.instrumentation_library_events[0].batches[0].size == 0 {
+            return None;
+        }
+
+        self.oldest_event_time = None;
+        let new_resource_events = Self::new_resource_events(&self.resource, &self.instrumentation_library, &self.batch_policy);
+        let resource_events = std::mem::replace(&mut self.resource_events, new_resource_events);
+        if let Some(dynamic_attributes) = self.dynamic_attributes.as_mut() {
+            dynamic_attributes.reset(&mut self.resource_events.instrumentation_library_events[0].batches[0]);
+        }
+        Some(self.encode_resource_events(resource_events))
+    }
+
+    /// Encodes the dictionaries, the timestamps and the configured columns of buffered events.
+    fn encode_resource_events(&self, mut resource_events: ResourceEvents) -> ResourceEvents {
+        resource_events.encode_dictionaries();
+        let batch = &mut resource_events.instrumentation_library_events[0].batches[0];
+        batch.encode_timestamps(self.time_encoding);
+        for (name, encoding) in &self.column_encodings {
+            if let Some(column) = batch.i64_values.iter_mut().find(|column| &column.name == name) {
+                column.encode_values(*encoding);
+            }
+        }
+        resource_events
+    }
+
+    /// Returns the number of events of the current batch.
+    pub fn pending_events(&self) -> usize {
+        self.resource_events.instrumentation_library_events[0].batches[0].size as usize
+    }
+
+    /// Returns the time at which the oldest buffered event has been recorded.
+    pub fn oldest_event_time(&self) -> Option<DateTime<Utc>> {
+        self.oldest_event_time
+    }
+
     pub fn serialize(&self) -> Result<Vec<u8>, EncodeError> {
         let mut buf: Vec<u8> = Vec::new();
         self.resource_events.encode(&mut buf)?;
         Ok(buf)
     }
 
-    pub fn deserialize(&mut self, buf: Vec<u8>) {
-        self.resource_events = ResourceEvents::decode(Bytes::from(buf)).unwrap();
+    /// Decodes and validates a serialized `ResourceEvents`, the delta-encoded columns are decoded. The current events
+    /// are left untouched on error.
+    pub fn deserialize(&mut self, buf: Vec<u8>) -> Result<(), Error> {
+        let mut resource_events = ResourceEvents::decode(Bytes::from(buf))?;
+        resource_events.validate()?;
+        resource_events.decode_delta_columns()?;
+        self.resource_events = resource_events;
+        Ok(())
     }
 
     pub fn reset_batch_event(&mut self) {
+        self.oldest_event_time = None;
         let batch = &mut self.resource_events.instrumentation_library_events[0].batches[0];
+        batch.clear();
+
+        if let Some(dynamic_attributes) = self.dynamic_attributes.as_mut() {
+            dynamic_attributes.reset(batch);
+        }
+    }
+}
+
+impl BatchEvent {
+    /// Removes all the events of the batch. The columns and the auxiliary entities are kept with their allocated
+    /// capacity.
+    pub fn clear(&mut self) {
+        self.size = 0;
+        self.start_time_unix_nano_column.clear();
+        self.end_time_unix_nano_column.clear();
+        self.time_encoding = Encoding::None as i32;
+        self.encoded_start_time_unix_nano_column.clear();
+        self.encoded_end_time_unix_nano_column.clear();
 
-        batch.start_time_unix_nano_column.clear();
-        batch.end_time_unix_nano_column.clear();
+        self.i64_values.iter_mut().for_each(Int64Column::clear);
+        self.f64_values.iter_mut().for_each(DoubleColumn::clear);
+        self.string_values.iter_mut().for_each(StringColumn::clear);
+        self.bool_values.iter_mut().for_each(BoolColumn::clear);
+        self.bytes_values.iter_mut().for_each(BytesColumn::clear);
+        self.i64_summary_values.iter_mut().for_each(Int64SummaryColumn::clear);
+        self.f64_summary_values.iter_mut().for_each(DoubleSummaryColumn::clear);
+        self.histogram_values.iter_mut().for_each(HistogramColumn::clear);
+        self.exponential_histogram_values.iter_mut().for_each(ExponentialHistogramColumn::clear);
+        self.auxiliary_entities.iter_mut().for_each(AuxiliaryEntity::clear);
+    }
+}
+
+impl AuxiliaryEntity {
+    /// Removes all the rows of the auxiliary entity, keeping the columns and their allocated capacity.
+    pub fn clear(&mut self) {
+        self.size = 0;
+        self.parent_ranks.clear();
+
+        self.i64_values.iter_mut().for_each(Int64Column::clear);
+        self.f64_values.iter_mut().for_each(DoubleColumn::clear);
+        self.string_values.iter_mut().for_each(StringColumn::clear);
+        self.bool_values.iter_mut().for_each(BoolColumn::clear);
+        self.bytes_values.iter_mut().for_each(BytesColumn::clear);
+        self.i64_summary_values.iter_mut().for_each(Int64SummaryColumn::clear);
+        self.f64_summary_values.iter_mut().for_each(DoubleSummaryColumn::clear);
+        self.histogram_values.iter_mut().for_each(HistogramColumn::clear);
+        self.exponential_histogram_values.iter_mut().for_each(ExponentialHistogramColumn::clear);
+    }
+}
 
-        for column in batch.i64_values.iter_mut() {
-            column.values.clear();
-            reset_validity_bitmap(&mut column.validity_bitmap);
+impl Int64Column {
+    /// Removes all the values, a delta encoded column goes back to plain values.
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.encoding = Encoding::None as i32;
+        self.encoded_values.clear();
+        reset_validity_bitmap(&mut self.validity_bitmap);
+    }
+}
+
+impl DoubleColumn {
+    pub fn clear(&mut self) {
+        self.values.clear();
+        reset_validity_bitmap(&mut self.validity_bitmap);
+    }
+}
+
+impl StringColumn {
+    /// Removes all the values, a dictionary encoded column goes back to plain values.
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.dictionary.clear();
+        self.dictionary_indices.clear();
+        reset_validity_bitmap(&mut self.validity_bitmap);
+    }
+}
+
+impl BoolColumn {
+    pub fn clear(&mut self) {
+        self.values.clear();
+        reset_validity_bitmap(&mut self.validity_bitmap);
+    }
+}
+
+impl BytesColumn {
+    pub fn clear(&mut self) {
+        self.values.clear();
+        reset_validity_bitmap(&mut self.validity_bitmap);
+    }
+}
+
+impl<T: OpenTelemetryArrowEvent> ArrowEventBatchHandler<T> {
+    /// Fails if the arrow schema of the events contains an unsupported data type (see `new_array_builder`).
+    pub fn new(batch_policy: BatchPolicy) -> Result<Self, Error> {
+        let arrow_schema = T::arrow_schema(&batch_policy);
+
+        Ok(ArrowEventBatchHandler {
+            schema_url: T::urn(),
+            builder: new_struct_builder(arrow_schema.fields(), batch_policy.max_size as usize)?,
+            complete_rows: vec![],
+            arrow_schema,
+            batch_policy,
+            resource: Resource::default(),
+            instrumentation_library: default_instrumentation_library(),
+            sink: None,
+            phantom_data: PhantomData,
+            resource_events: Self::new_resource_events(&Resource::default(), &default_instrumentation_library()),
+        })
+    }
+
+    /// Sets the sink receiving the encoded batches. Without sink, the last encoded batch is kept in
+    /// `resource_events` until the next flush.
+    pub fn with_sink(mut self, sink: impl EventSink + 'static) -> Self {
+        self.sink = Some(Box::new(sink));
+        self
+    }
+
+    /// Sets the resource of the recorded events (empty by default).
+    pub fn with_resource(mut self, resource: Resource) -> Self {
+        self.resource_events.resource = Some(resource.clone());
+        self.resource = resource;
+        self
+    }
+
+    /// Sets the instrumentation library of the recorded events (`otel-rust` by default).
+    pub fn with_instrumentation_library(mut self, instrumentation_library: InstrumentationLibrary) -> Self {
+        self.resource_events.instrumentation_library_events[0].instrumentation_library = Some(instrumentation_library.clone());
+        self.instrumentation_library = instrumentation_library;
+        self
+    }
+
+    fn new_resource_events(resource: &Resource, instrumentation_library: &InstrumentationLibrary) -> arrow_events::ResourceEvents {
+        arrow_events::ResourceEvents {
+            resource: Some(resource.clone()),
+            instrumentation_library_events: vec![
+                arrow_events::InstrumentationLibraryEvents {
+                    instrumentation_library: Some(instrumentation_library.clone()),
+                    batches: vec![
+                        arrow_events::BatchEvent {
+                            schema_url: T::urn(),
+                            size: 0,
+                            arrow_buffer: vec![],
+                        }
+                    ],
+                    dropped_events_count: 0,
+                }
+            ],
+            schema_url: "".into(),
         }
+    }
 
-        for column in batch.f64_values.iter_mut() {
-            column.values.clear();
-            reset_validity_bitmap(&mut column.validity_bitmap);
+    /// Appends the event to the array builders. The pending rows are encoded into the arrow buffer of the batch
+    /// as soon as the max size of the batch policy is reached. The event is rejected if a full batch can't be
+    /// exported. The values of an event failing partway through are discarded, the previous rows are kept.
+    pub fn record(&mut self, event: T) -> Result<(), Error> {
+        if self.pending_events() >= self.batch_policy.max_size as usize {
+            self.flush()?;
         }
 
-        for column in batch.string_values.iter_mut() {
-            column.values.clear();
-            reset_validity_bitmap(&mut column.validity_bitmap);
+        if let Err(err) = event.record_into(self) {
+            self.discard_partial_row()?;
+            return Err(err.into());
         }
+        self.builder.append(true)?;
 
-        batch.size = 0;
+        if self.pending_rows() >= self.batch_policy.max_size as usize {
+            self.flush()?;
+        }
+        Ok(())
+    }
 
-        for auxiliary_entity in batch.auxiliary_entities.iter_mut() {
-            auxiliary_entity.parent_ranks.clear();
+    /// Returns the builder of the nth top-level field of the arrow schema.
+    pub fn field_builder<B: ArrayBuilder>(&mut self, i: usize) -> Option<&mut B> {
+        self.builder.field_builder(i)
+    }
 
-            for column in auxiliary_entity.i64_values.iter_mut() {
-                column.values.clear();
-                reset_validity_bitmap(&mut column.validity_bitmap);
-            }
+    /// Returns the number of events not exported yet: the events recorded since the last flush and, with a sink,
+    /// the events of the batch rejected by the sink.
+    pub fn pending_events(&self) -> usize {
+        match self.sink {
+            Some(_) => self.pending_rows() + self.resource_events.instrumentation_library_events[0].batches[0].size as usize,
+            None => self.pending_rows(),
+        }
+    }
 
-            for column in auxiliary_entity.f64_values.iter_mut() {
-                column.values.clear();
-                reset_validity_bitmap(&mut column.validity_bitmap);
-            }
+    /// Returns the number of rows recorded since the last flush.
+    fn pending_rows(&self) -> usize {
+        self.builder.len() + self.complete_rows.iter().map(|record_batch| record_batch.num_rows()).sum::<usize>()
+    }
+
+    /// Resets the builder after an event failing partway through, its child builders may contain a different number
+    /// of values. The complete rows are copied into a record batch.
+    fn discard_partial_row(&mut self) -> Result<(), Error> {
+        let rows = self.builder.len();
+        let struct_array = self.builder.finish();
+        if rows > 0 {
+            let columns = struct_array.columns().into_iter()
+                .map(|column| {
+                    let mut data = MutableArrayData::new(vec![column.data()], false, rows);
+                    data.extend(0, 0, rows);
+                    make_array(data.freeze())
+                })
+                .collect();
+            let record_batch = self.new_record_batch(columns)?;
+            self.complete_rows.push(record_batch);
+        }
+        Ok(())
+    }
 
-            for column in auxiliary_entity.string_values.iter_mut() {
-                column.values.clear();
-                reset_validity_bitmap(&mut column.validity_bitmap);
+    fn new_record_batch(&self, columns: Vec<ArrayRef>) -> Result<RecordBatch, Error> {
+        // List builders always name their item field "item", so the field names of the schema are not enforced.
+        Ok(RecordBatch::try_new_with_options(
+            Arc::new(self.arrow_schema.clone()),
+            columns,
+            &RecordBatchOptions { match_field_names: false },
+        )?)
+    }
+
+    /// Encodes the pending events as an Arrow IPC stream into the arrow buffer of the batch (the previous content
+    /// of the buffer is replaced) and emits the batch to the sink (if any). Nothing is done if there is no pending
+    /// event. A batch rejected by the sink is kept, and emitted again by the next flush before the events recorded
+    /// in the meantime.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if self.sink.is_some() && self.resource_events.instrumentation_library_events[0].batches[0].size > 0 {
+            self.export_batch()?;
+        }
+        if self.pending_rows() == 0 {
+            return Ok(());
+        }
+
+        let mut record_batches = std::mem::take(&mut self.complete_rows);
+        if self.builder.len() > 0 {
+            let struct_array = self.builder.finish();
+            record_batches.push(self.new_record_batch(struct_array.columns().into_iter().cloned().collect())?);
+        }
+
+        let batch = &mut self.resource_events.instrumentation_library_events[0].batches[0];
+        batch.arrow_buffer.clear();
+        {
+            let mut writer = StreamWriter::try_new(&mut batch.arrow_buffer, &self.arrow_schema)?;
+            for record_batch in &record_batches {
+                writer.write(record_batch)?;
             }
+            writer.finish()?;
+        }
+        batch.size = record_batches.iter().map(|record_batch| record_batch.num_rows() as u32).sum();
+
+        self.export_batch()
+    }
+
+    /// Emits the encoded batch to the sink (if any). The batch is replaced by an empty batch of the resource and
+    /// instrumentation library of the handler only once accepted by the sink.
+    fn export_batch(&mut self) -> Result<(), Error> {
+        if let Some(sink) = self.sink.as_mut() {
+            sink.export_arrow_events(self.resource_events.clone())?;
+            self.resource_events = Self::new_resource_events(&self.resource, &self.instrumentation_library);
+        }
+        Ok(())
+    }
 
-            auxiliary_entity.size = 0;
+    /// Decodes the record batches of every batch event owned by this handler.
+    pub fn record_batches(&self) -> Result<Vec<RecordBatch>, Error> {
+        let mut record_batches = vec![];
+
+        for instrumentation_library_event in &self.resource_events.instrumentation_library_events {
+            for batch_event in &instrumentation_library_event.batches {
+                record_batches.extend(decode_record_batches(batch_event)?);
+            }
         }
 
-        let attributes = &mut batch.auxiliary_entities[0];
-        attributes.parent_ranks.clear();
-        attributes.string_values[0].values.clear();
-        attributes.string_values[1].values.clear();
-        attributes.size = 0;
+        Ok(record_batches)
     }
-}
 
-impl<T: OpenTelemetryArrowEvent> ArrowEventBatchHandler<T> {
-    pub fn record(&mut self, event: T) -> Result<(), Error> {
-        event.record_into(self);
+    pub fn serialize(&self) -> Result<Vec<u8>, EncodeError> {
+        let mut buf: Vec<u8> = Vec::new();
+        self.resource_events.encode(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Decodes a serialized `ResourceEvents`, the current events are left untouched on error.
+    pub fn deserialize(&mut self, buf: Vec<u8>) -> Result<(), Error> {
+        self.resource_events = arrow_events::ResourceEvents::decode(Bytes::from(buf))?;
         Ok(())
     }
 }
 
+/// Decodes the Arrow IPC stream of a batch event. An empty buffer returns no record batch.
+pub fn decode_record_batches(batch_event: &arrow_events::BatchEvent) -> Result<Vec<RecordBatch>, Error> {
+    if batch_event.arrow_buffer.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let reader = StreamReader::try_new(batch_event.arrow_buffer.as_slice())?;
+    let record_batches = reader.collect::<Result<Vec<RecordBatch>, ArrowError>>()?;
+    Ok(record_batches)
+}
+
+/// Creates a struct builder for the given fields. Supports the primitive, string, binary, struct and list fields
+/// (list of struct, string, int64, float64 and boolean), fails on any other data type.
+pub fn new_struct_builder(fields: &[Field], capacity: usize) -> Result<StructBuilder, Error> {
+    Ok(StructBuilder::new(
+        fields.to_vec(),
+        fields.iter().map(|field| new_array_builder(field.data_type(), capacity)).collect::<Result<_, _>>()?,
+    ))
+}
+
+pub fn new_array_builder(data_type: &DataType, capacity: usize) -> Result<Box<dyn ArrayBuilder>, Error> {
+    let builder: Box<dyn ArrayBuilder> = match data_type {
+        DataType::Struct(fields) => Box::new(new_struct_builder(fields, capacity)?),
+        DataType::List(field) => match field.data_type() {
+            DataType::Struct(fields) => Box::new(ListBuilder::with_capacity(new_struct_builder(fields, capacity)?, capacity)),
+            DataType::Utf8 => Box::new(ListBuilder::with_capacity(StringBuilder::new(capacity), capacity)),
+            DataType::Int64 => Box::new(ListBuilder::with_capacity(Int64Builder::new(capacity), capacity)),
+            DataType::Float64 => Box::new(ListBuilder::with_capacity(Float64Builder::new(capacity), capacity)),
+            DataType::Boolean => Box::new(ListBuilder::with_capacity(BooleanBuilder::new(capacity), capacity)),
+            data_type => return Err(Error::InvalidFormat(format!("list of {:?} is not supported", data_type))),
+        },
+        DataType::Boolean => Box::new(BooleanBuilder::new(capacity)),
+        DataType::Int8 => Box::new(Int8Builder::new(capacity)),
+        DataType::Int16 => Box::new(Int16Builder::new(capacity)),
+        DataType::Int32 => Box::new(Int32Builder::new(capacity)),
+        DataType::Int64 => Box::new(Int64Builder::new(capacity)),
+        DataType::UInt8 => Box::new(UInt8Builder::new(capacity)),
+        DataType::UInt16 => Box::new(UInt16Builder::new(capacity)),
+        DataType::UInt32 => Box::new(UInt32Builder::new(capacity)),
+        DataType::UInt64 => Box::new(UInt64Builder::new(capacity)),
+        DataType::Float32 => Box::new(Float32Builder::new(capacity)),
+        DataType::Float64 => Box::new(Float64Builder::new(capacity)),
+        DataType::Utf8 => Box::new(StringBuilder::new(capacity)),
+        DataType::Binary => Box::new(BinaryBuilder::new(capacity)),
+        DataType::Timestamp(TimeUnit::Nanosecond, _) => Box::new(TimestampNanosecondBuilder::new(capacity)),
+        data_type => return Err(Error::InvalidFormat(format!("data type {:?} is not supported", data_type))),
+    };
+    Ok(builder)
+}
+
+/// Builds a single batch containing all the events, i.e. a batch without size limit nor sink.
+pub fn to_batch_event<T: OpenTelemetryEvent>(events: Vec<T>) -> Result<BatchEvent, Error> {
+    let batch_policy = BatchPolicy::new(events.len().max(1) as u32, chrono::Duration::MAX);
+    let mut handler = EventBatchHandler::<T>::new(batch_policy);
+    for event in events {
+        handler.record(event)?;
+    }
+
+    let mut batch = handler.resource_events.instrumentation_library_events.swap_remove(0).batches.swap_remove(0);
+    batch.encode_dictionaries();
+    Ok(batch)
+}
+
 /// Note: This invariant nth_bit/8 < bytes.len() is enforced by design (code generated by the macro).
 #[inline(always)]
 pub fn set_nth_bit(validity_bitmap: &mut Vec<u8>, nth_bit: usize) {
     validity_bitmap[nth_bit / 8] |= 1 << (nth_bit % 8);
 }
 
+/// Same as `set_nth_bit` for validity bitmaps not bounded by the batch policy (e.g. auxiliary entities).
+#[inline(always)]
+pub fn grow_and_set_nth_bit(validity_bitmap: &mut Vec<u8>, nth_bit: usize) {
+    if nth_bit / 8 >= validity_bitmap.len() {
+        validity_bitmap.resize(nth_bit / 8 + 1, 0);
+    }
+    set_nth_bit(validity_bitmap, nth_bit);
+}
+
 #[inline(always)]
 pub fn is_valid_value(validity_bitmap: &Vec<u8>, nth_bit: usize) -> bool {
     validity_bitmap[nth_bit / 8] & (1 << (nth_bit % 8)) > 0
 }
 
+/// Updates a validity bitmap for a new value. An empty validity bitmap means that all the values are defined, so it is
+/// only materialized with the first null.
+pub fn push_validity(validity_bitmap: &mut Vec<u8>, nth_bit: usize, is_defined: bool) {
+    if is_defined {
+        if !validity_bitmap.is_empty() {
+            grow_and_set_nth_bit(validity_bitmap, nth_bit);
+        }
+        return;
+    }
+    if validity_bitmap.is_empty() {
+        for previous_bit in 0..nth_bit {
+            grow_and_set_nth_bit(validity_bitmap, previous_bit);
+        }
+    }
+    if nth_bit / 8 >= validity_bitmap.len() {
+        validity_bitmap.resize(nth_bit / 8 + 1, 0);
+    }
+}
+
+/// Same as `is_valid_value` for a possibly empty or short validity bitmap.
+#[inline(always)]
+pub fn is_defined(validity_bitmap: &[u8], nth_bit: usize) -> bool {
+    validity_bitmap.is_empty() || validity_bitmap.get(nth_bit / 8).is_some_and(|byte| byte & (1 << (nth_bit % 8)) != 0)
+}
+
 #[inline(always)]
 pub fn validity_bitmap(size: usize) -> Vec<u8> {
     vec![0; size/8 + if size%8 > 0 { 1} else {0}]
@@ -413,9 +1055,521 @@ pub fn reset_validity_bitmap(validity_bitmap: &mut Vec<u8>) {
     }
 }
 
+#[inline(always)]
+pub fn unix_nano_now() -> u64 {
+    Utc::now().unix_nano()
+}
+
 #[cfg(test)]
 mod test {
-    use crate::event::{set_nth_bit, validity_bitmap, reset_validity_bitmap};
+    use crate::event::{set_nth_bit, validity_bitmap, reset_validity_bitmap, BatchPolicy, EventCollector, EventBatchHandler, OpenTelemetryEvent, OpenTelemetryAuxiliaryEntity};
+    use serde_json::json;
+    use crate::event::{decode_record_batches, new_array_builder, Error, OpenTelemetryArrowEvent, ArrowEventBatchHandler};
+    use arrow::datatypes::{Schema, Field, DataType};
+    use arrow::array::{Array, Int64Builder, StringBuilder, StructBuilder, ListBuilder, Int64Array, StringArray, ListArray, StructArray};
+    use arrow::error::ArrowError;
+    use crate::sink::{MemorySink, ResourceBatch, UnavailableSink};
+    use crate::opentelemetry::proto::events::v1::ResourceEvents;
+    use crate::histogram::Histogram;
+    use crate::opentelemetry::proto::common::v1::{any_value, AnyValue, InstrumentationLibrary, KeyValue};
+    use crate::opentelemetry::proto::resource::v1::Resource;
+
+    #[derive(OpenTelemetryEvent)]
+    #[otel(urn = "urn:test:span")]
+    struct Span {
+        #[otel(start_time)]
+        start_time: u64,
+        #[otel(end_time)]
+        end_time: u64,
+        name: String,
+        #[otel(name = "latency", unit = "ms", logical_type = "gauge")]
+        latency_ms: u32,
+        status_code: Option<i64>,
+        score: f64,
+        sampled: bool,
+        #[otel(logical_type = "trace_event")]
+        events: Vec<SpanEvent>,
+    }
+
+    #[derive(OpenTelemetryAuxiliaryEntity)]
+    struct SpanEvent {
+        name: String,
+        dropped_attributes_count: Option<u32>,
+    }
+
+    struct Request {
+        latency_ms: i64,
+        user: Option<String>,
+        tags: Vec<(String, String)>,
+    }
+
+    impl OpenTelemetryArrowEvent for Request {
+        fn urn() -> String {
+            "urn:test:request".into()
+        }
+
+        fn arrow_schema(_batch_policy: &BatchPolicy) -> Schema {
+            Schema::new(vec![
+                Field::new("latency_ms", DataType::Int64, false),
+                Field::new("user", DataType::Utf8, true),
+                Field::new("tags", DataType::List(Box::new(Field::new(
+                    "tag",
+                    DataType::Struct(vec![
+                        Field::new("name", DataType::Utf8, false),
+                        Field::new("value", DataType::Utf8, false),
+                    ]),
+                    true,
+                ))), false),
+            ])
+        }
+
+        fn record_into(self, handler: &mut ArrowEventBatchHandler<Self>) -> Result<(), ArrowError> {
+            handler.field_builder::<Int64Builder>(0).unwrap().append_value(self.latency_ms)?;
+            handler.field_builder::<StringBuilder>(1).unwrap().append_option(self.user)?;
+            let tags = handler.field_builder::<ListBuilder<StructBuilder>>(2).unwrap();
+            for (name, value) in self.tags {
+                tags.values().field_builder::<StringBuilder>(0).unwrap().append_value(name)?;
+                // Fails partway through the event.
+                if value.is_empty() {
+                    return Err(ArrowError::InvalidArgumentError("empty tag value".into()));
+                }
+                tags.values().field_builder::<StringBuilder>(1).unwrap().append_value(value)?;
+                tags.values().append(true)?;
+            }
+            tags.append(true)
+        }
+    }
+
+    #[test]
+    fn test_arrow_record() {
+        let event_collector = EventCollector::new(BatchPolicy::new(2, chrono::Duration::seconds(10)));
+        let mut handler: ArrowEventBatchHandler<Request> = event_collector.arrow_event_handler().unwrap();
+
+        handler.record(Request { latency_ms: 10, user: Some("user_1".into()), tags: vec![("a".into(), "1".into()), ("b".into(), "2".into())] }).unwrap();
+        assert_eq!(handler.pending_events(), 1);
+        assert!(handler.resource_events.instrumentation_library_events[0].batches[0].arrow_buffer.is_empty());
+        handler.record(Request { latency_ms: 20, user: None, tags: vec![] }).unwrap();
+
+        // The max size of the batch policy is reached, the pending events have been encoded.
+        assert_eq!(handler.pending_events(), 0);
+        let batch = &handler.resource_events.instrumentation_library_events[0].batches[0];
+        assert_eq!(batch.size, 2);
+        assert_eq!(batch.schema_url, "urn:test:request");
+
+        let record_batches = handler.record_batches().unwrap();
+        assert_eq!(record_batches.len(), 1);
+        let record_batch = &record_batches[0];
+        assert_eq!(record_batch.num_rows(), 2);
+        assert_eq!(record_batch.schema().field(2).name(), "tags");
+
+        let latencies = record_batch.column(0).as_any().downcast_ref::<Int64Array>().unwrap();
+        assert_eq!(latencies.values(), &[10, 20]);
+        let users = record_batch.column(1).as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(users.value(0), "user_1");
+        assert!(users.is_null(1));
+        let tags = record_batch.column(2).as_any().downcast_ref::<ListArray>().unwrap();
+        assert_eq!(tags.value_length(0), 2);
+        assert_eq!(tags.value_length(1), 0);
+        let first_tags = tags.value(0);
+        let first_tags = first_tags.as_any().downcast_ref::<StructArray>().unwrap();
+        let tag_values = first_tags.column(1).as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(tag_values.value(1), "2");
+
+        // An explicit flush encodes the remaining events.
+        handler.record(Request { latency_ms: 30, user: None, tags: vec![] }).unwrap();
+        handler.flush().unwrap();
+        let record_batches = handler.record_batches().unwrap();
+        assert_eq!(record_batches[0].num_rows(), 1);
+        assert_eq!(handler.resource_events.instrumentation_library_events[0].batches[0].size, 1);
+
+        // Malformed input and unsupported data types are reported as errors.
+        let serialized_events = handler.serialize().unwrap();
+        assert!(handler.deserialize(vec![0xff]).is_err());
+        handler.deserialize(serialized_events).unwrap();
+        assert_eq!(handler.record_batches().unwrap().len(), 1);
+        assert!(matches!(new_array_builder(&DataType::Date32, 1), Err(Error::InvalidFormat(_))));
+        assert!(matches!(new_array_builder(&DataType::List(Box::new(Field::new("item", DataType::Int8, true))), 1), Err(Error::InvalidFormat(_))));
+    }
+
+    #[test]
+    fn test_arrow_record_failure() {
+        let mut handler = ArrowEventBatchHandler::<Request>::new(BatchPolicy::new(10, chrono::Duration::seconds(10))).unwrap();
+        let tags = |tags: &[(&str, &str)]| tags.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+
+        handler.record(Request { latency_ms: 10, user: Some("user_1".into()), tags: tags(&[("a", "1")]) }).unwrap();
+        handler.record(Request { latency_ms: 20, user: None, tags: vec![] }).unwrap();
+        // The event fails after appending its latency, its user and the name of its second tag.
+        let result = handler.record(Request { latency_ms: 30, user: Some("user_3".into()), tags: tags(&[("b", "2"), ("c", "")]) });
+        assert!(matches!(result, Err(Error::ArrowError(_))));
+        assert_eq!(handler.pending_events(), 2);
+        handler.record(Request { latency_ms: 40, user: None, tags: tags(&[("d", "4")]) }).unwrap();
+        assert_eq!(handler.pending_events(), 3);
+
+        handler.flush().unwrap();
+        assert_eq!(handler.resource_events.instrumentation_library_events[0].batches[0].size, 3);
+        let record_batches = handler.record_batches().unwrap();
+        let latencies: Vec<i64> = record_batches.iter()
+            .flat_map(|record_batch| record_batch.column(0).as_any().downcast_ref::<Int64Array>().unwrap().values().to_vec())
+            .collect();
+        assert_eq!(latencies, vec![10, 20, 40]);
+        let last_batch = record_batches.last().unwrap();
+        let users = last_batch.column(1).as_any().downcast_ref::<StringArray>().unwrap();
+        assert!(users.is_null(0));
+        let tags = last_batch.column(2).as_any().downcast_ref::<ListArray>().unwrap().value(0);
+        let tag_names = tags.as_any().downcast_ref::<StructArray>().unwrap().column(0).as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(tag_names.len(), 1);
+        assert_eq!(tag_names.value(0), "d");
+    }
+
+    fn request(latency_ms: i64) -> Request {
+        Request { latency_ms, user: None, tags: vec![] }
+    }
+
+    fn exported_latencies(sink: &MemorySink) -> Vec<Vec<i64>> {
+        sink.batches().into_iter().map(|batch| match batch {
+            ResourceBatch::ArrowEvents(resource_events) => {
+                let record_batches = decode_record_batches(&resource_events.instrumentation_library_events[0].batches[0]).unwrap();
+                record_batches.iter()
+                    .flat_map(|record_batch| record_batch.column(0).as_any().downcast_ref::<Int64Array>().unwrap().values().to_vec())
+                    .collect()
+            }
+            _ => panic!("unexpected batch type"),
+        }).collect()
+    }
+
+    #[test]
+    fn test_arrow_flush_failure() {
+        let sink = MemorySink::new();
+        let unavailable_sink = UnavailableSink::new(false, sink.clone());
+        let mut handler = ArrowEventBatchHandler::<Request>::new(BatchPolicy::new(2, chrono::Duration::seconds(10))).unwrap()
+            .with_sink(unavailable_sink.clone());
+
+        // The full batch is kept when the sink fails, the next events are rejected.
+        handler.record(request(10)).unwrap();
+        assert!(handler.record(request(20)).is_err());
+        assert_eq!(handler.pending_events(), 2);
+        assert!(handler.record(request(30)).is_err());
+        assert!(handler.flush().is_err());
+        assert_eq!(handler.pending_events(), 2);
+        assert!(sink.is_empty());
+
+        // The batch is exported once the sink is available again.
+        unavailable_sink.set_available(true);
+        handler.record(request(30)).unwrap();
+        assert_eq!(exported_latencies(&sink), vec![vec![10, 20]]);
+        assert_eq!(handler.pending_events(), 1);
+
+        // A batch rejected by an explicit flush is exported before the events recorded in the meantime.
+        unavailable_sink.set_available(false);
+        assert!(handler.flush().is_err());
+        handler.record(request(40)).unwrap();
+        unavailable_sink.set_available(true);
+        handler.flush().unwrap();
+        assert_eq!(exported_latencies(&sink), vec![vec![10, 20], vec![30], vec![40]]);
+        assert_eq!(handler.pending_events(), 0);
+    }
+
+    fn exported_events(sink: &MemorySink) -> Vec<ResourceEvents> {
+        sink.batches().into_iter().map(|batch| match batch {
+            ResourceBatch::Events(resource_events) => resource_events,
+            _ => panic!("unexpected batch type"),
+        }).collect()
+    }
+
+    fn span(start_time: u64) -> Span {
+        Span {
+            start_time,
+            end_time: start_time + 1,
+            name: format!("span_{}", start_time),
+            latency_ms: 10,
+            status_code: None,
+            score: 0.0,
+            sampled: true,
+            events: vec![SpanEvent { name: "event".into(), dropped_attributes_count: None }],
+        }
+    }
+
+    #[test]
+    fn test_flush() {
+        let sink = MemorySink::new();
+        let mut handler: EventBatchHandler<Span> = EventBatchHandler::new(BatchPolicy::new(2, chrono::Duration::seconds(10)))
+            .with_sink(sink.clone());
+
+        // Flush by size, as soon as the batch is full
+        handler.record(span(1)).unwrap();
+        assert!(sink.is_empty());
+        handler.record(span(2)).unwrap();
+        {
+            let exported = exported_events(&sink);
+            assert_eq!(exported.len(), 1);
+            let batch = &exported[0].instrumentation_library_events[0].batches[0];
+            assert_eq!(batch.size, 2);
+            assert_eq!(batch.start_time_unix_nano_column, vec![1, 2]);
+            assert_eq!(batch.auxiliary_entities[0].size, 2);
+        }
+        assert_eq!(handler.pending_events(), 0);
+        handler.record(span(3)).unwrap();
+        assert_eq!(handler.resource_events.instrumentation_library_events[0].batches[0].size, 1);
+
+        // Flush by age
+        let oldest_event_time = handler.oldest_event_time().unwrap();
+        assert!(!handler.tick(oldest_event_time + chrono::Duration::seconds(9)).unwrap());
+        assert!(handler.tick(oldest_event_time + chrono::Duration::seconds(10)).unwrap());
+        assert!(handler.oldest_event_time().is_none());
+        assert!(!handler.tick(oldest_event_time + chrono::Duration::seconds(20)).unwrap());
+        {
+            let exported = exported_events(&sink);
+            assert_eq!(exported.len(), 2);
+            assert_eq!(exported[1].instrumentation_library_events[0].batches[0].start_time_unix_nano_column, vec![3]);
+        }
+
+        // Explicit flush, an empty batch is not emitted
+        handler.flush().unwrap();
+        assert_eq!(sink.len(), 2);
+        handler.record(span(4)).unwrap();
+        handler.flush().unwrap();
+        assert_eq!(sink.len(), 3);
+        assert_eq!(handler.resource_events.instrumentation_library_events[0].batches[0].size, 0);
+    }
+
+    #[test]
+    fn test_flush_failure() {
+        let sink = MemorySink::new();
+        let unavailable_sink = UnavailableSink::new(false, sink.clone());
+        let mut handler: EventBatchHandler<Span> = EventBatchHandler::new(BatchPolicy::new(2, chrono::Duration::seconds(10)))
+            .with_sink(unavailable_sink.clone());
+
+        // The full batch is kept when the sink fails.
+        handler.record(span(1)).unwrap();
+        assert!(handler.record(span(2)).is_err());
+        assert_eq!(handler.pending_events(), 2);
+        assert!(handler.flush().is_err());
+        assert!(handler.record(span(3)).is_err());
+        assert_eq!(handler.pending_events(), 2);
+        assert!(sink.is_empty());
+
+        // The batch is exported once the sink is available again.
+        unavailable_sink.set_available(true);
+        handler.record(span(3)).unwrap();
+        let exported = exported_events(&sink);
+        assert_eq!(exported.len(), 1);
+        assert_eq!(exported[0].instrumentation_library_events[0].batches[0].start_time_unix_nano_column, vec![1, 2]);
+        assert_eq!(exported[0].instrumentation_library_events[0].batches[0].auxiliary_entities[0].size, 2);
+        assert_eq!(handler.pending_events(), 1);
+    }
+
+    #[test]
+    fn test_derive() {
+        let event_collector = EventCollector::new(BatchPolicy::new(10, chrono::Duration::seconds(10)));
+        let mut handler: EventBatchHandler<Span> = event_collector.event_handler();
+
+        handler.record(Span {
+            start_time: 1,
+            end_time: 2,
+            name: "span_1".into(),
+            latency_ms: 10,
+            status_code: Some(1),
+            score: 0.5,
+            sampled: true,
+            events: vec![
+                SpanEvent { name: "event_1".into(), dropped_attributes_count: None },
+                SpanEvent { name: "event_2".into(), dropped_attributes_count: Some(3) },
+            ],
+        }).unwrap();
+        handler.record(Span {
+            start_time: 3,
+            end_time: 4,
+            name: "span_2".into(),
+            latency_ms: 20,
+            status_code: None,
+            score: 1.5,
+            sampled: false,
+            events: vec![],
+        }).unwrap();
+
+        let batch = &handler.resource_events.instrumentation_library_events[0].batches[0];
+        assert_eq!(batch.schema_url, "urn:test:span");
+        assert_eq!(batch.i64_values[0].name, "latency");
+        assert_eq!(batch.i64_values[0].unit, "ms");
+        assert_eq!(batch.i64_values[0].logical_type, 1);
+        assert!(batch.i64_values[0].validity_bitmap.is_empty());
+        assert_eq!(batch.i64_values[1].name, "status_code");
+        assert_eq!(batch.i64_values[1].values, vec![1, 0]);
+        assert_eq!(batch.auxiliary_entities[0].parent_column, "events");
+        assert_eq!(batch.auxiliary_entities[0].logical_type, 1);
+        assert_eq!(batch.auxiliary_entities[0].parent_ranks, vec![0, 0]);
+
+        assert_eq!(handler.to_json_value().unwrap(), json!([
+            {
+                "@schema_url": "urn:test:span",
+                "@start_time_unix_nano": 1,
+                "@end_time_unix_nano": 2,
+                "name": "span_1",
+                "latency": 10,
+                "status_code": 1,
+                "score": 0.5,
+                "sampled": true,
+                "events": [
+                    { "name": "event_1" },
+                    { "name": "event_2", "dropped_attributes_count": 3 },
+                ],
+            },
+            {
+                "@schema_url": "urn:test:span",
+                "@start_time_unix_nano": 3,
+                "@end_time_unix_nano": 4,
+                "name": "span_2",
+                "latency": 20,
+                "score": 1.5,
+                "sampled": false,
+            },
+        ]));
+
+        assert_eq!(Span::urn(), "urn:test:span");
+        assert_eq!(<SpanEvent as OpenTelemetryAuxiliaryEntity>::auxiliary_entity("events", Default::default(), &handler.batch_policy).string_values[0].name, "name");
+    }
+
+    #[derive(OpenTelemetryEvent)]
+    #[otel(urn = "urn:test:measurement")]
+    struct Measurement {
+        #[otel(start_time)]
+        time: u64,
+        value: f64,
+        payload: Vec<u8>,
+        valid: Option<bool>,
+        latency: Histogram,
+    }
+
+    #[test]
+    fn test_reset_batch_event() {
+        let mut handler: EventBatchHandler<Span> = EventBatchHandler::new(BatchPolicy::new(10, chrono::Duration::seconds(10)));
+        handler.record(span(1)).unwrap();
+        handler.record(span(2)).unwrap();
+        handler.flush().unwrap();
+
+        let batch = &handler.resource_events.instrumentation_library_events[0].batches[0];
+        assert_eq!(batch.size, 0);
+        assert!(batch.start_time_unix_nano_column.is_empty());
+        assert!(batch.string_values[0].values.is_empty());
+        assert!(batch.bool_values[0].values.is_empty());
+        assert_eq!(batch.i64_values[1].validity_bitmap, vec![0; 2]);
+        assert_eq!(batch.auxiliary_entities[0].size, 0);
+        assert!(batch.auxiliary_entities[0].parent_ranks.is_empty());
+        assert!(batch.auxiliary_entities[0].string_values[0].values.is_empty());
+        assert!(handler.oldest_event_time().is_none());
+
+        handler.record(span(3)).unwrap();
+        let batch = &handler.resource_events.instrumentation_library_events[0].batches[0];
+        batch.validate().unwrap();
+        assert_eq!(batch.auxiliary_entities[0].parent_ranks, vec![0]);
+    }
+
+    #[test]
+    fn test_reset_batch_event_without_auxiliary_entities() {
+        let mut handler: EventBatchHandler<Measurement> = EventBatchHandler::new(BatchPolicy::new(10, chrono::Duration::seconds(10)));
+        for time in 0..2 {
+            handler.record(Measurement { time, value: 1.5, payload: vec![1, 2], valid: Some(true), latency: Histogram { count: 1, ..Default::default() } }).unwrap();
+        }
+        handler.flush().unwrap();
+
+        let batch = &handler.resource_events.instrumentation_library_events[0].batches[0];
+        assert_eq!(batch.size, 0);
+        assert!(batch.auxiliary_entities.is_empty());
+        assert!(batch.f64_values[0].values.is_empty());
+        assert!(batch.f64_values[0].values.capacity() >= 2);
+        assert!(batch.bytes_values[0].values.is_empty());
+        assert!(batch.bool_values[0].values.is_empty());
+        assert!(batch.histogram_values[0].is_empty());
+
+        handler.record(Measurement { time: 3, value: 2.5, payload: vec![], valid: None, latency: Histogram::default() }).unwrap();
+        let batch = &handler.resource_events.instrumentation_library_events[0].batches[0];
+        batch.validate().unwrap();
+        assert_eq!(handler.to_json_value().unwrap()[0]["value"], json!(2.5));
+    }
+
+    fn resource(service_name: &str) -> Resource {
+        Resource {
+            attributes: vec![KeyValue { key: "service.name".into(), value: Some(AnyValue { value: Some(any_value::Value::StringValue(service_name.into())) }) }],
+            dropped_attributes_count: 0,
+        }
+    }
+
+    #[test]
+    fn test_collector() {
+        let library = InstrumentationLibrary { name: "http".into(), version: "0.1".into() };
+        let event_collector = EventCollector::new(BatchPolicy::new(10, chrono::Duration::seconds(10)))
+            .with_resource(resource("frontend"));
+        let mut spans: EventBatchHandler<Span> = event_collector.event_handler();
+        let mut measurements: EventBatchHandler<Measurement> = event_collector.event_handler()
+            .with_instrumentation_library(library.clone());
+        let mut backend_spans: EventBatchHandler<Span> = EventBatchHandler::new(BatchPolicy::new(10, chrono::Duration::seconds(10)))
+            .with_resource(resource("backend"));
+        assert_eq!(spans.resource_events.resource, Some(resource("frontend")));
+        assert_eq!(measurements.resource_events.instrumentation_library_events[0].instrumentation_library, Some(library.clone()));
+
+        for time in 0..2 {
+            spans.record(span(time)).unwrap();
+            backend_spans.record(span(time)).unwrap();
+            measurements.record(Measurement { time, value: 1.0, payload: vec![], valid: None, latency: Histogram::default() }).unwrap();
+        }
+        event_collector.collect(&mut spans);
+        event_collector.collect(&mut measurements);
+        event_collector.collect(&mut backend_spans);
+        spans.record(span(2)).unwrap();
+        event_collector.collect(&mut spans);
+        event_collector.collect(&mut spans);
+        assert_eq!(spans.resource_events.instrumentation_library_events[0].batches[0].size, 0);
+
+        let sink = MemorySink::new();
+        event_collector.export(&mut sink.clone()).unwrap();
+        assert_eq!(event_collector.pending_events(), 0);
+        let exported = exported_events(&sink);
+        assert_eq!(exported.len(), 2);
+        assert!(event_collector.take().is_empty());
+
+        let frontend = &exported[0];
+        assert_eq!(frontend.resource, Some(resource("frontend")));
+        assert_eq!(frontend.instrumentation_library_events.len(), 2);
+        let default_library_events = &frontend.instrumentation_library_events[0];
+        assert_eq!(default_library_events.instrumentation_library.as_ref().map(|library| library.name.as_str()), Some("otel-rust"));
+        assert_eq!(default_library_events.batches.iter().map(|batch| batch.size).collect::<Vec<_>>(), vec![2, 1]);
+        let http_library_events = &frontend.instrumentation_library_events[1];
+        assert_eq!(http_library_events.instrumentation_library, Some(library));
+        assert_eq!(http_library_events.batches[0].schema_url, "urn:test:measurement");
+
+        assert_eq!(exported[1].resource, Some(resource("backend")));
+        assert_eq!(exported[1].instrumentation_library_events[0].batches[0].schema_url, "urn:test:span");
+        exported.iter().for_each(|resource_events| resource_events.validate().unwrap());
+    }
+
+    #[test]
+    fn test_arrow_resource() {
+        let library = InstrumentationLibrary { name: "http".into(), version: "0.1".into() };
+        let sink = MemorySink::new();
+        let unavailable_sink = UnavailableSink::new(false, sink.clone());
+        let mut handler = ArrowEventBatchHandler::<Request>::new(BatchPolicy::new(1, chrono::Duration::seconds(10))).unwrap()
+            .with_resource(resource("frontend"))
+            .with_instrumentation_library(library.clone())
+            .with_sink(unavailable_sink.clone());
+
+        // The resource and library are kept in the rejected batch, and reapplied to the batch following an export.
+        assert!(handler.record(request(10)).is_err());
+        unavailable_sink.set_available(true);
+        handler.record(request(20)).unwrap();
+        handler.flush().unwrap();
+        let exported = sink.batches();
+        assert_eq!(exported.len(), 2);
+        for batch in exported {
+            match batch {
+                ResourceBatch::ArrowEvents(resource_events) => {
+                    assert_eq!(resource_events.resource, Some(resource("frontend")));
+                    assert_eq!(resource_events.instrumentation_library_events[0].instrumentation_library, Some(library.clone()));
+                }
+                _ => panic!("unexpected batch type"),
+            }
+        }
+        assert_eq!(handler.resource_events.resource, Some(resource("frontend")));
+    }
 
     #[test]
     fn test() {
@@ -0,0 +1,470 @@
+use std::borrow::Cow;
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::event::Error;
+use crate::histogram::{ExponentialHistogram, Histogram};
+use crate::summary::Summary;
+use crate::opentelemetry::proto::events::v1::{AuxiliaryEntity, BatchEvent, BoolColumn, BytesColumn, DoubleColumn, DoubleSummaryColumn, ExponentialHistogramColumn, HistogramColumn, Int64Column, Int64SummaryColumn, StringColumn};
+
+pub use otel_multivariate_time_series_derive::{FromBatchEvent, FromAuxiliaryEntity};
+
+/// Counterpart of `OpenTelemetryEvent`, reconstructs an event from a row of a `BatchEvent`.
+/// Usually implemented via `#[derive(FromBatchEvent)]`.
+pub trait FromBatchEvent: Sized {
+    fn from_event_row(row: &EventRow) -> Result<Self, Error>;
+}
+
+/// Counterpart of `OpenTelemetryAuxiliaryEntity`, reconstructs an entity from a row of an `AuxiliaryEntity`.
+/// Usually implemented via `#[derive(FromAuxiliaryEntity)]`.
+pub trait FromAuxiliaryEntity: Sized {
+    fn from_row(row: &Row) -> Result<Self, Error>;
+}
+
+/// A timestamp that can be created from a number of nanoseconds since the unix epoch.
+pub trait FromUnixNano {
+    fn from_unix_nano(unix_nano: u64) -> Self;
+}
+
+impl FromUnixNano for u64 {
+    fn from_unix_nano(unix_nano: u64) -> Self {
+        unix_nano
+    }
+}
+
+impl FromUnixNano for i64 {
+    fn from_unix_nano(unix_nano: u64) -> Self {
+        unix_nano as i64
+    }
+}
+
+impl FromUnixNano for DateTime<Utc> {
+    fn from_unix_nano(unix_nano: u64) -> Self {
+        Utc.timestamp_nanos(unix_nano as i64)
+    }
+}
+
+/// The columns of a batch event or of an auxiliary entity.
+#[derive(Debug, Clone, Copy)]
+pub struct Columns<'a> {
+    pub i64_values: &'a [Int64Column],
+    pub f64_values: &'a [DoubleColumn],
+    pub string_values: &'a [StringColumn],
+    pub bool_values: &'a [BoolColumn],
+    pub bytes_values: &'a [BytesColumn],
+    pub histogram_values: &'a [HistogramColumn],
+    pub exponential_histogram_values: &'a [ExponentialHistogramColumn],
+    pub i64_summary_values: &'a [Int64SummaryColumn],
+    pub f64_summary_values: &'a [DoubleSummaryColumn],
+}
+
+/// A row of a batch event or of an auxiliary entity. The values are looked up by column name.
+/// Getters return an error if the column doesn't exist and `None` if the value is null.
+#[derive(Debug, Clone, Copy)]
+pub struct Row<'a> {
+    columns: Columns<'a>,
+    row: usize,
+}
+
+/// A row of a batch event, i.e. a row with start/end times and auxiliary entities.
+#[derive(Debug, Clone, Copy)]
+pub struct EventRow<'a> {
+    reader: &'a BatchEventReader<'a>,
+    row: Row<'a>,
+}
+
+/// Row-oriented access to a batch event. The auxiliary entities are indexed per parent row at creation time and
+/// delta encoded columns are decoded into a copy of the batch.
+#[derive(Debug)]
+pub struct BatchEventReader<'a> {
+    batch: Cow<'a, BatchEvent>,
+    /// For each auxiliary entity, offsets[i]..offsets[i+1] is the range of rows attached to the event i.
+    auxiliary_offsets: Vec<Vec<usize>>,
+}
+
+/// An iterator over the events of a batch.
+pub struct Events<'a, T> {
+    reader: &'a BatchEventReader<'a>,
+    next_row: usize,
+    phantom_data: PhantomData<T>,
+}
+
+impl<'a> Columns<'a> {
+    pub fn of_batch(batch: &'a BatchEvent) -> Self {
+        Columns {
+            i64_values: &batch.i64_values,
+            f64_values: &batch.f64_values,
+            string_values: &batch.string_values,
+            bool_values: &batch.bool_values,
+            bytes_values: &batch.bytes_values,
+            histogram_values: &batch.histogram_values,
+            exponential_histogram_values: &batch.exponential_histogram_values,
+            i64_summary_values: &batch.i64_summary_values,
+            f64_summary_values: &batch.f64_summary_values,
+        }
+    }
+
+    pub fn of_auxiliary_entity(auxiliary_entity: &'a AuxiliaryEntity) -> Self {
+        Columns {
+            i64_values: &auxiliary_entity.i64_values,
+            f64_values: &auxiliary_entity.f64_values,
+            string_values: &auxiliary_entity.string_values,
+            bool_values: &auxiliary_entity.bool_values,
+            bytes_values: &auxiliary_entity.bytes_values,
+            histogram_values: &auxiliary_entity.histogram_values,
+            exponential_histogram_values: &auxiliary_entity.exponential_histogram_values,
+            i64_summary_values: &auxiliary_entity.i64_summary_values,
+            f64_summary_values: &auxiliary_entity.f64_summary_values,
+        }
+    }
+}
+
+impl<'a> Row<'a> {
+    pub fn new(columns: Columns<'a>, row: usize) -> Self {
+        Row { columns, row }
+    }
+
+    pub fn row(&self) -> usize {
+        self.row
+    }
+
+    pub fn i64_value(&self, name: &str) -> Result<Option<i64>, Error> {
+        let column = find_column(self.columns.i64_values, name, |column| &column.name)?;
+        Ok(value_at(&column.values, &column.validity_bitmap, name, self.row)?.copied())
+    }
+
+    pub fn f64_value(&self, name: &str) -> Result<Option<f64>, Error> {
+        let column = find_column(self.columns.f64_values, name, |column| &column.name)?;
+        Ok(value_at(&column.values, &column.validity_bitmap, name, self.row)?.copied())
+    }
+
+    pub fn string_value(&self, name: &str) -> Result<Option<&'a str>, Error> {
+        let column = find_column(self.columns.string_values, name, |column| &column.name)?;
+        if !column.is_dictionary_encoded() {
+            return Ok(value_at(&column.values, &column.validity_bitmap, name, self.row)?.map(|value| value.as_str()));
+        }
+        // The validity bitmap is checked against the dictionary indices, the value is then resolved in the dictionary.
+        Ok(match value_at(&column.dictionary_indices, &column.validity_bitmap, name, self.row)? {
+            Some(&index) => Some(column.dictionary.get(index as usize)
+                .ok_or_else(|| Error::InvalidFormat(format!("dictionary index {} of column '{}' out of range", index, name)))?
+                .as_str()),
+            None => None,
+        })
+    }
+
+    pub fn bool_value(&self, name: &str) -> Result<Option<bool>, Error> {
+        let column = find_column(self.columns.bool_values, name, |column| &column.name)?;
+        Ok(value_at(&column.values, &column.validity_bitmap, name, self.row)?.copied())
+    }
+
+    pub fn bytes_value(&self, name: &str) -> Result<Option<&'a [u8]>, Error> {
+        let column = find_column(self.columns.bytes_values, name, |column| &column.name)?;
+        Ok(value_at(&column.values, &column.validity_bitmap, name, self.row)?.map(|value| value.as_slice()))
+    }
+
+    pub fn histogram_value(&self, name: &str) -> Result<Option<Histogram>, Error> {
+        let column = find_column(self.columns.histogram_values, name, |column| &column.name)?;
+        check_row(column.len(), name, self.row)?;
+        Ok(column.value(self.row))
+    }
+
+    pub fn exponential_histogram_value(&self, name: &str) -> Result<Option<ExponentialHistogram>, Error> {
+        let column = find_column(self.columns.exponential_histogram_values, name, |column| &column.name)?;
+        check_row(column.len(), name, self.row)?;
+        Ok(column.value(self.row))
+    }
+
+    pub fn i64_summary_value(&self, name: &str) -> Result<Option<Summary<i64>>, Error> {
+        let column = find_column(self.columns.i64_summary_values, name, |column| &column.name)?;
+        check_row(column.len(), name, self.row)?;
+        Ok(column.value(self.row))
+    }
+
+    pub fn f64_summary_value(&self, name: &str) -> Result<Option<Summary<f64>>, Error> {
+        let column = find_column(self.columns.f64_summary_values, name, |column| &column.name)?;
+        check_row(column.len(), name, self.row)?;
+        Ok(column.value(self.row))
+    }
+}
+
+impl<'a> EventRow<'a> {
+    pub fn start_time_unix_nano(&self) -> Result<u64, Error> {
+        self.reader.batch.start_time_unix_nano_column.get(self.row.row).copied()
+            .ok_or_else(|| Error::MissingValue { column: "start_time_unix_nano".into(), row: self.row.row })
+    }
+
+    pub fn end_time_unix_nano(&self) -> Result<u64, Error> {
+        self.reader.batch.end_time_unix_nano_column.get(self.row.row).copied()
+            .ok_or_else(|| Error::MissingValue { column: "end_time_unix_nano".into(), row: self.row.row })
+    }
+
+    /// Returns the rows of the auxiliary entity `parent_column` attached to this event.
+    pub fn auxiliary_rows(&self, parent_column: &str) -> Result<impl Iterator<Item=Row<'a>>, Error> {
+        let rank = self.reader.batch.auxiliary_entities.iter()
+            .position(|auxiliary_entity| auxiliary_entity.parent_column == parent_column)
+            .ok_or_else(|| Error::MissingColumn(parent_column.into()))?;
+        let columns = Columns::of_auxiliary_entity(&self.reader.batch.auxiliary_entities[rank]);
+        let offsets = &self.reader.auxiliary_offsets[rank];
+
+        Ok((offsets[self.row.row]..offsets[self.row.row + 1]).map(move |row| Row::new(columns, row)))
+    }
+}
+
+impl<'a> Deref for EventRow<'a> {
+    type Target = Row<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.row
+    }
+}
+
+impl<'a> BatchEventReader<'a> {
+    /// Fails if the batch doesn't pass `BatchEvent::validate`.
+    pub fn new(batch: &'a BatchEvent) -> Result<Self, Error> {
+        batch.validate()?;
+        let batch = if batch.is_delta_encoded() {
+            let mut decoded_batch = batch.clone();
+            decoded_batch.decode_delta_columns()?;
+            Cow::Owned(decoded_batch)
+        } else {
+            Cow::Borrowed(batch)
+        };
+        let size = batch.size as usize;
+        let mut auxiliary_offsets = Vec::with_capacity(batch.auxiliary_entities.len());
+
+        for auxiliary_entity in &batch.auxiliary_entities {
+            let mut offsets = vec![0; size + 1];
+            for &parent_rank in &auxiliary_entity.parent_ranks {
+                offsets[parent_rank as usize + 1] += 1;
+            }
+            for i in 0..size {
+                offsets[i + 1] += offsets[i];
+            }
+
+            auxiliary_offsets.push(offsets);
+        }
+
+        Ok(BatchEventReader { batch, auxiliary_offsets })
+    }
+
+    /// The batch being read, delta encoded columns decoded.
+    pub fn batch(&self) -> &BatchEvent {
+        &self.batch
+    }
+
+    pub fn len(&self) -> usize {
+        self.batch.size as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn row(&'a self, row: usize) -> EventRow<'a> {
+        EventRow { reader: self, row: Row::new(Columns::of_batch(&self.batch), row) }
+    }
+
+    pub fn rows(&'a self) -> impl Iterator<Item=EventRow<'a>> {
+        (0..self.len()).map(move |row| self.row(row))
+    }
+
+    pub fn events<T: FromBatchEvent>(&'a self) -> Events<'a, T> {
+        Events { reader: self, next_row: 0, phantom_data: PhantomData }
+    }
+}
+
+impl<'a, T: FromBatchEvent> Iterator for Events<'a, T> {
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next_row >= self.reader.len() {
+            return None;
+        }
+        let row = self.reader.row(self.next_row);
+        self.next_row += 1;
+        Some(T::from_event_row(&row))
+    }
+}
+
+/// Decodes all the events of a batch.
+pub fn decode_batch_event<T: FromBatchEvent>(batch: &BatchEvent) -> Result<Vec<T>, Error> {
+    BatchEventReader::new(batch)?.events().collect()
+}
+
+/// Converts a null value of a non optional field into an error.
+pub fn required<T>(value: Option<T>, column: &str, row: usize) -> Result<T, Error> {
+    value.ok_or_else(|| Error::MissingValue { column: column.into(), row })
+}
+
+fn find_column<'a, C>(columns: &'a [C], name: &str, column_name: impl Fn(&C) -> &String) -> Result<&'a C, Error> {
+    columns.iter()
+        .find(|column| column_name(column) == name)
+        .ok_or_else(|| Error::MissingColumn(name.into()))
+}
+
+fn value_at<'a, T>(values: &'a [T], validity_bitmap: &[u8], name: &str, row: usize) -> Result<Option<&'a T>, Error> {
+    if !validity_bitmap.is_empty() {
+        match validity_bitmap.get(row / 8) {
+            Some(byte) if byte & (1 << (row % 8)) == 0 => return Ok(None),
+            Some(_) => {}
+            None => return Err(Error::InvalidFormat(format!("validity bitmap of column '{}' too short for row {}", name, row))),
+        }
+    }
+
+    values.get(row)
+        .map(Some)
+        .ok_or_else(|| Error::InvalidFormat(format!("column '{}' has {} values, row {} requested", name, values.len(), row)))
+}
+
+fn check_row(length: usize, name: &str, row: usize) -> Result<(), Error> {
+    if row >= length {
+        return Err(Error::InvalidFormat(format!("column '{}' has {} values, row {} requested", name, length, row)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::event::{BatchPolicy, EventBatchHandler, Error, OpenTelemetryEvent, OpenTelemetryAuxiliaryEntity};
+    use crate::event_decoder::{BatchEventReader, FromBatchEvent, FromAuxiliaryEntity, decode_batch_event};
+    use chrono::{DateTime, TimeZone, Utc};
+
+    #[derive(Debug, Clone, PartialEq, OpenTelemetryEvent, FromBatchEvent)]
+    #[otel(urn = "urn:test:request")]
+    struct Request {
+        #[otel(start_time)]
+        start_time: DateTime<Utc>,
+        #[otel(end_time)]
+        end_time: u64,
+        #[otel(name = "http.method")]
+        method: String,
+        http_code: u16,
+        user: Option<String>,
+        latency_ms: Option<f64>,
+        cached: Option<bool>,
+        payload: Vec<u8>,
+        #[otel(logical_type = "attribute")]
+        attributes: Vec<Attribute>,
+        #[otel(skip)]
+        local_only: u32,
+    }
+
+    #[derive(Debug, Clone, PartialEq, OpenTelemetryAuxiliaryEntity, FromAuxiliaryEntity)]
+    struct Attribute {
+        key: String,
+        value: Option<i64>,
+    }
+
+    fn requests() -> Vec<Request> {
+        vec![
+            Request {
+                start_time: Utc.timestamp_nanos(1_000),
+                end_time: 2_000,
+                method: "GET".into(),
+                http_code: 200,
+                user: Some("user_1".into()),
+                latency_ms: Some(12.5),
+                cached: None,
+                payload: vec![1, 2, 3],
+                attributes: vec![
+                    Attribute { key: "a".into(), value: Some(1) },
+                    Attribute { key: "b".into(), value: None },
+                ],
+                local_only: 0,
+            },
+            Request {
+                start_time: Utc.timestamp_nanos(3_000),
+                end_time: 4_000,
+                method: "POST".into(),
+                http_code: 500,
+                user: None,
+                latency_ms: None,
+                cached: Some(true),
+                payload: vec![],
+                attributes: vec![],
+                local_only: 0,
+            },
+            Request {
+                start_time: Utc.timestamp_nanos(5_000),
+                end_time: 6_000,
+                method: "PUT".into(),
+                http_code: 201,
+                user: None,
+                latency_ms: Some(1.0),
+                cached: Some(false),
+                payload: vec![4],
+                attributes: vec![Attribute { key: "c".into(), value: Some(3) }],
+                local_only: 0,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut handler: EventBatchHandler<Request> = EventBatchHandler::new(BatchPolicy::new(10, chrono::Duration::seconds(10)));
+        for request in requests() {
+            handler.record(Request { local_only: 42, ..request }).unwrap();
+        }
+        let batch = &handler.resource_events.instrumentation_library_events[0].batches[0];
+
+        let decoded: Vec<Request> = decode_batch_event(batch).unwrap();
+        assert_eq!(decoded, requests());
+
+        let reader = BatchEventReader::new(batch).unwrap();
+        assert_eq!(reader.len(), 3);
+        let row = reader.row(0);
+        assert_eq!(row.string_value("http.method").unwrap(), Some("GET"));
+        assert_eq!(row.bool_value("cached").unwrap(), None);
+        assert_eq!(row.auxiliary_rows("attributes").unwrap().count(), 2);
+        assert_eq!(reader.row(1).auxiliary_rows("attributes").unwrap().count(), 0);
+        assert!(matches!(row.i64_value("unknown"), Err(Error::MissingColumn(_))));
+    }
+
+    #[test]
+    fn test_dictionary_encoded_batch() {
+        let mut handler: EventBatchHandler<Request> = EventBatchHandler::new(BatchPolicy::new(10, chrono::Duration::seconds(10)));
+        let requests: Vec<Request> = requests().into_iter().cycle().take(9).collect();
+        for request in requests.clone() {
+            handler.record(request).unwrap();
+        }
+        let mut batch = handler.resource_events.instrumentation_library_events[0].batches[0].clone();
+        batch.encode_dictionaries();
+        assert!(batch.string_values[0].is_dictionary_encoded());
+
+        let decoded: Vec<Request> = decode_batch_event(&batch).unwrap();
+        assert_eq!(decoded, requests);
+    }
+
+    #[test]
+    fn test_invalid_batch() {
+        let mut handler: EventBatchHandler<Request> = EventBatchHandler::new(BatchPolicy::new(10, chrono::Duration::seconds(10)));
+        for request in requests() {
+            handler.record(request).unwrap();
+        }
+        let batch = &handler.resource_events.instrumentation_library_events[0].batches[0];
+
+        // Unsorted parent ranks
+        let mut invalid_batch = batch.clone();
+        invalid_batch.auxiliary_entities[0].parent_ranks = vec![2, 0, 0];
+        assert!(matches!(BatchEventReader::new(&invalid_batch), Err(Error::InvalidParentRank { row: 1, .. })));
+
+        // Out of range parent rank
+        let mut invalid_batch = batch.clone();
+        invalid_batch.auxiliary_entities[0].parent_ranks = vec![0, 0, 3];
+        assert!(matches!(BatchEventReader::new(&invalid_batch), Err(Error::InvalidParentRank { row: 2, parent_rank: 3, .. })));
+
+        // Null value for a non optional field
+        let mut invalid_batch = batch.clone();
+        invalid_batch.string_values[0].validity_bitmap = vec![0b110];
+        let result: Result<Vec<Request>, Error> = decode_batch_event(&invalid_batch);
+        assert!(matches!(result, Err(Error::MissingValue { row: 0, .. })));
+
+        // Truncated column
+        let mut invalid_batch = batch.clone();
+        invalid_batch.i64_values[0].values.pop();
+        let result: Result<Vec<Request>, Error> = decode_batch_event(&invalid_batch);
+        assert!(matches!(result, Err(Error::InvalidColumnLength { .. })));
+    }
+}
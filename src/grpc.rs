@@ -0,0 +1,458 @@
+//! OTLP/gRPC transport (tonic) for the columnar events, the Arrow events and the metrics.
+//!
+//! `GrpcSink` is a blocking `EventSink` exporting the batches of a handler to the `EventsService`,
+//! `ArrowEventsService` and `MetricsService` of a collector. `GrpcReceiver` implements these services (and the
+//! `LogsService` and `TraceService`) and forwards the received batches, once validated, to a local sink.
+//!
+//! The transport failures are reported as `GrpcError`, wrapped into `Error::SinkError` by the `EventSink` methods.
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{Channel, Endpoint, Server};
+use tonic::{Request, Response, Status};
+
+use crate::event::Error;
+use crate::opentelemetry::proto::arrow_events::v1 as arrow_events;
+use crate::opentelemetry::proto::collector::arrow_events::v1::{ExportArrowEventsServiceRequest, ExportArrowEventsServiceResponse};
+use crate::opentelemetry::proto::collector::arrow_events::v1::arrow_events_service_client::ArrowEventsServiceClient;
+use crate::opentelemetry::proto::collector::arrow_events::v1::arrow_events_service_server::{ArrowEventsService, ArrowEventsServiceServer};
+use crate::opentelemetry::proto::collector::events::v1::{ExportEventsServiceRequest, ExportEventsServiceResponse};
+use crate::opentelemetry::proto::collector::events::v1::events_service_client::EventsServiceClient;
+use crate::opentelemetry::proto::collector::events::v1::events_service_server::{EventsService, EventsServiceServer};
+use crate::opentelemetry::proto::collector::logs::v1::{ExportLogsServiceRequest, ExportLogsServiceResponse};
+use crate::opentelemetry::proto::collector::logs::v1::logs_service_server::{LogsService, LogsServiceServer};
+use crate::opentelemetry::proto::collector::metrics::v1::{ExportMetricsServiceRequest, ExportMetricsServiceResponse};
+use crate::opentelemetry::proto::collector::metrics::v1::metrics_service_client::MetricsServiceClient;
+use crate::opentelemetry::proto::collector::metrics::v1::metrics_service_server::{MetricsService, MetricsServiceServer};
+use crate::opentelemetry::proto::collector::trace::v1::{ExportTraceServiceRequest, ExportTraceServiceResponse};
+use crate::opentelemetry::proto::collector::trace::v1::trace_service_server::{TraceService, TraceServiceServer};
+use crate::opentelemetry::proto::events::v1::ResourceEvents;
+use crate::opentelemetry::proto::metrics::v1::ResourceMetrics;
+use crate::{log_event, span_event};
+use crate::schema::SchemaRegistry;
+use crate::sink::EventSink;
+
+#[derive(thiserror::Error, Debug)]
+pub enum GrpcError {
+    #[error("gRPC Transport Error (error: {0})")]
+    TransportError(#[from] tonic::transport::Error),
+    #[error("gRPC Error (status: {0})")]
+    StatusError(Box<Status>),
+    #[error("Invalid Endpoint (error: {0})")]
+    InvalidEndpoint(String),
+    #[error(transparent)]
+    EventError(#[from] Error),
+}
+
+impl From<Status> for GrpcError {
+    fn from(status: Status) -> Self {
+        GrpcError::StatusError(Box::new(status))
+    }
+}
+
+impl From<GrpcError> for Error {
+    fn from(err: GrpcError) -> Self {
+        match err {
+            GrpcError::EventError(err) => err,
+            err => Error::SinkError(Box::new(err)),
+        }
+    }
+}
+
+/// Exports the batches to a collector. Each export blocks until the collector responds, so this sink must not be used
+/// from an async context.
+#[derive(Debug)]
+pub struct GrpcSink {
+    runtime: Runtime,
+    events_client: EventsServiceClient<Channel>,
+    arrow_events_client: ArrowEventsServiceClient<Channel>,
+    metrics_client: MetricsServiceClient<Channel>,
+}
+
+/// Receives the batches exported by a `GrpcSink` (or any OTLP/gRPC client of these services). The batches that
+/// don't pass validation are rejected with `INVALID_ARGUMENT`. The logs and spans received by the `LogsService` and
+/// `TraceService` are converted into columnar events (see `log_event` and `span_event`).
+///
+/// The sink runs on a blocking thread, so it can be a blocking sink (e.g. a `GrpcSink` forwarding to another
+/// collector). The resource groups of a request are exported in order and the export stops at the first failure of
+/// the sink: the request is rejected with `UNAVAILABLE` if no group was exported (the request can be retried), and with
+/// `INTERNAL` otherwise (not retryable, a retry would export the first groups again), the message giving the number of
+/// exported groups.
+#[derive(Debug)]
+pub struct GrpcReceiver<S: EventSink> {
+    sink: Arc<Mutex<S>>,
+    schema_registry: Arc<SchemaRegistry>,
+}
+
+impl GrpcSink {
+    /// Connects to a collector, e.g. `http://127.0.0.1:4317`.
+    pub fn connect(endpoint: impl Into<String>) -> Result<Self, GrpcError> {
+        let endpoint = Endpoint::from_shared(endpoint.into()).map_err(|err| GrpcError::InvalidEndpoint(err.to_string()))?;
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().map_err(Error::from)?;
+        let channel = runtime.block_on(endpoint.connect())?;
+
+        Ok(GrpcSink {
+            runtime,
+            events_client: EventsServiceClient::new(channel.clone()),
+            arrow_events_client: ArrowEventsServiceClient::new(channel.clone()),
+            metrics_client: MetricsServiceClient::new(channel),
+        })
+    }
+}
+
+impl EventSink for GrpcSink {
+    fn export_events(&mut self, resource_events: ResourceEvents) -> Result<(), Error> {
+        let request = ExportEventsServiceRequest { resource_events: vec![resource_events] };
+        self.runtime.block_on(self.events_client.export(request)).map_err(GrpcError::from)?;
+        Ok(())
+    }
+
+    fn export_arrow_events(&mut self, resource_events: arrow_events::ResourceEvents) -> Result<(), Error> {
+        let request = ExportArrowEventsServiceRequest { resource_events: vec![resource_events] };
+        self.runtime.block_on(self.arrow_events_client.export(request)).map_err(GrpcError::from)?;
+        Ok(())
+    }
+
+    fn export_metrics(&mut self, resource_metrics: ResourceMetrics) -> Result<(), Error> {
+        let request = ExportMetricsServiceRequest { resource_metrics: vec![resource_metrics] };
+        self.runtime.block_on(self.metrics_client.export(request)).map_err(GrpcError::from)?;
+        Ok(())
+    }
+}
+
+impl<S: EventSink + Send + 'static> GrpcReceiver<S> {
+    pub fn new(sink: S) -> Self {
+        GrpcReceiver { sink: Arc::new(Mutex::new(sink)), schema_registry: Arc::new(SchemaRegistry::new()) }
+    }
+
+    /// Validates the received batches of the registered URNs against their schema.
+    pub fn with_schema_registry(mut self, schema_registry: SchemaRegistry) -> Self {
+        self.schema_registry = Arc::new(schema_registry);
+        self
+    }
+
+    /// Serves the events, Arrow events, metrics, logs and trace services until `shutdown` completes.
+    pub async fn serve(self, listener: TcpListener, shutdown: impl Future<Output=()>) -> Result<(), GrpcError> {
+        Server::builder()
+            .add_service(EventsServiceServer::new(self.clone()))
+            .add_service(ArrowEventsServiceServer::new(self.clone()))
+            .add_service(MetricsServiceServer::new(self.clone()))
+            .add_service(LogsServiceServer::new(self.clone()))
+            .add_service(TraceServiceServer::new(self))
+            .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown)
+            .await?;
+        Ok(())
+    }
+
+    /// Exports the resource groups of a request in order on a blocking thread, stopping at the first failure.
+    async fn export<B: Send + 'static>(&self, batches: Vec<B>, export: fn(&mut S, B) -> Result<(), Error>) -> Result<(), Status> {
+        let sink = self.sink.clone();
+        let count = batches.len();
+        let result = tokio::task::spawn_blocking(move || {
+            let mut sink = sink.lock().expect("receiver sink lock poisoned");
+            for (exported, batch) in batches.into_iter().enumerate() {
+                export(&mut sink, batch).map_err(|err| (exported, err))?;
+            }
+            Ok(())
+        }).await.map_err(|err| Status::internal(err.to_string()))?;
+
+        result.map_err(|(exported, err)| match exported {
+            0 => Status::unavailable(err.to_string()),
+            _ => Status::internal(format!("{} ({} of {} resource groups exported)", err, exported, count)),
+        })
+    }
+}
+
+impl<S: EventSink> Clone for GrpcReceiver<S> {
+    fn clone(&self) -> Self {
+        GrpcReceiver { sink: self.sink.clone(), schema_registry: self.schema_registry.clone() }
+    }
+}
+
+#[tonic::async_trait]
+impl<S: EventSink + Send + 'static> EventsService for GrpcReceiver<S> {
+    async fn export(&self, request: Request<ExportEventsServiceRequest>) -> Result<Response<ExportEventsServiceResponse>, Status> {
+        let request = request.into_inner();
+        for resource_events in &request.resource_events {
+            self.schema_registry.validate(resource_events).map_err(|err| Status::invalid_argument(err.to_string()))?;
+        }
+        self.export(request.resource_events, S::export_events).await?;
+        Ok(Response::new(ExportEventsServiceResponse {}))
+    }
+}
+
+#[tonic::async_trait]
+impl<S: EventSink + Send + 'static> ArrowEventsService for GrpcReceiver<S> {
+    async fn export(&self, request: Request<ExportArrowEventsServiceRequest>) -> Result<Response<ExportArrowEventsServiceResponse>, Status> {
+        self.export(request.into_inner().resource_events, S::export_arrow_events).await?;
+        Ok(Response::new(ExportArrowEventsServiceResponse {}))
+    }
+}
+
+#[tonic::async_trait]
+impl<S: EventSink + Send + 'static> MetricsService for GrpcReceiver<S> {
+    async fn export(&self, request: Request<ExportMetricsServiceRequest>) -> Result<Response<ExportMetricsServiceResponse>, Status> {
+        let request = request.into_inner();
+        for resource_metrics in &request.resource_metrics {
+            resource_metrics.validate().map_err(|err| Status::invalid_argument(err.to_string()))?;
+        }
+        self.export(request.resource_metrics, S::export_metrics).await?;
+        Ok(Response::new(ExportMetricsServiceResponse {}))
+    }
+}
+
+#[tonic::async_trait]
+impl<S: EventSink + Send + 'static> LogsService for GrpcReceiver<S> {
+    async fn export(&self, request: Request<ExportLogsServiceRequest>) -> Result<Response<ExportLogsServiceResponse>, Status> {
+        let resource_events = request.into_inner().resource_logs.into_iter()
+            .map(log_event::to_resource_events)
+            .collect::<Result<Vec<_>, Error>>()
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        self.export(resource_events, S::export_events).await?;
+        Ok(Response::new(ExportLogsServiceResponse {}))
+    }
+}
+
+#[tonic::async_trait]
+impl<S: EventSink + Send + 'static> TraceService for GrpcReceiver<S> {
+    async fn export(&self, request: Request<ExportTraceServiceRequest>) -> Result<Response<ExportTraceServiceResponse>, Status> {
+        let resource_events = request.into_inner().resource_spans.into_iter()
+            .map(span_event::to_resource_events)
+            .collect::<Result<Vec<_>, Error>>()
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        self.export(resource_events, S::export_events).await?;
+        Ok(Response::new(ExportTraceServiceResponse {}))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::event::{BatchPolicy, EventBatchHandler, Error, OpenTelemetryEvent};
+    use crate::event_decoder::{FromBatchEvent, decode_batch_event};
+    use crate::grpc::{GrpcError, GrpcReceiver, GrpcSink};
+    use crate::log_event::to_resource_logs;
+    use crate::opentelemetry::proto::collector::events::v1::ExportEventsServiceRequest;
+    use crate::opentelemetry::proto::collector::events::v1::events_service_client::EventsServiceClient;
+    use crate::opentelemetry::proto::collector::logs::v1::ExportLogsServiceRequest;
+    use crate::opentelemetry::proto::collector::logs::v1::logs_service_client::LogsServiceClient;
+    use crate::opentelemetry::proto::collector::trace::v1::ExportTraceServiceRequest;
+    use crate::opentelemetry::proto::collector::trace::v1::trace_service_client::TraceServiceClient;
+    use crate::opentelemetry::proto::common::v1::InstrumentationLibrary;
+    use crate::opentelemetry::proto::logs::v1::{InstrumentationLibraryLogs, LogRecord, ResourceLogs};
+    use crate::opentelemetry::proto::trace::v1::{InstrumentationLibrarySpans, ResourceSpans, Span};
+    use crate::span_event::to_resource_spans;
+    use crate::schema::SchemaRegistry;
+    use crate::sink::{EventSink, MemorySink, ResourceBatch};
+    use tokio::net::TcpListener;
+    use tonic::Code;
+
+    #[derive(Debug, Clone, PartialEq, OpenTelemetryEvent, FromBatchEvent)]
+    #[otel(urn = "urn:test:request")]
+    struct Request {
+        #[otel(start_time)]
+        start_time: u64,
+        #[otel(end_time)]
+        end_time: u64,
+        method: String,
+        http_code: u16,
+    }
+
+    #[test]
+    fn test_export_events() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let received_batches = MemorySink::new();
+        let (shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
+        let mut schema_registry = SchemaRegistry::new();
+        schema_registry.register_event::<Request>();
+        let receiver = GrpcReceiver::new(received_batches.clone()).with_schema_registry(schema_registry);
+        let server = runtime.spawn(receiver.serve(listener, async {
+            shutdown_receiver.await.ok();
+        }));
+
+        let requests = vec![
+            Request { start_time: 1, end_time: 2, method: "GET".into(), http_code: 200 },
+            Request { start_time: 3, end_time: 4, method: "POST".into(), http_code: 500 },
+        ];
+        let mut handler = EventBatchHandler::<Request>::new(BatchPolicy::new(10, chrono::Duration::seconds(10)))
+            .with_sink(GrpcSink::connect(endpoint.clone()).unwrap());
+        for request in requests.clone() {
+            handler.record(request).unwrap();
+        }
+        handler.flush().unwrap();
+
+        match received_batches.take().as_slice() {
+            [ResourceBatch::Events(resource_events)] => {
+                let batch = &resource_events.instrumentation_library_events[0].batches[0];
+                assert_eq!(decode_batch_event::<Request>(batch).unwrap(), requests);
+            }
+            batches => panic!("unexpected batches: {:?}", batches),
+        }
+
+        let mut invalid_resource_events = handler.resource_events.clone();
+        invalid_resource_events.instrumentation_library_events[0].batches[0].size = 1;
+        let mut sink = GrpcSink::connect(endpoint).unwrap();
+        match sink.export_events(invalid_resource_events) {
+            Err(Error::SinkError(err)) => {
+                assert!(matches!(err.downcast_ref::<GrpcError>(), Some(GrpcError::StatusError(status)) if status.code() == Code::InvalidArgument));
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        // Valid batch but not matching the registered schema.
+        let mut mismatching_resource_events = handler.resource_events.clone();
+        mismatching_resource_events.instrumentation_library_events[0].batches[0].string_values.clear();
+        match sink.export_events(mismatching_resource_events) {
+            Err(Error::SinkError(err)) => {
+                assert!(matches!(err.downcast_ref::<GrpcError>(), Some(GrpcError::StatusError(status)) if status.code() == Code::InvalidArgument));
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert!(received_batches.is_empty());
+
+        // The graceful shutdown waits for the connections to be closed.
+        drop(sink);
+        drop(handler);
+        shutdown_sender.send(()).unwrap();
+        runtime.block_on(server).unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_forward() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (shutdown_sender, shutdown_receiver) = tokio::sync::watch::channel(());
+
+        // The collector only accepts the batches matching the schema of `Request`.
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let collector_endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let received_batches = MemorySink::new();
+        let mut schema_registry = SchemaRegistry::new();
+        schema_registry.register_event::<Request>();
+        let mut shutdown = shutdown_receiver.clone();
+        let collector = runtime.spawn(GrpcReceiver::new(received_batches.clone()).with_schema_registry(schema_registry)
+            .serve(listener, async move { shutdown.changed().await.ok(); }));
+
+        // The agent forwards the batches to the collector with a blocking sink.
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let agent_endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let agent_receiver = GrpcReceiver::new(GrpcSink::connect(collector_endpoint).unwrap());
+        let mut shutdown = shutdown_receiver;
+        let agent = runtime.spawn(agent_receiver.clone().serve(listener, async move { shutdown.changed().await.ok(); }));
+
+        let batches = MemorySink::new();
+        let mut handler = EventBatchHandler::<Request>::new(BatchPolicy::new(10, chrono::Duration::seconds(10)))
+            .with_sink(batches.clone());
+        handler.record(Request { start_time: 1, end_time: 2, method: "GET".into(), http_code: 200 }).unwrap();
+        handler.flush().unwrap();
+        let resource_events = match batches.take().pop() {
+            Some(ResourceBatch::Events(resource_events)) => resource_events,
+            batch => panic!("unexpected batch: {:?}", batch),
+        };
+        let mut mismatching_resource_events = resource_events.clone();
+        mismatching_resource_events.instrumentation_library_events[0].batches[0].string_values.clear();
+
+        runtime.block_on(async {
+            let mut client = EventsServiceClient::connect(agent_endpoint).await.unwrap();
+            client.export(ExportEventsServiceRequest { resource_events: vec![resource_events.clone()] }).await.unwrap();
+            assert_eq!(received_batches.take(), vec![ResourceBatch::Events(resource_events.clone())]);
+
+            // Nothing exported, the request can be retried.
+            let status = client.export(ExportEventsServiceRequest { resource_events: vec![mismatching_resource_events.clone()] })
+                .await.unwrap_err();
+            assert_eq!(status.code(), Code::Unavailable);
+
+            // The first group is exported, a retry would export it again.
+            let request = ExportEventsServiceRequest { resource_events: vec![resource_events.clone(), mismatching_resource_events] };
+            let status = client.export(request).await.unwrap_err();
+            assert_eq!(status.code(), Code::Internal);
+            assert!(status.message().contains("1 of 2 resource groups exported"));
+            assert_eq!(received_batches.take(), vec![ResourceBatch::Events(resource_events)]);
+        });
+
+        // The runtime of the `GrpcSink` can't be dropped by the tasks of the agent, and its connection must be closed
+        // before the collector shuts down.
+        shutdown_sender.send(()).unwrap();
+        runtime.block_on(agent).unwrap().unwrap();
+        drop(agent_receiver);
+        runtime.block_on(collector).unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_export_logs() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let received_batches = MemorySink::new();
+        let (shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
+        let server = runtime.spawn(GrpcReceiver::new(received_batches.clone()).serve(listener, async {
+            shutdown_receiver.await.ok();
+        }));
+
+        let resource_logs = ResourceLogs {
+            resource: None,
+            instrumentation_library_logs: vec![InstrumentationLibraryLogs {
+                instrumentation_library: Some(InstrumentationLibrary { name: "logger".into(), version: "1.0".into() }),
+                logs: vec![
+                    LogRecord { time_unix_nano: 1, severity_text: "INFO".into(), ..Default::default() },
+                    LogRecord { time_unix_nano: 2, name: "crash".into(), ..Default::default() },
+                ],
+                schema_url: "https://opentelemetry.io/schemas/1.7.0".into(),
+            }],
+            schema_url: "".into(),
+        };
+        runtime.block_on(async {
+            let mut client = LogsServiceClient::connect(endpoint).await.unwrap();
+            client.export(ExportLogsServiceRequest { resource_logs: vec![resource_logs.clone()] }).await.unwrap();
+        });
+
+        match received_batches.take().as_slice() {
+            [ResourceBatch::Events(resource_events)] => assert_eq!(to_resource_logs(resource_events).unwrap(), resource_logs),
+            batches => panic!("unexpected batches: {:?}", batches),
+        }
+
+        shutdown_sender.send(()).unwrap();
+        runtime.block_on(server).unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_export_spans() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let received_batches = MemorySink::new();
+        let (shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
+        let server = runtime.spawn(GrpcReceiver::new(received_batches.clone()).serve(listener, async {
+            shutdown_receiver.await.ok();
+        }));
+
+        let resource_spans = ResourceSpans {
+            resource: None,
+            instrumentation_library_spans: vec![InstrumentationLibrarySpans {
+                instrumentation_library: Some(InstrumentationLibrary { name: "tracer".into(), version: "1.0".into() }),
+                spans: vec![
+                    Span { trace_id: vec![1; 16], span_id: vec![1; 8], name: "span_1".into(), start_time_unix_nano: 1, end_time_unix_nano: 2, ..Default::default() },
+                    Span { trace_id: vec![1; 16], span_id: vec![2; 8], parent_span_id: vec![1; 8], name: "span_2".into(), ..Default::default() },
+                ],
+                schema_url: "https://opentelemetry.io/schemas/1.7.0".into(),
+            }],
+            schema_url: "".into(),
+        };
+        runtime.block_on(async {
+            let mut client = TraceServiceClient::connect(endpoint).await.unwrap();
+            client.export(ExportTraceServiceRequest { resource_spans: vec![resource_spans.clone()] }).await.unwrap();
+        });
+
+        match received_batches.take().as_slice() {
+            [ResourceBatch::Events(resource_events)] => assert_eq!(to_resource_spans(resource_events).unwrap(), resource_spans),
+            batches => panic!("unexpected batches: {:?}", batches),
+        }
+
+        shutdown_sender.send(()).unwrap();
+        runtime.block_on(server).unwrap().unwrap();
+    }
+}
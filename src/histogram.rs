@@ -0,0 +1,457 @@
+//! Columnar histograms: explicit bucket and exponential histograms stored one per row in `HistogramValues` and
+//! `ExponentialHistogramValues`, the bucket lists of all the rows being concatenated (see `histogram.proto`).
+//!
+//! `Histogram` and `ExponentialHistogram` are the row representations used to append and read the values, e.g. as
+//! fields of an `OpenTelemetryEvent`. Explicit bucket histograms convert to and from the standard
+//! `HistogramDataPoint`, min and max being lost as this version of OTLP doesn't define them.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::event::{is_defined, push_validity, reset_validity_bitmap, BatchPolicy};
+use crate::opentelemetry::proto::common::v1::KeyValue;
+use crate::opentelemetry::proto::events::v1::{
+    AggregationTemporality, ExponentialHistogramColumn, ExponentialHistogramValues, HistogramColumn, HistogramValues,
+};
+use crate::opentelemetry::proto::metrics::v1::HistogramDataPoint;
+
+/// An explicit bucket histogram, `bucket_counts` has one more element than `explicit_bounds`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Histogram {
+    pub count: u64,
+    pub sum: f64,
+    pub bucket_counts: Vec<u64>,
+    pub explicit_bounds: Vec<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+}
+
+/// The buckets of one side (positive or negative values) of an exponential histogram.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Buckets {
+    pub offset: i32,
+    pub bucket_counts: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExponentialHistogram {
+    pub count: u64,
+    pub sum: f64,
+    pub scale: i32,
+    pub zero_count: u64,
+    pub positive: Buckets,
+    pub negative: Buckets,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+}
+
+impl Histogram {
+    /// Converts the row into a standard data point, min and max are dropped.
+    pub fn into_data_point(self, attributes: Vec<KeyValue>, start_time_unix_nano: u64, time_unix_nano: u64) -> HistogramDataPoint {
+        HistogramDataPoint {
+            attributes,
+            start_time_unix_nano,
+            time_unix_nano,
+            count: self.count,
+            sum: self.sum,
+            bucket_counts: self.bucket_counts,
+            explicit_bounds: self.explicit_bounds,
+            ..Default::default()
+        }
+    }
+
+    pub fn to_json_value(&self) -> Value {
+        serde_json::to_value(self).unwrap_or(Value::Null)
+    }
+}
+
+impl From<&HistogramDataPoint> for Histogram {
+    fn from(data_point: &HistogramDataPoint) -> Self {
+        Histogram {
+            count: data_point.count,
+            sum: data_point.sum,
+            bucket_counts: data_point.bucket_counts.clone(),
+            explicit_bounds: data_point.explicit_bounds.clone(),
+            min: None,
+            max: None,
+        }
+    }
+}
+
+impl ExponentialHistogram {
+    pub fn to_json_value(&self) -> Value {
+        serde_json::to_value(self).unwrap_or(Value::Null)
+    }
+}
+
+impl HistogramValues {
+    pub fn len(&self) -> usize {
+        self.count_values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn push(&mut self, histogram: &Histogram) {
+        self.count_values.push(histogram.count);
+        self.sum_values.push(histogram.sum);
+        push_list(&mut self.bucket_counts, &mut self.bucket_counts_offsets, &histogram.bucket_counts);
+        push_list(&mut self.explicit_bounds, &mut self.explicit_bounds_offsets, &histogram.explicit_bounds);
+        push_optional(&mut self.min_values, &mut self.min_validity_bitmap, histogram.min);
+        push_optional(&mut self.max_values, &mut self.max_validity_bitmap, histogram.max);
+    }
+
+    /// Returns `None` if the row doesn't exist.
+    pub fn get(&self, row: usize) -> Option<Histogram> {
+        Some(Histogram {
+            count: *self.count_values.get(row)?,
+            sum: *self.sum_values.get(row)?,
+            bucket_counts: list_at(&self.bucket_counts, &self.bucket_counts_offsets, row)?.to_vec(),
+            explicit_bounds: list_at(&self.explicit_bounds, &self.explicit_bounds_offsets, row)?.to_vec(),
+            min: optional_at(&self.min_values, &self.min_validity_bitmap, row)?,
+            max: optional_at(&self.max_values, &self.max_validity_bitmap, row)?,
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=Histogram> + '_ {
+        (0..self.len()).filter_map(move |row| self.get(row))
+    }
+
+    /// Removes all the rows, keeping the allocated capacity.
+    pub fn clear(&mut self) {
+        self.count_values.clear();
+        self.sum_values.clear();
+        self.bucket_counts_offsets.clear();
+        self.bucket_counts.clear();
+        self.explicit_bounds_offsets.clear();
+        self.explicit_bounds.clear();
+        self.min_values.clear();
+        self.min_validity_bitmap.clear();
+        self.max_values.clear();
+        self.max_validity_bitmap.clear();
+    }
+}
+
+impl ExponentialHistogramValues {
+    pub fn len(&self) -> usize {
+        self.count_values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn push(&mut self, histogram: &ExponentialHistogram) {
+        self.count_values.push(histogram.count);
+        self.sum_values.push(histogram.sum);
+        self.scale_values.push(histogram.scale);
+        self.zero_count_values.push(histogram.zero_count);
+        self.positive_offset_values.push(histogram.positive.offset);
+        push_list(&mut self.positive_bucket_counts, &mut self.positive_bucket_counts_offsets, &histogram.positive.bucket_counts);
+        self.negative_offset_values.push(histogram.negative.offset);
+        push_list(&mut self.negative_bucket_counts, &mut self.negative_bucket_counts_offsets, &histogram.negative.bucket_counts);
+        push_optional(&mut self.min_values, &mut self.min_validity_bitmap, histogram.min);
+        push_optional(&mut self.max_values, &mut self.max_validity_bitmap, histogram.max);
+    }
+
+    /// Returns `None` if the row doesn't exist.
+    pub fn get(&self, row: usize) -> Option<ExponentialHistogram> {
+        Some(ExponentialHistogram {
+            count: *self.count_values.get(row)?,
+            sum: *self.sum_values.get(row)?,
+            scale: *self.scale_values.get(row)?,
+            zero_count: *self.zero_count_values.get(row)?,
+            positive: Buckets {
+                offset: *self.positive_offset_values.get(row)?,
+                bucket_counts: list_at(&self.positive_bucket_counts, &self.positive_bucket_counts_offsets, row)?.to_vec(),
+            },
+            negative: Buckets {
+                offset: *self.negative_offset_values.get(row)?,
+                bucket_counts: list_at(&self.negative_bucket_counts, &self.negative_bucket_counts_offsets, row)?.to_vec(),
+            },
+            min: optional_at(&self.min_values, &self.min_validity_bitmap, row)?,
+            max: optional_at(&self.max_values, &self.max_validity_bitmap, row)?,
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=ExponentialHistogram> + '_ {
+        (0..self.len()).filter_map(move |row| self.get(row))
+    }
+
+    /// Removes all the rows, keeping the allocated capacity.
+    pub fn clear(&mut self) {
+        self.count_values.clear();
+        self.sum_values.clear();
+        self.scale_values.clear();
+        self.zero_count_values.clear();
+        self.positive_offset_values.clear();
+        self.positive_bucket_counts_offsets.clear();
+        self.positive_bucket_counts.clear();
+        self.negative_offset_values.clear();
+        self.negative_bucket_counts_offsets.clear();
+        self.negative_bucket_counts.clear();
+        self.min_values.clear();
+        self.min_validity_bitmap.clear();
+        self.max_values.clear();
+        self.max_validity_bitmap.clear();
+    }
+}
+
+impl HistogramColumn {
+    pub fn new(name: impl Into<String>, description: impl Into<String>, unit: impl Into<String>, aggregation_temporality: AggregationTemporality) -> Self {
+        HistogramColumn {
+            name: name.into(),
+            description: description.into(),
+            unit: unit.into(),
+            aggregation_temporality: aggregation_temporality as i32,
+            values: Some(HistogramValues::default()),
+            validity_bitmap: vec![],
+        }
+    }
+
+    /// Same as `new` with a validity bitmap sized for the batch policy.
+    pub fn new_optional(name: impl Into<String>, description: impl Into<String>, unit: impl Into<String>, aggregation_temporality: AggregationTemporality, batch_policy: &BatchPolicy) -> Self {
+        HistogramColumn {
+            validity_bitmap: crate::event::validity_bitmap(batch_policy.max_size as usize),
+            ..HistogramColumn::new(name, description, unit, aggregation_temporality)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.as_ref().map_or(0, HistogramValues::len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Appends a row, a null is stored as an empty histogram.
+    pub fn push(&mut self, histogram: Option<&Histogram>) {
+        let values = self.values.get_or_insert_with(Default::default);
+        push_validity(&mut self.validity_bitmap, values.len(), histogram.is_some());
+        values.push(histogram.unwrap_or(&Histogram::default()));
+    }
+
+    /// Returns `None` if the value is null or the row doesn't exist.
+    pub fn value(&self, row: usize) -> Option<Histogram> {
+        if !is_defined(&self.validity_bitmap, row) {
+            return None;
+        }
+        self.values.as_ref()?.get(row)
+    }
+
+    /// Removes all the rows, keeping the allocated capacity.
+    pub fn clear(&mut self) {
+        if let Some(values) = self.values.as_mut() {
+            values.clear();
+        }
+        reset_validity_bitmap(&mut self.validity_bitmap);
+    }
+}
+
+impl ExponentialHistogramColumn {
+    pub fn new(name: impl Into<String>, description: impl Into<String>, unit: impl Into<String>, aggregation_temporality: AggregationTemporality) -> Self {
+        ExponentialHistogramColumn {
+            name: name.into(),
+            description: description.into(),
+            unit: unit.into(),
+            aggregation_temporality: aggregation_temporality as i32,
+            values: Some(ExponentialHistogramValues::default()),
+            validity_bitmap: vec![],
+        }
+    }
+
+    /// Same as `new` with a validity bitmap sized for the batch policy.
+    pub fn new_optional(name: impl Into<String>, description: impl Into<String>, unit: impl Into<String>, aggregation_temporality: AggregationTemporality, batch_policy: &BatchPolicy) -> Self {
+        ExponentialHistogramColumn {
+            validity_bitmap: crate::event::validity_bitmap(batch_policy.max_size as usize),
+            ..ExponentialHistogramColumn::new(name, description, unit, aggregation_temporality)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.as_ref().map_or(0, ExponentialHistogramValues::len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Appends a row, a null is stored as an empty histogram.
+    pub fn push(&mut self, histogram: Option<&ExponentialHistogram>) {
+        let values = self.values.get_or_insert_with(Default::default);
+        push_validity(&mut self.validity_bitmap, values.len(), histogram.is_some());
+        values.push(histogram.unwrap_or(&ExponentialHistogram::default()));
+    }
+
+    /// Returns `None` if the value is null or the row doesn't exist.
+    pub fn value(&self, row: usize) -> Option<ExponentialHistogram> {
+        if !is_defined(&self.validity_bitmap, row) {
+            return None;
+        }
+        self.values.as_ref()?.get(row)
+    }
+
+    /// Removes all the rows, keeping the allocated capacity.
+    pub fn clear(&mut self) {
+        if let Some(values) = self.values.as_mut() {
+            values.clear();
+        }
+        reset_validity_bitmap(&mut self.validity_bitmap);
+    }
+}
+
+/// Appends a list and its end offset.
+fn push_list<T: Copy>(values: &mut Vec<T>, offsets: &mut Vec<u32>, list: &[T]) {
+    values.extend_from_slice(list);
+    offsets.push(values.len() as u32);
+}
+
+fn list_at<'a, T>(values: &'a [T], offsets: &[u32], row: usize) -> Option<&'a [T]> {
+    let start = if row == 0 { 0 } else { *offsets.get(row - 1)? as usize };
+    let end = *offsets.get(row)? as usize;
+    values.get(start..end)
+}
+
+fn push_optional(values: &mut Vec<f64>, validity_bitmap: &mut Vec<u8>, value: Option<f64>) {
+    push_validity(validity_bitmap, values.len(), value.is_some());
+    values.push(value.unwrap_or_default());
+}
+
+fn optional_at(values: &[f64], validity_bitmap: &[u8], row: usize) -> Option<Option<f64>> {
+    let value = *values.get(row)?;
+    Some(Some(value).filter(|_| is_defined(validity_bitmap, row)))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::event::{BatchPolicy, EventBatchHandler, OpenTelemetryEvent, to_batch_event};
+    use crate::event_decoder::{FromBatchEvent, decode_batch_event};
+    use crate::histogram::{Buckets, ExponentialHistogram, Histogram};
+    use crate::schema::{ColumnType, EventSchema};
+    use crate::opentelemetry::proto::events::v1::{ExponentialHistogramValues, HistogramValues};
+    use crate::opentelemetry::proto::events::v1::{AggregationTemporality, HistogramColumn};
+    use crate::opentelemetry::proto::metrics::v1::HistogramDataPoint;
+
+    fn histogram(count: u64, min: Option<f64>) -> Histogram {
+        Histogram { count, sum: count as f64 * 1.5, bucket_counts: vec![count, 0], explicit_bounds: vec![10.0], min, max: min.map(|min| min + 1.0) }
+    }
+
+    #[test]
+    fn test_histogram_values() {
+        let histograms = vec![histogram(1, Some(1.0)), Histogram::default(), histogram(3, None)];
+        let mut values = HistogramValues::default();
+        for histogram in &histograms {
+            values.push(histogram);
+        }
+
+        assert_eq!(values.len(), 3);
+        assert_eq!(values.bucket_counts_offsets, vec![2, 2, 4]);
+        assert_eq!(values.min_validity_bitmap, vec![0b001]);
+        assert_eq!(values.iter().collect::<Vec<_>>(), histograms);
+        assert_eq!(values.get(3), None);
+
+        let exponential_histogram = ExponentialHistogram {
+            count: 4,
+            sum: 2.0,
+            scale: -1,
+            zero_count: 1,
+            positive: Buckets { offset: -2, bucket_counts: vec![1, 2] },
+            negative: Buckets { offset: 0, bucket_counts: vec![] },
+            min: Some(-0.5),
+            max: Some(1.5),
+        };
+        let mut values = ExponentialHistogramValues::default();
+        values.push(&exponential_histogram);
+        values.push(&ExponentialHistogram::default());
+        assert_eq!(values.get(0), Some(exponential_histogram));
+        assert_eq!(values.get(1).map(|histogram| histogram.min), Some(None));
+    }
+
+    #[test]
+    fn test_histogram_column() {
+        let mut column = HistogramColumn::new("latency", "", "ms", AggregationTemporality::Delta);
+        column.push(Some(&histogram(1, None)));
+        assert!(column.validity_bitmap.is_empty());
+        column.push(None);
+        column.push(Some(&histogram(2, Some(0.5))));
+
+        assert_eq!(column.len(), 3);
+        assert_eq!(column.validity_bitmap, vec![0b101]);
+        assert_eq!(column.value(0), Some(histogram(1, None)));
+        assert_eq!(column.value(1), None);
+        assert_eq!(column.value(2), Some(histogram(2, Some(0.5))));
+    }
+
+    #[derive(Debug, Clone, PartialEq, OpenTelemetryEvent, FromBatchEvent)]
+    #[otel(urn = "urn:test:latency")]
+    struct Latency {
+        #[otel(start_time)]
+        time: u64,
+        endpoint: String,
+        #[otel(unit = "ms", aggregation_temporality = "delta")]
+        latency: Histogram,
+        #[otel(unit = "By")]
+        sizes: Option<ExponentialHistogram>,
+    }
+
+    fn latency_events() -> Vec<Latency> {
+        vec![
+            Latency { time: 1, endpoint: "/a".into(), latency: histogram(2, Some(1.0)), sizes: None },
+            Latency {
+                time: 2,
+                endpoint: "/b".into(),
+                latency: histogram(1, None),
+                sizes: Some(ExponentialHistogram { count: 1, sum: 8.0, positive: Buckets { offset: 3, bucket_counts: vec![1] }, ..Default::default() }),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_event() {
+        let batch = to_batch_event(latency_events()).unwrap();
+        batch.validate().unwrap();
+        assert_eq!(batch.histogram_values[0].unit, "ms");
+        assert_eq!(batch.histogram_values[0].aggregation_temporality, AggregationTemporality::Delta as i32);
+        assert_eq!(batch.exponential_histogram_values[0].validity_bitmap, vec![0b10]);
+        assert_eq!(decode_batch_event::<Latency>(&batch).unwrap(), latency_events());
+
+        let schema = EventSchema::of::<Latency>();
+        assert_eq!(schema.columns.iter().map(|column| column.column_type).collect::<Vec<_>>(), vec![ColumnType::String, ColumnType::Histogram, ColumnType::ExponentialHistogram]);
+        schema.validate(&batch).unwrap();
+
+        let mut invalid_batch = batch;
+        invalid_batch.histogram_values[0].values.as_mut().unwrap().bucket_counts_offsets[0] = 5;
+        assert!(invalid_batch.validate().is_err());
+
+        let mut handler = EventBatchHandler::<Latency>::new(BatchPolicy::new(10, chrono::Duration::MAX));
+        for event in latency_events() {
+            handler.record(event).unwrap();
+        }
+        let json = handler.to_json_value().unwrap();
+        assert_eq!(json[0]["latency"], serde_json::json!({"count": 2, "sum": 3.0, "bucket_counts": [2, 0], "explicit_bounds": [10.0], "min": 1.0, "max": 2.0}));
+        assert!(json[0].get("sizes").is_none());
+        assert_eq!(json[1]["sizes"]["positive"], serde_json::json!({"offset": 3, "bucket_counts": [1]}));
+    }
+
+    #[test]
+    fn test_data_point() {
+        let data_point = HistogramDataPoint {
+            start_time_unix_nano: 1,
+            time_unix_nano: 2,
+            count: 3,
+            sum: 4.5,
+            bucket_counts: vec![1, 2],
+            explicit_bounds: vec![1.0],
+            ..Default::default()
+        };
+        let histogram = Histogram::from(&data_point);
+        assert_eq!(histogram.min, None);
+        assert_eq!(histogram.into_data_point(vec![], 1, 2), data_point);
+    }
+}
@@ -0,0 +1,761 @@
+//! OTLP/HTTP transport for the columnar events, the Arrow events and the metrics.
+//!
+//! `HttpSink` POSTs the `Export*ServiceRequest` messages to `/v1/events`, `/v1/arrow_events` and `/v1/metrics`,
+//! encoded as protobuf (`application/x-protobuf`) or as the serde JSON of the prost messages (`application/x-serde+json`,
+//! which is NOT the OTLP/JSON encoding: snake_case field names, ids and bytes as arrays of numbers) and optionally
+//! compressed (`Content-Encoding: gzip`, `zstd` or `lz4`, the latter being a LZ4 block prefixed with its uncompressed
+//! size). OTLP/JSON requests (`application/json`) are rejected with 415.
+//! Requests rejected with 429 or 503 are retried after the delay given by the `Retry-After` header (delay in seconds
+//! or IMF-fixdate, the obsolete date formats fall back to the retry delay of the sink), capped by the max retry delay of
+//! the sink. Each attempt fails after the timeout of the sink. `HttpReceiver` is a minimal receiver for local testing.
+//!
+//! The transport failures are reported as `HttpError`, wrapped into `Error::SinkError` by the `EventSink` methods.
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use flate2::Compression as GzipLevel;
+use flate2::read::GzDecoder;
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use hyper::body::HttpBody;
+use hyper::client::HttpConnector;
+use hyper::header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, RETRY_AFTER};
+use hyper::server::conn::AddrIncoming;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Client, Method, Request, Response, Server, StatusCode};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+use tokio::time::Instant;
+
+use crate::event::Error;
+use crate::opentelemetry::proto::arrow_events::v1 as arrow_events;
+use crate::opentelemetry::proto::collector::arrow_events::v1::ExportArrowEventsServiceRequest;
+use crate::opentelemetry::proto::collector::events::v1::ExportEventsServiceRequest;
+use crate::opentelemetry::proto::collector::metrics::v1::ExportMetricsServiceRequest;
+use crate::opentelemetry::proto::events::v1::ResourceEvents;
+use crate::opentelemetry::proto::metrics::v1::ResourceMetrics;
+use crate::schema::SchemaRegistry;
+use crate::sink::EventSink;
+
+pub const EVENTS_PATH: &str = "/v1/events";
+pub const ARROW_EVENTS_PATH: &str = "/v1/arrow_events";
+pub const METRICS_PATH: &str = "/v1/metrics";
+
+/// Default max size of a request body received by `HttpReceiver`, before decompression (16 MiB).
+pub const DEFAULT_MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+/// Default max size of a request body received by `HttpReceiver`, after decompression (64 MiB).
+pub const DEFAULT_MAX_DECOMPRESSED_BODY_SIZE: usize = 64 * 1024 * 1024;
+/// Default timeout of a request sent by `HttpSink`, response body included.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Default max delay before retrying a request, whatever the `Retry-After` header of the response.
+pub const DEFAULT_MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(thiserror::Error, Debug)]
+pub enum HttpError {
+    #[error("HTTP Error (error: {0})")]
+    HyperError(#[from] hyper::Error),
+    #[error("HTTP Request Error (error: {0})")]
+    RequestError(#[from] hyper::http::Error),
+    #[error("HTTP Status Error (status: {status}, message: {message})")]
+    StatusError { status: u16, message: String },
+    #[error("Payload Too Large (max size: {0} bytes)")]
+    PayloadTooLarge(usize),
+    #[error("HTTP Timeout (timeout: {0:?})")]
+    Timeout(Duration),
+    #[error(transparent)]
+    EventError(#[from] Error),
+}
+
+impl From<HttpError> for Error {
+    fn from(err: HttpError) -> Self {
+        match err {
+            HttpError::EventError(err) => err,
+            err => Error::SinkError(Box::new(err)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentType {
+    Protobuf,
+    /// Serde JSON of the prost messages, only understood by `HttpReceiver`.
+    SerdeJson,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Lz4,
+}
+
+/// Exports the batches to an OTLP/HTTP endpoint. Each export blocks until the endpoint responds (retries included), so
+/// this sink must not be used from an async context.
+#[derive(Debug)]
+pub struct HttpSink {
+    runtime: Runtime,
+    client: Client<HttpConnector>,
+    endpoint: String,
+    content_type: ContentType,
+    compression: Compression,
+    max_retries: usize,
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+    timeout: Duration,
+}
+
+/// Receives the batches exported by an `HttpSink` and forwards them to a local sink. Invalid requests are rejected with
+/// 400 (undecodable or invalid batch), 404 (unknown path), 405 (not a POST), 413 (body too large, before or after
+/// decompression) or 415 (unsupported content type or encoding).
+///
+/// The sink runs on a blocking thread, so it can be a blocking sink (e.g. an `HttpSink`). The resource groups of a
+/// request are exported in order and the export stops at the first failure of the sink: the request is rejected with
+/// 503 if no group was exported (the request can be retried), and with 500 otherwise (a retry would export the first
+/// groups again), the message giving the number of exported groups.
+#[derive(Debug)]
+pub struct HttpReceiver<S: EventSink> {
+    sink: Arc<Mutex<S>>,
+    schema_registry: Arc<SchemaRegistry>,
+    max_body_size: usize,
+    max_decompressed_body_size: usize,
+}
+
+impl ContentType {
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            ContentType::Protobuf => "application/x-protobuf",
+            ContentType::SerdeJson => "application/x-serde+json",
+        }
+    }
+
+    /// Parses a `Content-Type` header, the parameters (e.g. `charset`) are ignored.
+    pub fn from_mime_type(mime_type: &str) -> Option<Self> {
+        match mime_type.split(';').next().unwrap_or_default().trim() {
+            "application/x-protobuf" => Some(ContentType::Protobuf),
+            "application/x-serde+json" => Some(ContentType::SerdeJson),
+            _ => None,
+        }
+    }
+
+    fn encode<M: Message + Serialize>(&self, message: &M) -> Result<Vec<u8>, Error> {
+        match self {
+            ContentType::Protobuf => {
+                let mut buf = Vec::with_capacity(message.encoded_len());
+                message.encode(&mut buf)?;
+                Ok(buf)
+            }
+            ContentType::SerdeJson => Ok(serde_json::to_vec(message)?),
+        }
+    }
+
+    fn decode<M: Message + Default + DeserializeOwned>(&self, buf: &[u8]) -> Result<M, Error> {
+        match self {
+            ContentType::Protobuf => Ok(M::decode(buf)?),
+            ContentType::SerdeJson => Ok(serde_json::from_slice(buf)?),
+        }
+    }
+}
+
+impl Compression {
+    /// Value of the `Content-Encoding` header, None without compression.
+    pub fn content_encoding(&self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Zstd => Some("zstd"),
+            Compression::Lz4 => Some("lz4"),
+        }
+    }
+
+    pub fn from_content_encoding(content_encoding: &str) -> Option<Self> {
+        match content_encoding.trim() {
+            "" | "identity" => Some(Compression::None),
+            "gzip" => Some(Compression::Gzip),
+            "zstd" => Some(Compression::Zstd),
+            "lz4" => Some(Compression::Lz4),
+            _ => None,
+        }
+    }
+
+    pub fn compress(&self, buf: Vec<u8>) -> Result<Vec<u8>, Error> {
+        match self {
+            Compression::None => Ok(buf),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), GzipLevel::default());
+                encoder.write_all(&buf)?;
+                Ok(encoder.finish()?)
+            }
+            Compression::Zstd => Ok(zstd::encode_all(buf.as_slice(), 0)?),
+            Compression::Lz4 => Ok(lz4_flex::compress_prepend_size(&buf)),
+        }
+    }
+
+    /// Fails with `HttpError::PayloadTooLarge` if the decompressed payload is larger than `max_size` bytes, the
+    /// decompression stops as soon as this size is exceeded.
+    pub fn decompress(&self, buf: Vec<u8>, max_size: usize) -> Result<Vec<u8>, HttpError> {
+        let decompressed_buf = match self {
+            Compression::None if buf.len() > max_size => return Err(HttpError::PayloadTooLarge(max_size)),
+            Compression::None => buf,
+            Compression::Gzip => read_to_end(GzDecoder::new(buf.as_slice()), max_size)?,
+            Compression::Zstd => read_to_end(zstd::Decoder::new(buf.as_slice()).map_err(Error::from)?, max_size)?,
+            Compression::Lz4 => {
+                if buf.len() < 4 {
+                    return Err(Error::InvalidFormat("missing lz4 size prefix".into()).into());
+                }
+                let size = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+                if size > max_size {
+                    return Err(HttpError::PayloadTooLarge(max_size));
+                }
+                lz4_flex::decompress(&buf[4..], size).map_err(|err| Error::InvalidFormat(err.to_string()))?
+            }
+        };
+        if decompressed_buf.len() > max_size {
+            return Err(HttpError::PayloadTooLarge(max_size));
+        }
+        Ok(decompressed_buf)
+    }
+}
+
+impl HttpSink {
+    /// Creates a sink exporting protobuf messages without compression to an endpoint, e.g. `http://127.0.0.1:4318`.
+    /// A rejected request is retried up to 3 times, each attempt times out after `DEFAULT_TIMEOUT`.
+    pub fn new(endpoint: impl Into<String>) -> Result<Self, HttpError> {
+        Ok(HttpSink {
+            runtime: tokio::runtime::Builder::new_current_thread().enable_all().build().map_err(Error::from)?,
+            client: Client::new(),
+            endpoint: endpoint.into().trim_end_matches('/').to_string(),
+            content_type: ContentType::Protobuf,
+            compression: Compression::None,
+            max_retries: 3,
+            retry_delay: Duration::from_secs(1),
+            max_retry_delay: DEFAULT_MAX_RETRY_DELAY,
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    pub fn with_content_type(mut self, content_type: ContentType) -> Self {
+        self.content_type = content_type;
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the number of retries of a request rejected with 429 or 503, and the delay between two attempts when the
+    /// response has no `Retry-After` header.
+    pub fn with_retries(mut self, max_retries: usize, retry_delay: Duration) -> Self {
+        self.max_retries = max_retries;
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    /// Sets the max delay between two attempts (`DEFAULT_MAX_RETRY_DELAY` by default), longer `Retry-After` delays are
+    /// shortened to this delay.
+    pub fn with_max_retry_delay(mut self, max_retry_delay: Duration) -> Self {
+        self.max_retry_delay = max_retry_delay;
+        self
+    }
+
+    /// Sets the timeout of each attempt, from the connection to the end of the response body (`DEFAULT_TIMEOUT` by
+    /// default). A request timing out fails with `HttpError::Timeout` and isn't retried.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn post<M: Message + Serialize>(&mut self, path: &str, message: &M) -> Result<(), HttpError> {
+        let body = self.compression.compress(self.content_type.encode(message)?)?;
+        let uri = format!("{}{}", self.endpoint, path);
+
+        let mut retries = 0;
+        loop {
+            let mut request = Request::builder()
+                .method(Method::POST)
+                .uri(uri.as_str())
+                .header(CONTENT_TYPE, self.content_type.mime_type());
+            if let Some(content_encoding) = self.compression.content_encoding() {
+                request = request.header(CONTENT_ENCODING, content_encoding);
+            }
+            let request = request.body(Body::from(body.clone()))?;
+
+            let deadline = Instant::now() + self.timeout;
+            let response = self.block_on(self.client.request(request), deadline)??;
+            let status = response.status();
+            if status.is_success() {
+                return Ok(());
+            }
+
+            let retryable = status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE;
+            if retryable && retries < self.max_retries {
+                let retry_delay = response.headers().get(RETRY_AFTER)
+                    .and_then(|retry_after| retry_after.to_str().ok())
+                    .and_then(|retry_after| parse_retry_after(retry_after, Utc::now()))
+                    .unwrap_or(self.retry_delay);
+                std::thread::sleep(retry_delay.min(self.max_retry_delay));
+                retries += 1;
+                continue;
+            }
+
+            let body = self.block_on(hyper::body::to_bytes(response.into_body()), deadline)??;
+            return Err(HttpError::StatusError { status: status.as_u16(), message: String::from_utf8_lossy(&body).into() });
+        }
+    }
+
+    /// Runs a step of a request, failing if it doesn't complete before the deadline of the attempt.
+    fn block_on<F: Future>(&self, future: F, deadline: Instant) -> Result<F::Output, HttpError> {
+        self.runtime.block_on(async { tokio::time::timeout_at(deadline, future).await })
+            .map_err(|_| HttpError::Timeout(self.timeout))
+    }
+}
+
+impl EventSink for HttpSink {
+    fn export_events(&mut self, resource_events: ResourceEvents) -> Result<(), Error> {
+        Ok(self.post(EVENTS_PATH, &ExportEventsServiceRequest { resource_events: vec![resource_events] })?)
+    }
+
+    fn export_arrow_events(&mut self, resource_events: arrow_events::ResourceEvents) -> Result<(), Error> {
+        Ok(self.post(ARROW_EVENTS_PATH, &ExportArrowEventsServiceRequest { resource_events: vec![resource_events] })?)
+    }
+
+    fn export_metrics(&mut self, resource_metrics: ResourceMetrics) -> Result<(), Error> {
+        Ok(self.post(METRICS_PATH, &ExportMetricsServiceRequest { resource_metrics: vec![resource_metrics] })?)
+    }
+}
+
+impl<S: EventSink + Send + 'static> HttpReceiver<S> {
+    pub fn new(sink: S) -> Self {
+        HttpReceiver {
+            sink: Arc::new(Mutex::new(sink)),
+            schema_registry: Arc::new(SchemaRegistry::new()),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            max_decompressed_body_size: DEFAULT_MAX_DECOMPRESSED_BODY_SIZE,
+        }
+    }
+
+    /// Sets the max size of a request body, before and after decompression (`DEFAULT_MAX_BODY_SIZE` and
+    /// `DEFAULT_MAX_DECOMPRESSED_BODY_SIZE` by default). Larger requests are rejected with 413.
+    pub fn with_max_body_size(mut self, max_body_size: usize, max_decompressed_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self.max_decompressed_body_size = max_decompressed_body_size;
+        self
+    }
+
+    /// Validates the received batches of the registered URNs against their schema.
+    pub fn with_schema_registry(mut self, schema_registry: SchemaRegistry) -> Self {
+        self.schema_registry = Arc::new(schema_registry);
+        self
+    }
+
+    /// Serves the export requests until `shutdown` completes.
+    pub async fn serve(self, listener: TcpListener, shutdown: impl Future<Output=()>) -> Result<(), HttpError> {
+        let make_service = make_service_fn(move |_| {
+            let receiver = self.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| receiver.clone().handle(request)))
+            }
+        });
+
+        Server::builder(AddrIncoming::from_listener(listener)?)
+            .serve(make_service)
+            .with_graceful_shutdown(shutdown)
+            .await?;
+        Ok(())
+    }
+
+    async fn handle(self, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+        let (status, message) = match self.receive(request).await {
+            Ok(()) => (StatusCode::OK, String::new()),
+            Err(err) => err,
+        };
+        let mut response = Response::new(Body::from(message));
+        *response.status_mut() = status;
+        Ok(response)
+    }
+
+    async fn receive(&self, request: Request<Body>) -> Result<(), (StatusCode, String)> {
+        if request.method() != Method::POST {
+            return Err((StatusCode::METHOD_NOT_ALLOWED, format!("unsupported method {}", request.method())));
+        }
+
+        let header = |name| request.headers().get(name).and_then(|value| value.to_str().ok()).unwrap_or_default();
+        let content_type = ContentType::from_mime_type(header(CONTENT_TYPE))
+            .ok_or_else(|| (StatusCode::UNSUPPORTED_MEDIA_TYPE, format!("unsupported content type '{}'", header(CONTENT_TYPE))))?;
+        let compression = Compression::from_content_encoding(header(CONTENT_ENCODING))
+            .ok_or_else(|| (StatusCode::UNSUPPORTED_MEDIA_TYPE, format!("unsupported content encoding '{}'", header(CONTENT_ENCODING))))?;
+        if header(CONTENT_LENGTH).parse::<u64>().ok() > Some(self.max_body_size as u64) {
+            return Err(payload_too_large(self.max_body_size));
+        }
+        let path = request.uri().path().to_string();
+
+        let body = read_body(request.into_body(), self.max_body_size).await?;
+        let body = compression.decompress(body, self.max_decompressed_body_size).map_err(|err| match err {
+            HttpError::PayloadTooLarge(max_size) => payload_too_large(max_size),
+            err => bad_request(err),
+        })?;
+
+        match path.as_str() {
+            EVENTS_PATH => {
+                let request: ExportEventsServiceRequest = content_type.decode(&body).map_err(bad_request)?;
+                for resource_events in &request.resource_events {
+                    self.schema_registry.validate(resource_events).map_err(bad_request)?;
+                }
+                self.export(request.resource_events, S::export_events).await?;
+            }
+            ARROW_EVENTS_PATH => {
+                let request: ExportArrowEventsServiceRequest = content_type.decode(&body).map_err(bad_request)?;
+                self.export(request.resource_events, S::export_arrow_events).await?;
+            }
+            METRICS_PATH => {
+                let request: ExportMetricsServiceRequest = content_type.decode(&body).map_err(bad_request)?;
+                for resource_metrics in &request.resource_metrics {
+                    resource_metrics.validate().map_err(bad_request)?;
+                }
+                self.export(request.resource_metrics, S::export_metrics).await?;
+            }
+            _ => return Err((StatusCode::NOT_FOUND, format!("unknown path {}", path))),
+        }
+        Ok(())
+    }
+
+    /// Exports the resource groups of a request in order on a blocking thread, stopping at the first failure.
+    async fn export<B: Send + 'static>(&self, batches: Vec<B>, export: fn(&mut S, B) -> Result<(), Error>) -> Result<(), (StatusCode, String)> {
+        let sink = self.sink.clone();
+        let count = batches.len();
+        let result = tokio::task::spawn_blocking(move || {
+            let mut sink = sink.lock().expect("receiver sink lock poisoned");
+            for (exported, batch) in batches.into_iter().enumerate() {
+                export(&mut sink, batch).map_err(|err| (exported, err))?;
+            }
+            Ok(())
+        }).await.map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+
+        result.map_err(|(exported, err)| match exported {
+            0 => (StatusCode::SERVICE_UNAVAILABLE, err.to_string()),
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, format!("{} ({} of {} resource groups exported)", err, exported, count)),
+        })
+    }
+}
+
+impl<S: EventSink> Clone for HttpReceiver<S> {
+    fn clone(&self) -> Self {
+        HttpReceiver {
+            sink: self.sink.clone(),
+            schema_registry: self.schema_registry.clone(),
+            max_body_size: self.max_body_size,
+            max_decompressed_body_size: self.max_decompressed_body_size,
+        }
+    }
+}
+
+/// Reads at most `max_size + 1` bytes, so that the caller can detect a payload larger than `max_size`.
+fn read_to_end<R: Read>(reader: R, max_size: usize) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::new();
+    reader.take((max_size as u64).saturating_add(1)).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Reads a request body, the reading stops as soon as the body is larger than `max_size`.
+async fn read_body(mut body: Body, max_size: usize) -> Result<Vec<u8>, (StatusCode, String)> {
+    let mut buf = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+        if buf.len() + chunk.len() > max_size {
+            return Err(payload_too_large(max_size));
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(buf)
+}
+
+/// Parses a `Retry-After` value, either a delay in seconds or an IMF-fixdate (e.g. `Wed, 21 Oct 2015 07:28:00 GMT`).
+/// A date in the past is a zero delay. Returns None for the obsolete date formats (RFC 850 and asctime).
+fn parse_retry_after(retry_after: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let retry_after = retry_after.trim();
+    if let Ok(seconds) = retry_after.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(retry_after).ok()?;
+    Some((date.with_timezone(&Utc) - now).to_std().unwrap_or_default())
+}
+
+fn bad_request(err: impl ToString) -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, err.to_string())
+}
+
+fn payload_too_large(max_size: usize) -> (StatusCode, String) {
+    (StatusCode::PAYLOAD_TOO_LARGE, format!("request body larger than {} bytes", max_size))
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    use chrono::{TimeZone, Utc};
+    use hyper::header::{CONTENT_TYPE, RETRY_AFTER};
+    use hyper::server::conn::AddrIncoming;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Client, Request, Response, Server, StatusCode};
+    use prost::Message;
+    use tokio::net::TcpListener;
+    use tokio::runtime::Runtime;
+
+    use crate::event::Error;
+    use crate::http::{parse_retry_after, Compression, ContentType, HttpError, HttpReceiver, HttpSink, EVENTS_PATH};
+    use crate::opentelemetry::proto::collector::events::v1::ExportEventsServiceRequest;
+    use crate::metrics_columnar::MultivariateMetricBuilder;
+    use crate::opentelemetry::proto::common::v1::InstrumentationLibrary;
+    use crate::opentelemetry::proto::events::v1::{BatchEvent, InstrumentationLibraryEvents, ResourceEvents};
+    use crate::opentelemetry::proto::metrics::v1::ResourceMetrics;
+    use crate::opentelemetry::proto::resource::v1::Resource;
+    use crate::sink::{EventSink, MemorySink, ResourceBatch};
+
+    fn resource_events(size: u32) -> ResourceEvents {
+        ResourceEvents {
+            instrumentation_library_events: vec![InstrumentationLibraryEvents {
+                batches: vec![BatchEvent {
+                    size,
+                    start_time_unix_nano_column: (0..size as u64).collect(),
+                    end_time_unix_nano_column: (0..size as u64).collect(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn resource_metrics() -> ResourceMetrics {
+        let mut builder = MultivariateMetricBuilder::new().attribute("host").double_gauge("cpu", "", "%");
+        builder.append(1u64, &["host_1"], &[0.5.into()]).unwrap();
+        builder.build_resource_metrics(Resource::default(), InstrumentationLibrary::default(), "").unwrap()
+    }
+
+    /// Returns the status of a request rejected by the endpoint, None if the export succeeded or failed otherwise.
+    fn rejected_status(result: Result<(), Error>) -> Option<u16> {
+        match result {
+            Err(Error::SinkError(err)) => match err.downcast_ref::<HttpError>() {
+                Some(HttpError::StatusError { status, .. }) => Some(*status),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_export() {
+        let runtime = Runtime::new().unwrap();
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let received_batches = MemorySink::new();
+        let (shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
+        let server = runtime.spawn(HttpReceiver::new(received_batches.clone()).serve(listener, async {
+            shutdown_receiver.await.ok();
+        }));
+
+        for &content_type in &[ContentType::Protobuf, ContentType::SerdeJson] {
+            for &compression in &[Compression::None, Compression::Gzip, Compression::Zstd, Compression::Lz4] {
+                let mut sink = HttpSink::new(endpoint.clone()).unwrap()
+                    .with_content_type(content_type)
+                    .with_compression(compression);
+                sink.export_events(resource_events(3)).unwrap();
+                sink.export_metrics(resource_metrics()).unwrap();
+                assert_eq!(received_batches.take(), vec![
+                    ResourceBatch::Events(resource_events(3)),
+                    ResourceBatch::Metrics(resource_metrics()),
+                ]);
+            }
+        }
+
+        let mut invalid_resource_events = resource_events(3);
+        invalid_resource_events.instrumentation_library_events[0].batches[0].size = 4;
+        let mut sink = HttpSink::new(endpoint).unwrap();
+        assert_eq!(rejected_status(sink.export_events(invalid_resource_events)), Some(400));
+        assert!(received_batches.is_empty());
+
+        shutdown_sender.send(()).unwrap();
+        runtime.block_on(server).unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_forward() {
+        let runtime = Runtime::new().unwrap();
+        let (shutdown_sender, shutdown_receiver) = tokio::sync::watch::channel(());
+
+        // The collector rejects the large batches.
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let collector_endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let received_batches = MemorySink::new();
+        let mut shutdown = shutdown_receiver.clone();
+        let collector = runtime.spawn(HttpReceiver::new(received_batches.clone()).with_max_body_size(1024, 1024)
+            .serve(listener, async move { shutdown.changed().await.ok(); }));
+
+        // The agent forwards the batches to the collector with a blocking sink.
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let agent_endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let agent_receiver = HttpReceiver::new(HttpSink::new(collector_endpoint).unwrap());
+        let mut shutdown = shutdown_receiver;
+        let agent = runtime.spawn(agent_receiver.clone().serve(listener, async move { shutdown.changed().await.ok(); }));
+
+        let export = |resource_events: Vec<ResourceEvents>| runtime.block_on(async {
+            let body = ExportEventsServiceRequest { resource_events }.encode_to_vec();
+            let request = Request::post(format!("{}{}", agent_endpoint, EVENTS_PATH))
+                .header(CONTENT_TYPE, ContentType::Protobuf.mime_type())
+                .body(Body::from(body))
+                .unwrap();
+            let response = Client::new().request(request).await.unwrap();
+            let status = response.status();
+            (status, String::from_utf8(hyper::body::to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap())
+        });
+        assert_eq!(export(vec![resource_events(1)]).0, StatusCode::OK);
+        assert_eq!(received_batches.take(), vec![ResourceBatch::Events(resource_events(1))]);
+
+        // Nothing exported, the request can be retried.
+        assert_eq!(export(vec![resource_events(1000)]).0, StatusCode::SERVICE_UNAVAILABLE);
+
+        // The first group is exported, a retry would export it again.
+        let (status, message) = export(vec![resource_events(1), resource_events(1000)]);
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(message.contains("1 of 2 resource groups exported"));
+        assert_eq!(received_batches.take(), vec![ResourceBatch::Events(resource_events(1))]);
+
+        // The runtime of the `HttpSink` can't be dropped by the tasks of the agent, and its connections must be closed
+        // before the collector shuts down.
+        shutdown_sender.send(()).unwrap();
+        runtime.block_on(agent).unwrap().unwrap();
+        drop(agent_receiver);
+        runtime.block_on(collector).unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_max_body_size() {
+        let runtime = Runtime::new().unwrap();
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let received_batches = MemorySink::new();
+        let (shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
+        let receiver = HttpReceiver::new(received_batches.clone()).with_max_body_size(1024, 4096);
+        let server = runtime.spawn(receiver.serve(listener, async {
+            shutdown_receiver.await.ok();
+        }));
+
+        // Body too large before decompression.
+        let mut sink = HttpSink::new(endpoint.clone()).unwrap();
+        sink.export_events(resource_events(10)).unwrap();
+        assert_eq!(rejected_status(sink.export_events(resource_events(1000))), Some(413));
+
+        // Body too large after decompression.
+        for &compression in &[Compression::Gzip, Compression::Zstd, Compression::Lz4] {
+            let mut sink = HttpSink::new(endpoint.clone()).unwrap().with_compression(compression);
+            sink.export_events(resource_events(10)).unwrap();
+            assert_eq!(rejected_status(sink.export_events(resource_events(10_000))), Some(413));
+        }
+        assert_eq!(received_batches.len(), 4);
+
+        shutdown_sender.send(()).unwrap();
+        runtime.block_on(server).unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_content_type() {
+        assert_eq!(ContentType::from_mime_type("application/x-protobuf"), Some(ContentType::Protobuf));
+        assert_eq!(ContentType::from_mime_type("application/x-serde+json; charset=utf-8"), Some(ContentType::SerdeJson));
+        // OTLP/JSON isn't supported.
+        assert_eq!(ContentType::from_mime_type("application/json"), None);
+    }
+
+    #[test]
+    fn test_decompress() {
+        for &compression in &[Compression::None, Compression::Gzip, Compression::Zstd, Compression::Lz4] {
+            let compressed = compression.compress(vec![7; 100]).unwrap();
+            assert_eq!(compression.decompress(compressed.clone(), 100).unwrap(), vec![7; 100]);
+            assert!(matches!(compression.decompress(compressed, 99), Err(HttpError::PayloadTooLarge(99))));
+        }
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = Utc.with_ymd_and_hms(2015, 10, 21, 7, 28, 0).unwrap();
+        assert_eq!(parse_retry_after(" 120 ", now), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:30:00 GMT", now), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now), Some(Duration::from_secs(0)));
+        assert_eq!(parse_retry_after("Wednesday, 21-Oct-15 07:30:00 GMT", now), None);
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    /// Starts a server responding `status` (with `Retry-After: retry_after`) to the first `failures` requests, then 200.
+    fn flaky_server(runtime: &Runtime, status: StatusCode, failures: usize, retry_after: &'static str) -> (String, Arc<AtomicUsize>) {
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let request_count = Arc::new(AtomicUsize::new(0));
+
+        let counter = request_count.clone();
+        let make_service = make_service_fn(move |_| {
+            let counter = counter.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |_| {
+                    let count = counter.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        let mut response = Response::new(Body::empty());
+                        if count < failures {
+                            *response.status_mut() = status;
+                            response.headers_mut().insert(RETRY_AFTER, retry_after.parse().unwrap());
+                        }
+                        Ok::<_, Infallible>(response)
+                    }
+                }))
+            }
+        });
+        let _guard = runtime.enter();
+        let server = Server::builder(AddrIncoming::from_listener(listener).unwrap()).serve(make_service);
+        runtime.spawn(server);
+
+        (endpoint, request_count)
+    }
+
+    #[test]
+    fn test_retry() {
+        let runtime = Runtime::new().unwrap();
+
+        let (endpoint, request_count) = flaky_server(&runtime, StatusCode::SERVICE_UNAVAILABLE, 2, "0");
+        let mut sink = HttpSink::new(endpoint).unwrap().with_retries(2, Duration::from_secs(60));
+        sink.export_events(resource_events(1)).unwrap();
+        assert_eq!(request_count.load(Ordering::SeqCst), 3);
+
+        let (endpoint, request_count) = flaky_server(&runtime, StatusCode::TOO_MANY_REQUESTS, usize::MAX, "0");
+        let mut sink = HttpSink::new(endpoint).unwrap().with_retries(1, Duration::from_secs(60));
+        assert_eq!(rejected_status(sink.export_events(resource_events(1))), Some(429));
+        assert_eq!(request_count.load(Ordering::SeqCst), 2);
+
+        // The delay requested by the endpoint is capped.
+        let (endpoint, request_count) = flaky_server(&runtime, StatusCode::TOO_MANY_REQUESTS, 1, "3600");
+        let mut sink = HttpSink::new(endpoint).unwrap().with_max_retry_delay(Duration::from_millis(10));
+        let start = Instant::now();
+        sink.export_events(resource_events(1)).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!(request_count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_timeout() {
+        // The connections are queued by the listener, but never accepted.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let mut sink = HttpSink::new(endpoint).unwrap().with_timeout(Duration::from_millis(100));
+        let result = sink.export_events(resource_events(1));
+        assert!(matches!(result, Err(Error::SinkError(err)) if matches!(err.downcast_ref::<HttpError>(), Some(HttpError::Timeout(_)))));
+    }
+}
@@ -1,10 +1,33 @@
 pub mod multivariate_ts_gen;
 pub mod metrics_std;
 pub mod metrics_columnar;
+pub mod metrics_conversion;
 pub mod event;
+pub mod event_decoder;
 pub mod serializer;
 pub mod error;
 pub mod native_trace;
+pub mod native_log;
+pub mod log_event;
+pub mod span_event;
+pub mod attribute;
+pub mod histogram;
+pub mod summary;
+pub mod dynamic_attributes;
+pub mod schema;
+pub mod sink;
+pub mod collector;
+pub mod pipeline;
+pub mod wal;
+pub mod container;
+pub mod validation;
+pub mod dictionary;
+pub mod delta_encoding;
+pub mod grpc;
+pub mod http;
+
+// Allows the code generated by the derive macros to refer to this crate from its own tests.
+extern crate self as otel_multivariate_time_series;
 
 pub mod opentelemetry {
     pub mod proto {
@@ -32,6 +55,12 @@ pub mod opentelemetry {
             }
         }
 
+        pub mod logs {
+            pub mod v1 {
+                include!(concat!(env!("OUT_DIR"), "/opentelemetry.proto.logs.v1.rs"));
+            }
+        }
+
         pub mod events {
             pub mod v1 {
                 include!(concat!(env!("OUT_DIR"), "/opentelemetry.proto.events.v1.rs"));
@@ -43,5 +72,37 @@ pub mod opentelemetry {
                 include!(concat!(env!("OUT_DIR"), "/opentelemetry.proto.arrow_events.v1.rs"));
             }
         }
+
+        pub mod collector {
+            pub mod metrics {
+                pub mod v1 {
+                    include!(concat!(env!("OUT_DIR"), "/opentelemetry.proto.collector.metrics.v1.rs"));
+                }
+            }
+
+            pub mod trace {
+                pub mod v1 {
+                    include!(concat!(env!("OUT_DIR"), "/opentelemetry.proto.collector.trace.v1.rs"));
+                }
+            }
+
+            pub mod logs {
+                pub mod v1 {
+                    include!(concat!(env!("OUT_DIR"), "/opentelemetry.proto.collector.logs.v1.rs"));
+                }
+            }
+
+            pub mod events {
+                pub mod v1 {
+                    include!(concat!(env!("OUT_DIR"), "/opentelemetry.proto.collector.events.v1.rs"));
+                }
+            }
+
+            pub mod arrow_events {
+                pub mod v1 {
+                    include!(concat!(env!("OUT_DIR"), "/opentelemetry.proto.collector.arrow_events.v1.rs"));
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,204 @@
+//! Columnar representation of the OTLP logs: a `LogRecord` is mapped onto a `LogEvent` row of a `BatchEvent`, so the
+//! logs benefit from the columnar encodings (dictionaries, delta encoded timestamps, ...).
+
+use crate::attribute::{Attribute, TypedValue, non_empty, to_attributes, to_key_values};
+use crate::event::{self, Error, OpenTelemetryEvent};
+use crate::event_decoder::{FromBatchEvent, decode_batch_event};
+use crate::opentelemetry::proto::events::v1::{BatchEvent, InstrumentationLibraryEvents, ResourceEvents};
+use crate::opentelemetry::proto::logs::v1::{InstrumentationLibraryLogs, LogRecord, ResourceLogs};
+
+/// A log record, the body is split into typed columns (see `TypedValue`).
+#[derive(Debug, Clone, Default, PartialEq, OpenTelemetryEvent, FromBatchEvent)]
+#[otel(urn = "urn:opentelemetry:log")]
+pub struct LogEvent {
+    #[otel(start_time)]
+    pub time_unix_nano: u64,
+    pub severity_number: i32,
+    pub severity_text: Option<String>,
+    pub name: Option<String>,
+    #[otel(name = "body")]
+    pub body_string: Option<String>,
+    #[otel(name = "body")]
+    pub body_int: Option<i64>,
+    #[otel(name = "body")]
+    pub body_double: Option<f64>,
+    #[otel(name = "body")]
+    pub body_bool: Option<bool>,
+    #[otel(name = "body")]
+    pub body_bytes: Option<Vec<u8>>,
+    #[otel(name = "body_json")]
+    pub body_json: Option<String>,
+    pub flags: u32,
+    #[otel(logical_type = "trace_id")]
+    pub trace_id: Option<Vec<u8>>,
+    #[otel(logical_type = "span_id")]
+    pub span_id: Option<Vec<u8>>,
+    pub dropped_attributes_count: u32,
+    #[otel(logical_type = "attribute")]
+    pub attributes: Vec<Attribute>,
+}
+
+impl LogEvent {
+    /// Empty strings and ids are stored as nulls.
+    pub fn new(log_record: LogRecord) -> Result<Self, Error> {
+        let body = TypedValue::new(log_record.body)?;
+        Ok(LogEvent {
+            time_unix_nano: log_record.time_unix_nano,
+            severity_number: log_record.severity_number,
+            severity_text: non_empty(log_record.severity_text),
+            name: non_empty(log_record.name),
+            body_string: body.string_value,
+            body_int: body.int_value,
+            body_double: body.double_value,
+            body_bool: body.bool_value,
+            body_bytes: body.bytes_value,
+            body_json: body.json_value,
+            flags: log_record.flags,
+            trace_id: Some(log_record.trace_id).filter(|trace_id| !trace_id.is_empty()),
+            span_id: Some(log_record.span_id).filter(|span_id| !span_id.is_empty()),
+            dropped_attributes_count: log_record.dropped_attributes_count,
+            attributes: to_attributes(log_record.attributes)?,
+        })
+    }
+
+    pub fn into_log_record(self) -> Result<LogRecord, Error> {
+        let body = TypedValue {
+            string_value: self.body_string,
+            int_value: self.body_int,
+            double_value: self.body_double,
+            bool_value: self.body_bool,
+            bytes_value: self.body_bytes,
+            json_value: self.body_json,
+        };
+        Ok(LogRecord {
+            time_unix_nano: self.time_unix_nano,
+            severity_number: self.severity_number,
+            severity_text: self.severity_text.unwrap_or_default(),
+            name: self.name.unwrap_or_default(),
+            body: body.into_any_value()?,
+            attributes: to_key_values(self.attributes)?,
+            dropped_attributes_count: self.dropped_attributes_count,
+            flags: self.flags,
+            trace_id: self.trace_id.unwrap_or_default(),
+            span_id: self.span_id.unwrap_or_default(),
+        })
+    }
+}
+
+/// Builds a batch of `LogEvent`s.
+pub fn to_batch_event(log_records: Vec<LogRecord>) -> Result<BatchEvent, Error> {
+    let log_events = log_records.into_iter().map(LogEvent::new).collect::<Result<Vec<_>, Error>>()?;
+    event::to_batch_event(log_events)
+}
+
+/// Converts the logs of each instrumentation library into a batch of `LogEvent`s.
+pub fn to_resource_events(resource_logs: ResourceLogs) -> Result<ResourceEvents, Error> {
+    Ok(ResourceEvents {
+        resource: resource_logs.resource,
+        instrumentation_library_events: resource_logs.instrumentation_library_logs.into_iter()
+            .map(|instrumentation_library_logs| Ok(InstrumentationLibraryEvents {
+                instrumentation_library: instrumentation_library_logs.instrumentation_library,
+                batches: vec![to_batch_event(instrumentation_library_logs.logs)?],
+                dropped_events_count: 0,
+                schema_url: instrumentation_library_logs.schema_url,
+            }))
+            .collect::<Result<_, Error>>()?,
+        schema_url: resource_logs.schema_url,
+    })
+}
+
+/// Inverse of `to_resource_events`, fails if a batch is not a batch of `LogEvent`s.
+pub fn to_resource_logs(resource_events: &ResourceEvents) -> Result<ResourceLogs, Error> {
+    let mut instrumentation_library_logs = Vec::with_capacity(resource_events.instrumentation_library_events.len());
+
+    for instrumentation_library_events in &resource_events.instrumentation_library_events {
+        let mut logs = vec![];
+        for batch in &instrumentation_library_events.batches {
+            if batch.schema_url != LogEvent::urn() {
+                return Err(Error::InvalidFormat(format!("unexpected schema url '{}' (expected '{}')", batch.schema_url, LogEvent::urn())));
+            }
+            for log_event in decode_batch_event::<LogEvent>(batch)? {
+                logs.push(log_event.into_log_record()?);
+            }
+        }
+        instrumentation_library_logs.push(InstrumentationLibraryLogs {
+            instrumentation_library: instrumentation_library_events.instrumentation_library.clone(),
+            logs,
+            schema_url: instrumentation_library_events.schema_url.clone(),
+        });
+    }
+
+    Ok(ResourceLogs {
+        resource: resource_events.resource.clone(),
+        instrumentation_library_logs,
+        schema_url: resource_events.schema_url.clone(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::log_event::{to_resource_events, to_resource_logs};
+    use crate::opentelemetry::proto::common::v1::{AnyValue, InstrumentationLibrary, KeyValue, KeyValueList};
+    use crate::opentelemetry::proto::common::v1::any_value::Value;
+    use crate::opentelemetry::proto::logs::v1::{InstrumentationLibraryLogs, LogRecord, ResourceLogs, SeverityNumber};
+
+    fn any_value(value: Value) -> Option<AnyValue> {
+        Some(AnyValue { value: Some(value) })
+    }
+
+    fn log_records() -> Vec<LogRecord> {
+        vec![
+            LogRecord {
+                time_unix_nano: 1_000,
+                severity_number: SeverityNumber::Info as i32,
+                severity_text: "INFO".into(),
+                body: any_value(Value::StringValue("user logged in".into())),
+                attributes: vec![
+                    KeyValue { key: "user".into(), value: any_value(Value::StringValue("user_1".into())) },
+                    KeyValue { key: "attempts".into(), value: any_value(Value::IntValue(2)) },
+                ],
+                trace_id: vec![1; 16],
+                span_id: vec![2; 8],
+                flags: 1,
+                ..Default::default()
+            },
+            LogRecord {
+                time_unix_nano: 2_000,
+                severity_number: SeverityNumber::Error as i32,
+                name: "crash".into(),
+                body: any_value(Value::KvlistValue(KeyValueList {
+                    values: vec![KeyValue { key: "code".into(), value: any_value(Value::IntValue(500)) }],
+                })),
+                dropped_attributes_count: 3,
+                ..Default::default()
+            },
+            LogRecord { time_unix_nano: 3_000, body: any_value(Value::DoubleValue(0.5)), ..Default::default() },
+        ]
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let resource_logs = ResourceLogs {
+            resource: None,
+            instrumentation_library_logs: vec![InstrumentationLibraryLogs {
+                instrumentation_library: Some(InstrumentationLibrary { name: "logger".into(), version: "1.0".into() }),
+                logs: log_records(),
+                schema_url: "https://opentelemetry.io/schemas/1.7.0".into(),
+            }],
+            schema_url: "https://opentelemetry.io/schemas/1.8.0".into(),
+        };
+
+        let resource_events = to_resource_events(resource_logs.clone()).unwrap();
+        resource_events.validate().unwrap();
+        let batch = &resource_events.instrumentation_library_events[0].batches[0];
+        assert_eq!(batch.size, 3);
+        assert_eq!(batch.schema_url, "urn:opentelemetry:log");
+        assert_eq!(batch.auxiliary_entities[0].size, 2);
+
+        assert_eq!(to_resource_logs(&resource_events).unwrap(), resource_logs);
+
+        let mut invalid_resource_events = resource_events;
+        invalid_resource_events.instrumentation_library_events[0].batches[0].schema_url = "urn:test".into();
+        assert!(to_resource_logs(&invalid_resource_events).is_err());
+    }
+}
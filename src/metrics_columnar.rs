@@ -1,94 +1,377 @@
+use crate::delta_encoding::Encoding;
+use crate::event::{Error, UnixNano};
+use crate::histogram::{ExponentialHistogram, Histogram};
 use crate::multivariate_ts_gen::MultivariateDataPoint;
 use crate::opentelemetry::proto::resource::v1::Resource;
-use crate::opentelemetry::proto::common::v1::{KeyValue, AnyValue, InstrumentationLibrary};
-use crate::opentelemetry::proto::common::v1::any_value::Value;
-use crate::opentelemetry::proto::metrics::v1::{ResourceMetrics, InstrumentationLibraryMetrics, MultivariateMetric, ColumnarAttribute, ColumnarMetric, ColumnarGauge, ColumnarNumberDataPoint, IntValues, columnar_number_data_point};
+use crate::opentelemetry::proto::common::v1::InstrumentationLibrary;
+use crate::opentelemetry::proto::metrics::v1::{ResourceMetrics, InstrumentationLibraryMetrics, MultivariateMetric, ColumnarAttribute, ColumnarMetric, ColumnarGauge, ColumnarSum, ColumnarHistogram, ColumnarExponentialHistogram, ColumnarNumberDataPoint, IntValues, DoubleValues, AggregationTemporality, columnar_number_data_point};
 use crate::opentelemetry::proto::metrics::v1::columnar_metric::Data;
 
-pub fn gen_columnar_metrics(time_series: &[MultivariateDataPoint]) -> ResourceMetrics {
-    ResourceMetrics {
-        resource: Some(Resource {
-            attributes: vec![
-                KeyValue { key: "key_1".into(), value: Some(AnyValue { value: Some(Value::StringValue("val1".into())) }) },
-                KeyValue { key: "key_2".into(), value: Some(AnyValue { value: Some(Value::StringValue("val2".into())) }) },
-                KeyValue { key: "key_3".into(), value: Some(AnyValue { value: Some(Value::StringValue("val3".into())) }) },
+/// A value of a metric column, see `MultivariateMetricBuilder::append`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetricValue {
+    Int(i64),
+    Double(f64),
+    Histogram(Histogram),
+    ExponentialHistogram(ExponentialHistogram),
+}
+
+/// Builds a `MultivariateMetric` row by row.
+///
+/// The attribute and metric columns are declared first, then each row provides one value per attribute and one value
+/// per metric, in declaration order.
+#[derive(Debug, Clone, Default)]
+pub struct MultivariateMetricBuilder {
+    multivariate_metric: MultivariateMetric,
+    rows_with_start_time: usize,
+    time_encoding: Option<Encoding>,
+    metric_encodings: Vec<(String, Encoding)>,
+}
+
+impl MultivariateMetricBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares a string attribute column.
+    pub fn attribute(mut self, name: impl Into<String>) -> Self {
+        self.multivariate_metric.attributes.push(ColumnarAttribute { name: name.into(), ..Default::default() });
+        self
+    }
+
+    pub fn int_gauge(self, name: impl Into<String>, description: impl Into<String>, unit: impl Into<String>) -> Self {
+        self.metric(name, description, unit, gauge(int_values()))
+    }
+
+    pub fn double_gauge(self, name: impl Into<String>, description: impl Into<String>, unit: impl Into<String>) -> Self {
+        self.metric(name, description, unit, gauge(double_values()))
+    }
+
+    pub fn int_sum(self, name: impl Into<String>, description: impl Into<String>, unit: impl Into<String>, aggregation_temporality: AggregationTemporality, is_monotonic: bool) -> Self {
+        self.metric(name, description, unit, sum(int_values(), aggregation_temporality, is_monotonic))
+    }
+
+    pub fn double_sum(self, name: impl Into<String>, description: impl Into<String>, unit: impl Into<String>, aggregation_temporality: AggregationTemporality, is_monotonic: bool) -> Self {
+        self.metric(name, description, unit, sum(double_values(), aggregation_temporality, is_monotonic))
+    }
+
+    pub fn histogram(self, name: impl Into<String>, description: impl Into<String>, unit: impl Into<String>, aggregation_temporality: AggregationTemporality) -> Self {
+        self.metric(name, description, unit, Data::Histogram(ColumnarHistogram {
+            data_points: Some(Default::default()),
+            aggregation_temporality: aggregation_temporality as i32,
+        }))
+    }
+
+    pub fn exponential_histogram(self, name: impl Into<String>, description: impl Into<String>, unit: impl Into<String>, aggregation_temporality: AggregationTemporality) -> Self {
+        self.metric(name, description, unit, Data::ExponentialHistogram(ColumnarExponentialHistogram {
+            data_points: Some(Default::default()),
+            aggregation_temporality: aggregation_temporality as i32,
+        }))
+    }
+
+    /// Delta encodes the timestamp columns when the metric is built.
+    pub fn time_encoding(mut self, encoding: Encoding) -> Self {
+        self.time_encoding = Some(encoding);
+        self
+    }
+
+    /// Delta encodes an int metric (e.g. a monotonic sum) when the metric is built.
+    pub fn metric_encoding(mut self, name: impl Into<String>, encoding: Encoding) -> Self {
+        self.metric_encodings.push((name.into(), encoding));
+        self
+    }
+
+    fn metric(mut self, name: impl Into<String>, description: impl Into<String>, unit: impl Into<String>, data: Data) -> Self {
+        self.multivariate_metric.metrics.push(ColumnarMetric {
+            name: name.into(),
+            description: description.into(),
+            unit: unit.into(),
+            data: Some(data),
+        });
+        self
+    }
+
+    /// Number of rows appended so far.
+    pub fn len(&self) -> usize {
+        self.multivariate_metric.time_unix_nano_column.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Appends a row without start time.
+    pub fn append(&mut self, time: impl UnixNano, attributes: &[&str], values: &[MetricValue]) -> Result<(), Error> {
+        self.append_row(None, time.unix_nano(), attributes, values)
+    }
+
+    /// Appends a row with a start time (cumulative or delta sums). Either all the rows or none of them must have a
+    /// start time.
+    pub fn append_with_start_time(&mut self, start_time: impl UnixNano, time: impl UnixNano, attributes: &[&str], values: &[MetricValue]) -> Result<(), Error> {
+        self.append_row(Some(start_time.unix_nano()), time.unix_nano(), attributes, values)
+    }
+
+    fn append_row(&mut self, start_time: Option<u64>, time: u64, attributes: &[&str], values: &[MetricValue]) -> Result<(), Error> {
+        let metric = &mut self.multivariate_metric;
+
+        if attributes.len() != metric.attributes.len() {
+            return Err(Error::InvalidFormat(format!("expected {} attribute values, got {}", metric.attributes.len(), attributes.len())));
+        }
+        if values.len() != metric.metrics.len() {
+            return Err(Error::InvalidFormat(format!("expected {} metric values, got {}", metric.metrics.len(), values.len())));
+        }
+        // Check the value types before appending anything to keep the columns aligned on error.
+        for (column, value) in metric.metrics.iter().zip(values) {
+            match (&column.data, value) {
+                (Some(Data::Histogram(_)), MetricValue::Histogram(_)) => continue,
+                (Some(Data::ExponentialHistogram(_)), MetricValue::ExponentialHistogram(_)) => continue,
+                _ => {}
+            }
+            match (number_values(column), value) {
+                (Some(columnar_number_data_point::Value::AsInts(_)), MetricValue::Int(_)) => {}
+                (Some(columnar_number_data_point::Value::AsDoubles(_)), MetricValue::Double(_)) => {}
+                _ => return Err(Error::InvalidFormat(format!("unexpected value type {:?} for metric '{}'", value, column.name))),
+            }
+        }
+
+        for (column, value) in metric.attributes.iter_mut().zip(attributes) {
+            column.values.push(value.to_string());
+        }
+        for (column, value) in metric.metrics.iter_mut().zip(values) {
+            match (&mut column.data, value) {
+                (Some(Data::Histogram(histogram)), MetricValue::Histogram(value)) => {
+                    histogram.data_points.get_or_insert_with(Default::default).push(value);
+                    continue;
+                }
+                (Some(Data::ExponentialHistogram(histogram)), MetricValue::ExponentialHistogram(value)) => {
+                    histogram.data_points.get_or_insert_with(Default::default).push(value);
+                    continue;
+                }
+                _ => {}
+            }
+            match (number_values_mut(column), value) {
+                (Some(columnar_number_data_point::Value::AsInts(ints)), MetricValue::Int(value)) => ints.value.push(*value),
+                (Some(columnar_number_data_point::Value::AsDoubles(doubles)), MetricValue::Double(value)) => doubles.value.push(*value),
+                _ => unreachable!("value types checked above"),
+            }
+        }
+        metric.time_unix_nano_column.push(time);
+        if let Some(start_time) = start_time {
+            metric.start_time_unix_nano_column.push(start_time);
+            self.rows_with_start_time += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the multivariate metric, fails if some rows have a start time and others don't or if a column name is
+    /// declared twice. Low cardinality attributes are dictionary encoded and the requested delta encodings are applied.
+    pub fn build(self) -> Result<MultivariateMetric, Error> {
+        let mut metric = self.multivariate_metric;
+
+        if self.rows_with_start_time != 0 && self.rows_with_start_time != metric.time_unix_nano_column.len() {
+            return Err(Error::InvalidFormat(format!("{} rows out of {} have a start time", self.rows_with_start_time, metric.time_unix_nano_column.len())));
+        }
+        let mut names: Vec<&str> = metric.attributes.iter().map(|column| column.name.as_str())
+            .chain(metric.metrics.iter().map(|column| column.name.as_str()))
+            .collect();
+        names.sort_unstable();
+        if let Some(name) = names.windows(2).find(|names| names[0] == names[1]) {
+            return Err(Error::InvalidFormat(format!("duplicate column '{}'", name[0])));
+        }
+
+        metric.encode_dictionaries();
+        if let Some(encoding) = self.time_encoding {
+            metric.encode_timestamps(encoding);
+        }
+        for (name, encoding) in &self.metric_encodings {
+            metric.encode_metric(name, *encoding);
+        }
+        Ok(metric)
+    }
+
+    /// Returns a `ResourceMetrics` containing the multivariate metric for the given resource and instrumentation
+    /// library.
+    pub fn build_resource_metrics(self, resource: Resource, instrumentation_library: InstrumentationLibrary, schema_url: impl Into<String>) -> Result<ResourceMetrics, Error> {
+        let schema_url = schema_url.into();
+
+        Ok(ResourceMetrics {
+            resource: Some(resource),
+            instrumentation_library_metrics: vec![
+                InstrumentationLibraryMetrics {
+                    instrumentation_library: Some(instrumentation_library),
+                    metrics: vec![],
+                    multivariate_metrics: vec![self.build()?],
+                    schema_url: schema_url.clone(),
+                }
             ],
+            schema_url,
+        })
+    }
+}
+
+impl From<i64> for MetricValue {
+    fn from(value: i64) -> Self {
+        MetricValue::Int(value)
+    }
+}
+
+impl From<f64> for MetricValue {
+    fn from(value: f64) -> Self {
+        MetricValue::Double(value)
+    }
+}
+
+impl From<Histogram> for MetricValue {
+    fn from(value: Histogram) -> Self {
+        MetricValue::Histogram(value)
+    }
+}
+
+impl From<ExponentialHistogram> for MetricValue {
+    fn from(value: ExponentialHistogram) -> Self {
+        MetricValue::ExponentialHistogram(value)
+    }
+}
+
+fn int_values() -> columnar_number_data_point::Value {
+    columnar_number_data_point::Value::AsInts(IntValues { value: vec![], ..Default::default() })
+}
+
+fn double_values() -> columnar_number_data_point::Value {
+    columnar_number_data_point::Value::AsDoubles(DoubleValues { value: vec![] })
+}
+
+fn gauge(values: columnar_number_data_point::Value) -> Data {
+    Data::Gauge(ColumnarGauge { data_points: Some(ColumnarNumberDataPoint { value: Some(values) }) })
+}
+
+fn sum(values: columnar_number_data_point::Value, aggregation_temporality: AggregationTemporality, is_monotonic: bool) -> Data {
+    Data::Sum(ColumnarSum {
+        data_points: Some(ColumnarNumberDataPoint { value: Some(values) }),
+        aggregation_temporality: aggregation_temporality as i32,
+        is_monotonic,
+    })
+}
+
+fn number_values(metric: &ColumnarMetric) -> Option<&columnar_number_data_point::Value> {
+    match &metric.data {
+        Some(Data::Gauge(ColumnarGauge { data_points: Some(data_points) })) => data_points.value.as_ref(),
+        Some(Data::Sum(ColumnarSum { data_points: Some(data_points), .. })) => data_points.value.as_ref(),
+        _ => None,
+    }
+}
+
+fn number_values_mut(metric: &mut ColumnarMetric) -> Option<&mut columnar_number_data_point::Value> {
+    match &mut metric.data {
+        Some(Data::Gauge(ColumnarGauge { data_points: Some(data_points) })) => data_points.value.as_mut(),
+        Some(Data::Sum(ColumnarSum { data_points: Some(data_points), .. })) => data_points.value.as_mut(),
+        _ => None,
+    }
+}
+
+pub fn gen_columnar_metrics(time_series: &[MultivariateDataPoint], resource: Resource, instrumentation_library: InstrumentationLibrary) -> ResourceMetrics {
+    let mut builder = MultivariateMetricBuilder::new()
+        .attribute("method")
+        .attribute("dns_lookup_ms_label_class")
+        .attribute("source")
+        .attribute("url")
+        .attribute("tls_handshake_ms_label_class")
+        .attribute("remote_address")
+        .attribute("content_transfer_ms_label_class")
+        .attribute("server_processing_ms_label_class")
+        .attribute("tcp_connection_ms_label_class")
+        .int_gauge("tls_handshake_ms", "", "ms")
+        .int_gauge("dns_lookup_ms", "", "ms")
+        .int_gauge("server_processing_ms", "", "ms")
+        .int_gauge("tcp_connection_ms", "", "ms")
+        .int_gauge("content_transfer_ms", "", "ms")
+        .int_gauge("health_status", "", "")
+        .int_gauge("failure_count", "", "")
+        .int_gauge("size", "", "");
+
+    for p in time_series {
+        let tags = &p.evt.tags;
+        let fields = &p.evt.fields;
+        builder.append_with_start_time(
+            p.ts,
+            p.ts,
+            &[&tags.method, &tags.dns_lookup_ms_label_class, &tags.source, &tags.url, &tags.tls_handshake_ms_label_class,
+                &tags.remote_address, &tags.content_transfer_ms_label_class, &tags.server_processing_ms_label_class,
+                &tags.tcp_connection_ms_label_class],
+            &[fields.tls_handshake_ms.into(), fields.dns_lookup_ms.into(), fields.server_processing_ms.into(),
+                fields.tcp_connection_ms.into(), fields.content_transfer_ms.into(), fields.health_status.into(),
+                fields.failure_count.into(), fields.size.into()],
+        ).expect("row matching the declared columns");
+    }
+
+    builder.build_resource_metrics(resource, instrumentation_library, "tbd").expect("valid multivariate metric")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::event::Error;
+    use crate::metrics_columnar::{MultivariateMetricBuilder, MetricValue};
+    use crate::opentelemetry::proto::common::v1::{InstrumentationLibrary, KeyValue, AnyValue};
+    use crate::opentelemetry::proto::common::v1::any_value::Value;
+    use crate::opentelemetry::proto::resource::v1::Resource;
+    use crate::opentelemetry::proto::metrics::v1::{AggregationTemporality, ColumnarSum, columnar_number_data_point};
+    use crate::opentelemetry::proto::metrics::v1::columnar_metric::Data;
+
+    fn builder() -> MultivariateMetricBuilder {
+        MultivariateMetricBuilder::new()
+            .attribute("host")
+            .attribute("method")
+            .int_gauge("latency", "request latency", "ms")
+            .double_sum("bytes", "transferred bytes", "By", AggregationTemporality::Cumulative, true)
+    }
+
+    #[test]
+    fn test_builder() {
+        let mut builder = builder();
+        builder.append(1u64, &["host_1", "GET"], &[10.into(), 100.5.into()]).unwrap();
+        builder.append(2u64, &["host_2", "POST"], &[20.into(), 200.0.into()]).unwrap();
+        assert_eq!(builder.len(), 2);
+
+        let resource = Resource {
+            attributes: vec![KeyValue { key: "service.name".into(), value: Some(AnyValue { value: Some(Value::StringValue("test".into())) }) }],
             dropped_attributes_count: 0,
-        }),
-        instrumentation_library_metrics: vec![
-            InstrumentationLibraryMetrics {
-                instrumentation_library: Some(InstrumentationLibrary { name: "rust-std".into(), version: "1.0".into() }),
-                metrics: vec![],
-                multivariate_metrics: vec![
-                    MultivariateMetric {
-                        attributes: vec![
-                            ColumnarAttribute { name: "method".into(), values: time_series.iter().map(|p| p.evt.tags.method.clone()).collect() },
-                            ColumnarAttribute { name: "dns_lookup_ms_label_class".into(), values: time_series.iter().map(|p| p.evt.tags.dns_lookup_ms_label_class.clone()).collect() },
-                            ColumnarAttribute { name: "source".into(), values: time_series.iter().map(|p| p.evt.tags.source.clone()).collect() },
-                            ColumnarAttribute { name: "url".into(), values: time_series.iter().map(|p| p.evt.tags.url.clone()).collect() },
-                            ColumnarAttribute { name: "tls_handshake_ms_label_class".into(), values: time_series.iter().map(|p| p.evt.tags.tls_handshake_ms_label_class.clone()).collect() },
-                            ColumnarAttribute { name: "remote_address".into(), values: time_series.iter().map(|p| p.evt.tags.remote_address.clone()).collect() },
-                            ColumnarAttribute { name: "content_transfer_ms_label_class".into(), values: time_series.iter().map(|p| p.evt.tags.content_transfer_ms_label_class.clone()).collect() },
-                            ColumnarAttribute { name: "server_processing_ms_label_class".into(), values: time_series.iter().map(|p| p.evt.tags.server_processing_ms_label_class.clone()).collect() },
-                            ColumnarAttribute { name: "tcp_connection_ms_label_class".into(), values: time_series.iter().map(|p| p.evt.tags.tcp_connection_ms_label_class.clone()).collect() },
-                        ],
-                        time_unix_nano_column: time_series.iter().map(|p| p.ts.timestamp_nanos() as u64).collect(),
-                        start_time_unix_nano_column: time_series.iter().map(|p| p.ts.timestamp_nanos() as u64).collect(),
-                        metrics: vec![
-                            ColumnarMetric {
-                                name: "tls_handshake_ms".into(),
-                                description: "".into(),
-                                unit: "ms".into(),
-                                data: Some(Data::Gauge(ColumnarGauge { data_points: Some(ColumnarNumberDataPoint { value: Some(columnar_number_data_point::Value::AsInts(IntValues { value: time_series.iter().map(|p| p.evt.fields.tls_handshake_ms).collect() })) }) }))
-                            },
-                            ColumnarMetric {
-                                name: "dns_lookup_ms".into(),
-                                description: "".into(),
-                                unit: "ms".into(),
-                                data: Some(Data::Gauge(ColumnarGauge { data_points: Some(ColumnarNumberDataPoint { value: Some(columnar_number_data_point::Value::AsInts(IntValues { value: time_series.iter().map(|p| p.evt.fields.dns_lookup_ms).collect() })) }) }))
-                            },
-                            ColumnarMetric {
-                                name: "server_processing_ms".into(),
-                                description: "".into(),
-                                unit: "ms".into(),
-                                data: Some(Data::Gauge(ColumnarGauge { data_points: Some(ColumnarNumberDataPoint { value: Some(columnar_number_data_point::Value::AsInts(IntValues { value: time_series.iter().map(|p| p.evt.fields.server_processing_ms).collect() })) }) }))
-                            },
-                            ColumnarMetric {
-                                name: "tcp_connection_ms".into(),
-                                description: "".into(),
-                                unit: "ms".into(),
-                                data: Some(Data::Gauge(ColumnarGauge { data_points: Some(ColumnarNumberDataPoint { value: Some(columnar_number_data_point::Value::AsInts(IntValues { value: time_series.iter().map(|p| p.evt.fields.tcp_connection_ms).collect() })) }) }))
-                            },
-                            ColumnarMetric {
-                                name: "content_transfer_ms".into(),
-                                description: "".into(),
-                                unit: "ms".into(),
-                                data: Some(Data::Gauge(ColumnarGauge { data_points: Some(ColumnarNumberDataPoint { value: Some(columnar_number_data_point::Value::AsInts(IntValues { value: time_series.iter().map(|p| p.evt.fields.content_transfer_ms).collect() })) }) }))
-                            },
-                            ColumnarMetric {
-                                name: "health_status".into(),
-                                description: "".into(),
-                                unit: "".into(),
-                                data: Some(Data::Gauge(ColumnarGauge { data_points: Some(ColumnarNumberDataPoint { value: Some(columnar_number_data_point::Value::AsInts(IntValues { value: time_series.iter().map(|p| p.evt.fields.health_status).collect() })) }) }))
-                            },
-                            ColumnarMetric {
-                                name: "failure_count".into(),
-                                description: "".into(),
-                                unit: "".into(),
-                                data: Some(Data::Gauge(ColumnarGauge { data_points: Some(ColumnarNumberDataPoint { value: Some(columnar_number_data_point::Value::AsInts(IntValues { value: time_series.iter().map(|p| p.evt.fields.failure_count).collect() })) }) }))
-                            },
-                            ColumnarMetric {
-                                name: "size".into(),
-                                description: "".into(),
-                                unit: "".into(),
-                                data: Some(Data::Gauge(ColumnarGauge { data_points: Some(ColumnarNumberDataPoint { value: Some(columnar_number_data_point::Value::AsInts(IntValues { value: time_series.iter().map(|p| p.evt.fields.size).collect() })) }) }))
-                            },
-                        ],
-                    }
-                ],
-                schema_url: "tbd".into()
+        };
+        let library = InstrumentationLibrary { name: "test-lib".into(), version: "0.1".into() };
+        let resource_metrics = builder.build_resource_metrics(resource.clone(), library.clone(), "schema").unwrap();
+
+        assert_eq!(resource_metrics.resource, Some(resource));
+        assert_eq!(resource_metrics.schema_url, "schema");
+        let library_metrics = &resource_metrics.instrumentation_library_metrics[0];
+        assert_eq!(library_metrics.instrumentation_library, Some(library));
+        let metric = &library_metrics.multivariate_metrics[0];
+        assert_eq!(metric.time_unix_nano_column, vec![1, 2]);
+        assert!(metric.start_time_unix_nano_column.is_empty());
+        assert_eq!(metric.attributes[1].values, vec!["GET".to_string(), "POST".to_string()]);
+        assert_eq!(metric.metrics[0].name, "latency");
+        match &metric.metrics[1].data {
+            Some(Data::Sum(ColumnarSum { data_points: Some(data_points), aggregation_temporality, is_monotonic })) => {
+                assert_eq!(*aggregation_temporality, AggregationTemporality::Cumulative as i32);
+                assert!(is_monotonic);
+                assert_eq!(data_points.value, Some(columnar_number_data_point::Value::AsDoubles(crate::opentelemetry::proto::metrics::v1::DoubleValues { value: vec![100.5, 200.0] })));
             }
-        ],
-        schema_url: "tbd".into(),
+            data => panic!("unexpected data {:?}", data),
+        }
+    }
+
+    #[test]
+    fn test_invalid_rows() {
+        let mut builder = builder();
+        assert!(matches!(builder.append(1u64, &["host_1"], &[10.into(), 100.5.into()]), Err(Error::InvalidFormat(_))));
+        assert!(matches!(builder.append(1u64, &["host_1", "GET"], &[10.into()]), Err(Error::InvalidFormat(_))));
+        assert!(matches!(builder.append(1u64, &["host_1", "GET"], &[MetricValue::Double(10.0), 100.5.into()]), Err(Error::InvalidFormat(_))));
+        assert!(builder.is_empty());
+
+        builder.append_with_start_time(0u64, 1u64, &["host_1", "GET"], &[10.into(), 100.5.into()]).unwrap();
+        builder.append(2u64, &["host_1", "GET"], &[10.into(), 100.5.into()]).unwrap();
+        assert!(matches!(builder.build(), Err(Error::InvalidFormat(_))));
+
+        let builder = MultivariateMetricBuilder::new().attribute("latency").int_gauge("latency", "", "ms");
+        assert!(matches!(builder.build(), Err(Error::InvalidFormat(_))));
     }
-}
\ No newline at end of file
+}
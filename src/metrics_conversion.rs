@@ -0,0 +1,428 @@
+//! Conversions between the standard OTLP metrics (`Metric`) and the columnar representation (`MultivariateMetric`).
+//!
+//! Standard gauges, sums and histograms are grouped into a multivariate metric when their data points share the same
+//! timestamps and the same string attributes, row by row. Metrics that can't be represented losslessly in a columnar
+//! form (summaries, deprecated int metrics, non-string attributes, labels, exemplars, ...) are left untouched.
+//! Exponential histograms have no standard counterpart in this version of OTLP and can't be exploded.
+
+use std::collections::HashMap;
+
+use crate::event::Error;
+use crate::histogram::Histogram;
+use crate::opentelemetry::proto::common::v1::{KeyValue, AnyValue};
+use crate::opentelemetry::proto::events::v1::HistogramValues;
+use crate::opentelemetry::proto::common::v1::any_value;
+use crate::opentelemetry::proto::metrics::v1::{ResourceMetrics, Metric, Gauge, Sum, NumberDataPoint, MultivariateMetric, ColumnarAttribute, ColumnarMetric, ColumnarGauge, ColumnarSum, ColumnarNumberDataPoint, IntValues, DoubleValues, columnar_number_data_point, number_data_point};
+use crate::opentelemetry::proto::metrics::v1::{Histogram as HistogramMetric, HistogramDataPoint, ColumnarHistogram};
+use crate::opentelemetry::proto::metrics::v1::{metric, columnar_metric};
+
+/// Rows shared by the metrics of a multivariate metric: attribute names, start times, times and attribute values.
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct Rows {
+    attribute_names: Vec<String>,
+    start_time_unix_nano_column: Vec<u64>,
+    time_unix_nano_column: Vec<u64>,
+    attribute_values: Vec<Vec<String>>,
+}
+
+/// Groups the gauges, sums and histograms sharing the same rows into multivariate metrics. Returns the multivariate metrics and
+/// the metrics that can't be converted. The relative order of the metrics is preserved within a multivariate metric.
+/// Low cardinality attributes are dictionary encoded.
+pub fn to_multivariate_metrics(metrics: Vec<Metric>) -> (Vec<MultivariateMetric>, Vec<Metric>) {
+    let mut multivariate_metrics: Vec<MultivariateMetric> = vec![];
+    let mut groups: HashMap<Rows, usize> = HashMap::new();
+    let mut other_metrics = vec![];
+
+    for metric in metrics {
+        let rows = match rows(&metric) {
+            Some(rows) => rows,
+            None => {
+                other_metrics.push(metric);
+                continue;
+            }
+        };
+        let column = columnar_metric(metric);
+
+        match groups.get(&rows) {
+            Some(&index) => multivariate_metrics[index].metrics.push(column),
+            None => {
+                let all_start_times_zero = rows.start_time_unix_nano_column.iter().all(|&start_time| start_time == 0);
+                multivariate_metrics.push(MultivariateMetric {
+                    attributes: rows.attribute_names.iter().enumerate()
+                        .map(|(i, name)| ColumnarAttribute {
+                            name: name.clone(),
+                            values: rows.attribute_values.iter().map(|values| values[i].clone()).collect(),
+                            ..Default::default()
+                        })
+                        .collect(),
+                    time_unix_nano_column: rows.time_unix_nano_column.clone(),
+                    start_time_unix_nano_column: if all_start_times_zero { vec![] } else { rows.start_time_unix_nano_column.clone() },
+                    metrics: vec![column],
+                    ..Default::default()
+                });
+                groups.insert(rows, multivariate_metrics.len() - 1);
+            }
+        }
+    }
+
+    for multivariate_metric in &mut multivariate_metrics {
+        multivariate_metric.encode_dictionaries();
+    }
+    (multivariate_metrics, other_metrics)
+}
+
+/// Explodes a multivariate metric into one standard gauge, sum or histogram per metric column. Fails on exponential
+/// histograms.
+pub fn to_standard_metrics(multivariate_metric: &MultivariateMetric) -> Result<Vec<Metric>, Error> {
+    multivariate_metric.validate()?;
+    let decoded_metric;
+    let multivariate_metric = if multivariate_metric.is_delta_encoded() {
+        let mut metric = multivariate_metric.clone();
+        metric.decode_delta_columns()?;
+        decoded_metric = metric;
+        &decoded_metric
+    } else {
+        multivariate_metric
+    };
+    let row_count = multivariate_metric.time_unix_nano_column.len();
+    let start_times = &multivariate_metric.start_time_unix_nano_column;
+
+    let attributes = |row: usize| -> Vec<KeyValue> {
+        multivariate_metric.attributes.iter()
+            .map(|attribute| KeyValue {
+                key: attribute.name.clone(),
+                value: Some(AnyValue { value: Some(any_value::Value::StringValue(attribute.value(row).unwrap_or_default().to_string())) }),
+            })
+            .collect()
+    };
+    let data_points = |values: &columnar_number_data_point::Value| -> Vec<NumberDataPoint> {
+        (0..row_count).map(|row| NumberDataPoint {
+            attributes: attributes(row),
+            start_time_unix_nano: start_times.get(row).copied().unwrap_or_default(),
+            time_unix_nano: multivariate_metric.time_unix_nano_column[row],
+            value: Some(match values {
+                columnar_number_data_point::Value::AsInts(ints) => number_data_point::Value::AsInt(ints.value[row]),
+                columnar_number_data_point::Value::AsDoubles(doubles) => number_data_point::Value::AsDouble(doubles.value[row]),
+            }),
+            ..Default::default()
+        }).collect()
+    };
+
+    multivariate_metric.metrics.iter()
+        .map(|column| {
+            let data = match &column.data {
+                Some(columnar_metric::Data::Gauge(_)) => metric::Data::Gauge(Gauge {
+                    data_points: data_points(number_values(column).expect("validated metric values")),
+                }),
+                Some(columnar_metric::Data::Sum(sum)) => metric::Data::Sum(Sum {
+                    data_points: data_points(number_values(column).expect("validated metric values")),
+                    aggregation_temporality: sum.aggregation_temporality,
+                    is_monotonic: sum.is_monotonic,
+                }),
+                Some(columnar_metric::Data::Histogram(histogram)) => metric::Data::Histogram(HistogramMetric {
+                    data_points: histogram.data_points.iter()
+                        .flat_map(|values| values.iter())
+                        .enumerate()
+                        .map(|(row, histogram)| histogram.into_data_point(attributes(row), start_times.get(row).copied().unwrap_or_default(), multivariate_metric.time_unix_nano_column[row]))
+                        .collect(),
+                    aggregation_temporality: histogram.aggregation_temporality,
+                }),
+                Some(columnar_metric::Data::ExponentialHistogram(_)) => {
+                    return Err(Error::InvalidFormat(format!("exponential histogram '{}' has no standard representation", column.name)));
+                }
+                None => unreachable!("metric without data has no values"),
+            };
+
+            Ok(Metric {
+                name: column.name.clone(),
+                description: column.description.clone(),
+                unit: column.unit.clone(),
+                data: Some(data),
+            })
+        })
+        .collect()
+}
+
+/// Converts all the convertible standard metrics into multivariate metrics.
+pub fn to_columnar_resource_metrics(mut resource_metrics: ResourceMetrics) -> ResourceMetrics {
+    for library_metrics in &mut resource_metrics.instrumentation_library_metrics {
+        let (multivariate_metrics, metrics) = to_multivariate_metrics(std::mem::take(&mut library_metrics.metrics));
+        library_metrics.metrics = metrics;
+        library_metrics.multivariate_metrics.extend(multivariate_metrics);
+    }
+    resource_metrics
+}
+
+/// Converts all the multivariate metrics into standard metrics.
+pub fn to_standard_resource_metrics(mut resource_metrics: ResourceMetrics) -> Result<ResourceMetrics, Error> {
+    for library_metrics in &mut resource_metrics.instrumentation_library_metrics {
+        for multivariate_metric in std::mem::take(&mut library_metrics.multivariate_metrics) {
+            library_metrics.metrics.extend(to_standard_metrics(&multivariate_metric)?);
+        }
+    }
+    Ok(resource_metrics)
+}
+
+/// Returns the rows of a gauge, a sum or a histogram if they can be represented in a multivariate metric.
+#[allow(deprecated)]
+fn rows(metric: &Metric) -> Option<Rows> {
+    // The attributes, start time and time of each data point.
+    let data_points: Vec<(&[KeyValue], u64, u64)> = match &metric.data {
+        Some(metric::Data::Gauge(Gauge { data_points })) | Some(metric::Data::Sum(Sum { data_points, .. })) => {
+            let is_int = matches!(data_points.first().and_then(|data_point| data_point.value.as_ref()), Some(number_data_point::Value::AsInt(_)));
+            for data_point in data_points {
+                if !data_point.labels.is_empty() || !data_point.exemplars.is_empty() {
+                    return None;
+                }
+                match (&data_point.value, is_int) {
+                    (Some(number_data_point::Value::AsInt(_)), true) | (Some(number_data_point::Value::AsDouble(_)), false) => {}
+                    _ => return None,
+                }
+            }
+            data_points.iter().map(|data_point| (data_point.attributes.as_slice(), data_point.start_time_unix_nano, data_point.time_unix_nano)).collect()
+        }
+        Some(metric::Data::Histogram(histogram)) => {
+            if histogram.data_points.iter().any(|data_point| !data_point.labels.is_empty() || !data_point.exemplars.is_empty()) {
+                return None;
+            }
+            histogram.data_points.iter().map(|data_point| (data_point.attributes.as_slice(), data_point.start_time_unix_nano, data_point.time_unix_nano)).collect()
+        }
+        _ => return None,
+    };
+
+    let attribute_names: Vec<String> = data_points.first()
+        .map(|(attributes, _, _)| attributes.iter().map(|attribute| attribute.key.clone()).collect())
+        .unwrap_or_default();
+    let mut attribute_values = Vec::with_capacity(data_points.len());
+
+    for (attributes, _, _) in &data_points {
+        if attributes.len() != attribute_names.len() {
+            return None;
+        }
+        let values = attributes.iter().zip(&attribute_names)
+            .map(|(attribute, name)| match &attribute.value {
+                Some(AnyValue { value: Some(any_value::Value::StringValue(value)) }) if &attribute.key == name => Some(value.clone()),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        attribute_values.push(values);
+    }
+
+    Some(Rows {
+        attribute_names,
+        start_time_unix_nano_column: data_points.iter().map(|&(_, start_time, _)| start_time).collect(),
+        time_unix_nano_column: data_points.iter().map(|&(_, _, time)| time).collect(),
+        attribute_values,
+    })
+}
+
+/// Converts a gauge, a sum or a histogram already checked by `rows` into a metric column.
+fn columnar_metric(metric: Metric) -> ColumnarMetric {
+    let values = |data_points: &[NumberDataPoint]| {
+        let value = match data_points.first().and_then(|data_point| data_point.value.as_ref()) {
+            Some(number_data_point::Value::AsDouble(_)) => columnar_number_data_point::Value::AsDoubles(DoubleValues {
+                value: data_points.iter().filter_map(|data_point| match data_point.value {
+                    Some(number_data_point::Value::AsDouble(value)) => Some(value),
+                    _ => None,
+                }).collect(),
+            }),
+            _ => columnar_number_data_point::Value::AsInts(IntValues {
+                value: data_points.iter().filter_map(|data_point| match data_point.value {
+                    Some(number_data_point::Value::AsInt(value)) => Some(value),
+                    _ => None,
+                }).collect(),
+                ..Default::default()
+            }),
+        };
+        Some(ColumnarNumberDataPoint { value: Some(value) })
+    };
+
+    let data = match metric.data {
+        Some(metric::Data::Gauge(gauge)) => columnar_metric::Data::Gauge(ColumnarGauge { data_points: values(&gauge.data_points) }),
+        Some(metric::Data::Sum(sum)) => columnar_metric::Data::Sum(ColumnarSum {
+            data_points: values(&sum.data_points),
+            aggregation_temporality: sum.aggregation_temporality,
+            is_monotonic: sum.is_monotonic,
+        }),
+        Some(metric::Data::Histogram(histogram)) => columnar_metric::Data::Histogram(ColumnarHistogram {
+            data_points: Some(histogram_values(&histogram.data_points)),
+            aggregation_temporality: histogram.aggregation_temporality,
+        }),
+        _ => unreachable!("only gauges, sums and histograms are converted"),
+    };
+
+    ColumnarMetric {
+        name: metric.name,
+        description: metric.description,
+        unit: metric.unit,
+        data: Some(data),
+    }
+}
+
+fn histogram_values(data_points: &[HistogramDataPoint]) -> HistogramValues {
+    let mut values = HistogramValues::default();
+    for data_point in data_points {
+        values.push(&Histogram::from(data_point));
+    }
+    values
+}
+
+fn number_values(metric: &ColumnarMetric) -> Option<&columnar_number_data_point::Value> {
+    match &metric.data {
+        Some(columnar_metric::Data::Gauge(ColumnarGauge { data_points: Some(data_points) })) => data_points.value.as_ref(),
+        Some(columnar_metric::Data::Sum(ColumnarSum { data_points: Some(data_points), .. })) => data_points.value.as_ref(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::histogram::{ExponentialHistogram, Histogram};
+    use crate::metrics_columnar::MultivariateMetricBuilder;
+    use crate::metrics_conversion::{to_multivariate_metrics, to_standard_metrics, to_columnar_resource_metrics, to_standard_resource_metrics};
+    use crate::opentelemetry::proto::common::v1::{KeyValue, AnyValue, InstrumentationLibrary};
+    use crate::opentelemetry::proto::common::v1::any_value::Value;
+    use crate::opentelemetry::proto::metrics::v1::{Metric, Gauge, Sum, NumberDataPoint, AggregationTemporality, number_data_point};
+    use crate::opentelemetry::proto::metrics::v1::metric::Data;
+    use crate::opentelemetry::proto::resource::v1::Resource;
+
+    fn attribute(key: &str, value: Value) -> KeyValue {
+        KeyValue { key: key.into(), value: Some(AnyValue { value: Some(value) }) }
+    }
+
+    fn data_point(time: u64, host: &str, value: number_data_point::Value) -> NumberDataPoint {
+        NumberDataPoint {
+            attributes: vec![attribute("host", Value::StringValue(host.into()))],
+            start_time_unix_nano: 0,
+            time_unix_nano: time,
+            value: Some(value),
+            ..Default::default()
+        }
+    }
+
+    fn standard_metrics() -> Vec<Metric> {
+        vec![
+            Metric {
+                name: "cpu".into(),
+                description: "cpu usage".into(),
+                unit: "1".into(),
+                data: Some(Data::Gauge(Gauge { data_points: vec![
+                    data_point(1, "host_1", number_data_point::Value::AsDouble(0.5)),
+                    data_point(1, "host_2", number_data_point::Value::AsDouble(0.7)),
+                ] })),
+            },
+            Metric {
+                name: "requests".into(),
+                description: "".into(),
+                unit: "".into(),
+                data: Some(Data::Sum(Sum {
+                    data_points: vec![
+                        data_point(1, "host_1", number_data_point::Value::AsInt(10)),
+                        data_point(1, "host_2", number_data_point::Value::AsInt(20)),
+                    ],
+                    aggregation_temporality: AggregationTemporality::Cumulative as i32,
+                    is_monotonic: true,
+                })),
+            },
+            Metric {
+                name: "memory".into(),
+                description: "".into(),
+                unit: "By".into(),
+                data: Some(Data::Gauge(Gauge { data_points: vec![
+                    NumberDataPoint { start_time_unix_nano: 1, ..data_point(2, "host_1", number_data_point::Value::AsInt(1024)) },
+                ] })),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_standard_round_trip() {
+        let non_string_attribute = Metric {
+            name: "disk".into(),
+            description: "".into(),
+            unit: "".into(),
+            data: Some(Data::Gauge(Gauge { data_points: vec![NumberDataPoint {
+                attributes: vec![attribute("device", Value::IntValue(1))],
+                time_unix_nano: 1,
+                value: Some(number_data_point::Value::AsInt(1)),
+                ..Default::default()
+            }] })),
+        };
+        let mut metrics = standard_metrics();
+        metrics.push(non_string_attribute.clone());
+
+        let (multivariate_metrics, other_metrics) = to_multivariate_metrics(metrics);
+        assert_eq!(other_metrics, vec![non_string_attribute]);
+        assert_eq!(multivariate_metrics.len(), 2);
+        assert_eq!(multivariate_metrics[0].metrics.len(), 2);
+        assert_eq!(multivariate_metrics[0].attributes[0].values, vec!["host_1".to_string(), "host_2".to_string()]);
+        assert!(multivariate_metrics[0].start_time_unix_nano_column.is_empty());
+        assert_eq!(multivariate_metrics[1].start_time_unix_nano_column, vec![1]);
+
+        let metrics: Vec<Metric> = multivariate_metrics.iter()
+            .flat_map(|multivariate_metric| to_standard_metrics(multivariate_metric).unwrap())
+            .collect();
+        assert_eq!(metrics, standard_metrics());
+    }
+
+    #[test]
+    fn test_multivariate_round_trip() {
+        let mut builder = MultivariateMetricBuilder::new()
+            .attribute("host")
+            .attribute("method")
+            .int_gauge("latency", "", "ms")
+            .double_sum("bytes", "", "By", AggregationTemporality::Delta, false);
+        builder.append_with_start_time(0u64, 1u64, &["host_1", "GET"], &[10.into(), 100.5.into()]).unwrap();
+        builder.append_with_start_time(1u64, 2u64, &["host_1", "GET"], &[20.into(), 200.0.into()]).unwrap();
+        let multivariate_metric = builder.build().unwrap();
+
+        let (multivariate_metrics, other_metrics) = to_multivariate_metrics(to_standard_metrics(&multivariate_metric).unwrap());
+        assert!(other_metrics.is_empty());
+        assert_eq!(multivariate_metrics, vec![multivariate_metric.clone()]);
+
+        let mut invalid_metric = multivariate_metric;
+        invalid_metric.time_unix_nano_column.pop();
+        assert!(to_standard_metrics(&invalid_metric).is_err());
+    }
+
+    #[test]
+    fn test_histogram_round_trip() {
+        let histogram = |count: u64| Histogram { count, sum: count as f64, bucket_counts: vec![0, count], explicit_bounds: vec![0.5], min: None, max: None };
+        let mut builder = MultivariateMetricBuilder::new()
+            .attribute("host")
+            .histogram("latency", "", "ms", AggregationTemporality::Delta);
+        builder.append(1u64, &["host_1"], &[histogram(1).into()]).unwrap();
+        builder.append(2u64, &["host_2"], &[histogram(2).into()]).unwrap();
+        let multivariate_metric = builder.build().unwrap();
+        multivariate_metric.validate().unwrap();
+
+        let metrics = to_standard_metrics(&multivariate_metric).unwrap();
+        match &metrics[0].data {
+            Some(Data::Histogram(standard_histogram)) => {
+                assert_eq!(standard_histogram.aggregation_temporality, AggregationTemporality::Delta as i32);
+                assert_eq!(standard_histogram.data_points[1].time_unix_nano, 2);
+                assert_eq!(standard_histogram.data_points[1].bucket_counts, vec![0, 2]);
+            }
+            data => panic!("unexpected data {:?}", data),
+        }
+        let (multivariate_metrics, other_metrics) = to_multivariate_metrics(metrics);
+        assert!(other_metrics.is_empty());
+        assert_eq!(multivariate_metrics, vec![multivariate_metric]);
+
+        let mut builder = MultivariateMetricBuilder::new().exponential_histogram("latency", "", "ms", AggregationTemporality::Delta);
+        builder.append(1u64, &[], &[ExponentialHistogram::default().into()]).unwrap();
+        assert!(to_standard_metrics(&builder.build().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_resource_metrics() {
+        let mut builder = MultivariateMetricBuilder::new().attribute("host").int_gauge("latency", "", "ms");
+        builder.append(1u64, &["host_1"], &[10.into()]).unwrap();
+        let resource_metrics = builder.build_resource_metrics(Resource::default(), InstrumentationLibrary::default(), "").unwrap();
+
+        let standard = to_standard_resource_metrics(resource_metrics.clone()).unwrap();
+        assert!(standard.instrumentation_library_metrics[0].multivariate_metrics.is_empty());
+        assert_eq!(standard.instrumentation_library_metrics[0].metrics.len(), 1);
+        assert_eq!(to_columnar_resource_metrics(standard), resource_metrics);
+    }
+}
@@ -0,0 +1,95 @@
+use crate::event::Error;
+use crate::log_event::to_resource_events;
+use crate::opentelemetry::proto::events::v1::ResourceEvents;
+use crate::opentelemetry::proto::logs::v1::{ResourceLogs, InstrumentationLibraryLogs, LogRecord};
+use crate::opentelemetry::proto::resource::v1::Resource;
+use crate::opentelemetry::proto::common::v1::InstrumentationLibrary;
+use prost::{Message, EncodeError, DecodeError};
+use bytes::Bytes;
+
+pub struct NativeLogHandler {
+    pub resource_logs: ResourceLogs,
+}
+
+impl NativeLogHandler {
+    pub fn new() -> Self {
+        Self {
+            resource_logs: ResourceLogs {
+                resource: Some(Resource {
+                    attributes: vec![],
+                    dropped_attributes_count: 0,
+                }),
+                instrumentation_library_logs: vec![Self::new_instrumentation_library_logs()],
+                schema_url: "".into()
+            },
+        }
+    }
+
+    fn new_instrumentation_library_logs() -> InstrumentationLibraryLogs {
+        InstrumentationLibraryLogs {
+            instrumentation_library: Some(InstrumentationLibrary { name: "otel-rust".into(), version: "1.0".into() }),
+            logs: vec![],
+            schema_url: "".into()
+        }
+    }
+
+    /// Adds the log record to the first instrumentation library, which is created if missing (e.g. after
+    /// deserializing a `ResourceLogs` without library).
+    pub fn record(&mut self, log_record: LogRecord) {
+        let instrumentation_library_logs = &mut self.resource_logs.instrumentation_library_logs;
+        if instrumentation_library_logs.is_empty() {
+            instrumentation_library_logs.push(Self::new_instrumentation_library_logs());
+        }
+        instrumentation_library_logs[0].logs.push(log_record);
+    }
+
+    pub fn clear(&mut self) {
+        if let Some(instrumentation_library_logs) = self.resource_logs.instrumentation_library_logs.first_mut() {
+            instrumentation_library_logs.logs.clear();
+        }
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>,EncodeError> {
+        let mut buf: Vec<u8> = Vec::new();
+        self.resource_logs.encode(&mut buf)?;
+        Ok(buf)
+    }
+
+    pub fn deserialize(&mut self, buf: Vec<u8>) -> Result<(), DecodeError> {
+        self.resource_logs = ResourceLogs::decode(Bytes::from(buf))?;
+        Ok(())
+    }
+
+    /// Columnar version of the recorded logs (see `log_event::LogEvent`).
+    pub fn to_resource_events(&self) -> Result<ResourceEvents, Error> {
+        to_resource_events(self.resource_logs.clone())
+    }
+}
+
+impl Default for NativeLogHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::native_log::NativeLogHandler;
+    use crate::opentelemetry::proto::logs::v1::{LogRecord, ResourceLogs};
+    use prost::Message;
+
+    #[test]
+    fn test_record_without_library() {
+        let mut handler = NativeLogHandler::new();
+        handler.deserialize(ResourceLogs::default().encode_to_vec()).unwrap();
+        handler.clear();
+        handler.record(LogRecord { time_unix_nano: 1, ..Default::default() });
+
+        let library_logs = &handler.resource_logs.instrumentation_library_logs;
+        assert_eq!(library_logs.len(), 1);
+        assert_eq!(library_logs[0].instrumentation_library.as_ref().unwrap().name, "otel-rust");
+        assert_eq!(library_logs[0].logs.len(), 1);
+        handler.clear();
+        assert!(handler.resource_logs.instrumentation_library_logs[0].logs.is_empty());
+    }
+}
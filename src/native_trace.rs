@@ -1,3 +1,6 @@
+use crate::event::Error;
+use crate::span_event::to_resource_events;
+use crate::opentelemetry::proto::events::v1::ResourceEvents;
 use crate::opentelemetry::proto::trace::v1::{ResourceSpans, InstrumentationLibrarySpans, Span};
 use crate::opentelemetry::proto::resource::v1::Resource;
 use crate::opentelemetry::proto::common::v1::InstrumentationLibrary;
@@ -45,5 +48,10 @@ impl NativeTraceHandler {
     pub fn deserialize(&mut self, buf: Vec<u8>) {
         self.resource_spans = ResourceSpans::decode(Bytes::from(buf)).unwrap();
     }
+
+    /// Columnar version of the recorded spans (see `span_event::SpanEvent`).
+    pub fn to_resource_events(&self) -> Result<ResourceEvents, Error> {
+        to_resource_events(self.resource_spans.clone())
+    }
 }
 
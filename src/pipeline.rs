@@ -0,0 +1,454 @@
+//! Asynchronous export pipeline (tokio).
+//!
+//! An `ExportPipeline` is a bounded queue between the handlers and an `Exporter`. The handlers push their batches via a
+//! `PipelineSink` without blocking, a batch is dropped according to the `DropPolicy` when the queue is full. Worker
+//! tasks pop the batches and export them, a failed export is retried with an exponential backoff. `shutdown` stops
+//! accepting batches, drains the queue and waits for the workers. Dropping the pipeline also stops accepting batches,
+//! the workers drain the queue in the background. A push into a closed pipeline fails with `PipelineError::Closed`,
+//! wrapped into `Error::SinkError` by the `PipelineSink`.
+
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+use crate::event::Error;
+use crate::opentelemetry::proto::arrow_events::v1 as arrow_events;
+use crate::opentelemetry::proto::events::v1::ResourceEvents;
+use crate::opentelemetry::proto::metrics::v1::ResourceMetrics;
+use crate::sink::{EventSink, ResourceBatch};
+
+#[derive(thiserror::Error, Debug)]
+pub enum PipelineError {
+    #[error("Pipeline Closed")]
+    Closed,
+    #[error("Exporter Task Error (error: {0})")]
+    JoinError(#[from] tokio::task::JoinError),
+    #[error(transparent)]
+    EventError(#[from] Error),
+}
+
+impl From<PipelineError> for Error {
+    fn from(err: PipelineError) -> Self {
+        match err {
+            PipelineError::EventError(err) => err,
+            err => Error::SinkError(Box::new(err)),
+        }
+    }
+}
+
+/// Destination of the batches of a pipeline, e.g. a collector.
+#[tonic::async_trait]
+pub trait Exporter: Debug + Send + Sync + 'static {
+    async fn export(&self, batch: ResourceBatch) -> Result<(), PipelineError>;
+}
+
+/// Batch dropped when a batch is pushed into a full queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropPolicy {
+    DropOldest,
+    DropNewest,
+}
+
+#[derive(Debug, Clone)]
+pub struct PipelineConfig {
+    pub queue_size: usize,
+    pub worker_count: usize,
+    /// Number of retries of a failed export, the batch is dropped once exhausted.
+    pub max_retries: usize,
+    /// Delay before the first retry, doubled for each following retry up to `max_backoff`.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub drop_policy: DropPolicy,
+}
+
+/// Counters of a pipeline since its start.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipelineStats {
+    pub exported_batches: u64,
+    /// Batches dropped because the queue was full.
+    pub dropped_batches: u64,
+    /// Batches dropped because the retries were exhausted.
+    pub failed_batches: u64,
+    pub retries: u64,
+}
+
+#[derive(Debug)]
+pub struct ExportPipeline {
+    queue: Arc<Queue>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+/// Pushes the exported batches into the queue of a pipeline. Clones share the same queue.
+#[derive(Debug, Clone)]
+pub struct PipelineSink {
+    queue: Arc<Queue>,
+}
+
+/// Exports the batches to a blocking sink (e.g. `GrpcSink` or `HttpSink`) from a blocking thread.
+#[derive(Debug)]
+pub struct SinkExporter<S: EventSink> {
+    sink: Arc<Mutex<S>>,
+}
+
+#[derive(Debug)]
+struct Queue {
+    batches: Mutex<VecDeque<ResourceBatch>>,
+    capacity: usize,
+    drop_policy: DropPolicy,
+    /// Only set while holding the `batches` lock, so that no batch is pushed after the closing.
+    closed: AtomicBool,
+    notify: Notify,
+    exported_batches: AtomicU64,
+    dropped_batches: AtomicU64,
+    failed_batches: AtomicU64,
+    retries: AtomicU64,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        PipelineConfig {
+            queue_size: 1024,
+            worker_count: 1,
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            drop_policy: DropPolicy::DropOldest,
+        }
+    }
+}
+
+impl PipelineConfig {
+    /// Delay before the nth retry (starting at 0).
+    fn backoff(&self, retry: usize) -> Duration {
+        let factor = 2u32.saturating_pow(retry.min(u32::MAX as usize) as u32);
+        self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+impl ExportPipeline {
+    /// Spawns the workers on the current tokio runtime.
+    pub fn start(config: PipelineConfig, exporter: impl Exporter) -> Self {
+        let queue = Arc::new(Queue {
+            batches: Mutex::new(VecDeque::with_capacity(config.queue_size)),
+            capacity: config.queue_size.max(1),
+            drop_policy: config.drop_policy,
+            closed: AtomicBool::new(false),
+            notify: Notify::new(),
+            exported_batches: AtomicU64::new(0),
+            dropped_batches: AtomicU64::new(0),
+            failed_batches: AtomicU64::new(0),
+            retries: AtomicU64::new(0),
+        });
+        let exporter: Arc<dyn Exporter> = Arc::new(exporter);
+        let workers = (0..config.worker_count.max(1))
+            .map(|_| tokio::spawn(run_worker(queue.clone(), exporter.clone(), config.clone())))
+            .collect();
+
+        ExportPipeline { queue, workers }
+    }
+
+    pub fn sink(&self) -> PipelineSink {
+        PipelineSink { queue: self.queue.clone() }
+    }
+
+    /// Pushes a batch into the queue, fails if the pipeline is shut down.
+    pub fn push(&self, batch: ResourceBatch) -> Result<(), PipelineError> {
+        self.queue.push(batch)
+    }
+
+    /// Returns the number of batches waiting for a worker.
+    pub fn queued_batches(&self) -> usize {
+        self.queue.batches().len()
+    }
+
+    pub fn stats(&self) -> PipelineStats {
+        self.queue.stats()
+    }
+
+    /// Stops accepting batches, exports the queued batches (retries included) and waits for the workers.
+    pub async fn shutdown(mut self) -> PipelineStats {
+        self.queue.close();
+        for worker in std::mem::take(&mut self.workers) {
+            // A worker only stops on a panic of the exporter, the remaining workers drain the queue.
+            worker.await.ok();
+        }
+        self.queue.stats()
+    }
+}
+
+impl Drop for ExportPipeline {
+    fn drop(&mut self) {
+        self.queue.close();
+    }
+}
+
+impl EventSink for PipelineSink {
+    fn export_events(&mut self, resource_events: ResourceEvents) -> Result<(), Error> {
+        Ok(self.queue.push(ResourceBatch::Events(resource_events))?)
+    }
+
+    fn export_arrow_events(&mut self, resource_events: arrow_events::ResourceEvents) -> Result<(), Error> {
+        Ok(self.queue.push(ResourceBatch::ArrowEvents(resource_events))?)
+    }
+
+    fn export_metrics(&mut self, resource_metrics: ResourceMetrics) -> Result<(), Error> {
+        Ok(self.queue.push(ResourceBatch::Metrics(resource_metrics))?)
+    }
+}
+
+impl<S: EventSink> SinkExporter<S> {
+    pub fn new(sink: S) -> Self {
+        SinkExporter { sink: Arc::new(Mutex::new(sink)) }
+    }
+}
+
+#[tonic::async_trait]
+impl<S: EventSink + Send + 'static> Exporter for SinkExporter<S> {
+    async fn export(&self, batch: ResourceBatch) -> Result<(), PipelineError> {
+        let sink = self.sink.clone();
+        tokio::task::spawn_blocking(move || {
+            batch.export_to(&mut *sink.lock().expect("exporter sink lock poisoned"))
+        }).await??;
+        Ok(())
+    }
+}
+
+impl Queue {
+    fn push(&self, batch: ResourceBatch) -> Result<(), PipelineError> {
+        {
+            let mut batches = self.batches();
+            if self.closed.load(Ordering::SeqCst) {
+                return Err(PipelineError::Closed);
+            }
+            if batches.len() >= self.capacity {
+                self.dropped_batches.fetch_add(1, Ordering::SeqCst);
+                match self.drop_policy {
+                    DropPolicy::DropOldest => {
+                        batches.pop_front();
+                    }
+                    DropPolicy::DropNewest => return Ok(()),
+                }
+            }
+            batches.push_back(batch);
+        }
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    /// Stops accepting batches and wakes up the idle workers.
+    fn close(&self) {
+        {
+            let _batches = self.batches();
+            self.closed.store(true, Ordering::SeqCst);
+        }
+        self.notify.notify_waiters();
+    }
+
+    /// Waits for the next batch, returns `None` once the queue is closed and empty.
+    async fn pop(&self) -> Option<ResourceBatch> {
+        loop {
+            // The waiter is registered before checking the queue so that a push or a shutdown can't be missed.
+            let notified = self.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if let Some(batch) = self.batches().pop_front() {
+                return Some(batch);
+            }
+            if self.closed.load(Ordering::SeqCst) {
+                return None;
+            }
+            notified.await;
+        }
+    }
+
+    fn batches(&self) -> MutexGuard<'_, VecDeque<ResourceBatch>> {
+        self.batches.lock().expect("pipeline queue lock poisoned")
+    }
+
+    fn stats(&self) -> PipelineStats {
+        PipelineStats {
+            exported_batches: self.exported_batches.load(Ordering::SeqCst),
+            dropped_batches: self.dropped_batches.load(Ordering::SeqCst),
+            failed_batches: self.failed_batches.load(Ordering::SeqCst),
+            retries: self.retries.load(Ordering::SeqCst),
+        }
+    }
+}
+
+async fn run_worker(queue: Arc<Queue>, exporter: Arc<dyn Exporter>, config: PipelineConfig) {
+    while let Some(batch) = queue.pop().await {
+        let mut retry = 0;
+        loop {
+            match exporter.export(batch.clone()).await {
+                Ok(()) => {
+                    queue.exported_batches.fetch_add(1, Ordering::SeqCst);
+                    break;
+                }
+                Err(_) if retry < config.max_retries => {
+                    queue.retries.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(config.backoff(retry)).await;
+                    retry += 1;
+                }
+                Err(_) => {
+                    queue.failed_batches.fetch_add(1, Ordering::SeqCst);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use crate::event::{BatchPolicy, Error, EventBatchHandler, OpenTelemetryEvent};
+    use crate::opentelemetry::proto::events::v1::ResourceEvents;
+    use crate::pipeline::{DropPolicy, ExportPipeline, Exporter, PipelineConfig, PipelineError, PipelineStats, SinkExporter};
+    use crate::sink::{EventSink, MemorySink, ResourceBatch};
+
+    /// Fails the first `failures` exports, then records the exported batches.
+    #[derive(Debug, Clone, Default)]
+    struct MockExporter {
+        failures: Arc<AtomicUsize>,
+        batches: Arc<Mutex<Vec<ResourceBatch>>>,
+    }
+
+    #[tonic::async_trait]
+    impl Exporter for MockExporter {
+        async fn export(&self, batch: ResourceBatch) -> Result<(), PipelineError> {
+            if self.failures.load(Ordering::SeqCst) > 0 {
+                self.failures.fetch_sub(1, Ordering::SeqCst);
+                return Err(Error::InvalidFormat("unavailable".into()).into());
+            }
+            self.batches.lock().unwrap().push(batch);
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, OpenTelemetryEvent)]
+    #[otel(urn = "urn:test:request")]
+    struct Request {
+        #[otel(start_time)]
+        time: u64,
+    }
+
+    fn batch(size: u32) -> ResourceBatch {
+        ResourceBatch::Events(ResourceEvents { schema_url: size.to_string(), ..Default::default() })
+    }
+
+    fn config(worker_count: usize, max_retries: usize) -> PipelineConfig {
+        PipelineConfig {
+            queue_size: 2,
+            worker_count,
+            max_retries,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+            drop_policy: DropPolicy::DropOldest,
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let config = config(1, 10);
+        assert_eq!(config.backoff(0), Duration::from_millis(1));
+        assert_eq!(config.backoff(1), Duration::from_millis(2));
+        assert_eq!(config.backoff(5), Duration::from_millis(4));
+        assert_eq!(config.backoff(usize::MAX), Duration::from_millis(4));
+    }
+
+    #[tokio::test]
+    async fn test_export_with_retries() {
+        let exporter = MockExporter { failures: Arc::new(AtomicUsize::new(2)), ..Default::default() };
+        let pipeline = ExportPipeline::start(PipelineConfig { queue_size: 16, ..config(2, 3) }, exporter.clone());
+
+        let mut handler = EventBatchHandler::<Request>::new(BatchPolicy::new(2, chrono::Duration::seconds(10)))
+            .with_sink(pipeline.sink());
+        for time in 0..5 {
+            handler.record(Request { time }).unwrap();
+        }
+        handler.flush().unwrap();
+
+        let stats = pipeline.shutdown().await;
+        assert_eq!(stats, PipelineStats { exported_batches: 3, dropped_batches: 0, failed_batches: 0, retries: 2 });
+        let sizes: u32 = exporter.batches.lock().unwrap().iter().map(|batch| match batch {
+            ResourceBatch::Events(resource_events) => resource_events.instrumentation_library_events[0].batches[0].size,
+            _ => 0,
+        }).sum();
+        assert_eq!(sizes, 5);
+    }
+
+    #[tokio::test]
+    async fn test_retries_exhausted() {
+        let exporter = MockExporter { failures: Arc::new(AtomicUsize::new(4)), ..Default::default() };
+        let pipeline = ExportPipeline::start(config(1, 1), exporter.clone());
+        pipeline.push(batch(1)).unwrap();
+        pipeline.push(batch(2)).unwrap();
+
+        let stats = pipeline.shutdown().await;
+        assert_eq!(stats, PipelineStats { exported_batches: 0, dropped_batches: 0, failed_batches: 2, retries: 2 });
+    }
+
+    #[tokio::test]
+    async fn test_drop_policies() {
+        for (drop_policy, expected_batches) in [(DropPolicy::DropOldest, vec![batch(2), batch(3)]), (DropPolicy::DropNewest, vec![batch(1), batch(2)])] {
+            // The exporter fails until the queue is full, the single worker then holds the batch 0.
+            let exporter = MockExporter { failures: Arc::new(AtomicUsize::new(usize::MAX)), ..Default::default() };
+            let pipeline = ExportPipeline::start(PipelineConfig { drop_policy, ..config(1, usize::MAX) }, exporter.clone());
+            pipeline.push(batch(0)).unwrap();
+            while pipeline.queued_batches() > 0 {
+                tokio::task::yield_now().await;
+            }
+            let mut sink = pipeline.sink();
+            for size in 1..4 {
+                sink.export_events(match batch(size) { ResourceBatch::Events(resource_events) => resource_events, _ => unreachable!() }).unwrap();
+            }
+            exporter.failures.store(0, Ordering::SeqCst);
+
+            let stats = pipeline.shutdown().await;
+            assert_eq!(stats.dropped_batches, 1);
+            assert_eq!(exporter.batches.lock().unwrap()[1..], expected_batches[..]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let received = MemorySink::new();
+        let pipeline = ExportPipeline::start(PipelineConfig { queue_size: 16, ..config(2, 0) }, SinkExporter::new(received.clone()));
+        let mut sink = pipeline.sink();
+        for size in 0..10 {
+            pipeline.push(batch(size)).unwrap();
+        }
+
+        let stats = pipeline.shutdown().await;
+        assert_eq!(stats.exported_batches, 10);
+        assert_eq!(received.len(), 10);
+        assert!(matches!(sink.export_events(ResourceEvents::default()), Err(Error::SinkError(err)) if matches!(err.downcast_ref(), Some(PipelineError::Closed))));
+    }
+
+    #[tokio::test]
+    async fn test_drop() {
+        let exporter = MockExporter::default();
+        let pipeline = ExportPipeline::start(PipelineConfig { queue_size: 16, ..config(2, 0) }, exporter.clone());
+        let mut sink = pipeline.sink();
+        for size in 0..10 {
+            pipeline.push(batch(size)).unwrap();
+        }
+        drop(pipeline);
+        assert!(matches!(sink.export_events(ResourceEvents::default()), Err(Error::SinkError(err)) if matches!(err.downcast_ref(), Some(PipelineError::Closed))));
+
+        // The workers drain the queue, then stop.
+        while Arc::strong_count(&exporter.batches) > 1 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(exporter.batches.lock().unwrap().len(), 10);
+    }
+}
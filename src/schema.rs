@@ -0,0 +1,919 @@
+//! Schema registry keyed by `BatchEvent.schema_url`.
+//!
+//! An `EventSchema` describes the column layout behind a URN (columns, types, units, logical types and auxiliary
+//! entities). It is derived from an `OpenTelemetryEvent` type or inferred from a batch, and can be serialized in JSON
+//! alongside the batches. A `SchemaRegistry` validates the batches of the registered URNs and decodes them without
+//! knowing their Rust type.
+//!
+//! Schema evolution: the columns are identified by name and type, so the changes between two versions of a schema are
+//! column additions and removals, type changes (a removal plus an addition), logical type changes and changes of
+//! nullability. `EventSchema::compatibility_errors` checks these changes against backward (new readers, old batches),
+//! forward (old readers, new batches) or full compatibility rules, and `EventSchema::project` adapts a batch of another
+//! version to a schema, the missing optional columns surfacing as nulls.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Number, Value};
+
+use crate::attribute::Attribute;
+use crate::dynamic_attributes::OVERFLOW_ATTRIBUTES;
+use crate::event::{BatchPolicy, Error, OpenTelemetryEvent, validity_bitmap};
+use crate::event_decoder::{BatchEventReader, Columns, FromAuxiliaryEntity, FromBatchEvent, Row, decode_batch_event};
+use crate::opentelemetry::proto::common::v1::{any_value, AnyValue};
+use crate::opentelemetry::proto::events::v1::{AggregationTemporality, AuxiliaryEntity, BatchEvent, BoolColumn, BytesColumn, DoubleColumn, DoubleSummaryColumn, ExponentialHistogramColumn, HistogramColumn, Int64Column, Int64SummaryColumn, ResourceEvents, StringColumn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnType {
+    Int64,
+    Double,
+    String,
+    Bool,
+    Bytes,
+    Histogram,
+    ExponentialHistogram,
+    Int64Summary,
+    DoubleSummary,
+}
+
+/// A column, `logical_type` is the value of the `LogicalType` enum of the column type (0 for the histograms and
+/// the summaries).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColumnSchema {
+    pub name: String,
+    pub column_type: ColumnType,
+    pub logical_type: i32,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub unit: String,
+    /// True if the column has a validity bitmap, i.e. may contain nulls.
+    pub optional: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuxiliaryEntitySchema {
+    pub parent_column: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub schema_url: String,
+    pub logical_type: i32,
+    pub columns: Vec<ColumnSchema>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventSchema {
+    pub urn: String,
+    pub columns: Vec<ColumnSchema>,
+    pub auxiliary_entities: Vec<AuxiliaryEntitySchema>,
+}
+
+/// A change between two versions of a schema. Columns of auxiliary entities are prefixed with `<parent_column>.`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaChange {
+    ColumnAdded { name: String, column_type: ColumnType, optional: bool },
+    ColumnRemoved { name: String, column_type: ColumnType, optional: bool },
+    TypeChanged { name: String, from: ColumnType, to: ColumnType },
+    LogicalTypeChanged { name: String, from: i32, to: i32 },
+    BecameOptional { name: String },
+    BecameRequired { name: String },
+    AuxiliaryEntityAdded { parent_column: String },
+    AuxiliaryEntityRemoved { parent_column: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compatibility {
+    /// Readers of the new schema can read the batches of the previous schema.
+    Backward,
+    /// Readers of the previous schema can read the batches of the new schema.
+    Forward,
+    Full,
+}
+
+/// The schemas known by a producer or a receiver.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SchemaRegistry {
+    schemas: BTreeMap<String, EventSchema>,
+}
+
+impl ColumnSchema {
+    fn of_columns(columns: Columns) -> Vec<ColumnSchema> {
+        let column = |name: &str, column_type, logical_type, description: &str, unit: &str, validity_bitmap: &[u8]| ColumnSchema {
+            name: name.into(),
+            column_type,
+            logical_type,
+            description: description.into(),
+            unit: unit.into(),
+            optional: !validity_bitmap.is_empty(),
+        };
+        let mut schemas = vec![];
+        schemas.extend(columns.i64_values.iter()
+            .map(|c| column(&c.name, ColumnType::Int64, c.logical_type, &c.description, &c.unit, &c.validity_bitmap)));
+        schemas.extend(columns.f64_values.iter()
+            .map(|c| column(&c.name, ColumnType::Double, c.logical_type, &c.description, &c.unit, &c.validity_bitmap)));
+        schemas.extend(columns.string_values.iter()
+            .map(|c| column(&c.name, ColumnType::String, c.logical_type, &c.description, "", &c.validity_bitmap)));
+        schemas.extend(columns.bool_values.iter()
+            .map(|c| column(&c.name, ColumnType::Bool, c.logical_type, &c.description, "", &c.validity_bitmap)));
+        schemas.extend(columns.bytes_values.iter()
+            .map(|c| column(&c.name, ColumnType::Bytes, c.logical_type, &c.description, "", &c.validity_bitmap)));
+        schemas.extend(columns.histogram_values.iter()
+            .map(|c| column(&c.name, ColumnType::Histogram, 0, &c.description, &c.unit, &c.validity_bitmap)));
+        schemas.extend(columns.exponential_histogram_values.iter()
+            .map(|c| column(&c.name, ColumnType::ExponentialHistogram, 0, &c.description, &c.unit, &c.validity_bitmap)));
+        schemas.extend(columns.i64_summary_values.iter()
+            .map(|c| column(&c.name, ColumnType::Int64Summary, 0, &c.description, &c.unit, &c.validity_bitmap)));
+        schemas.extend(columns.f64_summary_values.iter()
+            .map(|c| column(&c.name, ColumnType::DoubleSummary, 0, &c.description, &c.unit, &c.validity_bitmap)));
+        schemas
+    }
+
+    /// Returns the logical type and the validity bitmap of the column, if it exists.
+    fn find<'a>(&self, columns: &Columns<'a>) -> Option<(i32, &'a [u8])> {
+        let name = &self.name;
+        match self.column_type {
+            ColumnType::Int64 => columns.i64_values.iter().find(|c| &c.name == name).map(|c| (c.logical_type, c.validity_bitmap.as_slice())),
+            ColumnType::Double => columns.f64_values.iter().find(|c| &c.name == name).map(|c| (c.logical_type, c.validity_bitmap.as_slice())),
+            ColumnType::String => columns.string_values.iter().find(|c| &c.name == name).map(|c| (c.logical_type, c.validity_bitmap.as_slice())),
+            ColumnType::Bool => columns.bool_values.iter().find(|c| &c.name == name).map(|c| (c.logical_type, c.validity_bitmap.as_slice())),
+            ColumnType::Bytes => columns.bytes_values.iter().find(|c| &c.name == name).map(|c| (c.logical_type, c.validity_bitmap.as_slice())),
+            ColumnType::Histogram => columns.histogram_values.iter().find(|c| &c.name == name).map(|c| (0, c.validity_bitmap.as_slice())),
+            ColumnType::ExponentialHistogram => columns.exponential_histogram_values.iter().find(|c| &c.name == name).map(|c| (0, c.validity_bitmap.as_slice())),
+            ColumnType::Int64Summary => columns.i64_summary_values.iter().find(|c| &c.name == name).map(|c| (0, c.validity_bitmap.as_slice())),
+            ColumnType::DoubleSummary => columns.f64_summary_values.iter().find(|c| &c.name == name).map(|c| (0, c.validity_bitmap.as_slice())),
+        }
+    }
+
+    /// Reads the value of this column in a row, null values are mapped onto `Value::Null`.
+    fn json_value(&self, row: &Row) -> Result<Value, Error> {
+        let name = &self.name;
+        Ok(match self.column_type {
+            ColumnType::Int64 => row.i64_value(name)?.map(Value::from),
+            ColumnType::Double => row.f64_value(name)?.and_then(Number::from_f64).map(Value::Number),
+            ColumnType::String => row.string_value(name)?.map(Value::from),
+            ColumnType::Bool => row.bool_value(name)?.map(Value::Bool),
+            ColumnType::Bytes => row.bytes_value(name)?.map(Value::from),
+            ColumnType::Histogram => row.histogram_value(name)?.map(|histogram| histogram.to_json_value()),
+            ColumnType::ExponentialHistogram => row.exponential_histogram_value(name)?.map(|histogram| histogram.to_json_value()),
+            ColumnType::Int64Summary => row.i64_summary_value(name)?.map(|summary| summary.to_json_value()),
+            ColumnType::DoubleSummary => row.f64_summary_value(name)?.map(|summary| summary.to_json_value()),
+        }.unwrap_or(Value::Null))
+    }
+}
+
+impl AuxiliaryEntitySchema {
+    fn of_auxiliary_entity(auxiliary_entity: &AuxiliaryEntity) -> Self {
+        AuxiliaryEntitySchema {
+            parent_column: auxiliary_entity.parent_column.clone(),
+            schema_url: auxiliary_entity.schema_url.clone(),
+            logical_type: auxiliary_entity.logical_type,
+            columns: ColumnSchema::of_columns(Columns::of_auxiliary_entity(auxiliary_entity)),
+        }
+    }
+}
+
+impl EventSchema {
+    /// Schema of the batches built by an `EventBatchHandler<T>`.
+    pub fn of<T: OpenTelemetryEvent>() -> Self {
+        let batch_policy = BatchPolicy::new(1, chrono::Duration::zero());
+        EventSchema::of_batch(&BatchEvent {
+            schema_url: T::urn(),
+            i64_values: T::int64_columns(&batch_policy),
+            f64_values: T::double_columns(&batch_policy),
+            string_values: T::string_columns(&batch_policy),
+            bool_values: T::bool_columns(&batch_policy),
+            bytes_values: T::bytes_columns(&batch_policy),
+            histogram_values: T::histogram_columns(&batch_policy),
+            exponential_histogram_values: T::exponential_histogram_columns(&batch_policy),
+            i64_summary_values: T::int64_summary_columns(&batch_policy),
+            f64_summary_values: T::double_summary_columns(&batch_policy),
+            auxiliary_entities: T::auxiliary_entities(&batch_policy),
+            ..Default::default()
+        })
+    }
+
+    /// Infers the schema of a batch, a column is optional if it has a validity bitmap.
+    pub fn of_batch(batch: &BatchEvent) -> Self {
+        EventSchema {
+            urn: batch.schema_url.clone(),
+            columns: ColumnSchema::of_columns(Columns::of_batch(batch)),
+            auxiliary_entities: batch.auxiliary_entities.iter().map(AuxiliaryEntitySchema::of_auxiliary_entity).collect(),
+        }
+    }
+
+    /// Checks that the batch is valid (see `BatchEvent::validate`) and has exactly the columns and auxiliary entities
+    /// of this schema. The non optional columns must not contain nulls. A batch recorded in dynamic schema mode (i.e.
+    /// ending with the overflow entity, see `dynamic_attributes`) may also contain this entity and extra int64, double,
+    /// string, bool and bytes columns (the dynamic columns).
+    pub fn validate(&self, batch: &BatchEvent) -> Result<(), Error> {
+        batch.validate()?;
+        if batch.schema_url != self.urn {
+            return Err(self.mismatch(format!("unexpected schema url '{}'", batch.schema_url)));
+        }
+
+        let dynamic = self.is_dynamic(batch);
+        self.validate_columns("", &self.columns, Columns::of_batch(batch), batch.size as usize, dynamic)?;
+
+        let expected_entities = self.auxiliary_entities.len() + dynamic as usize;
+        if batch.auxiliary_entities.len() != expected_entities {
+            return Err(self.mismatch(format!("{} auxiliary entities (expected {})", batch.auxiliary_entities.len(), expected_entities)));
+        }
+        for entity_schema in &self.auxiliary_entities {
+            let auxiliary_entity = batch.auxiliary_entities.iter()
+                .find(|auxiliary_entity| auxiliary_entity.parent_column == entity_schema.parent_column)
+                .ok_or_else(|| self.mismatch(format!("missing auxiliary entity '{}'", entity_schema.parent_column)))?;
+            if auxiliary_entity.logical_type != entity_schema.logical_type {
+                return Err(self.mismatch(format!("unexpected logical type {} of auxiliary entity '{}'", auxiliary_entity.logical_type, entity_schema.parent_column)));
+            }
+            let prefix = format!("{}.", entity_schema.parent_column);
+            self.validate_columns(&prefix, &entity_schema.columns, Columns::of_auxiliary_entity(auxiliary_entity), auxiliary_entity.size as usize, false)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if the batch has been recorded in dynamic schema mode.
+    fn is_dynamic(&self, batch: &BatchEvent) -> bool {
+        batch.auxiliary_entities.last().map(|entity| entity.parent_column == OVERFLOW_ATTRIBUTES).unwrap_or(false)
+            && !self.auxiliary_entities.iter().any(|entity_schema| entity_schema.parent_column == OVERFLOW_ATTRIBUTES)
+    }
+
+    /// With `dynamic_columns`, the columns of the types supported by the dynamic schema mode may be missing from
+    /// `column_schemas`.
+    fn validate_columns(&self, prefix: &str, column_schemas: &[ColumnSchema], columns: Columns, size: usize, dynamic_columns: bool) -> Result<(), Error> {
+        if dynamic_columns {
+            let unexpected_column = ColumnSchema::of_columns(columns).into_iter()
+                .filter(|column| !column_schemas.iter().any(|column_schema| column_schema.name == column.name && column_schema.column_type == column.column_type))
+                .find(|column| !matches!(column.column_type, ColumnType::Int64 | ColumnType::Double | ColumnType::String | ColumnType::Bool | ColumnType::Bytes));
+            if let Some(column) = unexpected_column {
+                return Err(self.mismatch(format!("unexpected {:?} column '{}{}'", column.column_type, prefix, column.name)));
+            }
+        } else {
+            let column_count = columns.i64_values.len() + columns.f64_values.len() + columns.string_values.len()
+                + columns.bool_values.len() + columns.bytes_values.len() + columns.histogram_values.len()
+                + columns.exponential_histogram_values.len() + columns.i64_summary_values.len() + columns.f64_summary_values.len();
+            if column_count != column_schemas.len() {
+                return Err(self.mismatch(format!("{} columns in '{}' (expected {})", column_count, prefix, column_schemas.len())));
+            }
+        }
+
+        for column_schema in column_schemas {
+            let name = format!("{}{}", prefix, column_schema.name);
+            let (logical_type, validity_bitmap) = column_schema.find(&columns)
+                .ok_or_else(|| self.mismatch(format!("missing {:?} column '{}'", column_schema.column_type, name)))?;
+            if logical_type != column_schema.logical_type {
+                return Err(self.mismatch(format!("unexpected logical type {} of column '{}'", logical_type, name)));
+            }
+            if !column_schema.optional && !validity_bitmap.is_empty() {
+                if let Some(row) = (0..size).find(|&row| validity_bitmap[row / 8] & (1 << (row % 8)) == 0) {
+                    return Err(Error::MissingValue { column: name, row });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decodes a batch of this schema into JSON objects, one per event. An object contains the start/end times, the
+    /// values of the columns (null if not defined) and the rows of each auxiliary entity (as an array of objects).
+    /// For a batch recorded in dynamic schema mode, it also contains the values of the dynamic columns and the
+    /// overflow attributes (as an object keyed by attribute key).
+    pub fn decode_json(&self, batch: &BatchEvent) -> Result<Vec<Value>, Error> {
+        self.validate(batch)?;
+        let reader = BatchEventReader::new(batch)?;
+        let mut events = Vec::with_capacity(reader.len());
+        let dynamic = self.is_dynamic(batch);
+        let dynamic_columns: Vec<ColumnSchema> = match dynamic {
+            true => ColumnSchema::of_columns(Columns::of_batch(batch)).into_iter()
+                .filter(|column| !self.columns.iter().any(|column_schema| column_schema.name == column.name && column_schema.column_type == column.column_type))
+                .collect(),
+            false => vec![],
+        };
+
+        for row in reader.rows() {
+            let mut event = Map::new();
+            event.insert("start_time_unix_nano".into(), Value::from(row.start_time_unix_nano()?));
+            event.insert("end_time_unix_nano".into(), Value::from(row.end_time_unix_nano()?));
+            for column_schema in self.columns.iter().chain(&dynamic_columns) {
+                insert_json_value(&mut event, &column_schema.name, column_schema.json_value(&row)?);
+            }
+            if dynamic {
+                let mut overflow_attributes = Map::new();
+                for overflow_row in row.auxiliary_rows(OVERFLOW_ATTRIBUTES)? {
+                    let key_value = Attribute::from_row(&overflow_row)?.into_key_value()?;
+                    overflow_attributes.insert(key_value.key, key_value.value.as_ref().map(any_value_json).unwrap_or(Value::Null));
+                }
+                event.insert(OVERFLOW_ATTRIBUTES.into(), Value::Object(overflow_attributes));
+            }
+            for entity_schema in &self.auxiliary_entities {
+                let mut entity_rows = vec![];
+                for entity_row in row.auxiliary_rows(&entity_schema.parent_column)? {
+                    let mut entity_object = Map::new();
+                    for column_schema in &entity_schema.columns {
+                        insert_json_value(&mut entity_object, &column_schema.name, column_schema.json_value(&entity_row)?);
+                    }
+                    entity_rows.push(Value::Object(entity_object));
+                }
+                event.insert(entity_schema.parent_column.clone(), Value::Array(entity_rows));
+            }
+            events.push(Value::Object(event));
+        }
+
+        Ok(events)
+    }
+
+    /// Lists the changes from `previous` to this schema.
+    pub fn changes(&self, previous: &EventSchema) -> Vec<SchemaChange> {
+        let mut changes = column_changes("", &previous.columns, &self.columns);
+
+        for entity_schema in &self.auxiliary_entities {
+            let parent_column = &entity_schema.parent_column;
+            match previous.auxiliary_entities.iter().find(|previous| &previous.parent_column == parent_column) {
+                Some(previous_schema) => {
+                    if previous_schema.logical_type != entity_schema.logical_type {
+                        changes.push(SchemaChange::LogicalTypeChanged { name: parent_column.clone(), from: previous_schema.logical_type, to: entity_schema.logical_type });
+                    }
+                    changes.extend(column_changes(&format!("{}.", parent_column), &previous_schema.columns, &entity_schema.columns));
+                }
+                None => changes.push(SchemaChange::AuxiliaryEntityAdded { parent_column: parent_column.clone() }),
+            }
+        }
+        for previous_schema in &previous.auxiliary_entities {
+            if !self.auxiliary_entities.iter().any(|entity_schema| entity_schema.parent_column == previous_schema.parent_column) {
+                changes.push(SchemaChange::AuxiliaryEntityRemoved { parent_column: previous_schema.parent_column.clone() });
+            }
+        }
+
+        changes
+    }
+
+    /// Returns the changes from `previous` to this schema breaking the given compatibility. A missing auxiliary entity
+    /// is read as an empty entity and a missing optional column as nulls.
+    pub fn compatibility_errors(&self, previous: &EventSchema, compatibility: Compatibility) -> Vec<SchemaChange> {
+        let backward = compatibility != Compatibility::Forward;
+        let forward = compatibility != Compatibility::Backward;
+
+        self.changes(previous).into_iter()
+            .filter(|change| match change {
+                SchemaChange::ColumnAdded { optional, .. } => backward && !optional,
+                SchemaChange::ColumnRemoved { optional, .. } => forward && !optional,
+                SchemaChange::TypeChanged { .. } | SchemaChange::LogicalTypeChanged { .. } => true,
+                SchemaChange::BecameOptional { .. } => forward,
+                SchemaChange::BecameRequired { .. } => backward,
+                SchemaChange::AuxiliaryEntityAdded { .. } | SchemaChange::AuxiliaryEntityRemoved { .. } => false,
+            })
+            .collect()
+    }
+
+    /// Fails with the list of the incompatible changes, if any.
+    pub fn check_compatibility(&self, previous: &EventSchema, compatibility: Compatibility) -> Result<(), Error> {
+        let errors = self.compatibility_errors(previous, compatibility);
+        if errors.is_empty() {
+            return Ok(());
+        }
+        let errors = errors.iter().map(|change| change.to_string()).collect::<Vec<_>>();
+        Err(self.mismatch(format!("incompatible changes ({:?}): {}", compatibility, errors.join(", "))))
+    }
+
+    /// Adapts a batch of another version of this schema: the columns and auxiliary entities unknown to this schema are
+    /// dropped, the missing optional columns are filled with nulls and the missing auxiliary entities are empty.
+    /// Fails if a required column is missing or contains nulls.
+    pub fn project(&self, batch: &BatchEvent) -> Result<BatchEvent, Error> {
+        batch.validate()?;
+        let size = batch.size as usize;
+        let mut projected_batch = BatchEvent {
+            schema_url: self.urn.clone(),
+            size: batch.size,
+            start_time_unix_nano_column: batch.start_time_unix_nano_column.clone(),
+            end_time_unix_nano_column: batch.end_time_unix_nano_column.clone(),
+            time_encoding: batch.time_encoding,
+            encoded_start_time_unix_nano_column: batch.encoded_start_time_unix_nano_column.clone(),
+            encoded_end_time_unix_nano_column: batch.encoded_end_time_unix_nano_column.clone(),
+            ..Default::default()
+        };
+        let columns = project_columns("", &self.columns, Columns::of_batch(batch), size)?;
+        projected_batch.i64_values = columns.i64_values;
+        projected_batch.f64_values = columns.f64_values;
+        projected_batch.string_values = columns.string_values;
+        projected_batch.bool_values = columns.bool_values;
+        projected_batch.bytes_values = columns.bytes_values;
+        projected_batch.histogram_values = columns.histogram_values;
+        projected_batch.exponential_histogram_values = columns.exponential_histogram_values;
+        projected_batch.i64_summary_values = columns.i64_summary_values;
+        projected_batch.f64_summary_values = columns.f64_summary_values;
+
+        for entity_schema in &self.auxiliary_entities {
+            let auxiliary_entity = batch.auxiliary_entities.iter()
+                .find(|auxiliary_entity| auxiliary_entity.parent_column == entity_schema.parent_column);
+            let (entity_size, parent_ranks, columns) = match auxiliary_entity {
+                Some(auxiliary_entity) => {
+                    let prefix = format!("{}.", entity_schema.parent_column);
+                    let columns = project_columns(&prefix, &entity_schema.columns, Columns::of_auxiliary_entity(auxiliary_entity), auxiliary_entity.size as usize)?;
+                    (auxiliary_entity.size, auxiliary_entity.parent_ranks.clone(), columns)
+                }
+                None => (0, vec![], project_columns("", &entity_schema.columns, Columns::of_batch(&BatchEvent::default()), 0)?),
+            };
+            projected_batch.auxiliary_entities.push(AuxiliaryEntity {
+                schema_url: entity_schema.schema_url.clone(),
+                logical_type: entity_schema.logical_type,
+                size: entity_size,
+                parent_column: entity_schema.parent_column.clone(),
+                parent_ranks,
+                i64_values: columns.i64_values,
+                f64_values: columns.f64_values,
+                string_values: columns.string_values,
+                bool_values: columns.bool_values,
+                bytes_values: columns.bytes_values,
+                histogram_values: columns.histogram_values,
+                exponential_histogram_values: columns.exponential_histogram_values,
+                i64_summary_values: columns.i64_summary_values,
+                f64_summary_values: columns.f64_summary_values,
+            });
+        }
+
+        self.validate(&projected_batch)?;
+        Ok(projected_batch)
+    }
+
+    fn mismatch(&self, message: String) -> Error {
+        Error::SchemaMismatch { schema_url: self.urn.clone(), message }
+    }
+}
+
+impl SchemaRegistry {
+    pub fn new() -> Self {
+        SchemaRegistry::default()
+    }
+
+    /// Registers (or replaces) the schema of its URN.
+    pub fn register(&mut self, schema: EventSchema) {
+        self.schemas.insert(schema.urn.clone(), schema);
+    }
+
+    pub fn register_event<T: OpenTelemetryEvent>(&mut self) {
+        self.register(EventSchema::of::<T>());
+    }
+
+    pub fn get(&self, urn: &str) -> Option<&EventSchema> {
+        self.schemas.get(urn)
+    }
+
+    pub fn schemas(&self) -> impl Iterator<Item=&EventSchema> {
+        self.schemas.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.schemas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.schemas.is_empty()
+    }
+
+    /// Validates a batch against the schema of its URN, fails if the URN is not registered.
+    pub fn validate_batch(&self, batch: &BatchEvent) -> Result<(), Error> {
+        self.schema_of(batch)?.validate(batch)
+    }
+
+    /// Validates the batches of the registered URNs, the other batches are only checked with `BatchEvent::validate`.
+    pub fn validate(&self, resource_events: &ResourceEvents) -> Result<(), Error> {
+        for batch in resource_events.instrumentation_library_events.iter().flat_map(|events| &events.batches) {
+            match self.get(&batch.schema_url) {
+                Some(schema) => schema.validate(batch)?,
+                None => batch.validate()?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Registers a new version of the schema of its URN, fails if it breaks the compatibility with the registered
+    /// version.
+    pub fn register_compatible(&mut self, schema: EventSchema, compatibility: Compatibility) -> Result<(), Error> {
+        if let Some(previous) = self.get(&schema.urn) {
+            schema.check_compatibility(previous, compatibility)?;
+        }
+        self.register(schema);
+        Ok(())
+    }
+
+    /// Decodes a batch with the schema of its URN (see `EventSchema::decode_json`).
+    pub fn decode_json(&self, batch: &BatchEvent) -> Result<Vec<Value>, Error> {
+        self.schema_of(batch)?.decode_json(batch)
+    }
+
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string(&self.schemas.values().collect::<Vec<_>>())?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        let schemas: Vec<EventSchema> = serde_json::from_str(json)?;
+        let mut registry = SchemaRegistry::new();
+        for schema in schemas {
+            registry.register(schema);
+        }
+        Ok(registry)
+    }
+
+    fn schema_of(&self, batch: &BatchEvent) -> Result<&EventSchema, Error> {
+        self.get(&batch.schema_url).ok_or_else(|| Error::UnknownSchema(batch.schema_url.clone()))
+    }
+}
+
+impl fmt::Display for SchemaChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaChange::ColumnAdded { name, column_type, .. } => write!(f, "{:?} column '{}' added", column_type, name),
+            SchemaChange::ColumnRemoved { name, column_type, .. } => write!(f, "{:?} column '{}' removed", column_type, name),
+            SchemaChange::TypeChanged { name, from, to } => write!(f, "type of column '{}' changed from {:?} to {:?}", name, from, to),
+            SchemaChange::LogicalTypeChanged { name, from, to } => write!(f, "logical type of '{}' changed from {} to {}", name, from, to),
+            SchemaChange::BecameOptional { name } => write!(f, "column '{}' became optional", name),
+            SchemaChange::BecameRequired { name } => write!(f, "column '{}' became required", name),
+            SchemaChange::AuxiliaryEntityAdded { parent_column } => write!(f, "auxiliary entity '{}' added", parent_column),
+            SchemaChange::AuxiliaryEntityRemoved { parent_column } => write!(f, "auxiliary entity '{}' removed", parent_column),
+        }
+    }
+}
+
+/// Projected columns of a batch or of an auxiliary entity.
+#[derive(Default)]
+struct ProjectedColumns {
+    i64_values: Vec<Int64Column>,
+    f64_values: Vec<DoubleColumn>,
+    string_values: Vec<StringColumn>,
+    bool_values: Vec<BoolColumn>,
+    bytes_values: Vec<BytesColumn>,
+    histogram_values: Vec<HistogramColumn>,
+    exponential_histogram_values: Vec<ExponentialHistogramColumn>,
+    i64_summary_values: Vec<Int64SummaryColumn>,
+    f64_summary_values: Vec<DoubleSummaryColumn>,
+}
+
+fn project_columns(prefix: &str, column_schemas: &[ColumnSchema], columns: Columns, size: usize) -> Result<ProjectedColumns, Error> {
+    let mut projected_columns = ProjectedColumns::default();
+
+    for column_schema in column_schemas {
+        let name = &column_schema.name;
+        let found = match column_schema.column_type {
+            ColumnType::Int64 => push_column(&mut projected_columns.i64_values, columns.i64_values, name, |c| &c.name),
+            ColumnType::Double => push_column(&mut projected_columns.f64_values, columns.f64_values, name, |c| &c.name),
+            ColumnType::String => push_column(&mut projected_columns.string_values, columns.string_values, name, |c| &c.name),
+            ColumnType::Bool => push_column(&mut projected_columns.bool_values, columns.bool_values, name, |c| &c.name),
+            ColumnType::Bytes => push_column(&mut projected_columns.bytes_values, columns.bytes_values, name, |c| &c.name),
+            ColumnType::Histogram => push_column(&mut projected_columns.histogram_values, columns.histogram_values, name, |c| &c.name),
+            ColumnType::ExponentialHistogram => push_column(&mut projected_columns.exponential_histogram_values, columns.exponential_histogram_values, name, |c| &c.name),
+            ColumnType::Int64Summary => push_column(&mut projected_columns.i64_summary_values, columns.i64_summary_values, name, |c| &c.name),
+            ColumnType::DoubleSummary => push_column(&mut projected_columns.f64_summary_values, columns.f64_summary_values, name, |c| &c.name),
+        };
+        if found {
+            continue;
+        }
+        // An empty required column is also created, e.g. for a missing auxiliary entity.
+        if !column_schema.optional && size > 0 {
+            return Err(Error::MissingColumn(format!("{}{}", prefix, name)));
+        }
+
+        let name = name.clone();
+        let logical_type = column_schema.logical_type;
+        let description = column_schema.description.clone();
+        let validity_bitmap = validity_bitmap(size);
+        match column_schema.column_type {
+            ColumnType::Int64 => projected_columns.i64_values.push(Int64Column {
+                name, logical_type, description, unit: column_schema.unit.clone(), values: vec![0; size], validity_bitmap, ..Default::default()
+            }),
+            ColumnType::Double => projected_columns.f64_values.push(DoubleColumn {
+                name, logical_type, description, unit: column_schema.unit.clone(), values: vec![0.0; size], validity_bitmap, ..Default::default()
+            }),
+            ColumnType::String => projected_columns.string_values.push(StringColumn {
+                name, logical_type, description, values: vec![String::new(); size], validity_bitmap, ..Default::default()
+            }),
+            ColumnType::Bool => projected_columns.bool_values.push(BoolColumn {
+                name, logical_type, description, values: vec![false; size], validity_bitmap,
+            }),
+            ColumnType::Bytes => projected_columns.bytes_values.push(BytesColumn {
+                name, logical_type, description, values: vec![vec![]; size], validity_bitmap, ..Default::default()
+            }),
+            ColumnType::Histogram => {
+                let mut column = HistogramColumn {
+                    validity_bitmap,
+                    ..HistogramColumn::new(name, description, column_schema.unit.clone(), AggregationTemporality::Unspecified)
+                };
+                (0..size).for_each(|_| column.push(None));
+                projected_columns.histogram_values.push(column);
+            }
+            ColumnType::ExponentialHistogram => {
+                let mut column = ExponentialHistogramColumn {
+                    validity_bitmap,
+                    ..ExponentialHistogramColumn::new(name, description, column_schema.unit.clone(), AggregationTemporality::Unspecified)
+                };
+                (0..size).for_each(|_| column.push(None));
+                projected_columns.exponential_histogram_values.push(column);
+            }
+            ColumnType::Int64Summary => {
+                let mut column = Int64SummaryColumn {
+                    validity_bitmap,
+                    ..Int64SummaryColumn::new(name, description, column_schema.unit.clone(), AggregationTemporality::Unspecified)
+                };
+                (0..size).for_each(|_| column.push(None));
+                projected_columns.i64_summary_values.push(column);
+            }
+            ColumnType::DoubleSummary => {
+                let mut column = DoubleSummaryColumn {
+                    validity_bitmap,
+                    ..DoubleSummaryColumn::new(name, description, column_schema.unit.clone(), AggregationTemporality::Unspecified)
+                };
+                (0..size).for_each(|_| column.push(None));
+                projected_columns.f64_summary_values.push(column);
+            }
+        }
+    }
+
+    Ok(projected_columns)
+}
+
+/// Copies the column `name`, if it exists. Returns false otherwise.
+fn push_column<C: Clone>(projected_columns: &mut Vec<C>, columns: &[C], name: &str, column_name: impl Fn(&C) -> &String) -> bool {
+    match columns.iter().find(|column| column_name(column) == name) {
+        Some(column) => {
+            projected_columns.push(column.clone());
+            true
+        }
+        None => false,
+    }
+}
+
+fn column_changes(prefix: &str, previous_columns: &[ColumnSchema], columns: &[ColumnSchema]) -> Vec<SchemaChange> {
+    let find = |columns: &'_ [ColumnSchema], column: &ColumnSchema| columns.iter()
+        .find(|other| other.name == column.name && other.column_type == column.column_type)
+        .cloned();
+    let mut changes = vec![];
+    let mut added = vec![];
+
+    for column in columns {
+        let name = format!("{}{}", prefix, column.name);
+        match find(previous_columns, column) {
+            Some(previous) => {
+                if previous.logical_type != column.logical_type {
+                    changes.push(SchemaChange::LogicalTypeChanged { name: name.clone(), from: previous.logical_type, to: column.logical_type });
+                }
+                if previous.optional && !column.optional {
+                    changes.push(SchemaChange::BecameRequired { name });
+                } else if !previous.optional && column.optional {
+                    changes.push(SchemaChange::BecameOptional { name });
+                }
+            }
+            None => added.push(column),
+        }
+    }
+
+    let mut removed: Vec<_> = previous_columns.iter().filter(|previous| find(columns, previous).is_none()).collect();
+    for column in added {
+        let name = format!("{}{}", prefix, column.name);
+        // A column removed and added with the same name is a type change.
+        match removed.iter().position(|previous| previous.name == column.name) {
+            Some(position) => {
+                let previous = removed.remove(position);
+                changes.push(SchemaChange::TypeChanged { name, from: previous.column_type, to: column.column_type });
+            }
+            None => changes.push(SchemaChange::ColumnAdded { name, column_type: column.column_type, optional: column.optional }),
+        }
+    }
+    for previous in removed {
+        let name = format!("{}{}", prefix, previous.name);
+        changes.push(SchemaChange::ColumnRemoved { name, column_type: previous.column_type, optional: previous.optional });
+    }
+
+    changes
+}
+
+/// Decodes a batch written with another version of the schema of `T` (see `EventSchema::project`).
+pub fn decode_projected_batch_event<T: OpenTelemetryEvent + FromBatchEvent>(batch: &BatchEvent) -> Result<Vec<T>, Error> {
+    decode_batch_event(&EventSchema::of::<T>().project(batch)?)
+}
+
+/// Converts an attribute value, the bytes are an array of numbers as in the bytes columns.
+fn any_value_json(value: &AnyValue) -> Value {
+    match &value.value {
+        Some(any_value::Value::StringValue(value)) => Value::from(value.as_str()),
+        Some(any_value::Value::BoolValue(value)) => Value::Bool(*value),
+        Some(any_value::Value::IntValue(value)) => Value::from(*value),
+        Some(any_value::Value::DoubleValue(value)) => Number::from_f64(*value).map(Value::Number).unwrap_or(Value::Null),
+        Some(any_value::Value::BytesValue(value)) => Value::from(value.as_slice()),
+        Some(any_value::Value::ArrayValue(array)) => Value::Array(array.values.iter().map(any_value_json).collect()),
+        Some(any_value::Value::KvlistValue(kvlist)) => Value::Object(kvlist.values.iter()
+            .map(|key_value| (key_value.key.clone(), key_value.value.as_ref().map(any_value_json).unwrap_or(Value::Null)))
+            .collect()),
+        None => Value::Null,
+    }
+}
+
+/// Columns of different types may share a name (e.g. the typed values of an attribute), a null value doesn't override
+/// the value of another column.
+fn insert_json_value(object: &mut Map<String, Value>, name: &str, value: Value) {
+    if value.is_null() {
+        object.entry(name).or_insert(Value::Null);
+    } else {
+        object.insert(name.into(), value);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::attribute::Attribute;
+    use crate::dynamic_attributes::OVERFLOW_ATTRIBUTES;
+    use crate::event::{BatchPolicy, Error, EventBatchHandler, OpenTelemetryEvent, to_batch_event};
+    use crate::event_decoder::FromBatchEvent;
+    use crate::opentelemetry::proto::common::v1::{AnyValue, KeyValue};
+    use crate::opentelemetry::proto::common::v1::any_value::Value;
+    use crate::opentelemetry::proto::events::v1::Int64Column;
+    use crate::sink::{MemorySink, ResourceBatch};
+    use crate::schema::{ColumnSchema, ColumnType, Compatibility, EventSchema, SchemaChange, SchemaRegistry, decode_projected_batch_event};
+
+    #[derive(Debug, Clone, PartialEq, OpenTelemetryEvent)]
+    #[otel(urn = "urn:test:request")]
+    struct Request {
+        #[otel(start_time)]
+        start_time: u64,
+        method: String,
+        #[otel(unit = "ms")]
+        duration: f64,
+        user: Option<String>,
+        #[otel(logical_type = "attribute")]
+        attributes: Vec<Attribute>,
+    }
+
+    /// Second version of `Request`: `duration` became optional, `user` and `attributes` were removed and `status`
+    /// was added.
+    #[derive(Debug, Clone, PartialEq, OpenTelemetryEvent, FromBatchEvent)]
+    #[otel(urn = "urn:test:request")]
+    struct RequestV2 {
+        #[otel(start_time)]
+        start_time: u64,
+        method: String,
+        #[otel(unit = "ms")]
+        duration: Option<f64>,
+        status: Option<i64>,
+    }
+
+    fn requests() -> Vec<Request> {
+        vec![
+            Request {
+                start_time: 1,
+                method: "GET".into(),
+                duration: 1.5,
+                user: Some("user_1".into()),
+                attributes: vec![Attribute { key: "cached".into(), bool_value: Some(true), ..Default::default() }],
+            },
+            Request { start_time: 2, method: "POST".into(), duration: 10.0, user: None, attributes: vec![] },
+        ]
+    }
+
+    #[test]
+    fn test_schema() {
+        let schema = EventSchema::of::<Request>();
+        assert_eq!(schema.urn, "urn:test:request");
+        let columns: Vec<_> = schema.columns.iter().map(|column| (column.name.as_str(), column.column_type, column.optional)).collect();
+        assert_eq!(columns, vec![
+            ("duration", ColumnType::Double, false),
+            ("method", ColumnType::String, false),
+            ("user", ColumnType::String, true),
+        ]);
+        assert_eq!(schema.columns[0].unit, "ms");
+        assert_eq!(schema.auxiliary_entities[0].parent_column, "attributes");
+
+        let batch = to_batch_event(requests()).unwrap();
+        assert_eq!(EventSchema::of_batch(&batch), schema);
+        schema.validate(&batch).unwrap();
+
+        let mut missing_column = batch.clone();
+        missing_column.f64_values.clear();
+        assert!(matches!(schema.validate(&missing_column), Err(Error::SchemaMismatch { .. })));
+
+        let mut null_value = batch;
+        null_value.f64_values[0].validity_bitmap = vec![0b01];
+        assert!(matches!(schema.validate(&null_value), Err(Error::MissingValue { row: 1, .. })));
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = SchemaRegistry::new();
+        registry.register_event::<Request>();
+        let registry = SchemaRegistry::from_json(&registry.to_json().unwrap()).unwrap();
+        assert_eq!(registry.len(), 1);
+
+        let mut batch = to_batch_event(requests()).unwrap();
+        registry.validate_batch(&batch).unwrap();
+        assert_eq!(registry.decode_json(&batch).unwrap(), vec![
+            json!({
+                "start_time_unix_nano": 1, "end_time_unix_nano": 1, "duration": 1.5, "method": "GET", "user": "user_1",
+                "attributes": [{
+                    "key": "cached", "value": true, "value_json": null,
+                }],
+            }),
+            json!({
+                "start_time_unix_nano": 2, "end_time_unix_nano": 2, "duration": 10.0, "method": "POST", "user": null,
+                "attributes": [],
+            }),
+        ]);
+
+        batch.schema_url = "urn:test:unknown".into();
+        assert!(matches!(registry.validate_batch(&batch), Err(Error::UnknownSchema(_))));
+    }
+
+    #[test]
+    fn test_dynamic_attributes() {
+        let mut registry = SchemaRegistry::new();
+        registry.register_event::<Request>();
+
+        let sink = MemorySink::new();
+        let mut handler = EventBatchHandler::<Request>::new(BatchPolicy::new(10, chrono::Duration::seconds(10)))
+            .with_sink(sink.clone())
+            .with_dynamic_attributes(1);
+        for (request, status) in requests().into_iter().zip(200..) {
+            let attributes = vec![
+                KeyValue { key: "status".into(), value: Some(AnyValue { value: Some(Value::IntValue(status)) }) },
+                KeyValue { key: "region".into(), value: Some(AnyValue { value: Some(Value::StringValue("eu".into())) }) },
+            ];
+            handler.record_with_attributes(request, attributes).unwrap();
+        }
+        handler.flush().unwrap();
+
+        let mut batch = match sink.take().remove(0) {
+            ResourceBatch::Events(mut resource_events) => resource_events.instrumentation_library_events.remove(0).batches.remove(0),
+            batch => panic!("unexpected batch: {:?}", batch),
+        };
+        assert_eq!(batch.i64_values[0].name, "status");
+        assert_eq!(batch.auxiliary_entities.last().unwrap().parent_column, OVERFLOW_ATTRIBUTES);
+        registry.validate_batch(&batch).unwrap();
+        let events = registry.decode_json(&batch).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1]["status"], json!(201));
+        assert_eq!(events[1][OVERFLOW_ATTRIBUTES], json!({"region": "eu"}));
+
+        // The static columns are still checked, and the extra columns are only accepted at the top level.
+        let mut missing_column = batch.clone();
+        missing_column.f64_values.clear();
+        assert!(matches!(registry.validate_batch(&missing_column), Err(Error::SchemaMismatch { .. })));
+        let attributes = &mut batch.auxiliary_entities[0];
+        attributes.i64_values.push(Int64Column { name: "extra".into(), values: vec![0; attributes.size as usize], ..Default::default() });
+        assert!(matches!(registry.validate_batch(&batch), Err(Error::SchemaMismatch { .. })));
+
+        // Without the overflow entity, the extra columns are rejected.
+        let mut static_batch = to_batch_event(requests()).unwrap();
+        static_batch.i64_values.push(Int64Column { name: "status".into(), values: vec![200, 201], ..Default::default() });
+        assert!(matches!(registry.validate_batch(&static_batch), Err(Error::SchemaMismatch { .. })));
+    }
+
+    #[test]
+    fn test_compatibility() {
+        let v1 = EventSchema::of::<Request>();
+        let v2 = EventSchema::of::<RequestV2>();
+        assert_eq!(v2.changes(&v1), vec![
+            SchemaChange::BecameOptional { name: "duration".into() },
+            SchemaChange::ColumnAdded { name: "status".into(), column_type: ColumnType::Int64, optional: true },
+            SchemaChange::ColumnRemoved { name: "user".into(), column_type: ColumnType::String, optional: true },
+            SchemaChange::AuxiliaryEntityRemoved { parent_column: "attributes".into() },
+        ]);
+        assert!(v2.compatibility_errors(&v1, Compatibility::Backward).is_empty());
+        assert_eq!(v2.compatibility_errors(&v1, Compatibility::Forward), vec![SchemaChange::BecameOptional { name: "duration".into() }]);
+        assert!(v2.check_compatibility(&v1, Compatibility::Full).is_err());
+
+        let mut v3 = v2.clone();
+        v3.columns.iter_mut().find(|column| column.name == "method").unwrap().column_type = ColumnType::Bytes;
+        v3.columns.push(ColumnSchema {
+            name: "region".into(),
+            column_type: ColumnType::String,
+            logical_type: 0,
+            description: "".into(),
+            unit: "".into(),
+            optional: false,
+        });
+        assert_eq!(v3.compatibility_errors(&v2, Compatibility::Backward), vec![
+            SchemaChange::TypeChanged { name: "method".into(), from: ColumnType::String, to: ColumnType::Bytes },
+            SchemaChange::ColumnAdded { name: "region".into(), column_type: ColumnType::String, optional: false },
+        ]);
+
+        let mut registry = SchemaRegistry::new();
+        registry.register_compatible(v1, Compatibility::Backward).unwrap();
+        registry.register_compatible(v2.clone(), Compatibility::Backward).unwrap();
+        assert!(registry.register_compatible(v3, Compatibility::Backward).is_err());
+        assert_eq!(registry.get("urn:test:request"), Some(&v2));
+    }
+
+    #[test]
+    fn test_projection() {
+        let batch = to_batch_event(requests()).unwrap();
+        assert_eq!(decode_projected_batch_event::<RequestV2>(&batch).unwrap(), vec![
+            RequestV2 { start_time: 1, method: "GET".into(), duration: Some(1.5), status: None },
+            RequestV2 { start_time: 2, method: "POST".into(), duration: Some(10.0), status: None },
+        ]);
+
+        // The previous version can't read batches missing the required duration.
+        let v2_batch = to_batch_event(vec![RequestV2 { start_time: 3, method: "GET".into(), duration: None, status: Some(200) }]).unwrap();
+        let projected_batch = EventSchema::of::<RequestV2>().project(&v2_batch).unwrap();
+        assert_eq!(projected_batch, v2_batch);
+        assert!(matches!(EventSchema::of::<Request>().project(&v2_batch), Err(Error::MissingValue { .. })));
+    }
+}
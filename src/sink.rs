@@ -0,0 +1,456 @@
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Stdout, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use prost::Message;
+use serde::{Deserialize, Serialize};
+
+use crate::event::Error;
+use crate::opentelemetry::proto::arrow_events::v1 as arrow_events;
+use crate::opentelemetry::proto::events::v1::ResourceEvents;
+use crate::opentelemetry::proto::metrics::v1::ResourceMetrics;
+
+/// Destination of the batches completed by the event handlers (by size, by age or explicitly flushed).
+pub trait EventSink: Debug {
+    fn export_events(&mut self, resource_events: ResourceEvents) -> Result<(), Error>;
+    fn export_arrow_events(&mut self, resource_events: arrow_events::ResourceEvents) -> Result<(), Error>;
+    fn export_metrics(&mut self, resource_metrics: ResourceMetrics) -> Result<(), Error>;
+
+    /// Flushes the batches buffered by the sink itself (if any).
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// A batch received by a sink.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ResourceBatch {
+    Events(ResourceEvents),
+    ArrowEvents(arrow_events::ResourceEvents),
+    Metrics(ResourceMetrics),
+}
+
+/// Keeps the exported batches in memory. Clones share the same buffer, so a clone can be kept to inspect the
+/// batches exported by a handler owning the sink.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySink {
+    batches: Arc<Mutex<Vec<ResourceBatch>>>,
+}
+
+/// Writes the exported batches into a stream of length-delimited protobuf messages. Each message is prefixed by
+/// a one byte tag identifying the type of the message (see `LengthDelimitedReader`).
+#[derive(Debug)]
+pub struct LengthDelimitedSink<W: Write + Debug> {
+    writer: W,
+    buffer: Vec<u8>,
+}
+
+/// Reads the batches written by a `LengthDelimitedSink`.
+#[derive(Debug)]
+pub struct LengthDelimitedReader<R: Read> {
+    reader: R,
+    buffer: Vec<u8>,
+    max_frame_length: usize,
+}
+
+/// Writes the exported batches as JSON, one batch per line.
+#[derive(Debug)]
+pub struct JsonSink<W: Write + Debug> {
+    writer: W,
+}
+
+pub(crate) const EVENTS_TAG: u8 = 1;
+pub(crate) const ARROW_EVENTS_TAG: u8 = 2;
+pub(crate) const METRICS_TAG: u8 = 3;
+
+/// Default max length of a message read by a `LengthDelimitedReader` (64 MiB).
+pub const DEFAULT_MAX_FRAME_LENGTH: usize = 64 * 1024 * 1024;
+
+impl ResourceBatch {
+    /// Exports the batch to the sink method matching its type.
+    pub fn export_to<S: EventSink + ?Sized>(self, sink: &mut S) -> Result<(), Error> {
+        match self {
+            ResourceBatch::Events(resource_events) => sink.export_events(resource_events),
+            ResourceBatch::ArrowEvents(resource_events) => sink.export_arrow_events(resource_events),
+            ResourceBatch::Metrics(resource_metrics) => sink.export_metrics(resource_metrics),
+        }
+    }
+
+    pub(crate) fn tag(&self) -> u8 {
+        match self {
+            ResourceBatch::Events(_) => EVENTS_TAG,
+            ResourceBatch::ArrowEvents(_) => ARROW_EVENTS_TAG,
+            ResourceBatch::Metrics(_) => METRICS_TAG,
+        }
+    }
+
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) -> Result<(), Error> {
+        match self {
+            ResourceBatch::Events(resource_events) => resource_events.encode(buf)?,
+            ResourceBatch::ArrowEvents(resource_events) => resource_events.encode(buf)?,
+            ResourceBatch::Metrics(resource_metrics) => resource_metrics.encode(buf)?,
+        }
+        Ok(())
+    }
+
+    fn encode_length_delimited(&self, buf: &mut Vec<u8>) -> Result<(), Error> {
+        match self {
+            ResourceBatch::Events(resource_events) => resource_events.encode_length_delimited(buf)?,
+            ResourceBatch::ArrowEvents(resource_events) => resource_events.encode_length_delimited(buf)?,
+            ResourceBatch::Metrics(resource_metrics) => resource_metrics.encode_length_delimited(buf)?,
+        }
+        Ok(())
+    }
+
+    /// Decodes and validates a batch encoded by `encode`.
+    pub(crate) fn decode(tag: u8, buf: Bytes) -> Result<Self, Error> {
+        let batch = match tag {
+            EVENTS_TAG => ResourceBatch::Events(ResourceEvents::decode(buf)?),
+            ARROW_EVENTS_TAG => ResourceBatch::ArrowEvents(arrow_events::ResourceEvents::decode(buf)?),
+            METRICS_TAG => ResourceBatch::Metrics(ResourceMetrics::decode(buf)?),
+            tag => return Err(Error::InvalidFormat(format!("unknown batch tag {}", tag))),
+        };
+        batch.validate()?;
+        Ok(batch)
+    }
+
+    /// Checks the columnar invariants of the events and the metrics, the Arrow events are not checked.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        match self {
+            ResourceBatch::Events(resource_events) => resource_events.validate(),
+            ResourceBatch::ArrowEvents(_) => Ok(()),
+            ResourceBatch::Metrics(resource_metrics) => resource_metrics.validate(),
+        }
+    }
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.batches.lock().expect("memory sink lock poisoned").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns a copy of the exported batches.
+    pub fn batches(&self) -> Vec<ResourceBatch> {
+        self.batches.lock().expect("memory sink lock poisoned").clone()
+    }
+
+    /// Removes and returns the exported batches.
+    pub fn take(&self) -> Vec<ResourceBatch> {
+        std::mem::take(&mut *self.batches.lock().expect("memory sink lock poisoned"))
+    }
+
+    fn push(&self, batch: ResourceBatch) {
+        self.batches.lock().expect("memory sink lock poisoned").push(batch);
+    }
+}
+
+impl EventSink for MemorySink {
+    fn export_events(&mut self, resource_events: ResourceEvents) -> Result<(), Error> {
+        self.push(ResourceBatch::Events(resource_events));
+        Ok(())
+    }
+
+    fn export_arrow_events(&mut self, resource_events: arrow_events::ResourceEvents) -> Result<(), Error> {
+        self.push(ResourceBatch::ArrowEvents(resource_events));
+        Ok(())
+    }
+
+    fn export_metrics(&mut self, resource_metrics: ResourceMetrics) -> Result<(), Error> {
+        self.push(ResourceBatch::Metrics(resource_metrics));
+        Ok(())
+    }
+}
+
+impl LengthDelimitedSink<BufWriter<File>> {
+    /// Creates (or truncates) the file.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write + Debug> LengthDelimitedSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, buffer: vec![] }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write(&mut self, batch: &ResourceBatch) -> Result<(), Error> {
+        self.buffer.clear();
+        self.buffer.push(batch.tag());
+        batch.encode_length_delimited(&mut self.buffer)?;
+        self.writer.write_all(&self.buffer)?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Debug> EventSink for LengthDelimitedSink<W> {
+    fn export_events(&mut self, resource_events: ResourceEvents) -> Result<(), Error> {
+        self.write(&ResourceBatch::Events(resource_events))
+    }
+
+    fn export_arrow_events(&mut self, resource_events: arrow_events::ResourceEvents) -> Result<(), Error> {
+        self.write(&ResourceBatch::ArrowEvents(resource_events))
+    }
+
+    fn export_metrics(&mut self, resource_metrics: ResourceMetrics) -> Result<(), Error> {
+        self.write(&ResourceBatch::Metrics(resource_metrics))
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl LengthDelimitedReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Ok(Self::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: Read> LengthDelimitedReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader, buffer: vec![], max_frame_length: DEFAULT_MAX_FRAME_LENGTH }
+    }
+
+    /// Sets the max length of a message (`DEFAULT_MAX_FRAME_LENGTH` by default). A longer message is reported as an
+    /// `Error::InvalidFormat` before its buffer is allocated.
+    pub fn with_max_frame_length(mut self, max_frame_length: usize) -> Self {
+        self.max_frame_length = max_frame_length;
+        self
+    }
+
+    /// Reads and validates the next batch, returns None at the end of the stream.
+    pub fn read_batch(&mut self) -> Result<Option<ResourceBatch>, Error> {
+        let mut tag = [0u8; 1];
+        match self.reader.read_exact(&mut tag) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+
+        let len = self.read_varint()?;
+        if len > self.max_frame_length as u64 {
+            return Err(Error::InvalidFormat(format!("frame length {} exceeds the max frame length {}", len, self.max_frame_length)));
+        }
+        self.buffer.resize(len as usize, 0);
+        self.reader.read_exact(&mut self.buffer)?;
+        ResourceBatch::decode(tag[0], Bytes::copy_from_slice(&self.buffer)).map(Some)
+    }
+
+    fn read_varint(&mut self) -> Result<u64, Error> {
+        let mut value = 0u64;
+        let mut byte = [0u8; 1];
+
+        for shift in (0..64).step_by(7) {
+            self.reader.read_exact(&mut byte)?;
+            value |= ((byte[0] & 0x7f) as u64) << shift;
+            if byte[0] & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(Error::InvalidFormat("invalid length delimiter".into()))
+    }
+}
+
+impl<R: Read> Iterator for LengthDelimitedReader<R> {
+    type Item = Result<ResourceBatch, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_batch().transpose()
+    }
+}
+
+impl JsonSink<Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl<W: Write + Debug> JsonSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write(&mut self, batch: &ResourceBatch) -> Result<(), Error> {
+        serde_json::to_writer(&mut self.writer, batch)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Debug> EventSink for JsonSink<W> {
+    fn export_events(&mut self, resource_events: ResourceEvents) -> Result<(), Error> {
+        self.write(&ResourceBatch::Events(resource_events))
+    }
+
+    fn export_arrow_events(&mut self, resource_events: arrow_events::ResourceEvents) -> Result<(), Error> {
+        self.write(&ResourceBatch::ArrowEvents(resource_events))
+    }
+
+    fn export_metrics(&mut self, resource_metrics: ResourceMetrics) -> Result<(), Error> {
+        self.write(&ResourceBatch::Metrics(resource_metrics))
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Test sink failing the exports while `available` is false, the other exports go to `sink`.
+#[cfg(test)]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct UnavailableSink {
+    pub(crate) available: Arc<std::sync::atomic::AtomicBool>,
+    pub(crate) sink: MemorySink,
+}
+
+#[cfg(test)]
+impl UnavailableSink {
+    pub(crate) fn new(available: bool, sink: MemorySink) -> Self {
+        Self { available: Arc::new(available.into()), sink }
+    }
+
+    pub(crate) fn set_available(&self, available: bool) {
+        self.available.store(available, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    fn check_available(&self) -> Result<(), Error> {
+        if self.available.load(std::sync::atomic::Ordering::SeqCst) {
+            Ok(())
+        } else {
+            Err(Error::InvalidFormat("unavailable".into()))
+        }
+    }
+}
+
+#[cfg(test)]
+impl EventSink for UnavailableSink {
+    fn export_events(&mut self, resource_events: ResourceEvents) -> Result<(), Error> {
+        self.check_available()?;
+        self.sink.export_events(resource_events)
+    }
+
+    fn export_arrow_events(&mut self, resource_events: arrow_events::ResourceEvents) -> Result<(), Error> {
+        self.check_available()?;
+        self.sink.export_arrow_events(resource_events)
+    }
+
+    fn export_metrics(&mut self, resource_metrics: ResourceMetrics) -> Result<(), Error> {
+        self.check_available()?;
+        self.sink.export_metrics(resource_metrics)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::event::Error;
+    use crate::sink::{EventSink, MemorySink, LengthDelimitedSink, LengthDelimitedReader, JsonSink, ResourceBatch, EVENTS_TAG};
+    use crate::opentelemetry::proto::events::v1::{ResourceEvents, InstrumentationLibraryEvents, BatchEvent};
+    use crate::opentelemetry::proto::arrow_events::v1 as arrow_events;
+    use crate::opentelemetry::proto::metrics::v1::ResourceMetrics;
+
+    fn resource_events(size: u32) -> ResourceEvents {
+        ResourceEvents {
+            resource: None,
+            instrumentation_library_events: vec![InstrumentationLibraryEvents {
+                instrumentation_library: None,
+                batches: vec![BatchEvent { schema_url: "urn:test".into(), size, start_time_unix_nano_column: vec![1; size as usize], end_time_unix_nano_column: vec![1; size as usize], ..Default::default() }],
+                dropped_events_count: 0,
+                schema_url: "".into(),
+            }],
+            schema_url: "".into(),
+        }
+    }
+
+    fn export_all(sink: &mut impl EventSink) {
+        sink.export_events(resource_events(2)).unwrap();
+        sink.export_arrow_events(arrow_events::ResourceEvents { schema_url: "arrow".into(), ..Default::default() }).unwrap();
+        sink.export_metrics(ResourceMetrics { schema_url: "metrics".into(), ..Default::default() }).unwrap();
+        sink.export_events(resource_events(300)).unwrap();
+        sink.flush().unwrap();
+    }
+
+    #[test]
+    fn test_memory_sink() {
+        let sink = MemorySink::new();
+        export_all(&mut sink.clone());
+
+        assert_eq!(sink.len(), 4);
+        assert_eq!(sink.batches()[0], ResourceBatch::Events(resource_events(2)));
+        assert!(matches!(sink.batches()[2], ResourceBatch::Metrics(_)));
+        assert_eq!(sink.take().len(), 4);
+        assert!(sink.is_empty());
+    }
+
+    #[test]
+    fn test_length_delimited_sink() {
+        let mut sink = LengthDelimitedSink::new(vec![]);
+        export_all(&mut sink);
+
+        let buffer = sink.into_inner();
+        let batches: Vec<ResourceBatch> = LengthDelimitedReader::new(buffer.as_slice()).collect::<Result<_, _>>().unwrap();
+        assert_eq!(batches.len(), 4);
+        assert_eq!(batches[0], ResourceBatch::Events(resource_events(2)));
+        assert_eq!(batches[1], ResourceBatch::ArrowEvents(arrow_events::ResourceEvents { schema_url: "arrow".into(), ..Default::default() }));
+        assert_eq!(batches[2], ResourceBatch::Metrics(ResourceMetrics { schema_url: "metrics".into(), ..Default::default() }));
+        assert_eq!(batches[3], ResourceBatch::Events(resource_events(300)));
+
+        // A truncated stream is reported as an error.
+        let mut reader = LengthDelimitedReader::new(&buffer[..buffer.len() - 1]);
+        for _ in 0..3 {
+            assert!(reader.read_batch().unwrap().is_some());
+        }
+        assert!(reader.read_batch().is_err());
+
+        // A batch violating the columnar invariants is rejected.
+        let mut invalid_events = resource_events(2);
+        invalid_events.instrumentation_library_events[0].batches[0].end_time_unix_nano_column.pop();
+        let mut sink = LengthDelimitedSink::new(vec![]);
+        sink.export_events(invalid_events).unwrap();
+        let buffer = sink.into_inner();
+        assert!(matches!(LengthDelimitedReader::new(buffer.as_slice()).read_batch(), Err(Error::InvalidColumnLength { .. })));
+
+        // A frame longer than the max frame length is rejected.
+        let mut sink = LengthDelimitedSink::new(vec![]);
+        sink.export_events(resource_events(2)).unwrap();
+        let buffer = sink.into_inner();
+        let mut reader = LengthDelimitedReader::new(buffer.as_slice()).with_max_frame_length(buffer.len() - 3);
+        assert!(matches!(reader.read_batch(), Err(Error::InvalidFormat(_))));
+        let mut reader = LengthDelimitedReader::new(buffer.as_slice()).with_max_frame_length(buffer.len() - 2);
+        assert!(reader.read_batch().unwrap().is_some());
+        let huge_frame = [EVENTS_TAG, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f];
+        assert!(matches!(LengthDelimitedReader::new(&huge_frame[..]).read_batch(), Err(Error::InvalidFormat(_))));
+    }
+
+    #[test]
+    fn test_json_sink() {
+        let mut sink = JsonSink::new(vec![]);
+        export_all(&mut sink);
+
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        let batches: Vec<ResourceBatch> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(batches.len(), 4);
+        assert_eq!(batches[3], ResourceBatch::Events(resource_events(300)));
+    }
+}
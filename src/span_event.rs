@@ -0,0 +1,317 @@
+//! Columnar representation of the OTLP spans: a `Span` is mapped onto a `SpanEvent` row of a `BatchEvent`. The span
+//! events and links are stored in auxiliary entities (`TRACE_EVENT` and `TRACE_LINK`) and their attributes in two
+//! additional attribute entities referencing them by rank (see `IndexedAttribute`).
+//!
+//! Note: the deprecated status code is not preserved.
+
+use crate::attribute::{Attribute, IndexedAttribute, non_empty, to_attributes, to_key_values};
+use crate::event::{self, Error, OpenTelemetryAuxiliaryEntity, OpenTelemetryEvent};
+use crate::event_decoder::{FromAuxiliaryEntity, FromBatchEvent, decode_batch_event};
+use crate::opentelemetry::proto::common::v1::KeyValue;
+use crate::opentelemetry::proto::events::v1::{BatchEvent, InstrumentationLibraryEvents, ResourceEvents};
+use crate::opentelemetry::proto::trace::v1::{InstrumentationLibrarySpans, ResourceSpans, Span, Status};
+use crate::opentelemetry::proto::trace::v1::span::{Event, Link};
+
+/// A span, the status is null when the span has no status.
+#[derive(Debug, Clone, Default, PartialEq, OpenTelemetryEvent, FromBatchEvent)]
+#[otel(urn = "urn:opentelemetry:span")]
+pub struct SpanEvent {
+    #[otel(start_time)]
+    pub start_time_unix_nano: u64,
+    #[otel(end_time)]
+    pub end_time_unix_nano: u64,
+    #[otel(logical_type = "trace_id")]
+    pub trace_id: Option<Vec<u8>>,
+    #[otel(logical_type = "span_id")]
+    pub span_id: Option<Vec<u8>>,
+    pub trace_state: Option<String>,
+    #[otel(logical_type = "span_id")]
+    pub parent_span_id: Option<Vec<u8>>,
+    pub name: String,
+    pub kind: i32,
+    pub dropped_attributes_count: u32,
+    pub dropped_events_count: u32,
+    pub dropped_links_count: u32,
+    pub status_code: Option<i32>,
+    pub status_message: Option<String>,
+    #[otel(logical_type = "attribute")]
+    pub attributes: Vec<Attribute>,
+    #[otel(logical_type = "trace_event")]
+    pub events: Vec<TraceEvent>,
+    #[otel(logical_type = "attribute")]
+    pub event_attributes: Vec<IndexedAttribute>,
+    #[otel(logical_type = "trace_link")]
+    pub links: Vec<TraceLink>,
+    #[otel(logical_type = "attribute")]
+    pub link_attributes: Vec<IndexedAttribute>,
+}
+
+/// A span event, its attributes are stored in `SpanEvent::event_attributes`.
+#[derive(Debug, Clone, Default, PartialEq, OpenTelemetryAuxiliaryEntity, FromAuxiliaryEntity)]
+pub struct TraceEvent {
+    pub time_unix_nano: u64,
+    pub name: String,
+    pub dropped_attributes_count: u32,
+}
+
+/// A span link, its attributes are stored in `SpanEvent::link_attributes`.
+#[derive(Debug, Clone, Default, PartialEq, OpenTelemetryAuxiliaryEntity, FromAuxiliaryEntity)]
+pub struct TraceLink {
+    #[otel(logical_type = "trace_id")]
+    pub trace_id: Vec<u8>,
+    #[otel(logical_type = "span_id")]
+    pub span_id: Vec<u8>,
+    pub trace_state: Option<String>,
+    pub dropped_attributes_count: u32,
+}
+
+impl SpanEvent {
+    /// Empty strings and ids are stored as nulls.
+    pub fn new(span: Span) -> Result<Self, Error> {
+        let mut events = Vec::with_capacity(span.events.len());
+        let mut event_attributes = vec![];
+        for (index, event) in span.events.into_iter().enumerate() {
+            event_attributes.extend(to_indexed_attributes(index, event.attributes)?);
+            events.push(TraceEvent {
+                time_unix_nano: event.time_unix_nano,
+                name: event.name,
+                dropped_attributes_count: event.dropped_attributes_count,
+            });
+        }
+
+        let mut links = Vec::with_capacity(span.links.len());
+        let mut link_attributes = vec![];
+        for (index, link) in span.links.into_iter().enumerate() {
+            link_attributes.extend(to_indexed_attributes(index, link.attributes)?);
+            links.push(TraceLink {
+                trace_id: link.trace_id,
+                span_id: link.span_id,
+                trace_state: non_empty(link.trace_state),
+                dropped_attributes_count: link.dropped_attributes_count,
+            });
+        }
+
+        Ok(SpanEvent {
+            start_time_unix_nano: span.start_time_unix_nano,
+            end_time_unix_nano: span.end_time_unix_nano,
+            trace_id: Some(span.trace_id).filter(|trace_id| !trace_id.is_empty()),
+            span_id: Some(span.span_id).filter(|span_id| !span_id.is_empty()),
+            trace_state: non_empty(span.trace_state),
+            parent_span_id: Some(span.parent_span_id).filter(|span_id| !span_id.is_empty()),
+            name: span.name,
+            kind: span.kind,
+            dropped_attributes_count: span.dropped_attributes_count,
+            dropped_events_count: span.dropped_events_count,
+            dropped_links_count: span.dropped_links_count,
+            status_code: span.status.as_ref().map(|status| status.code),
+            status_message: span.status.and_then(|status| non_empty(status.message)),
+            attributes: to_attributes(span.attributes)?,
+            events,
+            event_attributes,
+            links,
+            link_attributes,
+        })
+    }
+
+    /// Fails if an event or link attribute references a missing event or link.
+    pub fn into_span(self) -> Result<Span, Error> {
+        let mut events: Vec<Event> = self.events.into_iter()
+            .map(|event| Event {
+                time_unix_nano: event.time_unix_nano,
+                name: event.name,
+                attributes: vec![],
+                dropped_attributes_count: event.dropped_attributes_count,
+            })
+            .collect();
+        for attribute in self.event_attributes {
+            let event = events.get_mut(attribute.parent_index as usize)
+                .ok_or_else(|| Error::InvalidFormat(format!("span event {} not found", attribute.parent_index)))?;
+            event.attributes.push(attribute.into_key_value()?);
+        }
+
+        let mut links: Vec<Link> = self.links.into_iter()
+            .map(|link| Link {
+                trace_id: link.trace_id,
+                span_id: link.span_id,
+                trace_state: link.trace_state.unwrap_or_default(),
+                attributes: vec![],
+                dropped_attributes_count: link.dropped_attributes_count,
+            })
+            .collect();
+        for attribute in self.link_attributes {
+            let link = links.get_mut(attribute.parent_index as usize)
+                .ok_or_else(|| Error::InvalidFormat(format!("span link {} not found", attribute.parent_index)))?;
+            link.attributes.push(attribute.into_key_value()?);
+        }
+
+        let status_message = self.status_message.unwrap_or_default();
+        Ok(Span {
+            trace_id: self.trace_id.unwrap_or_default(),
+            span_id: self.span_id.unwrap_or_default(),
+            trace_state: self.trace_state.unwrap_or_default(),
+            parent_span_id: self.parent_span_id.unwrap_or_default(),
+            name: self.name,
+            kind: self.kind,
+            start_time_unix_nano: self.start_time_unix_nano,
+            end_time_unix_nano: self.end_time_unix_nano,
+            attributes: to_key_values(self.attributes)?,
+            dropped_attributes_count: self.dropped_attributes_count,
+            events,
+            dropped_events_count: self.dropped_events_count,
+            links,
+            dropped_links_count: self.dropped_links_count,
+            status: self.status_code.map(|code| Status { message: status_message, code, ..Default::default() }),
+        })
+    }
+}
+
+/// Builds a batch of `SpanEvent`s.
+pub fn to_batch_event(spans: Vec<Span>) -> Result<BatchEvent, Error> {
+    let span_events = spans.into_iter().map(SpanEvent::new).collect::<Result<Vec<_>, Error>>()?;
+    event::to_batch_event(span_events)
+}
+
+/// Converts the spans of each instrumentation library into a batch of `SpanEvent`s.
+pub fn to_resource_events(resource_spans: ResourceSpans) -> Result<ResourceEvents, Error> {
+    Ok(ResourceEvents {
+        resource: resource_spans.resource,
+        instrumentation_library_events: resource_spans.instrumentation_library_spans.into_iter()
+            .map(|instrumentation_library_spans| Ok(InstrumentationLibraryEvents {
+                instrumentation_library: instrumentation_library_spans.instrumentation_library,
+                batches: vec![to_batch_event(instrumentation_library_spans.spans)?],
+                dropped_events_count: 0,
+                schema_url: instrumentation_library_spans.schema_url,
+            }))
+            .collect::<Result<_, Error>>()?,
+        schema_url: resource_spans.schema_url,
+    })
+}
+
+/// Inverse of `to_resource_events`, fails if a batch is not a batch of `SpanEvent`s.
+pub fn to_resource_spans(resource_events: &ResourceEvents) -> Result<ResourceSpans, Error> {
+    let mut instrumentation_library_spans = Vec::with_capacity(resource_events.instrumentation_library_events.len());
+
+    for instrumentation_library_events in &resource_events.instrumentation_library_events {
+        let mut spans = vec![];
+        for batch in &instrumentation_library_events.batches {
+            if batch.schema_url != SpanEvent::urn() {
+                return Err(Error::InvalidFormat(format!("unexpected schema url '{}' (expected '{}')", batch.schema_url, SpanEvent::urn())));
+            }
+            for span_event in decode_batch_event::<SpanEvent>(batch)? {
+                spans.push(span_event.into_span()?);
+            }
+        }
+        instrumentation_library_spans.push(InstrumentationLibrarySpans {
+            instrumentation_library: instrumentation_library_events.instrumentation_library.clone(),
+            spans,
+            schema_url: instrumentation_library_events.schema_url.clone(),
+        });
+    }
+
+    Ok(ResourceSpans {
+        resource: resource_events.resource.clone(),
+        instrumentation_library_spans,
+        schema_url: resource_events.schema_url.clone(),
+    })
+}
+
+fn to_indexed_attributes(parent_index: usize, key_values: Vec<KeyValue>) -> Result<Vec<IndexedAttribute>, Error> {
+    key_values.into_iter().map(|key_value| IndexedAttribute::new(parent_index as u32, key_value)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::opentelemetry::proto::common::v1::{AnyValue, InstrumentationLibrary, KeyValue};
+    use crate::opentelemetry::proto::common::v1::any_value::Value;
+    use crate::opentelemetry::proto::events::v1::auxiliary_entity;
+    use crate::opentelemetry::proto::trace::v1::{InstrumentationLibrarySpans, ResourceSpans, Span, Status};
+    use crate::opentelemetry::proto::trace::v1::span::{Event, Link, SpanKind};
+    use crate::opentelemetry::proto::trace::v1::status::StatusCode;
+    use crate::span_event::{to_resource_events, to_resource_spans};
+
+    fn key_value(key: &str, value: Value) -> KeyValue {
+        KeyValue { key: key.into(), value: Some(AnyValue { value: Some(value) }) }
+    }
+
+    fn spans() -> Vec<Span> {
+        vec![
+            Span {
+                trace_id: vec![1; 16],
+                span_id: vec![2; 8],
+                name: "GET /users".into(),
+                kind: SpanKind::Server as i32,
+                start_time_unix_nano: 1_000,
+                end_time_unix_nano: 2_000,
+                attributes: vec![key_value("http.status_code", Value::IntValue(500))],
+                events: vec![
+                    Event { time_unix_nano: 1_100, name: "cache miss".into(), ..Default::default() },
+                    Event {
+                        time_unix_nano: 1_500,
+                        name: "exception".into(),
+                        attributes: vec![
+                            key_value("exception.type", Value::StringValue("Timeout".into())),
+                            key_value("exception.escaped", Value::BoolValue(false)),
+                        ],
+                        dropped_attributes_count: 1,
+                    },
+                ],
+                links: vec![Link {
+                    trace_id: vec![3; 16],
+                    span_id: vec![4; 8],
+                    trace_state: "vendor=1".into(),
+                    attributes: vec![key_value("link.weight", Value::DoubleValue(0.5))],
+                    dropped_attributes_count: 0,
+                }],
+                status: Some(Status { message: "timeout".into(), code: StatusCode::Error as i32, ..Default::default() }),
+                ..Default::default()
+            },
+            Span {
+                trace_id: vec![1; 16],
+                span_id: vec![5; 8],
+                parent_span_id: vec![2; 8],
+                name: "SELECT users".into(),
+                kind: SpanKind::Client as i32,
+                start_time_unix_nano: 1_200,
+                end_time_unix_nano: 1_900,
+                dropped_events_count: 2,
+                dropped_links_count: 1,
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let resource_spans = ResourceSpans {
+            resource: None,
+            instrumentation_library_spans: vec![InstrumentationLibrarySpans {
+                instrumentation_library: Some(InstrumentationLibrary { name: "tracer".into(), version: "1.0".into() }),
+                spans: spans(),
+                schema_url: "https://opentelemetry.io/schemas/1.7.0".into(),
+            }],
+            schema_url: "https://opentelemetry.io/schemas/1.8.0".into(),
+        };
+
+        let resource_events = to_resource_events(resource_spans.clone()).unwrap();
+        resource_events.validate().unwrap();
+        let batch = &resource_events.instrumentation_library_events[0].batches[0];
+        assert_eq!(batch.size, 2);
+        assert_eq!(batch.schema_url, "urn:opentelemetry:span");
+        let entity_sizes: Vec<_> = batch.auxiliary_entities.iter()
+            .map(|entity| (entity.parent_column.as_str(), entity.logical_type, entity.size))
+            .collect();
+        assert_eq!(entity_sizes, vec![
+            ("attributes", auxiliary_entity::LogicalType::Attribute as i32, 1),
+            ("events", auxiliary_entity::LogicalType::TraceEvent as i32, 2),
+            ("event_attributes", auxiliary_entity::LogicalType::Attribute as i32, 2),
+            ("links", auxiliary_entity::LogicalType::TraceLink as i32, 1),
+            ("link_attributes", auxiliary_entity::LogicalType::Attribute as i32, 1),
+        ]);
+
+        assert_eq!(to_resource_spans(&resource_events).unwrap(), resource_spans);
+
+        let mut invalid_resource_events = resource_events;
+        invalid_resource_events.instrumentation_library_events[0].batches[0].schema_url = "urn:test".into();
+        assert!(to_resource_spans(&invalid_resource_events).is_err());
+    }
+}
@@ -0,0 +1,291 @@
+//! Summary columns (`Int64SummaryColumn`, `DoubleSummaryColumn`): min, max, count and sum per row, with optional
+//! quantiles whose lists are concatenated like the histogram buckets (see `events.proto`).
+//!
+//! `Summary<T>` is the row representation used to append and read the values, e.g. as a `Summary<i64>` or
+//! `Summary<f64>` field of an `OpenTelemetryEvent`. A summary maps onto a standard `SummaryDataPoint`, min and max
+//! being its 0 and 1 quantiles.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::event::{is_defined, push_validity, reset_validity_bitmap, validity_bitmap, BatchPolicy};
+use crate::opentelemetry::proto::common::v1::KeyValue;
+use crate::opentelemetry::proto::events::v1::{AggregationTemporality, DoubleSummaryColumn, Int64SummaryColumn};
+use crate::opentelemetry::proto::metrics::v1::SummaryDataPoint;
+use crate::opentelemetry::proto::metrics::v1::summary_data_point::ValueAtQuantile;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Summary<T> {
+    pub min: T,
+    pub max: T,
+    pub count: T,
+    pub sum: T,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub quantile_values: Vec<ValueAtQuantile>,
+}
+
+/// A value of a summary column.
+pub trait SummaryValue: Copy {
+    fn to_f64(self) -> f64;
+}
+
+impl SummaryValue for i64 {
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl SummaryValue for f64 {
+    fn to_f64(self) -> f64 {
+        self
+    }
+}
+
+impl<T: Serialize> Summary<T> {
+    pub fn to_json_value(&self) -> Value {
+        serde_json::to_value(self).unwrap_or(Value::Null)
+    }
+}
+
+impl<T: SummaryValue> Summary<T> {
+    /// Converts the row into a standard data point, min and max become the 0 and 1 quantiles (skipped if NaN).
+    pub fn into_data_point(self, attributes: Vec<KeyValue>, start_time_unix_nano: u64, time_unix_nano: u64) -> SummaryDataPoint {
+        let (min, max) = (self.min.to_f64(), self.max.to_f64());
+        let mut quantile_values = Vec::with_capacity(self.quantile_values.len() + 2);
+        if !min.is_nan() {
+            quantile_values.push(ValueAtQuantile { quantile: 0.0, value: min });
+        }
+        quantile_values.extend(self.quantile_values);
+        if !max.is_nan() {
+            quantile_values.push(ValueAtQuantile { quantile: 1.0, value: max });
+        }
+
+        SummaryDataPoint {
+            attributes,
+            start_time_unix_nano,
+            time_unix_nano,
+            count: self.count.to_f64() as u64,
+            sum: self.sum.to_f64(),
+            quantile_values,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&SummaryDataPoint> for Summary<f64> {
+    /// min and max are the 0 and 1 quantiles of the data point, NaN if not defined.
+    fn from(data_point: &SummaryDataPoint) -> Self {
+        let quantile = |quantile: f64| data_point.quantile_values.iter()
+            .find(|value_at_quantile| value_at_quantile.quantile == quantile)
+            .map_or(f64::NAN, |value_at_quantile| value_at_quantile.value);
+
+        Summary {
+            min: quantile(0.0),
+            max: quantile(1.0),
+            count: data_point.count as f64,
+            sum: data_point.sum,
+            quantile_values: data_point.quantile_values.iter()
+                .filter(|value_at_quantile| value_at_quantile.quantile != 0.0 && value_at_quantile.quantile != 1.0)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+macro_rules! impl_summary_column {
+    ($column:ident, $value:ty) => {
+        impl $column {
+            pub fn new(name: impl Into<String>, description: impl Into<String>, unit: impl Into<String>, aggregation_temporality: AggregationTemporality) -> Self {
+                $column {
+                    name: name.into(),
+                    description: description.into(),
+                    unit: unit.into(),
+                    aggregation_temporality: aggregation_temporality as i32,
+                    ..Default::default()
+                }
+            }
+
+            /// Same as `new` with a validity bitmap sized for the batch policy.
+            pub fn new_optional(name: impl Into<String>, description: impl Into<String>, unit: impl Into<String>, aggregation_temporality: AggregationTemporality, batch_policy: &BatchPolicy) -> Self {
+                $column {
+                    validity_bitmap: validity_bitmap(batch_policy.max_size as usize),
+                    ..$column::new(name, description, unit, aggregation_temporality)
+                }
+            }
+
+            pub fn len(&self) -> usize {
+                self.count_values.len()
+            }
+
+            pub fn is_empty(&self) -> bool {
+                self.len() == 0
+            }
+
+            /// Appends a row, a null is stored as a zero summary. The quantile offsets are only materialized with the
+            /// first quantiles.
+            pub fn push(&mut self, summary: Option<&Summary<$value>>) {
+                let row = self.len();
+                push_validity(&mut self.validity_bitmap, row, summary.is_some());
+                let null = Summary::default();
+                let summary = summary.unwrap_or(&null);
+
+                self.min_values.push(summary.min);
+                self.max_values.push(summary.max);
+                self.count_values.push(summary.count);
+                self.sum_values.push(summary.sum);
+                let has_quantiles = !summary.quantile_values.is_empty();
+                if has_quantiles && self.quantile_offsets.is_empty() {
+                    self.quantile_offsets.resize(row, 0);
+                }
+                if has_quantiles || !self.quantile_offsets.is_empty() {
+                    self.quantiles.extend(summary.quantile_values.iter().map(|value_at_quantile| value_at_quantile.quantile));
+                    self.quantile_values.extend(summary.quantile_values.iter().map(|value_at_quantile| value_at_quantile.value));
+                    self.quantile_offsets.push(self.quantiles.len() as u32);
+                }
+            }
+
+            /// Returns `None` if the value is null or the row doesn't exist.
+            pub fn value(&self, row: usize) -> Option<Summary<$value>> {
+                if !is_defined(&self.validity_bitmap, row) {
+                    return None;
+                }
+                let quantiles = if self.quantile_offsets.is_empty() {
+                    0..0
+                } else {
+                    let start = if row == 0 { 0 } else { *self.quantile_offsets.get(row - 1)? as usize };
+                    start..*self.quantile_offsets.get(row)? as usize
+                };
+
+                Some(Summary {
+                    min: *self.min_values.get(row)?,
+                    max: *self.max_values.get(row)?,
+                    count: *self.count_values.get(row)?,
+                    sum: *self.sum_values.get(row)?,
+                    quantile_values: self.quantiles.get(quantiles.clone())?.iter()
+                        .zip(self.quantile_values.get(quantiles)?)
+                        .map(|(&quantile, &value)| ValueAtQuantile { quantile, value })
+                        .collect(),
+                })
+            }
+
+            /// Removes all the rows, keeping the allocated capacity.
+            pub fn clear(&mut self) {
+                self.min_values.clear();
+                self.max_values.clear();
+                self.count_values.clear();
+                self.sum_values.clear();
+                self.quantile_offsets.clear();
+                self.quantiles.clear();
+                self.quantile_values.clear();
+                reset_validity_bitmap(&mut self.validity_bitmap);
+            }
+        }
+    };
+}
+
+impl_summary_column!(Int64SummaryColumn, i64);
+impl_summary_column!(DoubleSummaryColumn, f64);
+
+#[cfg(test)]
+mod test {
+    use crate::event::{BatchPolicy, EventBatchHandler, OpenTelemetryEvent, to_batch_event};
+    use crate::event_decoder::{FromBatchEvent, decode_batch_event};
+    use crate::opentelemetry::proto::events::v1::{AggregationTemporality, Int64SummaryColumn};
+    use crate::opentelemetry::proto::metrics::v1::SummaryDataPoint;
+    use crate::opentelemetry::proto::metrics::v1::summary_data_point::ValueAtQuantile;
+    use crate::schema::{ColumnType, EventSchema};
+    use crate::summary::Summary;
+
+    fn quantile(quantile: f64, value: f64) -> ValueAtQuantile {
+        ValueAtQuantile { quantile, value }
+    }
+
+    #[derive(Debug, Clone, PartialEq, OpenTelemetryEvent, FromBatchEvent)]
+    #[otel(urn = "urn:test:request_stats")]
+    struct RequestStats {
+        #[otel(start_time)]
+        time: u64,
+        #[otel(unit = "ms", aggregation_temporality = "delta")]
+        latency: Summary<f64>,
+        #[otel(unit = "By")]
+        size: Option<Summary<i64>>,
+    }
+
+    fn request_stats() -> Vec<RequestStats> {
+        vec![
+            RequestStats {
+                time: 1,
+                latency: Summary { min: 1.0, max: 9.0, count: 3.0, sum: 15.0, quantile_values: vec![quantile(0.5, 5.0)] },
+                size: None,
+            },
+            RequestStats {
+                time: 2,
+                latency: Summary { min: 2.0, max: 2.0, count: 1.0, sum: 2.0, quantile_values: vec![] },
+                size: Some(Summary { min: 10, max: 20, count: 2, sum: 30, quantile_values: vec![] }),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_summary_column() {
+        let mut column = Int64SummaryColumn::new("size", "", "By", AggregationTemporality::Delta);
+        column.push(Some(&Summary { min: 1, max: 2, count: 2, sum: 3, quantile_values: vec![] }));
+        assert!(column.quantile_offsets.is_empty());
+        column.push(None);
+        column.push(Some(&Summary { min: 1, max: 4, count: 3, sum: 7, quantile_values: vec![quantile(0.5, 2.0), quantile(0.9, 4.0)] }));
+
+        assert_eq!(column.len(), 3);
+        assert_eq!(column.validity_bitmap, vec![0b101]);
+        assert_eq!(column.quantile_offsets, vec![0, 0, 2]);
+        assert_eq!(column.value(1), None);
+        assert_eq!(column.value(2).unwrap().quantile_values, vec![quantile(0.5, 2.0), quantile(0.9, 4.0)]);
+
+        column.clear();
+        assert!(column.is_empty());
+        assert!(column.quantile_offsets.is_empty());
+    }
+
+    #[test]
+    fn test_event() {
+        let batch = to_batch_event(request_stats()).unwrap();
+        batch.validate().unwrap();
+        assert_eq!(batch.f64_summary_values[0].unit, "ms");
+        assert_eq!(batch.i64_summary_values[0].validity_bitmap, vec![0b10]);
+        assert_eq!(decode_batch_event::<RequestStats>(&batch).unwrap(), request_stats());
+
+        let schema = EventSchema::of::<RequestStats>();
+        assert_eq!(schema.columns.iter().map(|column| column.column_type).collect::<Vec<_>>(), vec![ColumnType::Int64Summary, ColumnType::DoubleSummary]);
+        schema.validate(&batch).unwrap();
+
+        let mut invalid_batch = batch;
+        invalid_batch.f64_summary_values[0].quantile_values.pop();
+        assert!(invalid_batch.validate().is_err());
+
+        let mut handler = EventBatchHandler::<RequestStats>::new(BatchPolicy::new(10, chrono::Duration::MAX));
+        for event in request_stats() {
+            handler.record(event).unwrap();
+        }
+        let json = handler.to_json_value().unwrap();
+        assert_eq!(json[0]["latency"], serde_json::json!({"min": 1.0, "max": 9.0, "count": 3.0, "sum": 15.0, "quantile_values": [{"quantile": 0.5, "value": 5.0}]}));
+        assert!(json[0].get("size").is_none());
+        assert_eq!(json[1]["size"], serde_json::json!({"min": 10, "max": 20, "count": 2, "sum": 30}));
+    }
+
+    #[test]
+    fn test_data_point() {
+        let data_point = SummaryDataPoint {
+            start_time_unix_nano: 1,
+            time_unix_nano: 2,
+            count: 4,
+            sum: 10.0,
+            quantile_values: vec![quantile(0.0, 1.0), quantile(0.5, 2.0), quantile(1.0, 4.0)],
+            ..Default::default()
+        };
+        let summary = Summary::from(&data_point);
+        assert_eq!(summary, Summary { min: 1.0, max: 4.0, count: 4.0, sum: 10.0, quantile_values: vec![quantile(0.5, 2.0)] });
+        assert_eq!(summary.into_data_point(vec![], 1, 2), data_point);
+
+        let data_point = SummaryDataPoint { count: 1, sum: 1.0, ..Default::default() };
+        assert_eq!(Summary::from(&data_point).into_data_point(vec![], 0, 0), data_point);
+    }
+}
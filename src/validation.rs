@@ -0,0 +1,428 @@
+//! Invariant checks for the columnar payloads (`BatchEvent`, `MultivariateMetric`).
+//!
+//! Every column must have one value per row and the validity bitmaps, when defined, must cover all the rows. The
+//! parent ranks of an auxiliary entity must be sorted and refer to an existing event. The indices of a dictionary encoded
+//! column must refer to an entry of its dictionary. Delta encoded columns are checked without being decoded. The bucket
+//! offsets of a histogram column must be sorted and end with the last bucket, each explicit bucket histogram having one
+//! bucket more than explicit bounds.
+
+use crate::delta_encoding::{encoded_len, Encoding};
+use crate::event::Error;
+use crate::opentelemetry::proto::events::v1::{ResourceEvents, BatchEvent, AuxiliaryEntity, Int64Column, DoubleColumn, StringColumn, BoolColumn, BytesColumn, Int64SummaryColumn, DoubleSummaryColumn, HistogramColumn, ExponentialHistogramColumn};
+use crate::opentelemetry::proto::events::v1::{HistogramValues, ExponentialHistogramValues};
+use crate::opentelemetry::proto::metrics::v1::{ResourceMetrics, MultivariateMetric, ColumnarGauge, ColumnarSum, ColumnarHistogram, ColumnarExponentialHistogram, columnar_number_data_point};
+use crate::opentelemetry::proto::metrics::v1::columnar_metric::Data;
+
+/// Columns shared by batch events and auxiliary entities.
+struct Columns<'a> {
+    i64_values: &'a [Int64Column],
+    f64_values: &'a [DoubleColumn],
+    string_values: &'a [StringColumn],
+    bool_values: &'a [BoolColumn],
+    bytes_values: &'a [BytesColumn],
+    i64_summary_values: &'a [Int64SummaryColumn],
+    f64_summary_values: &'a [DoubleSummaryColumn],
+    histogram_values: &'a [HistogramColumn],
+    exponential_histogram_values: &'a [ExponentialHistogramColumn],
+}
+
+impl ResourceEvents {
+    pub fn validate(&self) -> Result<(), Error> {
+        self.instrumentation_library_events.iter()
+            .flat_map(|instrumentation_library_events| &instrumentation_library_events.batches)
+            .try_for_each(|batch| batch.validate())
+    }
+}
+
+impl BatchEvent {
+    pub fn validate(&self) -> Result<(), Error> {
+        let size = self.size as usize;
+
+        if self.time_encoding == Encoding::None as i32 {
+            check_length("start_time_unix_nano_column", self.start_time_unix_nano_column.len(), size)?;
+            check_length("end_time_unix_nano_column", self.end_time_unix_nano_column.len(), size)?;
+        } else {
+            check_encoded_column("start_time_unix_nano_column", self.start_time_unix_nano_column.len(), &self.encoded_start_time_unix_nano_column, size)?;
+            check_encoded_column("end_time_unix_nano_column", self.end_time_unix_nano_column.len(), &self.encoded_end_time_unix_nano_column, size)?;
+        }
+        validate_columns("", size, Columns {
+            i64_values: &self.i64_values,
+            f64_values: &self.f64_values,
+            string_values: &self.string_values,
+            bool_values: &self.bool_values,
+            bytes_values: &self.bytes_values,
+            i64_summary_values: &self.i64_summary_values,
+            f64_summary_values: &self.f64_summary_values,
+            histogram_values: &self.histogram_values,
+            exponential_histogram_values: &self.exponential_histogram_values,
+        })?;
+
+        self.auxiliary_entities.iter().try_for_each(|auxiliary_entity| auxiliary_entity.validate(size))
+    }
+}
+
+impl AuxiliaryEntity {
+    /// `parent_size` is the number of events of the batch containing this auxiliary entity.
+    pub fn validate(&self, parent_size: usize) -> Result<(), Error> {
+        let size = self.size as usize;
+        let prefix = format!("{}.", self.parent_column);
+
+        check_length(&format!("{}parent_ranks", prefix), self.parent_ranks.len(), size)?;
+        let mut previous_rank = 0;
+        for (row, &parent_rank) in self.parent_ranks.iter().enumerate() {
+            if parent_rank < previous_rank || parent_rank as usize >= parent_size {
+                return Err(Error::InvalidParentRank { parent_column: self.parent_column.clone(), row, parent_rank, parent_size });
+            }
+            previous_rank = parent_rank;
+        }
+
+        validate_columns(&prefix, size, Columns {
+            i64_values: &self.i64_values,
+            f64_values: &self.f64_values,
+            string_values: &self.string_values,
+            bool_values: &self.bool_values,
+            bytes_values: &self.bytes_values,
+            i64_summary_values: &self.i64_summary_values,
+            f64_summary_values: &self.f64_summary_values,
+            histogram_values: &self.histogram_values,
+            exponential_histogram_values: &self.exponential_histogram_values,
+        })
+    }
+}
+
+impl ResourceMetrics {
+    pub fn validate(&self) -> Result<(), Error> {
+        self.instrumentation_library_metrics.iter()
+            .flat_map(|instrumentation_library_metrics| &instrumentation_library_metrics.multivariate_metrics)
+            .try_for_each(|multivariate_metric| multivariate_metric.validate())
+    }
+}
+
+impl MultivariateMetric {
+    pub fn validate(&self) -> Result<(), Error> {
+        let size = if self.time_encoding == Encoding::None as i32 {
+            let size = self.time_unix_nano_column.len();
+            if !self.start_time_unix_nano_column.is_empty() {
+                check_length("start_time_unix_nano_column", self.start_time_unix_nano_column.len(), size)?;
+            }
+            size
+        } else {
+            let size = encoded_len(&self.encoded_time_unix_nano_column);
+            check_encoded_column("time_unix_nano_column", self.time_unix_nano_column.len(), &self.encoded_time_unix_nano_column, size)?;
+            if !self.start_time_unix_nano_column.is_empty() || !self.encoded_start_time_unix_nano_column.is_empty() {
+                check_encoded_column("start_time_unix_nano_column", self.start_time_unix_nano_column.len(), &self.encoded_start_time_unix_nano_column, size)?;
+            }
+            size
+        };
+
+        for attribute in &self.attributes {
+            check_length(&attribute.name, attribute.len(), size)?;
+            check_dictionary(&attribute.name, &attribute.values, &attribute.dictionary, &attribute.dictionary_indices)?;
+        }
+        for metric in &self.metrics {
+            let data_points = match &metric.data {
+                Some(Data::Gauge(ColumnarGauge { data_points })) => data_points.as_ref(),
+                Some(Data::Sum(ColumnarSum { data_points, .. })) => data_points.as_ref(),
+                Some(Data::Histogram(ColumnarHistogram { data_points, .. })) => {
+                    check_histogram_values(&metric.name, data_points.as_ref(), size)?;
+                    continue;
+                }
+                Some(Data::ExponentialHistogram(ColumnarExponentialHistogram { data_points, .. })) => {
+                    check_exponential_histogram_values(&metric.name, data_points.as_ref(), size)?;
+                    continue;
+                }
+                None => None,
+            };
+            let length = match data_points.and_then(|data_points| data_points.value.as_ref()) {
+                Some(columnar_number_data_point::Value::AsInts(ints)) if ints.is_delta_encoded() => {
+                    check_encoded_column(&metric.name, ints.value.len(), &ints.encoded_value, size)?;
+                    ints.len()
+                }
+                Some(columnar_number_data_point::Value::AsInts(ints)) => ints.value.len(),
+                Some(columnar_number_data_point::Value::AsDoubles(doubles)) => doubles.value.len(),
+                None => return Err(Error::InvalidFormat(format!("metric '{}' has no values", metric.name))),
+            };
+            check_length(&metric.name, length, size)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn validate_columns(prefix: &str, size: usize, columns: Columns) -> Result<(), Error> {
+    let column_name = |name: &str| format!("{}{}", prefix, name);
+
+    for column in columns.i64_values {
+        if column.is_delta_encoded() {
+            check_encoded_column(&column_name(&column.name), column.values.len(), &column.encoded_values, size)?;
+        }
+        check_length(&column_name(&column.name), column.len(), size)?;
+        check_validity_bitmap(&column_name(&column.name), &column.validity_bitmap, size)?;
+    }
+    for column in columns.f64_values {
+        check_length(&column_name(&column.name), column.values.len(), size)?;
+        check_validity_bitmap(&column_name(&column.name), &column.validity_bitmap, size)?;
+    }
+    for column in columns.string_values {
+        check_length(&column_name(&column.name), column.len(), size)?;
+        check_dictionary(&column_name(&column.name), &column.values, &column.dictionary, &column.dictionary_indices)?;
+        check_validity_bitmap(&column_name(&column.name), &column.validity_bitmap, size)?;
+    }
+    for column in columns.bool_values {
+        check_length(&column_name(&column.name), column.values.len(), size)?;
+        check_validity_bitmap(&column_name(&column.name), &column.validity_bitmap, size)?;
+    }
+    for column in columns.bytes_values {
+        check_length(&column_name(&column.name), column.values.len(), size)?;
+        check_validity_bitmap(&column_name(&column.name), &column.validity_bitmap, size)?;
+    }
+    for column in columns.i64_summary_values {
+        let name = column_name(&column.name);
+        check_length(&format!("{}.min", name), column.min_values.len(), size)?;
+        check_length(&format!("{}.max", name), column.max_values.len(), size)?;
+        check_length(&format!("{}.count", name), column.count_values.len(), size)?;
+        check_length(&format!("{}.sum", name), column.sum_values.len(), size)?;
+        check_quantiles(&name, &column.quantile_offsets, &column.quantiles, &column.quantile_values, size)?;
+        check_validity_bitmap(&name, &column.validity_bitmap, size)?;
+    }
+    for column in columns.f64_summary_values {
+        let name = column_name(&column.name);
+        check_length(&format!("{}.min", name), column.min_values.len(), size)?;
+        check_length(&format!("{}.max", name), column.max_values.len(), size)?;
+        check_length(&format!("{}.count", name), column.count_values.len(), size)?;
+        check_length(&format!("{}.sum", name), column.sum_values.len(), size)?;
+        check_quantiles(&name, &column.quantile_offsets, &column.quantiles, &column.quantile_values, size)?;
+        check_validity_bitmap(&name, &column.validity_bitmap, size)?;
+    }
+    for column in columns.histogram_values {
+        let name = column_name(&column.name);
+        check_histogram_values(&name, column.values.as_ref(), size)?;
+        check_validity_bitmap(&name, &column.validity_bitmap, size)?;
+    }
+    for column in columns.exponential_histogram_values {
+        let name = column_name(&column.name);
+        check_exponential_histogram_values(&name, column.values.as_ref(), size)?;
+        check_validity_bitmap(&name, &column.validity_bitmap, size)?;
+    }
+
+    Ok(())
+}
+
+/// Missing values are only accepted for an empty column.
+fn check_histogram_values(column: &str, values: Option<&HistogramValues>, size: usize) -> Result<(), Error> {
+    let values = match values {
+        Some(values) => values,
+        None => return check_length(column, 0, size),
+    };
+    check_length(&format!("{}.count", column), values.count_values.len(), size)?;
+    check_length(&format!("{}.sum", column), values.sum_values.len(), size)?;
+    check_offsets(&format!("{}.bucket_counts", column), &values.bucket_counts_offsets, values.bucket_counts.len(), size)?;
+    check_offsets(&format!("{}.explicit_bounds", column), &values.explicit_bounds_offsets, values.explicit_bounds.len(), size)?;
+    check_buckets(column, &values.bucket_counts_offsets, &values.explicit_bounds_offsets)?;
+    check_length(&format!("{}.min", column), values.min_values.len(), size)?;
+    check_validity_bitmap(&format!("{}.min", column), &values.min_validity_bitmap, size)?;
+    check_length(&format!("{}.max", column), values.max_values.len(), size)?;
+    check_validity_bitmap(&format!("{}.max", column), &values.max_validity_bitmap, size)
+}
+
+fn check_exponential_histogram_values(column: &str, values: Option<&ExponentialHistogramValues>, size: usize) -> Result<(), Error> {
+    let values = match values {
+        Some(values) => values,
+        None => return check_length(column, 0, size),
+    };
+    check_length(&format!("{}.count", column), values.count_values.len(), size)?;
+    check_length(&format!("{}.sum", column), values.sum_values.len(), size)?;
+    check_length(&format!("{}.scale", column), values.scale_values.len(), size)?;
+    check_length(&format!("{}.zero_count", column), values.zero_count_values.len(), size)?;
+    check_length(&format!("{}.positive.offset", column), values.positive_offset_values.len(), size)?;
+    check_offsets(&format!("{}.positive.bucket_counts", column), &values.positive_bucket_counts_offsets, values.positive_bucket_counts.len(), size)?;
+    check_length(&format!("{}.negative.offset", column), values.negative_offset_values.len(), size)?;
+    check_offsets(&format!("{}.negative.bucket_counts", column), &values.negative_bucket_counts_offsets, values.negative_bucket_counts.len(), size)?;
+    check_length(&format!("{}.min", column), values.min_values.len(), size)?;
+    check_validity_bitmap(&format!("{}.min", column), &values.min_validity_bitmap, size)?;
+    check_length(&format!("{}.max", column), values.max_values.len(), size)?;
+    check_validity_bitmap(&format!("{}.max", column), &values.max_validity_bitmap, size)
+}
+
+/// A histogram has one bucket more than explicit bounds, or no bucket at all (e.g. null row).
+fn check_buckets(column: &str, bucket_counts_offsets: &[u32], explicit_bounds_offsets: &[u32]) -> Result<(), Error> {
+    let (mut bucket_counts_start, mut explicit_bounds_start) = (0, 0);
+    for (row, (&bucket_counts_end, &explicit_bounds_end)) in bucket_counts_offsets.iter().zip(explicit_bounds_offsets).enumerate() {
+        let bucket_counts = bucket_counts_end - bucket_counts_start;
+        let explicit_bounds = explicit_bounds_end - explicit_bounds_start;
+        if explicit_bounds != bucket_counts.saturating_sub(1) {
+            return Err(Error::InvalidFormat(format!(
+                "row {} of column '{}' has {} bucket counts for {} explicit bounds", row, column, bucket_counts, explicit_bounds
+            )));
+        }
+        bucket_counts_start = bucket_counts_end;
+        explicit_bounds_start = explicit_bounds_end;
+    }
+    Ok(())
+}
+
+/// The quantiles of a summary column are optional, the offsets are empty if no row has quantiles.
+fn check_quantiles(column: &str, offsets: &[u32], quantiles: &[f64], quantile_values: &[f64], size: usize) -> Result<(), Error> {
+    let column = format!("{}.quantiles", column);
+    check_length(&column, quantile_values.len(), quantiles.len())?;
+    if offsets.is_empty() {
+        return check_length(&column, quantiles.len(), 0);
+    }
+    check_offsets(&column, offsets, quantiles.len(), size)
+}
+
+/// The end offsets of the lists of a column, one per row, must be sorted and end with the last value.
+fn check_offsets(column: &str, offsets: &[u32], values_length: usize, size: usize) -> Result<(), Error> {
+    check_length(column, offsets.len(), size)?;
+    if offsets.windows(2).any(|offsets| offsets[0] > offsets[1]) {
+        return Err(Error::InvalidFormat(format!("offsets of column '{}' are not sorted", column)));
+    }
+    let last_offset = offsets.last().map_or(0, |&offset| offset as usize);
+    if last_offset != values_length {
+        return Err(Error::InvalidFormat(format!("offsets of column '{}' end at {} (expected {})", column, last_offset, values_length)));
+    }
+    Ok(())
+}
+
+fn check_length(column: &str, length: usize, expected_length: usize) -> Result<(), Error> {
+    if length != expected_length {
+        return Err(Error::InvalidColumnLength { column: column.into(), length, expected_length });
+    }
+    Ok(())
+}
+
+/// A delta encoded column has no plain values.
+fn check_encoded_column(column: &str, plain_length: usize, encoded_values: &[u8], size: usize) -> Result<(), Error> {
+    if plain_length != 0 {
+        return Err(Error::InvalidFormat(format!("delta encoded column '{}' has plain values", column)));
+    }
+    check_length(column, encoded_len(encoded_values), size)
+}
+
+/// A dictionary encoded column has no plain values and only indices referring to an entry of the dictionary.
+fn check_dictionary(column: &str, values: &[String], dictionary: &[String], dictionary_indices: &[u32]) -> Result<(), Error> {
+    if dictionary.is_empty() {
+        if !dictionary_indices.is_empty() {
+            return Err(Error::InvalidFormat(format!("column '{}' has dictionary indices but no dictionary", column)));
+        }
+        return Ok(());
+    }
+    if !values.is_empty() {
+        return Err(Error::InvalidFormat(format!("dictionary encoded column '{}' has plain values", column)));
+    }
+    match dictionary_indices.iter().position(|&index| index as usize >= dictionary.len()) {
+        Some(row) => Err(Error::InvalidDictionaryIndex { column: column.into(), row, index: dictionary_indices[row], dictionary_size: dictionary.len() }),
+        None => Ok(()),
+    }
+}
+
+/// An empty validity bitmap means that all the values are valid.
+fn check_validity_bitmap(column: &str, validity_bitmap: &[u8], size: usize) -> Result<(), Error> {
+    if !validity_bitmap.is_empty() && validity_bitmap.len() * 8 < size {
+        return Err(Error::InvalidValidityBitmap { column: column.into(), length: validity_bitmap.len(), size });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::event::Error;
+    use crate::histogram::Histogram;
+    use crate::metrics_columnar::MultivariateMetricBuilder;
+    use crate::opentelemetry::proto::events::v1::{BatchEvent, AuxiliaryEntity, Int64Column, StringColumn, HistogramColumn, AggregationTemporality};
+
+    fn batch_event() -> BatchEvent {
+        BatchEvent {
+            size: 2,
+            start_time_unix_nano_column: vec![1, 2],
+            end_time_unix_nano_column: vec![1, 2],
+            i64_values: vec![Int64Column { name: "status".into(), values: vec![200, 500], validity_bitmap: vec![0b11], ..Default::default() }],
+            auxiliary_entities: vec![AuxiliaryEntity {
+                size: 3,
+                parent_column: "tags".into(),
+                parent_ranks: vec![0, 0, 1],
+                string_values: vec![StringColumn { name: "key".into(), values: vec!["a".into(), "b".into(), "c".into()], ..Default::default() }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_batch_event() {
+        batch_event().validate().unwrap();
+
+        let mut batch = batch_event();
+        batch.end_time_unix_nano_column.pop();
+        assert!(matches!(batch.validate(), Err(Error::InvalidColumnLength { column, length: 1, expected_length: 2 }) if column == "end_time_unix_nano_column"));
+
+        let mut batch = batch_event();
+        batch.i64_values[0].values.push(404);
+        assert!(matches!(batch.validate(), Err(Error::InvalidColumnLength { column, length: 3, expected_length: 2 }) if column == "status"));
+
+        let mut batch = batch_event();
+        batch.size = 9;
+        batch.start_time_unix_nano_column = vec![0; 9];
+        batch.end_time_unix_nano_column = vec![0; 9];
+        batch.i64_values[0].values = vec![0; 9];
+        assert!(matches!(batch.validate(), Err(Error::InvalidValidityBitmap { column, length: 1, size: 9 }) if column == "status"));
+
+        let mut batch = batch_event();
+        batch.auxiliary_entities[0].string_values[0].values.pop();
+        assert!(matches!(batch.validate(), Err(Error::InvalidColumnLength { column, .. }) if column == "tags.key"));
+
+        let mut batch = batch_event();
+        batch.auxiliary_entities[0].parent_ranks = vec![1, 0, 1];
+        assert!(matches!(batch.validate(), Err(Error::InvalidParentRank { row: 1, parent_rank: 0, .. })));
+
+        let mut batch = batch_event();
+        batch.auxiliary_entities[0].parent_ranks = vec![0, 0, 2];
+        assert!(matches!(batch.validate(), Err(Error::InvalidParentRank { row: 2, parent_rank: 2, parent_size: 2, .. })));
+
+        let mut batch = batch_event();
+        let column = &mut batch.auxiliary_entities[0].string_values[0];
+        column.values.clear();
+        column.dictionary = vec!["a".into(), "b".into()];
+        column.dictionary_indices = vec![0, 1, 0];
+        batch.validate().unwrap();
+        batch.auxiliary_entities[0].string_values[0].dictionary_indices[2] = 2;
+        assert!(matches!(batch.validate(), Err(Error::InvalidDictionaryIndex { row: 2, index: 2, dictionary_size: 2, .. })));
+    }
+
+    #[test]
+    fn test_histogram_column() {
+        let mut batch = batch_event();
+        let mut column = HistogramColumn::new("latency", "", "ms", AggregationTemporality::Delta);
+        column.push(Some(&Histogram { count: 3, bucket_counts: vec![1, 2], explicit_bounds: vec![10.0], ..Default::default() }));
+        column.push(None);
+        batch.histogram_values.push(column);
+        batch.validate().unwrap();
+
+        let values = batch.histogram_values[0].values.as_mut().unwrap();
+        values.bucket_counts.push(0);
+        values.bucket_counts_offsets = vec![3, 3];
+        assert!(matches!(batch.validate(), Err(Error::InvalidFormat(message)) if message.starts_with("row 0 of column 'latency'")));
+    }
+
+    #[test]
+    fn test_multivariate_metric() {
+        let mut builder = MultivariateMetricBuilder::new().attribute("host").int_gauge("latency", "", "ms");
+        builder.append(1u64, &["host_1"], &[10.into()]).unwrap();
+        builder.append(2u64, &["host_2"], &[20.into()]).unwrap();
+        let multivariate_metric = builder.build().unwrap();
+        multivariate_metric.validate().unwrap();
+
+        let mut invalid_metric = multivariate_metric.clone();
+        invalid_metric.attributes[0].values.pop();
+        assert!(matches!(invalid_metric.validate(), Err(Error::InvalidColumnLength { column, length: 1, expected_length: 2 }) if column == "host"));
+
+        let mut invalid_metric = multivariate_metric.clone();
+        invalid_metric.start_time_unix_nano_column = vec![0];
+        assert!(matches!(invalid_metric.validate(), Err(Error::InvalidColumnLength { column, .. }) if column == "start_time_unix_nano_column"));
+
+        let mut invalid_metric = multivariate_metric;
+        invalid_metric.time_unix_nano_column.push(3);
+        invalid_metric.attributes[0].values.push("host_3".into());
+        assert!(matches!(invalid_metric.validate(), Err(Error::InvalidColumnLength { column, length: 2, expected_length: 3 }) if column == "latency"));
+    }
+}
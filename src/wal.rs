@@ -0,0 +1,864 @@
+//! Disk-backed write-ahead log of the exported batches.
+//!
+//! The batches are appended to segment files (`<sequence>.wal`) as protobuf records protected by a CRC32 checksum:
+//! `tag (1 byte) | length (u32 LE) | crc32 of tag and payload (u32 LE) | payload`. A record is identified by the
+//! `WalPosition` following it. Acknowledging a position persists it in the `ack` file, the segments only containing
+//! acknowledged records are then deleted. On restart the log is reopened, a torn record at the end of the last segment
+//! is truncated and the unacknowledged records are replayed. A corrupted record is never truncated, it is reported by
+//! the replay and its segment can be moved out of the log with `WriteAheadLog::quarantine`. The delivery is at-least-once, a batch exported but not
+//! yet acknowledged before a crash is replayed. A failed write or sync poisons the log until it is reopened, so that the
+//! positions never skip a buffered record. The log failures are reported as `WalError`, wrapped into
+//! `Error::SinkError` by the `WalSink`.
+
+use std::collections::VecDeque;
+use std::convert::{TryFrom, TryInto};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+
+use crate::event::Error;
+use crate::opentelemetry::proto::arrow_events::v1 as arrow_events;
+use crate::opentelemetry::proto::events::v1::ResourceEvents;
+use crate::opentelemetry::proto::metrics::v1::ResourceMetrics;
+use crate::sink::{EventSink, ResourceBatch};
+
+const MAGIC: &[u8; 8] = b"OTELWAL1";
+const HEADER_LEN: u64 = MAGIC.len() as u64;
+const RECORD_HEADER_LEN: u64 = 9;
+const ACK_FILE: &str = "ack";
+const ACK_TMP_FILE: &str = "ack.tmp";
+
+#[derive(thiserror::Error, Debug)]
+pub enum WalError {
+    #[error("Disk Budget Exceeded (usage: {usage} bytes, budget: {budget} bytes)")]
+    DiskBudgetExceeded { usage: u64, budget: u64 },
+    #[error("Checksum Mismatch (segment: {segment}, offset: {offset})")]
+    ChecksumMismatch { segment: u64, offset: u64 },
+    /// The segment ends before the end of the record (or of the segment header at offset 0).
+    #[error("Truncated Record (segment: {segment}, offset: {offset})")]
+    TruncatedRecord { segment: u64, offset: u64 },
+    /// The ack file is corrupted, it must be fixed or deleted (replaying the whole log).
+    #[error("Invalid Ack File (path: {0})")]
+    InvalidAck(PathBuf),
+    /// The record (or the segment header at offset 0) can't be decoded.
+    #[error("Invalid Record (segment: {segment}, offset: {offset}, error: {error})")]
+    InvalidRecord { segment: u64, offset: u64, error: Error },
+    #[error("Poisoned Log (a previous write or sync failed, the log must be reopened)")]
+    Poisoned,
+    /// Failure of the sink of a `WalSink`, the batch is kept in the log.
+    #[error("Export Error (error: {0})")]
+    ExportError(Error),
+    #[error(transparent)]
+    EventError(#[from] Error),
+}
+
+impl WalError {
+    /// Returns the segment of a corrupted record, that can be moved out of the log with `WriteAheadLog::quarantine`.
+    pub fn corrupted_segment(&self) -> Option<u64> {
+        match self {
+            WalError::ChecksumMismatch { segment, .. }
+            | WalError::TruncatedRecord { segment, .. }
+            | WalError::InvalidRecord { segment, .. } => Some(*segment),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for WalError {
+    fn from(err: io::Error) -> Self {
+        WalError::EventError(err.into())
+    }
+}
+
+impl From<WalError> for Error {
+    fn from(err: WalError) -> Self {
+        match err {
+            WalError::EventError(err) | WalError::ExportError(err) => err,
+            err => Error::SinkError(Box::new(err)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WalConfig {
+    /// A new segment is started once a record would make the current one exceed this size (in bytes).
+    pub segment_size: u64,
+    /// Maximum size of the segments (in bytes), an append exceeding it is rejected.
+    pub disk_budget: u64,
+    /// Syncs the segment to the disk after each append.
+    pub sync_on_append: bool,
+}
+
+/// Position following a record in the log.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WalPosition {
+    pub segment: u64,
+    pub offset: u64,
+}
+
+#[derive(Debug)]
+pub struct WriteAheadLog {
+    dir: PathBuf,
+    config: WalConfig,
+    /// Segments by increasing sequence, the last one is being written.
+    segments: VecDeque<Segment>,
+    writer: BufWriter<File>,
+    acknowledged: WalPosition,
+    buffer: Vec<u8>,
+    /// Set by a failed write or sync, the size of the active segment is then unknown.
+    poisoned: bool,
+    #[cfg(test)]
+    fail_sync: bool,
+}
+
+/// Reads the unacknowledged records of a log, see `WriteAheadLog::pending`.
+#[derive(Debug)]
+pub struct WalReader {
+    segments: VecDeque<(u64, u64, PathBuf)>,
+    current: Option<SegmentReader>,
+}
+
+/// Persists the batches in a write-ahead log before exporting them to a sink. A batch is only acknowledged (and
+/// eventually deleted) once the sink has accepted it, so the batches of a failed export are kept on disk and exported
+/// first by the next export, including after a restart. Only `flush` and `export_pending` report the sink errors.
+#[derive(Debug)]
+pub struct WalSink<S: EventSink> {
+    wal: WriteAheadLog,
+    sink: S,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    sequence: u64,
+    size: u64,
+}
+
+#[derive(Debug)]
+struct SegmentReader {
+    sequence: u64,
+    reader: BufReader<File>,
+    offset: u64,
+    /// Size of the segment when it has been opened, the records appended afterwards are not read.
+    len: u64,
+}
+
+impl Default for WalConfig {
+    fn default() -> Self {
+        WalConfig {
+            segment_size: 16 * 1024 * 1024,
+            disk_budget: 256 * 1024 * 1024,
+            sync_on_append: false,
+        }
+    }
+}
+
+impl WriteAheadLog {
+    /// Opens (or creates) the log stored in `dir`. The appends continue in the last segment, after its last valid
+    /// record.
+    pub fn open<P: AsRef<Path>>(dir: P, config: WalConfig) -> Result<Self, WalError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut sequences = vec![];
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) == Some("wal") {
+                if let Some(sequence) = path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse::<u64>().ok()) {
+                    sequences.push(sequence);
+                }
+            }
+        }
+        sequences.sort_unstable();
+
+        let mut segments = VecDeque::with_capacity(sequences.len().max(1));
+        for &sequence in &sequences {
+            let size = fs::metadata(segment_path(&dir, sequence))?.len();
+            segments.push_back(Segment { sequence, size });
+        }
+
+        let last_sequence = segments.back().map(|last| last.sequence);
+        let writer = match last_sequence {
+            Some(sequence) => match Self::recover(&dir, sequence)? {
+                Some(size) => {
+                    segments.back_mut().expect("no last segment").size = size;
+                    BufWriter::new(OpenOptions::new().append(true).open(segment_path(&dir, sequence))?)
+                }
+                None => {
+                    // The corrupted segment is kept for the replay to report it, the appends go to a new segment.
+                    segments.push_back(Segment { sequence: sequence + 1, size: HEADER_LEN });
+                    Self::create_segment(&dir, sequence + 1)?
+                }
+            },
+            None => {
+                segments.push_back(Segment { sequence: 0, size: HEADER_LEN });
+                Self::create_segment(&dir, 0)?
+            }
+        };
+
+        let mut wal = WriteAheadLog {
+            acknowledged: read_ack(&dir)?,
+            dir,
+            config,
+            segments,
+            writer,
+            buffer: vec![],
+            poisoned: false,
+            #[cfg(test)]
+            fail_sync: false,
+        };
+        wal.delete_acknowledged_segments()?;
+        Ok(wal)
+    }
+
+    /// Appends a batch and returns its position. Fails without writing anything if the batch is invalid (see
+    /// `ResourceBatch::validate`), so that it can't block the replay, or if the disk budget would be exceeded. A failed
+    /// write or sync poisons the log, the next appends fail with `WalError::Poisoned` until the log is reopened.
+    pub fn append(&mut self, batch: &ResourceBatch) -> Result<WalPosition, WalError> {
+        self.check_poisoned()?;
+        batch.validate()?;
+        self.buffer.clear();
+        self.buffer.extend_from_slice(&[batch.tag(), 0, 0, 0, 0, 0, 0, 0, 0]);
+        batch.encode(&mut self.buffer)?;
+        let payload_len = self.buffer.len() as u64 - RECORD_HEADER_LEN;
+        let payload_len = u32::try_from(payload_len).map_err(|_| Error::InvalidFormat(format!("batch of {} bytes too large", payload_len)))?;
+        self.buffer[1..5].copy_from_slice(&payload_len.to_le_bytes());
+        let checksum = checksum(self.buffer[0], &self.buffer[RECORD_HEADER_LEN as usize..]);
+        self.buffer[5..9].copy_from_slice(&checksum.to_le_bytes());
+
+        let record_len = self.buffer.len() as u64;
+        let active = *self.active_segment();
+        let roll = active.size > HEADER_LEN && active.size + record_len > self.config.segment_size;
+        let required = record_len + if roll { HEADER_LEN } else { 0 };
+        if self.disk_usage() + required > self.config.disk_budget {
+            return Err(WalError::DiskBudgetExceeded { usage: self.disk_usage(), budget: self.config.disk_budget });
+        }
+
+        let result = self.write_record(roll);
+        self.poison_on_error(result)
+    }
+
+    /// Returns a reader of the unacknowledged records, by position.
+    pub fn pending(&mut self) -> Result<WalReader, WalError> {
+        self.check_poisoned()?;
+        let result = self.writer.flush().map_err(WalError::from);
+        self.poison_on_error(result)?;
+        let acknowledged = self.acknowledged;
+        let segments = self.segments.iter()
+            .filter(|segment| segment.sequence >= acknowledged.segment)
+            .map(|segment| {
+                let start = if segment.sequence == acknowledged.segment { acknowledged.offset.max(HEADER_LEN) } else { HEADER_LEN };
+                (segment.sequence, start, segment_path(&self.dir, segment.sequence))
+            })
+            .collect();
+        Ok(WalReader { segments, current: None })
+    }
+
+    /// Acknowledges the records up to `position` (included) and deletes the segments only containing acknowledged
+    /// records, except the segment being written.
+    pub fn acknowledge(&mut self, position: WalPosition) -> Result<(), WalError> {
+        if position <= self.acknowledged {
+            return Ok(());
+        }
+
+        let mut ack = Vec::with_capacity(20);
+        ack.extend_from_slice(&position.segment.to_le_bytes());
+        ack.extend_from_slice(&position.offset.to_le_bytes());
+        ack.extend_from_slice(&crc32fast::hash(&ack).to_le_bytes());
+        let tmp_path = self.dir.join(ACK_TMP_FILE);
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&ack)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, self.dir.join(ACK_FILE))?;
+        // Persists the rename.
+        File::open(&self.dir)?.sync_all()?;
+
+        self.acknowledged = position;
+        self.delete_acknowledged_segments()
+    }
+
+    /// Moves a corrupted segment out of the log (renamed `<sequence>.corrupt` for inspection), the replay skips its
+    /// records. The appends continue in a new segment if it is the segment being written.
+    pub fn quarantine(&mut self, sequence: u64) -> Result<(), WalError> {
+        self.check_poisoned()?;
+        if self.active_segment().sequence == sequence {
+            let result = self.start_segment();
+            self.poison_on_error(result)?;
+        }
+        // The segment may have been deleted meanwhile, once acknowledged.
+        if let Some(index) = self.segments.iter().position(|segment| segment.sequence == sequence) {
+            fs::rename(segment_path(&self.dir, sequence), self.dir.join(format!("{:020}.corrupt", sequence)))?;
+            self.segments.remove(index);
+        }
+        Ok(())
+    }
+
+    pub fn acknowledged(&self) -> WalPosition {
+        self.acknowledged
+    }
+
+    /// Returns the size of the segments (in bytes).
+    pub fn disk_usage(&self) -> u64 {
+        self.segments.iter().map(|segment| segment.size).sum()
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Flushes the buffered records and syncs the segment being written to the disk.
+    pub fn sync(&mut self) -> Result<(), WalError> {
+        self.check_poisoned()?;
+        let result = self.sync_segment();
+        self.poison_on_error(result)
+    }
+
+    /// Writes the encoded record of `buffer` into the active segment, or into a new segment if `roll`. The record is
+    /// counted as soon as it is buffered, it reaches the disk with the next flush.
+    fn write_record(&mut self, roll: bool) -> Result<WalPosition, WalError> {
+        if roll {
+            self.start_segment()?;
+        }
+
+        self.writer.write_all(&self.buffer)?;
+        let active = self.segments.back_mut().expect("no active segment");
+        active.size += self.buffer.len() as u64;
+        let position = WalPosition { segment: active.sequence, offset: active.size };
+        if self.config.sync_on_append {
+            self.sync_segment()?;
+        }
+        Ok(position)
+    }
+
+    /// Syncs the active segment and starts the next one.
+    fn start_segment(&mut self) -> Result<(), WalError> {
+        self.sync_segment()?;
+        let sequence = self.active_segment().sequence + 1;
+        self.writer = Self::create_segment(&self.dir, sequence)?;
+        self.segments.push_back(Segment { sequence, size: HEADER_LEN });
+        self.delete_acknowledged_segments()
+    }
+
+    fn sync_segment(&mut self) -> Result<(), WalError> {
+        #[cfg(test)]
+        if self.fail_sync {
+            return Err(io::Error::other("injected sync failure").into());
+        }
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        Ok(())
+    }
+
+    fn check_poisoned(&self) -> Result<(), WalError> {
+        if self.poisoned {
+            return Err(WalError::Poisoned);
+        }
+        Ok(())
+    }
+
+    fn poison_on_error<T>(&mut self, result: Result<T, WalError>) -> Result<T, WalError> {
+        if result.is_err() {
+            self.poisoned = true;
+        }
+        result
+    }
+
+    fn active_segment(&self) -> &Segment {
+        self.segments.back().expect("no active segment")
+    }
+
+    fn delete_acknowledged_segments(&mut self) -> Result<(), WalError> {
+        while self.segments.len() > 1 {
+            let segment = self.segments[0];
+            let acknowledged = segment.sequence < self.acknowledged.segment
+                || (segment.sequence == self.acknowledged.segment && self.acknowledged.offset >= segment.size);
+            if !acknowledged {
+                break;
+            }
+            fs::remove_file(segment_path(&self.dir, segment.sequence))?;
+            self.segments.pop_front();
+        }
+        Ok(())
+    }
+
+    fn create_segment(dir: &Path, sequence: u64) -> Result<BufWriter<File>, WalError> {
+        let mut writer = BufWriter::new(File::create(segment_path(dir, sequence))?);
+        writer.write_all(MAGIC)?;
+        Ok(writer)
+    }
+
+    /// Truncates the torn record at the end of the segment (e.g. after a crash during an append), returns the size of
+    /// the segment. Returns None without truncating anything if a record is corrupted (e.g. checksum mismatch).
+    fn recover(dir: &Path, sequence: u64) -> Result<Option<u64>, WalError> {
+        let path = segment_path(dir, sequence);
+        let valid_len = match SegmentReader::open(&path, sequence, HEADER_LEN) {
+            Ok(mut reader) => loop {
+                match reader.read_record() {
+                    Ok(Some(_)) => {}
+                    Ok(None) | Err(WalError::TruncatedRecord { .. }) => break reader.offset,
+                    Err(WalError::ChecksumMismatch { .. }) => return Ok(None),
+                    Err(err) => return Err(err),
+                }
+            },
+            Err(WalError::TruncatedRecord { .. }) => 0,
+            Err(WalError::InvalidRecord { .. }) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let file = OpenOptions::new().write(true).open(&path)?;
+        if valid_len == 0 {
+            // Torn segment header.
+            file.set_len(0)?;
+            (&file).write_all(MAGIC)?;
+        } else if file.metadata()?.len() != valid_len {
+            file.set_len(valid_len)?;
+        } else {
+            return Ok(Some(valid_len));
+        }
+        file.sync_data()?;
+        Ok(Some(valid_len.max(HEADER_LEN)))
+    }
+}
+
+impl WalReader {
+    fn next_record(&mut self) -> Result<Option<(WalPosition, ResourceBatch)>, WalError> {
+        loop {
+            if self.current.is_none() {
+                match self.segments.pop_front() {
+                    Some((sequence, start, path)) => self.current = Some(SegmentReader::open(&path, sequence, start)?),
+                    None => return Ok(None),
+                }
+            }
+
+            let reader = self.current.as_mut().expect("no current segment");
+            let offset = reader.offset;
+            match reader.read_record()? {
+                Some((tag, payload)) => {
+                    let batch = ResourceBatch::decode(tag, payload)
+                        .map_err(|error| WalError::InvalidRecord { segment: reader.sequence, offset, error })?;
+                    return Ok(Some((WalPosition { segment: reader.sequence, offset: reader.offset }, batch)));
+                }
+                None => self.current = None,
+            }
+        }
+    }
+}
+
+impl Iterator for WalReader {
+    type Item = Result<(WalPosition, ResourceBatch), WalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = self.next_record().transpose();
+        if matches!(record, Some(Err(_))) {
+            self.segments.clear();
+            self.current = None;
+        }
+        record
+    }
+}
+
+impl SegmentReader {
+    fn open(path: &Path, sequence: u64, start: u64) -> Result<Self, WalError> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let mut magic = [0u8; HEADER_LEN as usize];
+        match reader.read_exact(&mut magic) {
+            Ok(()) if &magic == MAGIC => {}
+            Ok(()) => return Err(WalError::InvalidRecord { segment: sequence, offset: 0, error: Error::InvalidFormat("invalid segment header".into()) }),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Err(WalError::TruncatedRecord { segment: sequence, offset: 0 }),
+            Err(err) => return Err(err.into()),
+        }
+        reader.seek(SeekFrom::Start(start))?;
+        Ok(SegmentReader { sequence, reader, offset: start, len })
+    }
+
+    /// Reads the next record, returns None at the end of the segment.
+    fn read_record(&mut self) -> Result<Option<(u8, Bytes)>, WalError> {
+        if self.offset >= self.len {
+            return Ok(None);
+        }
+        let mut header = [0u8; RECORD_HEADER_LEN as usize];
+        let read = read_full(&mut self.reader, &mut header)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        if read < header.len() {
+            return Err(WalError::TruncatedRecord { segment: self.sequence, offset: self.offset });
+        }
+
+        let len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+        let expected_checksum = u32::from_le_bytes([header[5], header[6], header[7], header[8]]);
+        if self.offset + RECORD_HEADER_LEN + len as u64 > self.len {
+            return Err(WalError::TruncatedRecord { segment: self.sequence, offset: self.offset });
+        }
+        let mut payload = vec![0u8; len];
+        if read_full(&mut self.reader, &mut payload)? < len {
+            return Err(WalError::TruncatedRecord { segment: self.sequence, offset: self.offset });
+        }
+        if checksum(header[0], &payload) != expected_checksum {
+            return Err(WalError::ChecksumMismatch { segment: self.sequence, offset: self.offset });
+        }
+
+        self.offset += RECORD_HEADER_LEN + len as u64;
+        Ok(Some((header[0], Bytes::from(payload))))
+    }
+}
+
+impl<S: EventSink> WalSink<S> {
+    pub fn new(wal: WriteAheadLog, sink: S) -> Self {
+        WalSink { wal, sink }
+    }
+
+    pub fn wal(&self) -> &WriteAheadLog {
+        &self.wal
+    }
+
+    /// Gives access to the log, e.g. to quarantine a corrupted segment blocking the replay.
+    pub fn wal_mut(&mut self) -> &mut WriteAheadLog {
+        &mut self.wal
+    }
+
+    pub fn into_inner(self) -> (WriteAheadLog, S) {
+        (self.wal, self.sink)
+    }
+
+    /// Exports the pending batches in order and acknowledges them, returns the number of exported batches. Stops at
+    /// the first failed export (`WalError::ExportError`) or corrupted record (see `WalError::corrupted_segment`), the
+    /// remaining batches are kept in the log.
+    pub fn export_pending(&mut self) -> Result<usize, WalError> {
+        let mut exported = 0;
+        for record in self.wal.pending()? {
+            let (position, batch) = record?;
+            batch.export_to(&mut self.sink).map_err(WalError::ExportError)?;
+            self.wal.acknowledge(position)?;
+            exported += 1;
+        }
+        Ok(exported)
+    }
+
+    /// Succeeds once the batch is persisted, a failed export is retried by the next export or flush. An invalid batch
+    /// is rejected without being persisted. The failures of the log replaying the batches (e.g. a corrupted record) are
+    /// reported, the batch being persisted nonetheless.
+    fn export(&mut self, batch: ResourceBatch) -> Result<(), WalError> {
+        self.wal.append(&batch)?;
+        match self.export_pending() {
+            Ok(_) | Err(WalError::ExportError(_)) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl<S: EventSink> EventSink for WalSink<S> {
+    fn export_events(&mut self, resource_events: ResourceEvents) -> Result<(), Error> {
+        Ok(self.export(ResourceBatch::Events(resource_events))?)
+    }
+
+    fn export_arrow_events(&mut self, resource_events: arrow_events::ResourceEvents) -> Result<(), Error> {
+        Ok(self.export(ResourceBatch::ArrowEvents(resource_events))?)
+    }
+
+    fn export_metrics(&mut self, resource_metrics: ResourceMetrics) -> Result<(), Error> {
+        Ok(self.export(ResourceBatch::Metrics(resource_metrics))?)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.wal.sync()?;
+        self.export_pending()?;
+        self.sink.flush()
+    }
+}
+
+fn segment_path(dir: &Path, sequence: u64) -> PathBuf {
+    dir.join(format!("{:020}.wal", sequence))
+}
+
+fn checksum(tag: u8, payload: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&[tag]);
+    hasher.update(payload);
+    hasher.finalize()
+}
+
+/// Returns the acknowledged position, the start of the log if the ack file is missing. Fails if the ack file is
+/// corrupted rather than replaying the whole log, the file must then be fixed or deleted.
+fn read_ack(dir: &Path) -> Result<WalPosition, WalError> {
+    let path = dir.join(ACK_FILE);
+    match fs::read(&path) {
+        Ok(ack) if ack.len() == 20 && crc32fast::hash(&ack[..16]).to_le_bytes() == ack[16..] => Ok(WalPosition {
+            segment: u64::from_le_bytes(ack[..8].try_into().expect("invalid ack length")),
+            offset: u64::from_le_bytes(ack[8..16].try_into().expect("invalid ack length")),
+        }),
+        Ok(_) => Err(WalError::InvalidAck(path)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(WalPosition::default()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Reads until the buffer is full or the end of the reader, returns the number of bytes read.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize, WalError> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use crate::event::{BatchPolicy, Error, EventBatchHandler, OpenTelemetryEvent};
+    use crate::opentelemetry::proto::events::v1::{BatchEvent, InstrumentationLibraryEvents, ResourceEvents};
+    use crate::opentelemetry::proto::metrics::v1::ResourceMetrics;
+    use crate::sink::{EventSink, MemorySink, ResourceBatch, UnavailableSink};
+    use crate::wal::{segment_path, WalConfig, WalError, WalPosition, WalSink, WriteAheadLog};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("otel-wal-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    fn batch(schema_url: &str) -> ResourceBatch {
+        ResourceBatch::Events(ResourceEvents { schema_url: schema_url.into(), ..Default::default() })
+    }
+
+    fn pending(wal: &mut WriteAheadLog) -> Vec<ResourceBatch> {
+        wal.pending().unwrap().map(|record| record.unwrap().1).collect()
+    }
+
+    #[derive(Debug, OpenTelemetryEvent)]
+    #[otel(urn = "urn:test:request")]
+    struct Request {
+        #[otel(start_time)]
+        time: u64,
+    }
+
+    #[test]
+    fn test_replay() {
+        let dir = temp_dir("replay");
+        let mut wal = WriteAheadLog::open(&dir, WalConfig::default()).unwrap();
+        let first = wal.append(&batch("1")).unwrap();
+        wal.append(&ResourceBatch::Metrics(ResourceMetrics { schema_url: "2".into(), ..Default::default() })).unwrap();
+        wal.append(&batch("3")).unwrap();
+        drop(wal);
+
+        let mut wal = WriteAheadLog::open(&dir, WalConfig::default()).unwrap();
+        assert_eq!(pending(&mut wal).len(), 3);
+        wal.acknowledge(first).unwrap();
+        wal.append(&batch("4")).unwrap();
+        drop(wal);
+
+        let mut wal = WriteAheadLog::open(&dir, WalConfig::default()).unwrap();
+        assert_eq!(wal.acknowledged(), first);
+        let batches = pending(&mut wal);
+        assert_eq!(batches.len(), 3);
+        assert!(matches!(&batches[0], ResourceBatch::Metrics(resource_metrics) if resource_metrics.schema_url == "2"));
+        assert_eq!(batches[2], batch("4"));
+        drop(wal);
+
+        // A corrupted ack file is reported instead of replaying the whole log.
+        let ack_path = dir.join("ack");
+        let mut ack = fs::read(&ack_path).unwrap();
+        ack[0] ^= 0xff;
+        fs::write(&ack_path, ack).unwrap();
+        assert!(matches!(WriteAheadLog::open(&dir, WalConfig::default()), Err(WalError::InvalidAck(path)) if path == ack_path));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_segments() {
+        let dir = temp_dir("segments");
+        let config = WalConfig { segment_size: 64, disk_budget: 190, sync_on_append: false };
+        let mut wal = WriteAheadLog::open(&dir, config.clone()).unwrap();
+        let schema_url = "x".repeat(30);
+        let positions: Vec<WalPosition> = (0..3).map(|_| wal.append(&batch(&schema_url)).unwrap()).collect();
+        assert_eq!(wal.segment_count(), 3);
+        assert_eq!(positions[1], WalPosition { segment: 1, offset: 49 });
+        assert!(matches!(wal.append(&batch(&schema_url)), Err(WalError::DiskBudgetExceeded { usage: 147, budget: 190 })));
+
+        // The acknowledged segments are deleted, except the segment being written.
+        wal.acknowledge(positions[1]).unwrap();
+        assert_eq!(wal.segment_count(), 1);
+        assert!(!segment_path(&dir, 0).exists());
+        wal.acknowledge(positions[2]).unwrap();
+        assert_eq!(wal.segment_count(), 1);
+        assert!(pending(&mut wal).is_empty());
+        wal.append(&batch(&schema_url)).unwrap();
+        assert_eq!((wal.segment_count(), wal.disk_usage()), (1, 49));
+        drop(wal);
+
+        let mut wal = WriteAheadLog::open(&dir, config).unwrap();
+        assert_eq!(pending(&mut wal), vec![batch(&schema_url)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_corruption() {
+        let dir = temp_dir("corruption");
+        let config = WalConfig { segment_size: 64, ..Default::default() };
+        let mut wal = WriteAheadLog::open(&dir, config.clone()).unwrap();
+        wal.append(&batch("1")).unwrap();
+        wal.append(&batch("2")).unwrap();
+        wal.sync().unwrap();
+        drop(wal);
+
+        // A torn record at the end of the last segment is truncated on open.
+        let mut file = OpenOptions::new().append(true).open(segment_path(&dir, 0)).unwrap();
+        file.write_all(&[1, 10, 0, 0, 0, 0]).unwrap();
+        drop(file);
+        let mut wal = WriteAheadLog::open(&dir, config.clone()).unwrap();
+        assert_eq!(wal.disk_usage(), 32);
+        wal.append(&batch(&"3".repeat(40))).unwrap();
+        assert_eq!(pending(&mut wal), vec![batch("1"), batch("2"), batch(&"3".repeat(40))]);
+        drop(wal);
+
+        // A record longer than the segment is truncated on open, without allocating its claimed length.
+        let mut file = OpenOptions::new().append(true).open(segment_path(&dir, 1)).unwrap();
+        file.write_all(&[1, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]).unwrap();
+        drop(file);
+        let mut wal = WriteAheadLog::open(&dir, config.clone()).unwrap();
+        assert_eq!(pending(&mut wal).len(), 3);
+        drop(wal);
+
+        // A corrupted record of a previous segment is reported.
+        let path = segment_path(&dir, 0);
+        let mut segment = fs::read(&path).unwrap();
+        let last = segment.len() - 1;
+        segment[last] ^= 0xff;
+        fs::write(&path, segment).unwrap();
+        let mut wal = WriteAheadLog::open(&dir, config.clone()).unwrap();
+        let records: Vec<_> = wal.pending().unwrap().collect();
+        assert_eq!(records.len(), 2);
+        assert!(matches!(records[1], Err(WalError::ChecksumMismatch { segment: 0, offset: 20 })));
+        drop(wal);
+
+        // A corrupted record in the middle of the last segment is kept, the appends go to a new segment.
+        let path = segment_path(&dir, 1);
+        let mut segment = fs::read(&path).unwrap();
+        let len = segment.len();
+        segment[20] ^= 0xff;
+        fs::write(&path, segment).unwrap();
+        let mut wal = WriteAheadLog::open(&dir, config).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), len as u64);
+        assert_eq!(wal.segment_count(), 3);
+        wal.append(&batch("4")).unwrap();
+        assert!(segment_path(&dir, 2).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let dir = temp_dir("quarantine");
+        let config = WalConfig { segment_size: 64, ..Default::default() };
+        let mut wal = WriteAheadLog::open(&dir, config.clone()).unwrap();
+        wal.append(&batch("1")).unwrap();
+        wal.append(&batch("2")).unwrap();
+        wal.append(&batch(&"3".repeat(40))).unwrap();
+        drop(wal);
+        let path = segment_path(&dir, 0);
+        let mut segment = fs::read(&path).unwrap();
+        let last = segment.len() - 1;
+        segment[last] ^= 0xff;
+        fs::write(&path, segment).unwrap();
+
+        // The corrupted record blocks the replay and is reported by the exports.
+        let received = MemorySink::new();
+        let sink = UnavailableSink::new(true, received.clone());
+        let mut wal_sink = WalSink::new(WriteAheadLog::open(&dir, config).unwrap(), sink);
+        let err = wal_sink.export_pending().unwrap_err();
+        assert!(matches!(err, WalError::ChecksumMismatch { segment: 0, offset: 20 }));
+        assert_eq!(received.len(), 1);
+        let events = ResourceEvents { schema_url: "4".into(), ..Default::default() };
+        assert!(matches!(wal_sink.export_events(events.clone()), Err(Error::SinkError(_))));
+
+        // Once the segment is quarantined, the replay continues with the next segment.
+        wal_sink.wal_mut().quarantine(err.corrupted_segment().unwrap()).unwrap();
+        assert!(!path.exists());
+        assert!(dir.join(format!("{:020}.corrupt", 0)).exists());
+        assert_eq!(wal_sink.export_pending().unwrap(), 2);
+        assert_eq!(received.batches()[1..], [batch(&"3".repeat(40)), ResourceBatch::Events(events)]);
+
+        // Quarantining the segment being written starts a new one.
+        wal_sink.wal_mut().quarantine(1).unwrap();
+        assert_eq!(wal_sink.wal().segment_count(), 1);
+        wal_sink.export_events(ResourceEvents::default()).unwrap();
+        assert_eq!(received.len(), 4);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sync_failure() {
+        let dir = temp_dir("sync_failure");
+        let config = WalConfig { sync_on_append: true, ..Default::default() };
+        let mut wal = WriteAheadLog::open(&dir, config.clone()).unwrap();
+        wal.append(&batch("1")).unwrap();
+        wal.fail_sync = true;
+        assert!(wal.append(&batch("2")).is_err());
+        wal.fail_sync = false;
+        assert!(matches!(wal.append(&batch("3")), Err(WalError::Poisoned)));
+        assert!(matches!(wal.sync(), Err(WalError::Poisoned)));
+        drop(wal);
+
+        // The record buffered before the failed sync is flushed on drop, the reopened log counts it.
+        let mut wal = WriteAheadLog::open(&dir, config).unwrap();
+        assert_eq!(pending(&mut wal), vec![batch("1"), batch("2")]);
+        let position = wal.append(&batch("3")).unwrap();
+        assert_eq!(position.offset, wal.disk_usage());
+        wal.acknowledge(position).unwrap();
+        assert!(pending(&mut wal).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_wal_sink() {
+        let dir = temp_dir("sink");
+        let received = MemorySink::new();
+        let sink = UnavailableSink::new(false, received.clone());
+        let wal_sink = WalSink::new(WriteAheadLog::open(&dir, WalConfig::default()).unwrap(), sink);
+        let mut handler = EventBatchHandler::<Request>::new(BatchPolicy::new(2, chrono::Duration::seconds(10)))
+            .with_sink(wal_sink);
+
+        // The collector is unavailable, the batches are kept in the log.
+        for time in 0..5 {
+            handler.record(Request { time }).unwrap();
+        }
+        handler.flush().unwrap();
+        assert!(received.is_empty());
+        drop(handler);
+
+        // On restart, the batches are replayed in order and acknowledged.
+        let sink = UnavailableSink::new(true, received.clone());
+        let mut wal_sink = WalSink::new(WriteAheadLog::open(&dir, WalConfig::default()).unwrap(), sink);
+        assert_eq!(wal_sink.export_pending().unwrap(), 3);
+        let sizes: Vec<u32> = received.batches().iter().map(|batch| match batch {
+            ResourceBatch::Events(resource_events) => resource_events.instrumentation_library_events[0].batches[0].size,
+            _ => 0,
+        }).collect();
+        assert_eq!(sizes, vec![2, 2, 1]);
+        assert_eq!(wal_sink.export_pending().unwrap(), 0);
+        wal_sink.flush().unwrap();
+
+        // An invalid batch is rejected before being appended, the log keeps replaying the valid batches.
+        let mut invalid_events = ResourceEvents {
+            instrumentation_library_events: vec![InstrumentationLibraryEvents { batches: vec![BatchEvent { size: 1, ..Default::default() }], ..Default::default() }],
+            ..Default::default()
+        };
+        assert!(matches!(wal_sink.export_events(invalid_events.clone()), Err(Error::InvalidColumnLength { .. })));
+        invalid_events.instrumentation_library_events[0].batches[0].start_time_unix_nano_column = vec![1];
+        invalid_events.instrumentation_library_events[0].batches[0].end_time_unix_nano_column = vec![1];
+        wal_sink.export_events(invalid_events).unwrap();
+        assert_eq!(received.len(), 4);
+        assert_eq!(wal_sink.export_pending().unwrap(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}